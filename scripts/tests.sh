@@ -37,12 +37,107 @@ trap cleanup EXIT
 echo "=== Connection Tests ==="
 redis-cli -p 6379 PING
 redis-cli -p 6379 ECHO "Hello Rudis"
+redis-cli -p 6379 HEALTH
+# Connections are logged at info, each command only at debug
+grep -q "client{id=.*}: Client connected" /tmp/rudis_test.log
+if grep -q "Executing command" /tmp/rudis_test.log; then
+    echo "ERROR: commands were logged at the default info level"
+    exit 1
+fi
+echo "✓ Default log level leaves out per-command lines"
+
+echo ""
+echo "=== Cluster Stub ==="
+redis-cli -p 6379 CLUSTER INFO
+redis-cli -p 6379 CLUSTER MYID
+redis-cli -p 6379 CLUSTER SLOTS
+
+echo ""
+echo "=== Command Key Specs ==="
+redis-cli -p 6379 COMMAND GETKEYS SET foo bar
+redis-cli -p 6379 COMMAND GETKEYS DEL a b c
+redis-cli -p 6379 COMMAND GETKEYS ZADD board 1 alice
+redis-cli -p 6379 COMMAND GETKEYS ZUNIONSTORE out 2 a b WEIGHTS 1 2
+redis-cli -p 6379 COMMAND GETKEYS ZDIFF 2 a b WITHSCORES
+if [ "$(redis-cli -p 6379 COMMAND GETKEYS LCS k1 k2 | tr '\n' ' ')" != "k1 k2 " ]; then
+    echo "ERROR: COMMAND GETKEYS LCS should return both keys"
+    exit 1
+fi
+redis-cli -p 6379 COMMAND GETKEYS PING 2>&1
+redis-cli -p 6379 COMMAND GETKEYS NOSUCHCMD foo 2>&1
+redis-cli -p 6379 COMMAND GETKEYS GET 2>&1
+redis-cli -p 6379 COMMAND GETKEYS 2>&1
+echo "✓ COMMAND GETKEYS follows the command table"
+redis-cli -p 6379 COMMAND DOCS GET
+if [ "$(redis-cli -p 6379 COMMAND DOCS GET | sed -n '1,5p' | tr '\n' ' ')" != "get summary Returns the string value of a key group string " ]; then
+    echo "ERROR: COMMAND DOCS GET should start with the name, summary and group"
+    exit 1
+fi
+if [ "$(redis-cli -p 6379 COMMAND DOCS DEL | grep -c multiple)" != "1" ]; then
+    echo "ERROR: COMMAND DOCS DEL should mark its key argument as repeating"
+    exit 1
+fi
+if [ "$(redis-cli -p 6379 COMMAND DOCS GET NOSUCHCMD SET | grep -cx 'get\|set')" != "2" ]; then
+    echo "ERROR: COMMAND DOCS should skip names that aren't commands"
+    exit 1
+fi
+if [ "$(redis-cli -p 6379 COMMAND DOCS | grep -cx summary)" -lt 100 ]; then
+    echo "ERROR: COMMAND DOCS without names should cover every command"
+    exit 1
+fi
+echo "✓ COMMAND DOCS describes commands from the command table"
+
+echo ""
+echo "=== Failover Stub ==="
+redis-cli -p 6379 FAILOVER
+redis-cli -p 6379 FAILOVER TO 127.0.0.1 6380 TIMEOUT 1000
+redis-cli -p 6379 FAILOVER ABORT
+redis-cli -p 6379 FAILOVER ABORT FORCE
+
+echo ""
+echo "=== WAITAOF ==="
+elapsed_ms() {
+    local start
+    start=$(date +%s%N)
+    "$@" > /dev/null
+    echo $((($(date +%s%N) - start) / 1000000))
+}
+# There is no AOF: nothing local can be waited for, and no replica acknowledges
+redis-cli -p 6379 WAITAOF 1 0 0 2>&1
+redis-cli -p 6379 WAITAOF 0 0 0
+if [ "$(elapsed_ms redis-cli -p 6379 WAITAOF 0 1 300)" -lt 250 ]; then
+    echo "ERROR: WAITAOF for a replica returned before its timeout"
+    exit 1
+fi
+redis-cli -p 6379 WAITAOF 0 1 -1 2>&1
 
 echo ""
 echo "=== String Operations ==="
 redis-cli -p 6379 FLUSHDB
 redis-cli -p 6379 SET mykey "Hello World"
 redis-cli -p 6379 GET mykey
+expect_range() {
+    local actual
+    actual=$(redis-cli -p 6379 "$1" mykey "$2" "$3")
+    if [ "$actual" != "$4" ]; then
+        echo "ERROR: $1 mykey $2 $3 returned '$actual', expected '$4'"
+        exit 1
+    fi
+}
+expect_range GETRANGE 0 4 "Hello"
+expect_range GETRANGE -5 -1 "World"
+expect_range GETRANGE 6 100 "World"
+# Out-of-range and reversed ranges are empty strings, never nil
+expect_range GETRANGE 11 20 ""
+expect_range GETRANGE 4 2 ""
+expect_range GETRANGE -1 -5 ""
+expect_range GETRANGE -100 -50 "H"
+expect_range SUBSTR 0 -7 "Hello"
+redis-cli -p 6379 GETRANGE nosuchkey 0 -1
+redis-cli -p 6379 SET mynum 12345 > /dev/null
+redis-cli -p 6379 GETRANGE mynum 1 2
+redis-cli -p 6379 GETRANGE mykey a 1 2>&1
+echo "✓ GETRANGE and SUBSTR follow Redis' range rules"
 redis-cli -p 6379 DEL mykey
 redis-cli -p 6379 GET mykey
 
@@ -53,6 +148,216 @@ redis-cli -p 6379 INCR counter
 redis-cli -p 6379 INCR counter
 redis-cli -p 6379 DECR counter
 redis-cli -p 6379 GET counter
+redis-cli -p 6379 INCRBY counter 100
+redis-cli -p 6379 DECRBY counter 30
+redis-cli -p 6379 INCRBY counter notanumber 2>&1
+# Overflow is refused in both directions and leaves the value alone
+redis-cli -p 6379 SET counter 9223372036854775800
+if [ "$(redis-cli -p 6379 INCRBY counter 8 2>&1)" != "ERR increment or decrement would overflow" ]; then
+    echo "ERROR: INCRBY overflow was not detected"
+    exit 1
+fi
+redis-cli -p 6379 SET counter -9223372036854775800
+if [ "$(redis-cli -p 6379 DECRBY counter 9 2>&1)" != "ERR increment or decrement would overflow" ]; then
+    echo "ERROR: DECRBY overflow was not detected"
+    exit 1
+fi
+redis-cli -p 6379 DECRBY counter -9223372036854775808 2>&1
+if [ "$(redis-cli -p 6379 GET counter)" != "-9223372036854775800" ]; then
+    echo "ERROR: a failed INCRBY/DECRBY changed the value"
+    exit 1
+fi
+echo "✓ INCRBY and DECRBY detect overflow"
+
+echo ""
+echo "=== String Encoding ==="
+redis-cli -p 6379 SET enc:n 41
+redis-cli -p 6379 OBJECT ENCODING enc:n
+redis-cli -p 6379 INCR enc:n
+redis-cli -p 6379 OBJECT ENCODING enc:n
+echo "APPEND demotes to a raw string:"
+redis-cli -p 6379 APPEND enc:n 0
+redis-cli -p 6379 OBJECT ENCODING enc:n
+redis-cli -p 6379 GET enc:n
+redis-cli -p 6379 SET enc:s 12345
+redis-cli -p 6379 SETRANGE enc:s 0 9
+redis-cli -p 6379 OBJECT ENCODING enc:s
+redis-cli -p 6379 GET enc:s
+redis-cli -p 6379 SET enc:z 007
+redis-cli -p 6379 OBJECT ENCODING enc:z
+
+echo ""
+echo "=== String Size Limit ==="
+redis-cli -p 6379 DEL huge
+for offset in 536870912 99999999999; do
+    if [ "$(redis-cli -p 6379 SETRANGE huge $offset x 2>&1)" != "ERR string exceeds maximum allowed size (proto-max-bulk-len)" ]; then
+        echo "ERROR: SETRANGE at offset $offset should exceed proto-max-bulk-len"
+        exit 1
+    fi
+done
+if [ "$(redis-cli -p 6379 EXISTS huge)" != "0" ]; then
+    echo "ERROR: a rejected SETRANGE created the key"
+    exit 1
+fi
+# A lower limit makes APPEND reach it without allocating 512MB
+redis-cli -p 6379 CONFIG SET proto-max-bulk-len 1048576
+redis-cli -p 6379 CONFIG GET proto-max-bulk-len
+redis-cli -p 6379 CONFIG GET tcp-keepalive
+redis-cli -p 6379 SETRANGE huge 1048570 abcdef
+redis-cli -p 6379 APPEND huge g 2>&1
+redis-cli -p 6379 SETRANGE huge 1048575 xy 2>&1
+redis-cli -p 6379 CONFIG SET proto-max-bulk-len 1000 2>&1
+# The parser refuses longer bulk strings before reading them
+exec 3<>/dev/tcp/127.0.0.1/6379
+sleep 0.2
+echo -n $'*3\r\n$3\r\nSET\r\n$4\r\nhuge\r\n$1048577\r\n' >&3
+if ! timeout 1 cat <&3 | grep -q "invalid bulk length"; then
+    echo "ERROR: a bulk string over proto-max-bulk-len was not rejected"
+    exit 1
+fi
+exec 3<&-
+redis-cli -p 6379 CONFIG SET proto-max-bulk-len 536870912
+redis-cli -p 6379 DEL huge
+echo "✓ String growth and bulk strings are bounded by proto-max-bulk-len"
+
+echo ""
+echo "=== Large Replies ==="
+# Replies are streamed to the socket a chunk at a time; they must arrive
+# byte for byte as if they had been built whole
+BIG_VALUE=$(head -c 20000 /dev/zero | tr '\0' v)
+redis-cli -p 6379 DEL biglist
+redis-cli -p 6379 RPUSH biglist "$BIG_VALUE" "$BIG_VALUE" short
+exec 3<>/dev/tcp/127.0.0.1/6379
+sleep 0.2
+echo -n $'*4\r\n$6\r\nLRANGE\r\n$7\r\nbiglist\r\n$1\r\n0\r\n$2\r\n-1\r\n' >&3
+# *3, two bulk strings of 20000 bytes and "short"
+if [ "$(timeout 1 cat <&3 | wc -c)" != "$((4 + 2 * (8 + 20000 + 2) + 11))" ]; then
+    echo "ERROR: a large LRANGE reply was not written in full"
+    exit 1
+fi
+exec 3<&-
+redis-cli -p 6379 DEBUG POPULATE 5000 bigkeys:
+if [ "$(redis-cli -p 6379 KEYS 'bigkeys:*' | wc -l)" != "5000" ]; then
+    echo "ERROR: KEYS did not return every key of a large reply"
+    exit 1
+fi
+redis-cli -p 6379 KEYS 'bigkeys:*' | xargs redis-cli -p 6379 DEL
+redis-cli -p 6379 DEL biglist
+echo "✓ Large replies arrived whole"
+
+echo ""
+echo "=== Collection Encodings ==="
+expect_encoding() {
+    local actual
+    actual=$(redis-cli -p 6379 OBJECT ENCODING "$1")
+    if [ "$actual" != "$2" ]; then
+        echo "ERROR: $1 is encoded as $actual, expected $2"
+        exit 1
+    fi
+    echo "$1: $actual"
+}
+redis-cli -p 6379 CONFIG SET list-max-listpack-size 3
+redis-cli -p 6379 CONFIG SET set-max-intset-entries 3
+redis-cli -p 6379 CONFIG SET set-max-listpack-entries 4
+redis-cli -p 6379 CONFIG SET set-max-listpack-value 5
+redis-cli -p 6379 CONFIG SET zset-max-listpack-entries 2
+redis-cli -p 6379 CONFIG SET zset-max-listpack-value 5
+redis-cli -p 6379 CONFIG SET hash-max-listpack-entries 2
+redis-cli -p 6379 CONFIG SET hash-max-listpack-value 5
+redis-cli -p 6379 CONFIG GET "*-max-*"
+redis-cli -p 6379 RPUSH enc:list a b c > /dev/null
+expect_encoding enc:list listpack
+redis-cli -p 6379 RPUSH enc:list d > /dev/null
+expect_encoding enc:list quicklist
+# A negative size limits bytes instead: -1 is 4kb
+redis-cli -p 6379 CONFIG SET list-max-listpack-size -1
+expect_encoding enc:list listpack
+redis-cli -p 6379 RPUSH enc:list "$(head -c 2500 /dev/zero | tr '\0' x)" > /dev/null
+expect_encoding enc:list listpack
+redis-cli -p 6379 RPUSH enc:list "$(head -c 2500 /dev/zero | tr '\0' x)" > /dev/null
+expect_encoding enc:list quicklist
+# DEBUG OBJECT derives quicklist nodes from the same limit: a, b, c, d and
+# the first 2500 bytes share a 4kb node, the second 2500 start another
+expect_ql_nodes() {
+    local actual
+    actual=$(redis-cli -p 6379 DEBUG OBJECT "$1" | grep -o "ql_nodes:[0-9]*")
+    if [ "$actual" != "ql_nodes:$2" ]; then
+        echo "ERROR: DEBUG OBJECT $1 reported $actual, expected ql_nodes:$2"
+        exit 1
+    fi
+    echo "$1: $actual"
+}
+expect_ql_nodes enc:list 2
+redis-cli -p 6379 CONFIG SET list-max-listpack-size 4
+redis-cli -p 6379 RPUSH enc:nodes 1 2 3 4 5 6 7 8 9 10 > /dev/null
+expect_ql_nodes enc:nodes 3
+redis-cli -p 6379 DEBUG OBJECT enc:nodes
+redis-cli -p 6379 CONFIG SET list-max-listpack-size -1
+# Listpack-encoded values have no quicklist fields
+redis-cli -p 6379 DEBUG OBJECT enc:nodes
+redis-cli -p 6379 DEBUG OBJECT enc:missing 2>&1
+redis-cli -p 6379 SADD enc:set 1 2 3 > /dev/null
+expect_encoding enc:set intset
+redis-cli -p 6379 SADD enc:set 4 > /dev/null
+expect_encoding enc:set listpack
+redis-cli -p 6379 SADD enc:set 5 > /dev/null
+expect_encoding enc:set hashtable
+# Only canonical 64-bit integers keep a set an intset
+redis-cli -p 6379 SADD enc:ints -5 0 9223372036854775807 > /dev/null
+expect_encoding enc:ints intset
+for member in 007 -0 +1 1.5 " 1"; do
+    redis-cli -p 6379 DEL enc:mixed > /dev/null
+    redis-cli -p 6379 SADD enc:mixed 1 "$member" > /dev/null
+    expect_encoding enc:mixed listpack
+done
+# Adding a non-integer converts to listpack only if every member fits one
+redis-cli -p 6379 SADD enc:small 1 2 > /dev/null
+redis-cli -p 6379 SADD enc:small x > /dev/null
+expect_encoding enc:small listpack
+redis-cli -p 6379 SADD enc:ints x > /dev/null
+expect_encoding enc:ints hashtable
+redis-cli -p 6379 SADD enc:strset a 1 > /dev/null
+expect_encoding enc:strset listpack
+redis-cli -p 6379 SADD enc:strset toolong > /dev/null
+expect_encoding enc:strset hashtable
+redis-cli -p 6379 ZADD enc:zset 1 a 2 b > /dev/null
+expect_encoding enc:zset listpack
+redis-cli -p 6379 ZADD enc:zset 3 c > /dev/null
+expect_encoding enc:zset skiplist
+redis-cli -p 6379 ZADD enc:zlong 1 toolong > /dev/null
+expect_encoding enc:zlong skiplist
+redis-cli -p 6379 HSET enc:hash a 1 b 2 > /dev/null
+expect_encoding enc:hash listpack
+redis-cli -p 6379 HSET enc:hash c 3 > /dev/null
+expect_encoding enc:hash hashtable
+redis-cli -p 6379 HSET enc:hlong f toolong > /dev/null
+expect_encoding enc:hlong hashtable
+# Both limits are inclusive, and apply to field names as well as values
+redis-cli -p 6379 HSET enc:hedge fffff vvvvv > /dev/null
+expect_encoding enc:hedge listpack
+redis-cli -p 6379 HSET enc:hname ffffff v > /dev/null
+expect_encoding enc:hname hashtable
+redis-cli -p 6379 HSET enc:hvalue f vvvvvv > /dev/null
+expect_encoding enc:hvalue hashtable
+# Growing a value past the limit converts a hash that fits on count
+redis-cli -p 6379 HSET enc:hgrow a 1 > /dev/null
+expect_encoding enc:hgrow listpack
+redis-cli -p 6379 HSET enc:hgrow a 123456 > /dev/null
+expect_encoding enc:hgrow hashtable
+# The thresholds are read when asked, so CONFIG SET changes the answer
+redis-cli -p 6379 CONFIG SET hash-max-listpack-entries 3 > /dev/null
+expect_encoding enc:hash listpack
+redis-cli -p 6379 CONFIG SET hash-max-listpack-entries 2 > /dev/null
+redis-cli -p 6379 CONFIG SET set-max-listpack-entries abc
+redis-cli -p 6379 CONFIG SET zset-max-listpack-value -1
+redis-cli -p 6379 CONFIG SET list-max-listpack-size -2
+redis-cli -p 6379 CONFIG SET set-max-intset-entries 512
+redis-cli -p 6379 CONFIG SET set-max-listpack-entries 128
+redis-cli -p 6379 CONFIG SET set-max-listpack-value 64
+redis-cli -p 6379 CONFIG SET zset-max-listpack-entries 128
+redis-cli -p 6379 CONFIG SET zset-max-listpack-value 64
+redis-cli -p 6379 CONFIG SET hash-max-listpack-entries 128
+redis-cli -p 6379 CONFIG SET hash-max-listpack-value 64
 
 echo ""
 echo "=== Key Operations ==="
@@ -62,6 +367,54 @@ redis-cli -p 6379 SET key2 val2
 redis-cli -p 6379 EXISTS key1
 redis-cli -p 6379 EXISTS nokey
 redis-cli -p 6379 DBSIZE
+redis-cli -p 6379 SELECT 0
+for index in 1 -1; do
+    if [ "$(redis-cli -p 6379 SELECT $index 2>&1)" != "ERR DB index is out of range" ]; then
+        echo "ERROR: SELECT $index should be out of range with a single database"
+        exit 1
+    fi
+done
+redis-cli -p 6379 SELECT zero 2>&1
+echo "SET over a list and a hash:"
+redis-cli -p 6379 RPUSH overwritten a b c
+redis-cli -p 6379 TYPE overwritten
+redis-cli -p 6379 SET overwritten plain
+if [ "$(redis-cli -p 6379 TYPE overwritten)" != "string" ]; then
+    echo "ERROR: SET did not replace the list with a string"
+    exit 1
+fi
+if [ "$(redis-cli -p 6379 LRANGE overwritten 0 -1 2>&1)" != "WRONGTYPE Operation against a key holding the wrong kind of value" ]; then
+    echo "ERROR: LRANGE still saw the list SET replaced"
+    exit 1
+fi
+redis-cli -p 6379 HSET overwritten:hash f v
+redis-cli -p 6379 SET overwritten:hash plain EX 100
+redis-cli -p 6379 TYPE overwritten:hash
+redis-cli -p 6379 GET overwritten:hash
+redis-cli -p 6379 TYPE nokey
+# FLUSHDB/FLUSHALL ASYNC empty the keyspace before replying and free it later
+redis-cli -p 6379 DEBUG POPULATE 200000 flush: > /dev/null
+if [ "$(redis-cli -p 6379 FLUSHDB ASYNC) $(redis-cli -p 6379 DBSIZE)" != "OK 0" ]; then
+    echo "ERROR: the database should be empty as soon as FLUSHDB ASYNC returns"
+    exit 1
+fi
+redis-cli -p 6379 SET flush:after v > /dev/null
+if [ "$(redis-cli -p 6379 GET flush:after)" != "v" ]; then
+    echo "ERROR: writes right after FLUSHDB ASYNC should be kept"
+    exit 1
+fi
+for mode in "FLUSHALL" "FLUSHALL sync" "FLUSHDB SYNC" "FLUSHALL ASYNC"; do
+    redis-cli -p 6379 SET flush:key v > /dev/null
+    if [ "$(redis-cli -p 6379 $mode) $(redis-cli -p 6379 DBSIZE)" != "OK 0" ]; then
+        echo "ERROR: $mode should empty the database"
+        exit 1
+    fi
+done
+if [ "$(redis-cli -p 6379 FLUSHDB LATER 2>&1)" != "ERR syntax error" ]; then
+    echo "ERROR: FLUSHDB should reject an unknown mode"
+    exit 1
+fi
+echo "✓ FLUSHDB and FLUSHALL take ASYNC and SYNC"
 
 echo ""
 echo "=== Pattern Matching ==="
@@ -74,6 +427,63 @@ redis-cli -p 6379 KEYS "*"
 echo "User keys:"
 redis-cli -p 6379 KEYS "user:*"
 
+echo ""
+echo "=== SCAN ==="
+redis-cli -p 6379 SCAN 0 MATCH "user:*" COUNT 100
+redis-cli -p 6379 RPUSH scan:list a > /dev/null
+redis-cli -p 6379 SCAN 0 TYPE list COUNT 100
+# TYPE keeps only keys of that type, using TYPE's names, after MATCH
+redis-cli -p 6379 SET scantype:s v > /dev/null
+redis-cli -p 6379 RPUSH scantype:l a > /dev/null
+redis-cli -p 6379 SADD scantype:set a > /dev/null
+redis-cli -p 6379 ZADD scantype:z 1 a > /dev/null
+redis-cli -p 6379 HSET scantype:h1 f v > /dev/null
+redis-cli -p 6379 HSET scantype:h2 f v > /dev/null
+redis-cli -p 6379 HSET other:h f v > /dev/null
+if [ "$(redis-cli -p 6379 SCAN 0 MATCH "scantype:*" TYPE hash COUNT 1000 | tail -n +2 | sort | tr '\n' ' ')" != "scantype:h1 scantype:h2 " ]; then
+    echo "ERROR: SCAN TYPE hash should return only the matching hashes"
+    exit 1
+fi
+for type in string list set zset; do
+    if [ "$(redis-cli -p 6379 SCAN 0 MATCH "scantype:*" TYPE $type COUNT 1000 | tail -n +2 | wc -l)" != "1" ]; then
+        echo "ERROR: SCAN TYPE $type should return one key"
+        exit 1
+    fi
+done
+echo "✓ SCAN TYPE filters by value type"
+redis-cli -p 6379 SCAN notacursor 2>&1
+redis-cli -p 6379 SCAN 0 COUNT 0 2>&1
+# Keys that exist for the whole iteration must all be returned, while other
+# keys are created and deleted in bulk between calls
+redis-cli -p 6379 DEBUG POPULATE 1000 stable: > /dev/null
+(
+    for round in 1 2 3 4 5 6 7 8; do
+        redis-cli -p 6379 DEBUG POPULATE 3000 "churn$round:" > /dev/null
+        redis-cli -p 6379 DEL $(seq -f "churn$((round - 1)):%g" 0 2999) > /dev/null
+    done
+) &
+CHURN_PID=$!
+cursor=0
+calls=0
+: > /tmp/rudis_scan.txt
+while :; do
+    reply=$(redis-cli -p 6379 SCAN "$cursor" MATCH "stable:*" COUNT 200)
+    cursor=$(echo "$reply" | head -1)
+    echo "$reply" | tail -n +2 >> /tmp/rudis_scan.txt
+    calls=$((calls + 1))
+    if [ "$cursor" = "0" ] || [ "$calls" -gt 1000 ]; then
+        break
+    fi
+done
+wait $CHURN_PID
+if [ "$(grep -c . /tmp/rudis_scan.txt | tr -d ' ')" -lt 1000 ] \
+    || [ "$(sort -u /tmp/rudis_scan.txt | grep -c .)" != "1000" ]; then
+    echo "ERROR: SCAN missed keys that existed for the whole iteration"
+    exit 1
+fi
+echo "✓ SCAN returned all 1000 stable keys in $calls calls while keys churned"
+redis-cli -p 6379 FLUSHDB > /dev/null
+
 echo ""
 echo "=== Expiration (EX) ==="
 redis-cli -p 6379 SET tempkey "temporary" EX 2
@@ -105,6 +515,146 @@ sleep 3
 echo "After expiry:"
 redis-cli -p 6379 GET expkey
 
+echo ""
+echo "=== SETEX, PSETEX and GETEX ==="
+redis-cli -p 6379 SETEX sxkey 100 "value"
+redis-cli -p 6379 PSETEX psxkey 100000 "value"
+redis-cli -p 6379 GETEX sxkey EX 200
+redis-cli -p 6379 GETEX sxkey PERSIST
+echo "Invalid expire times:"
+redis-cli -p 6379 SET badttl v EX 0 2>&1
+redis-cli -p 6379 SET badttl v PX -1 2>&1
+redis-cli -p 6379 SETEX badttl 0 v 2>&1
+redis-cli -p 6379 PSETEX badttl -100 v 2>&1
+redis-cli -p 6379 GETEX sxkey EX 0 2>&1
+redis-cli -p 6379 SET badttl v EX abc 2>&1
+redis-cli -p 6379 EXISTS badttl
+echo "EXPIRE with a non-positive TTL deletes the key:"
+redis-cli -p 6379 EXPIRE psxkey 0
+redis-cli -p 6379 EXISTS psxkey
+echo "EXPIRE changes only the expiry, never the value or its encoding:"
+redis-cli -p 6379 SET expint 12345
+redis-cli -p 6379 EXPIRE expint 100
+redis-cli -p 6379 PEXPIREAT expint 32503680000000
+if [ "$(redis-cli -p 6379 OBJECT ENCODING expint) $(redis-cli -p 6379 GET expint) $(redis-cli -p 6379 PEXPIRETIME expint)" != "int 12345 32503680000000" ]; then
+    echo "ERROR: EXPIRE and PEXPIREAT rewrote the value"
+    exit 1
+fi
+redis-cli -p 6379 GETEX expint PERSIST
+if [ "$(redis-cli -p 6379 OBJECT ENCODING expint) $(redis-cli -p 6379 PEXPIRETIME expint)" != "int -1" ]; then
+    echo "ERROR: GETEX PERSIST rewrote the value or kept the expiry"
+    exit 1
+fi
+redis-cli -p 6379 EXPIRE nosuchexpkey 100
+
+echo ""
+echo "=== Collection Expiry ==="
+redis-cli -p 6379 RPUSH exp:list a b
+redis-cli -p 6379 EXPIRE exp:list 100
+redis-cli -p 6379 TTL exp:list
+redis-cli -p 6379 PTTL exp:list
+redis-cli -p 6379 PERSIST exp:list
+redis-cli -p 6379 TTL exp:list
+redis-cli -p 6379 PEXPIREAT exp:list 1
+if [ "$(redis-cli -p 6379 LLEN exp:list) $(redis-cli -p 6379 TYPE exp:list)" != "0 none" ]; then
+    echo "ERROR: an expired list was still readable"
+    exit 1
+fi
+echo "✓ Expired list reads as missing"
+
+echo ""
+echo "=== Lazy-Only Expiry ==="
+redis-cli -p 6379 FLUSHDB
+redis-cli -p 6379 DEBUG SET-ACTIVE-EXPIRE 0
+redis-cli -p 6379 SET lazy:only v PX 100
+sleep 0.5
+# With the reaper paused the expired key is still counted until touched
+if [ "$(redis-cli -p 6379 DBSIZE)" != "1" ]; then
+    echo "ERROR: the reaper removed a key while active expiry was off"
+    exit 1
+fi
+redis-cli -p 6379 GET lazy:only
+if [ "$(redis-cli -p 6379 DBSIZE)" != "0" ]; then
+    echo "ERROR: accessing an expired key did not remove it"
+    exit 1
+fi
+echo "✓ Expired key stayed until accessed"
+redis-cli -p 6379 SET lazy:resumed v PX 100
+redis-cli -p 6379 DEBUG SET-ACTIVE-EXPIRE 1
+sleep 0.5
+if [ "$(redis-cli -p 6379 DBSIZE)" != "0" ]; then
+    echo "ERROR: re-enabling active expiry did not resume the reaper"
+    exit 1
+fi
+echo "✓ Reaper resumed after DEBUG SET-ACTIVE-EXPIRE 1"
+redis-cli -p 6379 DEBUG SET-ACTIVE-EXPIRE 2 2>&1
+redis-cli -p 6379 DEBUG JMAP
+
+echo ""
+echo "=== Keyspace Info ==="
+# INFO's expires= count follows every way a key gains or loses an expiry
+keyspace() {
+    redis-cli -p 6379 INFO | grep '^db0:' | tr -d '\r'
+}
+redis-cli -p 6379 FLUSHDB
+redis-cli -p 6379 SET ks:a v EX 100
+redis-cli -p 6379 SETEX ks:b 100 v
+redis-cli -p 6379 SET ks:c v
+redis-cli -p 6379 EXPIRE ks:c 100
+redis-cli -p 6379 HSET ks:h f v
+redis-cli -p 6379 HEXPIRE ks:h 100 FIELDS 1 f
+if [ "$(keyspace)" != "db0:keys=4,expires=3,avg_ttl=0" ]; then
+    echo "ERROR: expected 3 of 4 keys with an expiry, got '$(keyspace)'"
+    exit 1
+fi
+redis-cli -p 6379 GETEX ks:a PERSIST
+redis-cli -p 6379 SET ks:b overwritten
+redis-cli -p 6379 DEL ks:c
+if [ "$(keyspace)" != "db0:keys=3,expires=0,avg_ttl=0" ]; then
+    echo "ERROR: PERSIST, overwriting and DEL left expiries counted: '$(keyspace)'"
+    exit 1
+fi
+redis-cli -p 6379 SET ks:short v PX 100
+sleep 0.5
+if [ "$(keyspace)" != "db0:keys=3,expires=0,avg_ttl=0" ]; then
+    echo "ERROR: the reaper did not drop an expired key from the count: '$(keyspace)'"
+    exit 1
+fi
+redis-cli -p 6379 FLUSHDB
+if [ -n "$(keyspace)" ]; then
+    echo "ERROR: INFO listed an empty database"
+    exit 1
+fi
+echo "✓ INFO keyspace counted expiries through every change"
+
+echo "=== Memory Info ==="
+memory_field() {
+    redis-cli -p 6379 INFO | grep "^$1:" | cut -d: -f2 | tr -d '\r'
+}
+for field in used_memory used_memory_rss maxmemory; do
+    if ! [[ "$(memory_field $field)" =~ ^[0-9]+$ ]]; then
+        echo "ERROR: INFO $field should be an integer, got '$(memory_field $field)'"
+        exit 1
+    fi
+done
+if ! [[ "$(memory_field mem_fragmentation_ratio)" =~ ^[0-9]+\.[0-9]{2}$ ]]; then
+    echo "ERROR: INFO mem_fragmentation_ratio should be a number, got '$(memory_field mem_fragmentation_ratio)'"
+    exit 1
+fi
+before=$(memory_field used_memory)
+redis-cli -p 6379 SET mem:big "$(head -c 100000 /dev/zero | tr '\0' m)" > /dev/null
+after=$(memory_field used_memory)
+if [ $((after - before)) -lt 100000 ]; then
+    echo "ERROR: used_memory should grow by the size of a 100000 byte value ($before -> $after)"
+    exit 1
+fi
+redis-cli -p 6379 DEL mem:big
+if [ "$(memory_field used_memory)" -ge "$after" ]; then
+    echo "ERROR: used_memory should shrink after DEL"
+    exit 1
+fi
+echo "✓ INFO memory reports used, resident and max memory"
+
 echo ""
 echo "=== Multiple Keys ==="
 redis-cli -p 6379 FLUSHDB
@@ -121,6 +671,46 @@ redis-cli -p 6379 SET notnum abc
 redis-cli -p 6379 INCR notnum 2>&1
 redis-cli -p 6379 GET 2>&1
 redis-cli -p 6379 UNKNOWNCMD 2>&1
+redis-cli -p 6379 RPUSH notcounter "item"
+redis-cli -p 6379 INCR notcounter 2>&1
+redis-cli -p 6379 DECR notcounter 2>&1
+echo "List untouched:"
+redis-cli -p 6379 LRANGE notcounter 0 -1
+echo "Non-numeric arguments:"
+redis-cli -p 6379 LRANGE notcounter abc def 2>&1
+redis-cli -p 6379 LRANGE notcounter 0 end 2>&1
+redis-cli -p 6379 LINDEX notcounter first 2>&1
+redis-cli -p 6379 EXPIRE notnum notanumber 2>&1
+redis-cli -p 6379 SET notnum abc EX ten 2>&1
+redis-cli -p 6379 SET notnum abc PX ten 2>&1
+echo "Key kept its value and has no TTL:"
+redis-cli -p 6379 GET notnum
+# The length commands count only their own type; an int-encoded string has
+# the length of its digits
+redis-cli -p 6379 SET lenint 12345 > /dev/null
+redis-cli -p 6379 HSET lenhash f v > /dev/null
+for check in "STRLEN notnum 3" "STRLEN lenint 5" "STRLEN nosuchkey 0" "LLEN notcounter 1" "HLEN lenhash 1" "SCARD nosuchkey 0"; do
+    set -- $check
+    if [ "$(redis-cli -p 6379 $1 $2)" != "$3" ]; then
+        echo "ERROR: $1 $2 should be $3"
+        exit 1
+    fi
+done
+for cmd in STRLEN LLEN SCARD ZCARD; do
+    if [ "$(redis-cli -p 6379 $cmd lenhash 2>&1)" != "WRONGTYPE Operation against a key holding the wrong kind of value" ]; then
+        echo "ERROR: $cmd on a hash should be WRONGTYPE"
+        exit 1
+    fi
+done
+if [ "$(redis-cli -p 6379 HLEN notcounter 2>&1)" != "WRONGTYPE Operation against a key holding the wrong kind of value" ]; then
+    echo "ERROR: HLEN on a list should be WRONGTYPE"
+    exit 1
+fi
+if [ "$(redis-cli -p 6379 ZCARD 2>&1)" != "ERR wrong number of arguments for 'zcard' command" ]; then
+    echo "ERROR: ZCARD without a key should name itself in the arity error"
+    exit 1
+fi
+echo "✓ STRLEN, LLEN, SCARD, ZCARD and HLEN check their own type"
 
 echo ""
 echo "=== Concurrency Test ==="
@@ -151,6 +741,1133 @@ redis-cli -p 6379 LRANGE mylist 0 -1
 redis-cli -p 6379 LINDEX mylist 0
 redis-cli -p 6379 LINDEX mylist 1
 redis-cli -p 6379 LINDEX mylist 2
+# Each value goes to the head in turn, so the last argument ends up first
+redis-cli -p 6379 LPUSH multipush a b c
+if [ "$(redis-cli -p 6379 LRANGE multipush 0 -1 | tr '\n' ' ')" != "c b a " ]; then
+    echo "ERROR: LPUSH k a b c did not give c b a"
+    exit 1
+fi
+redis-cli -p 6379 LPUSH multipush d e
+redis-cli -p 6379 RPUSH multipush x y
+if [ "$(redis-cli -p 6379 LRANGE multipush 0 -1 | tr '\n' ' ')" != "e d c b a x y " ]; then
+    echo "ERROR: LPUSH onto an existing list put the values in the wrong order"
+    exit 1
+fi
+echo "✓ LPUSH kept Redis' ordering"
+redis-cli -p 6379 RPUSH remlist a b a c a
+redis-cli -p 6379 LREM remlist -2 a
+redis-cli -p 6379 LRANGE remlist 0 -1
+redis-cli -p 6379 LTRIM remlist 1 -1
+redis-cli -p 6379 LRANGE remlist 0 -1
+
+echo ""
+echo "=== Empty Collections Are Deleted ==="
+redis-cli -p 6379 FLUSHDB
+redis-cli -p 6379 RPUSH popped last
+redis-cli -p 6379 LPOP popped
+redis-cli -p 6379 RPUSH popped last
+redis-cli -p 6379 RPOP popped
+redis-cli -p 6379 RPUSH removed x x
+redis-cli -p 6379 LREM removed 0 x
+redis-cli -p 6379 RPUSH trimmed a b
+redis-cli -p 6379 LTRIM trimmed 5 10
+redis-cli -p 6379 SADD emptied m
+redis-cli -p 6379 SREM emptied m
+redis-cli -p 6379 ZADD zemptied 1 m
+redis-cli -p 6379 ZREM zemptied m
+for key in popped removed trimmed emptied zemptied; do
+    if [ "$(redis-cli -p 6379 EXISTS $key)" != "0" ]; then
+        echo "ERROR: empty collection $key still exists"
+        exit 1
+    fi
+done
+if [ "$(redis-cli -p 6379 DBSIZE)" != "0" ]; then
+    echo "ERROR: DBSIZE counts empty collections"
+    exit 1
+fi
+echo "✓ Removing the last element deletes the key"
+
+echo ""
+echo "=== Blocking List Pops ==="
+redis-cli -p 6379 DEL jobs
+# Two workers block on the same queue, the first one a little earlier
+redis-cli -p 6379 BLPOP jobs 5 > /tmp/rudis_worker_1.txt &
+WORKER_1=$!
+sleep 0.3
+redis-cli -p 6379 BLPOP jobs 5 > /tmp/rudis_worker_2.txt &
+WORKER_2=$!
+sleep 0.3
+redis-cli -p 6379 RPUSH jobs job1
+redis-cli -p 6379 RPUSH jobs job2
+wait $WORKER_1 $WORKER_2
+if [ "$(tr '\n' ' ' < /tmp/rudis_worker_1.txt)" != "jobs job1 " ] ||
+    [ "$(tr '\n' ' ' < /tmp/rudis_worker_2.txt)" != "jobs job2 " ]; then
+    echo "ERROR: blocked clients were not served one element each in wait order"
+    exit 1
+fi
+if [ "$(redis-cli -p 6379 LLEN jobs)" != "0" ]; then
+    echo "ERROR: a pushed element was delivered twice or left behind"
+    exit 1
+fi
+echo "✓ Each blocked client received exactly one element, in FIFO order"
+redis-cli -p 6379 RPUSH jobs a b c
+redis-cli -p 6379 BRPOP missing jobs 1
+redis-cli -p 6379 BLPOP missing 0.2
+redis-cli -p 6379 BLPOP jobs soon 2>&1
+redis-cli -p 6379 BLPOP jobs -1 2>&1
+# A client that disconnects while blocked leaves the element for others
+redis-cli -p 6379 DEL gone
+redis-cli -p 6379 BLPOP gone 0 > /dev/null &
+sleep 0.3
+kill $!
+sleep 0.3
+redis-cli -p 6379 RPUSH gone kept
+if [ "$(redis-cli -p 6379 LRANGE gone 0 -1)" != "kept" ]; then
+    echo "ERROR: an element was handed to a disconnected client"
+    exit 1
+fi
+echo "✓ Disconnected blocked clients leave the queue"
+
+echo ""
+echo "=== Set Operations ==="
+redis-cli -p 6379 DEL myset
+redis-cli -p 6379 SADD myset a b c
+redis-cli -p 6379 SADD myset c d
+redis-cli -p 6379 SCARD myset
+redis-cli -p 6379 SREM myset d missing
+redis-cli -p 6379 SMEMBERS myset | sort
+redis-cli -p 6379 SISMEMBER myset a
+redis-cli -p 6379 SISMEMBER myset z
+echo "SMISMEMBER with present and absent members:"
+redis-cli -p 6379 SMISMEMBER myset a z b y c
+echo "SMISMEMBER on a missing key:"
+redis-cli -p 6379 SMISMEMBER nosuchset a b
+redis-cli -p 6379 SMISMEMBER mylist a 2>&1
+
+echo ""
+echo "=== Sorted Set Operations ==="
+redis-cli -p 6379 DEL leaderboard
+redis-cli -p 6379 ZADD leaderboard 100 alice 250.5 bob 75 carol
+redis-cli -p 6379 ZADD leaderboard 300 alice
+echo "ZADD GT CH (only bob's raise counts, expect 1):"
+redis-cli -p 6379 ZADD leaderboard GT CH 200 alice 260 bob
+echo "ZADD INCR (expect 301):"
+redis-cli -p 6379 ZADD leaderboard INCR 1 alice
+echo "ZADD NX with GT (expect an error):"
+redis-cli -p 6379 ZADD leaderboard NX GT 1 alice 2>&1
+redis-cli -p 6379 ZRANGE leaderboard 0 -1 WITHSCORES
+redis-cli -p 6379 ZSCORE leaderboard bob
+echo "ZMSCORE with a missing member:"
+redis-cli -p 6379 ZMSCORE leaderboard alice nobody carol
+echo "ZMSCORE on a missing key:"
+redis-cli -p 6379 ZMSCORE nosuchzset a b
+redis-cli -p 6379 ZREM leaderboard carol
+redis-cli -p 6379 ZCARD leaderboard
+redis-cli -p 6379 ZMSCORE myset a 2>&1
+
+echo ""
+echo "=== Sorted Set Union and Intersection ==="
+redis-cli -p 6379 DEL za zb zs zout
+redis-cli -p 6379 ZADD za 1 a 2 b 3 c
+redis-cli -p 6379 ZADD zb 10 b 20 c 30 d
+redis-cli -p 6379 SADD zs c d e
+echo "Union, SUM with weights 2 and 0.5:"
+redis-cli -p 6379 ZUNIONSTORE zout 2 za zb WEIGHTS 2 0.5
+redis-cli -p 6379 ZRANGE zout 0 -1 WITHSCORES
+if [ "$(redis-cli -p 6379 ZRANGE zout 0 -1 WITHSCORES | tr '\n' ' ')" != "a 2 b 9 d 15 c 16 " ]; then
+    echo "ERROR: weighted ZUNIONSTORE SUM gave the wrong scores"
+    exit 1
+fi
+echo "Union, MIN:"
+redis-cli -p 6379 ZUNIONSTORE zout 2 za zb AGGREGATE MIN
+redis-cli -p 6379 ZRANGE zout 0 -1 WITHSCORES
+echo "Union, MAX:"
+redis-cli -p 6379 ZUNIONSTORE zout 2 za zb AGGREGATE MAX
+redis-cli -p 6379 ZRANGE zout 0 -1 WITHSCORES
+echo "Intersection with a plain set (score 1), SUM:"
+redis-cli -p 6379 ZINTERSTORE zout 3 za zb zs
+redis-cli -p 6379 ZRANGE zout 0 -1 WITHSCORES
+echo "Intersection, MIN and MAX:"
+redis-cli -p 6379 ZINTERSTORE zout 2 za zb AGGREGATE MIN
+redis-cli -p 6379 ZRANGE zout 0 -1 WITHSCORES
+redis-cli -p 6379 ZINTERSTORE zout 2 za zb AGGREGATE MAX
+redis-cli -p 6379 ZRANGE zout 0 -1 WITHSCORES
+redis-cli -p 6379 ZUNIONSTORE zout 3 za zb 2>&1
+# An empty result deletes the destination rather than leaving an empty key
+redis-cli -p 6379 ZADD zc 5 x
+redis-cli -p 6379 ZINTERSTORE zout 2 za zc
+if [ "$(redis-cli -p 6379 EXISTS zout)" != "0" ]; then
+    echo "ERROR: an empty ZINTERSTORE left its destination behind"
+    exit 1
+fi
+redis-cli -p 6379 SET zout string
+redis-cli -p 6379 ZUNIONSTORE zout 2 nosuchz1 nosuchz2
+if [ "$(redis-cli -p 6379 EXISTS zout)" != "0" ]; then
+    echo "ERROR: an empty ZUNIONSTORE left its destination behind"
+    exit 1
+fi
+echo "✓ Empty results deleted the destination"
+
+echo ""
+echo "=== Sorted Set Difference and Intersection Cardinality ==="
+redis-cli -p 6379 DEL za zb zs zout sa sb
+redis-cli -p 6379 ZADD za 1 a 2 b 3 c 4 d
+redis-cli -p 6379 ZADD zb 10 b
+redis-cli -p 6379 SADD zs d e
+# b is in the second key and d in the plain set, so only a and c remain,
+# keeping their scores from the first key
+if [ "$(redis-cli -p 6379 ZDIFF 3 za zb zs WITHSCORES | tr '\n' ' ')" != "a 1 c 3 " ]; then
+    echo "ERROR: ZDIFF kept a member present in a later key"
+    exit 1
+fi
+echo "✓ ZDIFF excluded members of the later keys"
+redis-cli -p 6379 ZDIFFSTORE zout 2 za zb
+redis-cli -p 6379 ZRANGE zout 0 -1 WITHSCORES
+# An empty difference deletes the destination
+redis-cli -p 6379 ZDIFFSTORE zout 2 zb za
+redis-cli -p 6379 EXISTS zout
+redis-cli -p 6379 ZINTERCARD 2 za zs
+redis-cli -p 6379 ZADD zb 3 c 4 d
+redis-cli -p 6379 ZINTERCARD 2 za zb
+redis-cli -p 6379 ZINTERCARD 2 za zb LIMIT 2
+redis-cli -p 6379 SADD sa x y z
+redis-cli -p 6379 SADD sb y z w
+redis-cli -p 6379 SINTERCARD 2 sa sb
+redis-cli -p 6379 SINTERCARD 2 sa sb LIMIT 1
+redis-cli -p 6379 SINTERCARD 2 sa missing
+redis-cli -p 6379 ZDIFF 0 za 2>&1
+redis-cli -p 6379 ZINTERCARD 0 za 2>&1
+redis-cli -p 6379 ZINTERCARD 3 za zb 2>&1
+redis-cli -p 6379 ZINTERCARD 2 za zb LIMIT -1 2>&1
+redis-cli -p 6379 SINTERCARD 2 sa za 2>&1
+
+echo ""
+echo "=== SORT ==="
+redis-cli -p 6379 DEL sortl sorts sortdest
+redis-cli -p 6379 RPUSH sortl 10 2 -3.5 2 100
+if [ "$(redis-cli -p 6379 SORT sortl | tr '\n' ' ')" != "-3.5 2 2 10 100 " ]; then
+    echo "ERROR: SORT did not order the list numerically"
+    exit 1
+fi
+redis-cli -p 6379 SORT sortl DESC LIMIT 1 2
+redis-cli -p 6379 SORT sortl ALPHA
+redis-cli -p 6379 SADD sorts banana apple cherry
+redis-cli -p 6379 SORT sorts 2>&1
+# STORE writes a list even when the source is a set
+redis-cli -p 6379 SORT sorts ALPHA STORE sortdest
+if [ "$(redis-cli -p 6379 TYPE sortdest) $(redis-cli -p 6379 LRANGE sortdest 0 -1 | tr '\n' ' ')" != "list apple banana cherry " ]; then
+    echo "ERROR: SORT STORE did not store the sorted set as a list"
+    exit 1
+fi
+redis-cli -p 6379 SORT nosuchsort STORE sortdest
+if [ "$(redis-cli -p 6379 EXISTS sortdest)" != "0" ]; then
+    echo "ERROR: an empty SORT STORE left its destination behind"
+    exit 1
+fi
+echo "✓ SORT STORE stored a list and an empty result deleted it"
+redis-cli -p 6379 COMMAND GETKEYS SORT sortl ALPHA STORE sortdest
+redis-cli -p 6379 SORT sortl BY weight_* 2>&1
+redis-cli -p 6379 SORT sortl LIMIT 0 2>&1
+
+echo ""
+echo "=== Hash Operations ==="
+redis-cli -p 6379 FLUSHDB
+redis-cli -p 6379 HSET user:1 name alice age 30
+redis-cli -p 6379 HSET user:1 age 31 city paris
+redis-cli -p 6379 HGET user:1 age
+redis-cli -p 6379 HGET user:1 missing
+redis-cli -p 6379 HLEN user:1
+redis-cli -p 6379 HEXISTS user:1 city
+redis-cli -p 6379 HGETALL user:1 | paste - - | sort
+redis-cli -p 6379 HDEL user:1 name age city
+redis-cli -p 6379 EXISTS user:1
+redis-cli -p 6379 SET user:str v
+redis-cli -p 6379 HSET user:str f v 2>&1
+# HSET counts new fields while HMSET replies OK; both keep insertion order
+redis-cli -p 6379 HSET ordered zebra 1 apple 2 mango 3
+redis-cli -p 6379 HMSET ordered banana 4 apple 5
+redis-cli -p 6379 HDEL ordered zebra
+redis-cli -p 6379 HSET ordered zebra 6
+if [ "$(redis-cli -p 6379 HKEYS ordered | tr '\n' ' ')" != "apple mango banana zebra " ]; then
+    echo "ERROR: HKEYS did not follow insertion order"
+    exit 1
+fi
+if [ "$(redis-cli -p 6379 HVALS ordered | tr '\n' ' ')" != "5 3 4 6 " ]; then
+    echo "ERROR: HVALS did not follow insertion order"
+    exit 1
+fi
+echo "✓ Hash fields kept insertion order"
+redis-cli -p 6379 HMSET ordered odd 2>&1
+# HSCAN walks insertion order too, with or without values
+if [ "$(redis-cli -p 6379 HSCAN ordered 0 COUNT 2 NOVALUES | tr '\n' ' ')" != "3 apple mango " ]; then
+    echo "ERROR: HSCAN NOVALUES did not return the first two field names"
+    exit 1
+fi
+if [ "$(redis-cli -p 6379 HSCAN ordered 3 COUNT 2 | tr '\n' ' ')" != "0 banana 4 zebra 6 " ]; then
+    echo "ERROR: HSCAN did not continue from the NOVALUES cursor"
+    exit 1
+fi
+echo "✓ HSCAN continued across NOVALUES"
+
+echo ""
+echo "=== Hash Field Expiration ==="
+redis-cli -p 6379 HSET session token abc refresh def user 1
+redis-cli -p 6379 HEXPIRE session 100 FIELDS 2 token missing
+redis-cli -p 6379 HPEXPIRE session 200 FIELDS 1 refresh
+redis-cli -p 6379 HTTL session FIELDS 3 token user missing
+redis-cli -p 6379 HPERSIST session FIELDS 2 token user
+redis-cli -p 6379 HTTL session FIELDS 1 token
+redis-cli -p 6379 HEXPIRE session 100 FIELDS 2 token 2>&1
+redis-cli -p 6379 HEXPIRE session 100 NOFIELDS 1 token 2>&1
+sleep 0.4
+# The expired field is gone from reads even before the reaper runs
+redis-cli -p 6379 HGET session refresh
+redis-cli -p 6379 HLEN session
+redis-cli -p 6379 HEXPIRE session 0 FIELDS 1 user
+redis-cli -p 6379 HGETALL session
+redis-cli -p 6379 HSET reaped f v
+redis-cli -p 6379 HPEXPIRE reaped 100 FIELDS 1 f
+sleep 0.5
+if [ "$(redis-cli -p 6379 EXISTS reaped)" != "0" ]; then
+    echo "ERROR: reaper left a hash whose only field expired"
+    exit 1
+fi
+echo "✓ Reaper deleted the hash once its last field expired"
+
+echo ""
+echo "=== Random Members ==="
+redis-cli -p 6379 DEL rs rh rz rone
+redis-cli -p 6379 SADD rs a b c
+redis-cli -p 6379 HSET rh f1 v1 f2 v2 f3 v3
+redis-cli -p 6379 ZADD rz 1 a 2 b 3 c
+redis-cli -p 6379 SADD rone only
+for cmd in SRANDMEMBER HRANDFIELD ZRANDMEMBER; do
+    key=$(case $cmd in SRANDMEMBER) echo rs;; HRANDFIELD) echo rh;; *) echo rz;; esac)
+    if [ -n "$(redis-cli -p 6379 $cmd $key 0)" ]; then
+        echo "ERROR: $cmd with count 0 returned elements"
+        exit 1
+    fi
+    # A positive count larger than the collection returns all of it, once each
+    if [ "$(redis-cli -p 6379 $cmd $key 10 | sort -u | wc -l)" != "3" ] \
+        || [ "$(redis-cli -p 6379 $cmd $key 10 | wc -l)" != "3" ]; then
+        echo "ERROR: $cmd with a count above the size did not return every element once"
+        exit 1
+    fi
+    # A negative count returns exactly that many, repeats allowed
+    if [ "$(redis-cli -p 6379 $cmd $key -10 | wc -l)" != "10" ]; then
+        echo "ERROR: $cmd with a negative count returned the wrong number of elements"
+        exit 1
+    fi
+    if [ -n "$(redis-cli -p 6379 $cmd nosuchkey 5)" ] || [ -n "$(redis-cli -p 6379 $cmd nosuchkey)" ]; then
+        echo "ERROR: $cmd on a missing key returned elements"
+        exit 1
+    fi
+done
+echo "✓ Zero, positive and negative counts follow Redis for all three commands"
+if [ "$(redis-cli -p 6379 SRANDMEMBER rone -3 | tr '\n' ' ')" != "only only only " ]; then
+    echo "ERROR: a negative count did not repeat members"
+    exit 1
+fi
+echo "✓ A negative count repeats members"
+redis-cli -p 6379 ZRANDMEMBER rz -4 WITHSCORES | paste - - | while read -r member score; do
+    case "$member:$score" in
+        a:1|b:2|c:3) ;;
+        *) echo "ERROR: ZRANDMEMBER WITHSCORES paired $member with $score"; exit 1;;
+    esac
+done
+redis-cli -p 6379 HRANDFIELD rh 5 WITHVALUES | paste - - | sort
+redis-cli -p 6379 SRANDMEMBER rs
+redis-cli -p 6379 SRANDMEMBER rs 1 WITHSCORES 2>&1
+redis-cli -p 6379 ZRANDMEMBER rz 1 WITHVALUES 2>&1
+redis-cli -p 6379 HRANDFIELD rh x 2>&1
+redis-cli -p 6379 HRANDFIELD rs 1 2>&1
+
+echo ""
+echo "=== Large List Range ==="
+redis-cli -p 6379 DEL biglist > /dev/null
+echo "Building a 100000-element list..."
+seq 1 100000 | xargs -n 100 redis-cli -p 6379 RPUSH biglist > /dev/null
+redis-cli -p 6379 LLEN biglist
+if [ "$(redis-cli -p 6379 LLEN biglist)" != "100000" ]; then
+    echo "ERROR: biglist does not have 100000 elements"
+    exit 1
+fi
+if [ "$(redis-cli -p 6379 OBJECT ENCODING biglist)" != "quicklist" ]; then
+    echo "ERROR: a 100000-element list should report quicklist"
+    exit 1
+fi
+# Readers copy elements outside the store lock, so writes keep flowing
+redis-cli -p 6379 LRANGE biglist 0 -1 > /tmp/rudis_biglist.txt &
+RANGE_PID=$!
+START=$(date +%s%N)
+for i in {1..20}; do
+    redis-cli -p 6379 SET "bl:$i" "v" > /dev/null
+done
+END=$(date +%s%N)
+wait $RANGE_PID
+echo "20 concurrent writes took $(( (END - START) / 1000000 ))ms"
+echo "LRANGE returned $(wc -l < /tmp/rudis_biglist.txt) elements"
+if ! seq 1 100000 | cmp -s - /tmp/rudis_biglist.txt; then
+    echo "ERROR: LRANGE 0 -1 did not return the elements 1..100000 in order"
+    exit 1
+fi
+if [ "$(redis-cli -p 6379 GET bl:20)" != "v" ]; then
+    echo "ERROR: a write made during the LRANGE was lost"
+    exit 1
+fi
+if [ "$(redis-cli -p 6379 LRANGE biglist 49999 50001 | tr '\n' ' ')" != "50000 50001 50002 " ]; then
+    echo "ERROR: LRANGE from the middle of biglist returned the wrong elements"
+    exit 1
+fi
+if [ "$(redis-cli -p 6379 LRANGE biglist -2 -1 | tr '\n' ' ')" != "99999 100000 " ]; then
+    echo "ERROR: LRANGE with negative offsets returned the wrong elements"
+    exit 1
+fi
+redis-cli -p 6379 DEL biglist
+
+echo ""
+echo "=== Longest Common Subsequence ==="
+redis-cli -p 6379 SET lcs:a "ohmytext"
+redis-cli -p 6379 SET lcs:b "mynewtext"
+redis-cli -p 6379 LCS lcs:a lcs:b
+redis-cli -p 6379 LCS lcs:a lcs:b LEN
+echo "Match ranges:"
+redis-cli -p 6379 LCS lcs:a lcs:b IDX
+echo "Ranges of at least 4 bytes:"
+redis-cli -p 6379 LCS lcs:a lcs:b IDX MINMATCHLEN 4 WITHMATCHLEN
+
+echo ""
+echo "=== Snapshot Reload ==="
+redis-cli -p 6379 FLUSHDB
+redis-cli -p 6379 SET snap:str "value"
+redis-cli -p 6379 SET snap:ttl "expiring" EX 100
+redis-cli -p 6379 RPUSH snap:list a b c
+redis-cli -p 6379 HSET snap:hash f1 v1 f2 v2
+redis-cli -p 6379 HEXPIRE snap:hash 100 FIELDS 1 f1
+redis-cli -p 6379 DEBUG RELOAD
+redis-cli -p 6379 GET snap:str
+redis-cli -p 6379 GET snap:ttl
+redis-cli -p 6379 LRANGE snap:list 0 -1
+redis-cli -p 6379 HTTL snap:hash FIELDS 2 f1 f2
+redis-cli -p 6379 DBSIZE
+
+echo ""
+echo "=== Transactions ==="
+redis-cli -p 6379 DEL tx:a tx:s tx:b
+send() {
+    echo -n "$1" >&3
+    sleep 0.2
+}
+exec 3<>/dev/tcp/127.0.0.1/6379
+# A queue-time error discards the whole transaction
+send $'*1\r\n$5\r\nMULTI\r\n'
+send $'*3\r\n$3\r\nSET\r\n$4\r\ntx:a\r\n$1\r\n1\r\n'
+send $'*1\r\n$9\r\nNOSUCHCMD\r\n'
+send $'*1\r\n$4\r\nEXEC\r\n'
+# A runtime error only fails its own command
+send $'*1\r\n$5\r\nMULTI\r\n'
+send $'*3\r\n$3\r\nSET\r\n$4\r\ntx:s\r\n$3\r\nabc\r\n'
+send $'*2\r\n$4\r\nINCR\r\n$4\r\ntx:s\r\n'
+send $'*3\r\n$3\r\nSET\r\n$4\r\ntx:b\r\n$1\r\n2\r\n'
+send $'*1\r\n$4\r\nEXEC\r\n'
+# Wrong arity is also caught while queueing; DISCARD clears the state
+send $'*1\r\n$5\r\nMULTI\r\n'
+send $'*1\r\n$3\r\nGET\r\n'
+send $'*1\r\n$7\r\nDISCARD\r\n'
+send $'*1\r\n$4\r\nEXEC\r\n'
+timeout 1 cat <&3 | tr -d '\r' > /tmp/rudis_multi.txt || true
+exec 3<&-
+cat /tmp/rudis_multi.txt
+EXPECTED=$(printf '%s\n' \
+    '+OK' '+QUEUED' "-ERR unknown command 'NOSUCHCMD'" \
+    '-EXECABORT Transaction discarded because of previous errors.' \
+    '+OK' '+QUEUED' '+QUEUED' '+QUEUED' \
+    '*3' '+OK' '-ERR value is not an integer or out of range' '+OK' \
+    '+OK' "-ERR wrong number of arguments for 'get' command" '+OK' \
+    '-ERR EXEC without MULTI')
+if [ "$(cat /tmp/rudis_multi.txt)" != "$EXPECTED" ]; then
+    echo "ERROR: unexpected transaction replies"
+    exit 1
+fi
+if [ "$(redis-cli -p 6379 EXISTS tx:a)" != "0" ] || [ "$(redis-cli -p 6379 GET tx:b)" != "2" ]; then
+    echo "ERROR: transaction effects are wrong"
+    exit 1
+fi
+echo "✓ EXECABORT discarded the queue and runtime errors left the rest applied"
+# WATCH: another client's write makes EXEC reply nil without running anything
+redis-cli -p 6379 SET tx:w 1 > /dev/null
+exec 3<>/dev/tcp/127.0.0.1/6379
+send $'WATCH tx:w\r\n'
+redis-cli -p 6379 INCR tx:w > /dev/null
+send $'MULTI\r\n'
+send $'SET tx:w from-exec\r\n'
+send $'EXEC\r\n'
+timeout 1 cat <&3 | tr -d '\r' | tr '\n' ' ' > /tmp/rudis_multi.txt || true
+exec 3<&-
+if [ "$(cat /tmp/rudis_multi.txt)" != "+OK +OK +QUEUED *-1 " ] || [ "$(redis-cli -p 6379 GET tx:w)" != "2" ]; then
+    echo "ERROR: EXEC ran although a watched key changed"
+    cat /tmp/rudis_multi.txt
+    exit 1
+fi
+echo "✓ WATCH aborted EXEC after a concurrent write"
+
+echo ""
+echo "=== Client Command Counters ==="
+exec 3<>/dev/tcp/127.0.0.1/6379
+echo -n $'*3\r\n$3\r\nSET\r\n$7\r\nc:count\r\n$3\r\nabc\r\n' >&3
+sleep 0.2
+# An erroring command is counted too
+echo -n $'*2\r\n$4\r\nINCR\r\n$7\r\nc:count\r\n' >&3
+sleep 0.2
+echo -n $'*2\r\n$6\r\nCLIENT\r\n$4\r\nINFO\r\n' >&3
+sleep 0.2
+timeout 1 cat <&3 | tr -d '\r' > /tmp/rudis_client.txt || true
+exec 3<&-
+cat /tmp/rudis_client.txt
+if ! grep -q "tot-cmds=3 cmd=client|info" /tmp/rudis_client.txt; then
+    echo "ERROR: CLIENT INFO did not count every command"
+    exit 1
+fi
+echo "✓ CLIENT INFO counted all three commands"
+redis-cli -p 6379 CLIENT LIST | grep -c "cmd=client|list"
+redis-cli -p 6379 CLIENT ID > /dev/null
+redis-cli -p 6379 CLIENT KILL 2>&1
+
+echo ""
+echo "=== Debug JSON Dump ==="
+redis-cli -p 6379 FLUSHDB
+redis-cli -p 6379 SET dump:str "hi"
+redis-cli -p 6379 RPUSH dump:list a b
+redis-cli -p 6379 HSET dump:hash f v
+redis-cli -p 6379 ZADD dump:zset inf m
+EXPECTED='{"keys":[{"key":"ZHVtcDpoYXNo","type":"hash","expires_at_ms":null,"value":[{"field":"Zg==","value":"dg==","expires_at_ms":null}]},{"key":"ZHVtcDpsaXN0","type":"list","expires_at_ms":null,"value":["YQ==","Yg=="]},{"key":"ZHVtcDpzdHI=","type":"string","expires_at_ms":null,"value":"aGk="},{"key":"ZHVtcDp6c2V0","type":"zset","expires_at_ms":null,"value":[{"member":"bQ==","score":"inf"}]}],"total":4,"next":null}'
+if [ "$(redis-cli -p 6379 DEBUG DUMPJSON)" != "$EXPECTED" ]; then
+    echo "ERROR: unexpected DEBUG DUMPJSON output"
+    exit 1
+fi
+echo "✓ DEBUG DUMPJSON matches the keyspace"
+# Pages are keyed by offset into the sorted key list
+redis-cli -p 6379 DEBUG DUMPJSON OFFSET 1 COUNT 2 | grep -o '"total":4,"next":3'
+redis-cli -p 6379 DEBUG DUMPJSON COUNT 2>&1
+
+echo ""
+echo "=== Debug Populate ==="
+redis-cli -p 6379 FLUSHDB
+redis-cli -p 6379 SET key:5 kept
+redis-cli -p 6379 DEBUG POPULATE 100000
+if [ "$(redis-cli -p 6379 DBSIZE)" != "100000" ]; then
+    echo "ERROR: DEBUG POPULATE did not create every key"
+    exit 1
+fi
+echo "✓ DEBUG POPULATE created 100000 keys"
+# Existing keys are left alone
+redis-cli -p 6379 GET key:5
+redis-cli -p 6379 GET key:99999
+redis-cli -p 6379 DEBUG POPULATE 3 bench: 16
+# APPEND of nothing reports the length: "value:2" padded to 16 bytes
+redis-cli -p 6379 APPEND bench:2 ""
+redis-cli -p 6379 DEBUG POPULATE -1 2>&1
+redis-cli -p 6379 FLUSHDB
+
+echo ""
+echo "=== Keyspace Hits and Misses ==="
+stat() {
+    redis-cli -p 6379 INFO | tr -d '\r' | grep "^$1:" | cut -d: -f2
+}
+redis-cli -p 6379 SET hm:str v
+redis-cli -p 6379 HSET hm:hash f v
+redis-cli -p 6379 RPUSH hm:list a
+redis-cli -p 6379 ZADD hm:zset 1 m
+HITS=$(stat keyspace_hits)
+MISSES=$(stat keyspace_misses)
+redis-cli -p 6379 GET hm:str
+redis-cli -p 6379 HGET hm:hash f
+redis-cli -p 6379 LINDEX hm:list 0
+redis-cli -p 6379 GET hm:nothing
+redis-cli -p 6379 HGET hm:nothing f
+# Both source keys of ZDIFF are looked up: one sorted set missing, one found
+redis-cli -p 6379 ZDIFF 2 hm:nozset hm:zset
+# WRONGTYPE is neither a hit nor a miss
+redis-cli -p 6379 LINDEX hm:str 0 2>&1
+# Writes don't count
+redis-cli -p 6379 SET hm:str w
+if [ "$(stat keyspace_hits)" != "$((HITS + 4))" ] || [ "$(stat keyspace_misses)" != "$((MISSES + 3))" ]; then
+    echo "ERROR: unexpected keyspace_hits/keyspace_misses ($(stat keyspace_hits)/$(stat keyspace_misses), started at $HITS/$MISSES)"
+    exit 1
+fi
+echo "✓ Reads counted 4 hits and 3 misses, errors and writes none"
+
+echo ""
+echo "=== Client Pause ==="
+redis-cli -p 6379 SET pause:key before
+redis-cli -p 6379 CLIENT PAUSE 1000 WRITE
+# Reads go through a WRITE pause; writes wait for it to end, then run
+if [ "$(elapsed_ms redis-cli -p 6379 GET pause:key)" -ge 500 ]; then
+    echo "ERROR: a read waited for a WRITE pause"
+    exit 1
+fi
+if [ "$(elapsed_ms redis-cli -p 6379 SET pause:key during)" -lt 500 ]; then
+    echo "ERROR: a write ran during a WRITE pause"
+    exit 1
+fi
+redis-cli -p 6379 GET pause:key
+echo "✓ CLIENT PAUSE WRITE held back writes only"
+# With ALL even reads wait, until CLIENT UNPAUSE lifts the pause early
+redis-cli -p 6379 CLIENT PAUSE 10000 ALL
+redis-cli -p 6379 SET pause:key unpaused > /dev/null &
+PAUSED_PID=$!
+sleep 0.5
+redis-cli -p 6379 CLIENT UNPAUSE
+wait $PAUSED_PID
+if [ "$(redis-cli -p 6379 GET pause:key)" != "unpaused" ]; then
+    echo "ERROR: the paused write did not run after CLIENT UNPAUSE"
+    exit 1
+fi
+echo "✓ CLIENT UNPAUSE let the waiting command run"
+redis-cli -p 6379 CLIENT PAUSE -1 2>&1
+redis-cli -p 6379 CLIENT PAUSE 100 READS 2>&1
+
+echo ""
+echo "=== DEBUG SLEEP ==="
+# Each connection is its own task and sleeping holds no lock, so other
+# clients keep reading and writing while one connection sleeps
+redis-cli -p 6379 SET sleep:key awake
+SLEEP_START=$(date +%s%N)
+redis-cli -p 6379 DEBUG SLEEP 2 > /dev/null &
+SLEEPER_PID=$!
+sleep 0.2
+if [ "$(elapsed_ms redis-cli -p 6379 GET sleep:key)" -ge 500 ] ||
+    [ "$(elapsed_ms redis-cli -p 6379 INCR sleep:counter)" -ge 500 ]; then
+    echo "ERROR: DEBUG SLEEP on one connection blocked another"
+    exit 1
+fi
+wait $SLEEPER_PID
+if [ $((($(date +%s%N) - SLEEP_START) / 1000000)) -lt 1800 ]; then
+    echo "ERROR: DEBUG SLEEP 2 returned early"
+    exit 1
+fi
+echo "✓ DEBUG SLEEP blocked only its own connection"
+redis-cli -p 6379 DEBUG SLEEP 0.1
+redis-cli -p 6379 DEBUG SLEEP -1 2>&1
+redis-cli -p 6379 DEBUG SLEEP soon 2>&1
+
+echo ""
+echo "=== Pub/Sub ==="
+# redis-cli blocks in subscribe mode, so speak RESP over a raw socket
+exec 3<>/dev/tcp/127.0.0.1/6379
+# echo writes each command in a single send; the server parses one per read
+echo -n $'*4\r\n$9\r\nSUBSCRIBE\r\n$2\r\nc1\r\n$2\r\nc2\r\n$2\r\nc3\r\n' >&3
+sleep 0.5
+redis-cli -p 6379 PUBLISH c2 hello
+sleep 0.5
+echo -n $'*1\r\n$11\r\nUNSUBSCRIBE\r\n' >&3
+sleep 0.5
+echo -n $'*1\r\n$11\r\nUNSUBSCRIBE\r\n' >&3
+sleep 0.5
+timeout 1 cat <&3 | tr -d '\r' > /tmp/rudis_pubsub.txt || true
+exec 3<&-
+cat /tmp/rudis_pubsub.txt
+# Three subscribe frames, the message, then unsubscribe counts 2, 1, 0 and
+# a final frame with a nil channel once nothing is left
+EXPECTED=$(printf '%s\n' \
+    '*3' '$9' 'subscribe' '$2' 'c1' ':1' \
+    '*3' '$9' 'subscribe' '$2' 'c2' ':2' \
+    '*3' '$9' 'subscribe' '$2' 'c3' ':3' \
+    '*3' '$7' 'message' '$2' 'c2' '$5' 'hello' \
+    '*3' '$11' 'unsubscribe' '$2' 'c1' ':2' \
+    '*3' '$11' 'unsubscribe' '$2' 'c2' ':1' \
+    '*3' '$11' 'unsubscribe' '$2' 'c3' ':0' \
+    '*3' '$11' 'unsubscribe' '$-1' ':0')
+if [ "$(cat /tmp/rudis_pubsub.txt)" != "$EXPECTED" ]; then
+    echo "ERROR: unexpected pub/sub replies"
+    exit 1
+fi
+echo "✓ UNSUBSCRIBE with no arguments dropped every channel"
+redis-cli -p 6379 PUBLISH c1 nobody
+
+echo ""
+echo "=== Pub/Sub Introspection ==="
+exec 3<>/dev/tcp/127.0.0.1/6379
+exec 4<>/dev/tcp/127.0.0.1/6379
+echo -n $'*3\r\n$9\r\nSUBSCRIBE\r\n$10\r\nnews.sport\r\n$9\r\nnews.tech\r\n' >&3
+echo -n $'*3\r\n$9\r\nSUBSCRIBE\r\n$10\r\nnews.sport\r\n$7\r\nweather\r\n' >&4
+sleep 0.2
+echo -n $'*3\r\n$10\r\nPSUBSCRIBE\r\n$6\r\nnews.*\r\n$1\r\n*\r\n' >&4
+sleep 0.5
+if [ "$(redis-cli -p 6379 PUBSUB CHANNELS | tr '\n' ' ')" != "news.sport news.tech weather " ]; then
+    echo "ERROR: PUBSUB CHANNELS did not list every active channel"
+    exit 1
+fi
+redis-cli -p 6379 PUBSUB CHANNELS 'news.*'
+if [ "$(redis-cli -p 6379 PUBSUB NUMSUB news.sport weather nobody | tr '\n' ' ')" != "news.sport 2 weather 1 nobody 0 " ]; then
+    echo "ERROR: unexpected PUBSUB NUMSUB counts"
+    exit 1
+fi
+redis-cli -p 6379 PUBSUB NUMPAT
+exec 3<&-
+exec 4<&-
+sleep 0.5
+# Channels disappear with their last subscriber
+if [ -n "$(redis-cli -p 6379 PUBSUB CHANNELS)" ] || [ "$(redis-cli -p 6379 PUBSUB NUMPAT)" != "0" ]; then
+    echo "ERROR: PUBSUB still reports subscriptions of closed connections"
+    exit 1
+fi
+echo "✓ PUBSUB reported only live subscriptions"
+redis-cli -p 6379 PUBSUB NUMPAT extra 2>&1
+redis-cli -p 6379 PUBSUB SHARDCHANNELS 2>&1
+
+echo ""
+echo "=== Pub/Sub Delivery Order ==="
+exec 3<>/dev/tcp/127.0.0.1/6379
+exec 4<>/dev/tcp/127.0.0.1/6379
+echo -n $'*2\r\n$9\r\nSUBSCRIBE\r\n$5\r\nburst\r\n' >&3
+echo -n $'*2\r\n$10\r\nPSUBSCRIBE\r\n$4\r\nbur*\r\n' >&4
+sleep 0.5
+# Two publishers racing on the same channel
+for publisher in a b; do
+    (for i in $(seq 1 50); do redis-cli -p 6379 PUBLISH burst "$publisher$i" > /dev/null; done) &
+    PUBLISHERS="$PUBLISHERS $!"
+done
+wait $PUBLISHERS
+sleep 0.5
+timeout 1 cat <&3 | tr -d '\r' | grep -E '^[ab][0-9]+$' > /tmp/rudis_order_1.txt || true
+timeout 1 cat <&4 | tr -d '\r' | grep -E '^[ab][0-9]+$' > /tmp/rudis_order_2.txt || true
+exec 3<&-
+exec 4<&-
+for publisher in a b; do
+    for file in /tmp/rudis_order_1.txt /tmp/rudis_order_2.txt; do
+        if [ "$(grep "^$publisher" "$file" | tr '\n' ' ')" != "$(seq -f "$publisher%g" 1 50 | tr '\n' ' ')" ]; then
+            echo "ERROR: messages from publisher $publisher arrived out of order in $file"
+            exit 1
+        fi
+    done
+done
+# Channel and pattern subscribers saw the interleaving identically
+if ! cmp -s /tmp/rudis_order_1.txt /tmp/rudis_order_2.txt; then
+    echo "ERROR: subscribers received the burst in different orders"
+    exit 1
+fi
+echo "✓ Both subscribers received all 100 messages in the same order"
+
+echo ""
+echo "=== RESP3 Push Messages ==="
+exec 3<>/dev/tcp/127.0.0.1/6379
+echo -n $'*2\r\n$5\r\nHELLO\r\n$1\r\n3\r\n' >&3
+sleep 0.2
+echo -n $'*2\r\n$9\r\nSUBSCRIBE\r\n$2\r\nr3\r\n' >&3
+sleep 0.2
+echo -n $'*2\r\n$10\r\nPSUBSCRIBE\r\n$2\r\nr*\r\n' >&3
+sleep 0.5
+redis-cli -p 6379 PUBLISH r3 pushed
+sleep 0.5
+timeout 1 cat <&3 | tr -d '\r' > /tmp/rudis_resp3.txt || true
+exec 3<&-
+cat /tmp/rudis_resp3.txt
+# HELLO 3 answers with a map, then confirmations and messages are pushes
+if [ "$(head -1 /tmp/rudis_resp3.txt)" != "%7" ] || ! grep -qx ':3' /tmp/rudis_resp3.txt; then
+    echo "ERROR: HELLO 3 did not reply with a RESP3 map"
+    exit 1
+fi
+EXPECTED=$(printf '%s\n' \
+    '>3' '$9' 'subscribe' '$2' 'r3' ':1' \
+    '>3' '$10' 'psubscribe' '$2' 'r*' ':2' \
+    '>3' '$7' 'message' '$2' 'r3' '$6' 'pushed' \
+    '>4' '$8' 'pmessage' '$2' 'r*' '$2' 'r3' '$6' 'pushed')
+if [ "$(sed -n '/^>/,$p' /tmp/rudis_resp3.txt)" != "$EXPECTED" ]; then
+    echo "ERROR: unexpected RESP3 pub/sub frames"
+    exit 1
+fi
+echo "✓ RESP3 subscribers received push frames"
+redis-cli -p 6379 HELLO 4 2>&1
+redis-cli -p 6379 HELLO 2 SETNAME hello-client | grep -A1 -x proto
+
+echo ""
+echo "=== Client Tracking ==="
+exec 3<>/dev/tcp/127.0.0.1/6379
+echo -n $'*2\r\n$5\r\nHELLO\r\n$1\r\n3\r\n' >&3
+sleep 0.2
+echo -n $'*3\r\n$6\r\nCLIENT\r\n$8\r\nTRACKING\r\n$2\r\nON\r\n' >&3
+sleep 0.2
+echo -n $'*2\r\n$3\r\nGET\r\n$7\r\ncached1\r\n' >&3
+sleep 0.2
+redis-cli -p 6379 CLIENT LIST | grep -c "flags=t"
+# Only the first change after a read is reported
+redis-cli -p 6379 SET cached1 a
+redis-cli -p 6379 SET cached1 b
+sleep 0.2
+echo -n $'*2\r\n$3\r\nGET\r\n$7\r\ncached1\r\n' >&3
+sleep 0.2
+redis-cli -p 6379 FLUSHDB
+sleep 0.5
+timeout 1 cat <&3 | tr -d '\r' > /tmp/rudis_tracking.txt || true
+exec 3<&-
+cat /tmp/rudis_tracking.txt
+EXPECTED=$(printf '%s\n' \
+    '+OK' '$-1' \
+    '>2' '$10' 'invalidate' '*1' '$7' 'cached1' \
+    '$1' 'b' \
+    '>2' '$10' 'invalidate' '$-1')
+# Skip the HELLO map; the GET replies sit between the invalidations
+if [ "$(sed -n '/^+OK/,$p' /tmp/rudis_tracking.txt)" != "$EXPECTED" ]; then
+    echo "ERROR: unexpected client tracking frames"
+    exit 1
+fi
+echo "✓ Tracked keys were invalidated once per read"
+redis-cli -p 6379 CLIENT TRACKING ON BCAST 2>&1
+
+echo ""
+echo "=== Expired Keyspace Events ==="
+RUDIS_ADDR=127.0.0.1:6382 RUDIS_NOTIFY_KEYSPACE_EVENTS=Ex cargo run > /tmp/rudis_events.log 2>&1 &
+EVENTS_PID=$!
+sleep 3
+exec 3<>/dev/tcp/127.0.0.1/6382
+echo -n $'*2\r\n$9\r\nSUBSCRIBE\r\n$22\r\n__keyevent@0__:expired\r\n' >&3
+sleep 0.5
+redis-cli -p 6382 SET lazy:key v PX 100
+redis-cli -p 6382 SET active:key v PX 100
+sleep 0.2
+# Lazy expiry on access, then leave the other key to the reaper
+redis-cli -p 6382 GET lazy:key
+redis-cli -p 6382 EXISTS lazy:key
+sleep 1
+redis-cli -p 6382 DBSIZE
+timeout 1 cat <&3 | tr -d '\r' > /tmp/rudis_events.txt || true
+exec 3<&-
+kill $EVENTS_PID 2>/dev/null || true
+for key in lazy:key active:key; do
+    if [ "$(grep -cx "$key" /tmp/rudis_events.txt)" != "1" ]; then
+        echo "ERROR: expected exactly one expired event for $key"
+        exit 1
+    fi
+done
+echo "✓ Lazy and active expiry each fired one expired event"
+
+echo ""
+echo "=== Output Buffer Limits ==="
+RUDIS_ADDR=127.0.0.1:6381 RUDIS_CLIENT_OUTPUT_BUFFER_LIMIT_NORMAL="16kb 0 0" cargo run > /tmp/rudis_limits.log 2>&1 &
+LIMITS_PID=$!
+sleep 3
+for i in {1..10}; do
+    redis-cli -p 6381 RPUSH biglist $(printf 'element-%04d ' {1..100}) > /dev/null
+done
+# A 1000-element reply is over the 16kb hard limit, so the server hangs up
+echo "Full LRANGE returned $(redis-cli -p 6381 LRANGE biglist 0 -1 2>/dev/null | grep -c element) elements"
+redis-cli -p 6381 LRANGE biglist 0 9 | wc -l
+kill $LIMITS_PID 2>/dev/null || true
+grep "output buffer limit reached" /tmp/rudis_limits.log
+
+echo ""
+echo "=== Save Schedule ==="
+rm -f /tmp/rudis_schedule.rudis
+RUDIS_ADDR=127.0.0.1:6383 RUDIS_SAVE="2 5" RUDIS_DBFILENAME=/tmp/rudis_schedule.rudis cargo run > /tmp/rudis_schedule.log 2>&1 &
+SCHEDULE_PID=$!
+sleep 3
+redis-cli -p 6383 CONFIG GET save
+redis-cli -p 6383 LASTSAVE
+for i in {1..5}; do
+    redis-cli -p 6383 SET schedule:$i v > /dev/null
+done
+sleep 2
+# Four more writes stay under the threshold, so no second save follows
+for i in {1..4}; do
+    redis-cli -p 6383 SET schedule:more:$i v > /dev/null
+done
+sleep 3
+if [ "$(grep -c "Saving..." /tmp/rudis_schedule.log)" != "1" ]; then
+    echo "ERROR: expected exactly one scheduled save"
+    exit 1
+fi
+echo "✓ Five writes in the window triggered exactly one save"
+redis-cli -p 6383 CONFIG SET save "1 1"
+redis-cli -p 6383 CONFIG SET save "1"
+redis-cli -p 6383 CONFIG GET save
+sleep 2
+if [ "$(grep -c "Saving..." /tmp/rudis_schedule.log)" != "2" ]; then
+    echo "ERROR: expected CONFIG SET save to trigger the pending save"
+    exit 1
+fi
+echo "✓ CONFIG SET save took effect at runtime"
+redis-cli -p 6383 BGSAVE
+sleep 1
+redis-cli -p 6383 SAVE
+kill $SCHEDULE_PID 2>/dev/null || true
+ls /tmp/rudis_schedule.rudis
+
+echo ""
+echo "=== Renamed Commands ==="
+RUDIS_ADDR=127.0.0.1:6384 RUDIS_RENAME_COMMAND="FLUSHDB= CONFIG=ADMIN-CONFIG" cargo run > /tmp/rudis_rename.log 2>&1 &
+RENAME_PID=$!
+sleep 3
+redis-cli -p 6384 SET keep me
+# Disabled and renamed commands are unknown under their old names
+if [ "$(redis-cli -p 6384 FLUSHDB 2>&1)" != "ERR unknown command 'FLUSHDB'" ]; then
+    echo "ERROR: FLUSHDB was not disabled"
+    exit 1
+fi
+redis-cli -p 6384 CONFIG GET save 2>&1
+redis-cli -p 6384 ADMIN-CONFIG GET dbfilename
+redis-cli -p 6384 GET keep
+# Renames apply inside transactions too: queueing FLUSHDB aborts EXEC
+exec 3<>/dev/tcp/127.0.0.1/6384
+echo -n $'*1\r\n$5\r\nMULTI\r\n' >&3
+sleep 0.2
+echo -n $'*1\r\n$7\r\nFLUSHDB\r\n' >&3
+sleep 0.2
+echo -n $'*1\r\n$4\r\nEXEC\r\n' >&3
+sleep 0.2
+timeout 1 cat <&3 | tr -d '\r' > /tmp/rudis_rename.txt || true
+exec 3<&-
+if [ "$(tail -1 /tmp/rudis_rename.txt)" != "-EXECABORT Transaction discarded because of previous errors." ]; then
+    echo "ERROR: a disabled command was queued inside MULTI"
+    exit 1
+fi
+echo "✓ Disabled commands stay unknown inside MULTI"
+kill $RENAME_PID 2>/dev/null || true
+# A bad rename refuses to start rather than leaving the command enabled
+if RUDIS_ADDR=127.0.0.1:6384 RUDIS_RENAME_COMMAND="FLUSHDBX=" timeout 10 cargo run > /tmp/rudis_rename_bad.log 2>&1; then
+    echo "ERROR: server started with an invalid RUDIS_RENAME_COMMAND"
+    exit 1
+fi
+grep "Invalid RUDIS_RENAME_COMMAND" /tmp/rudis_rename_bad.log
+
+echo ""
+echo "=== Multiple Listen Addresses ==="
+RUDIS_ADDR="127.0.0.1:6388, 127.0.0.1:6389" cargo run > /tmp/rudis_bind.log 2>&1 &
+BIND_PID=$!
+sleep 3
+grep "listening on" /tmp/rudis_bind.log
+redis-cli -p 6388 SET shared yes
+# Both listeners serve the same store
+if [ "$(redis-cli -p 6389 GET shared)" != "yes" ]; then
+    echo "ERROR: the second listener did not see the first one's write"
+    exit 1
+fi
+echo "✓ Both addresses serve the same data"
+kill $BIND_PID 2>/dev/null || true
+# The same address twice can't be bound, and the server must not start on the first alone
+if RUDIS_ADDR=127.0.0.1:6388,127.0.0.1:6388 timeout 10 cargo run > /tmp/rudis_bind_bad.log 2>&1; then
+    echo "ERROR: server started although an address failed to bind"
+    exit 1
+fi
+grep "Could not bind 127.0.0.1:6388" /tmp/rudis_bind_bad.log
+
+echo ""
+echo "=== Log Levels ==="
+RUDIS_ADDR=127.0.0.1:6390 RUDIS_LOG=debug cargo run > /tmp/rudis_log.log 2>&1 &
+LOG_PID=$!
+sleep 3
+redis-cli -p 6390 PING
+if ! grep -q "client{id=1 addr=127.0.0.1:[0-9]*}: Executing command command=PING" /tmp/rudis_log.log; then
+    echo "ERROR: RUDIS_LOG=debug did not log the command in its client span"
+    exit 1
+fi
+echo "✓ RUDIS_LOG=debug logs each command with the client id and address"
+kill $LOG_PID 2>/dev/null || true
+
+echo ""
+echo "=== Protocol Errors ==="
+# Command errors are replies: the connection stays usable
+exec 3<>/dev/tcp/127.0.0.1/6379
+sleep 0.2
+echo -n $'*1\r\n$7\r\nNOSUCHC\r\n' >&3
+sleep 0.2
+echo -n $'*1\r\n$4\r\nPING\r\n' >&3
+sleep 0.2
+timeout 1 cat <&3 | tr -d '\r' > /tmp/rudis_protocol.txt || true
+exec 3<&-
+if [ "$(tail -1 /tmp/rudis_protocol.txt)" != "+PONG" ]; then
+    echo "ERROR: an unknown command closed the connection"
+    exit 1
+fi
+# Pipelined commands in one write all run, in order
+exec 3<>/dev/tcp/127.0.0.1/6379
+sleep 0.2
+echo -n $'*3\r\n$3\r\nSET\r\n$4\r\npipe\r\n$1\r\n1\r\n*2\r\n$4\r\nINCR\r\n$4\r\npipe\r\n*2\r\n$3\r\nGET\r\n$4\r\npipe\r\n' >&3
+sleep 0.2
+timeout 1 cat <&3 | tr -d '\r' | tr '\n' ' ' > /tmp/rudis_protocol.txt || true
+exec 3<&-
+if [ "$(cat /tmp/rudis_protocol.txt)" != "+OK :2 \$1 2 " ]; then
+    echo "ERROR: pipelined commands were not all executed"
+    exit 1
+fi
+# Malformed framing gets one error reply, then the connection is closed
+exec 3<>/dev/tcp/127.0.0.1/6379
+sleep 0.2
+echo -n $'*2\r\n$4\r\nECHO\r\n$x\r\n' >&3
+sleep 0.2
+echo -n $'*1\r\n$4\r\nPING\r\n' >&3 2>/dev/null || true
+timeout 1 cat <&3 | tr -d '\r' > /tmp/rudis_protocol.txt || true
+exec 3<&-
+if [ "$(cat /tmp/rudis_protocol.txt)" != "-ERR Protocol error: invalid bulk length" ]; then
+    echo "ERROR: a protocol error did not close the connection"
+    cat /tmp/rudis_protocol.txt
+    exit 1
+fi
+echo "✓ Protocol errors close the connection, command errors don't"
+# Inline commands, as typed into telnet, are split like redis-cli splits them
+exec 3<>/dev/tcp/127.0.0.1/6379
+sleep 0.2
+echo -n $'SET "inline key" \'a\\tb\'\r\nGET "inline\\x20key"\r\nGET "open\r\n' >&3
+sleep 0.2
+timeout 1 cat <&3 | tr -d '\r' | tr '\n' ' ' > /tmp/rudis_protocol.txt || true
+exec 3<&-
+if [ "$(cat /tmp/rudis_protocol.txt)" != '+OK $4 a\tb -ERR Protocol error: unbalanced quotes in request ' ]; then
+    echo "ERROR: inline commands were not tokenized like redis-cli"
+    cat /tmp/rudis_protocol.txt
+    exit 1
+fi
+echo "✓ Inline commands take quotes and escapes"
+# A command that never completes is cut off at the query buffer limit
+RUDIS_ADDR=127.0.0.1:6386 RUDIS_CLIENT_QUERY_BUFFER_LIMIT=1024 cargo run > /tmp/rudis_query.log 2>&1 &
+QUERY_PID=$!
+sleep 3
+exec 3<>/dev/tcp/127.0.0.1/6386
+sleep 0.2
+echo -n $'*2\r\n$4\r\nECHO\r\n$4000\r\n' >&3
+head -c 2000 /dev/zero | tr '\0' x >&3
+sleep 0.5
+timeout 1 cat <&3 > /dev/null || true
+exec 3<&-
+grep "query buffer limit reached" /tmp/rudis_query.log
+redis-cli -p 6386 PING
+kill $QUERY_PID 2>/dev/null || true
+
+echo ""
+echo "=== Authentication ==="
+# Without requirepass the default user takes any password
+redis-cli -p 6379 AUTH secret 2>&1
+redis-cli -p 6379 AUTH default anything
+redis-cli -p 6379 HELLO 2 AUTH default anything | grep -A1 '^proto$'
+redis-cli -p 6379 HELLO 2 AUTH bob anything 2>&1
+RUDIS_ADDR=127.0.0.1:6387 RUDIS_REQUIREPASS=s3cret cargo run > /tmp/rudis_auth.log 2>&1 &
+AUTH_PID=$!
+sleep 3
+redis-cli -p 6387 GET k 2>&1
+redis-cli -p 6387 AUTH wrong 2>&1
+redis-cli -p 6387 AUTH s3cret
+exec 3<>/dev/tcp/127.0.0.1/6387
+sleep 0.2
+auth_send() {
+    echo -n "$1" >&3
+    sleep 0.2
+}
+auth_send $'*2\r\n$5\r\nHELLO\r\n$1\r\n3\r\n'
+# A failed HELLO AUTH neither authenticates nor switches protocol
+auth_send $'*5\r\n$5\r\nHELLO\r\n$1\r\n3\r\n$4\r\nAUTH\r\n$7\r\ndefault\r\n$5\r\nwrong\r\n'
+auth_send $'*2\r\n$3\r\nGET\r\n$1\r\nk\r\n'
+auth_send $'*5\r\n$5\r\nHELLO\r\n$1\r\n3\r\n$4\r\nAUTH\r\n$7\r\ndefault\r\n$6\r\ns3cret\r\n'
+auth_send $'*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n'
+timeout 1 cat <&3 | tr -d '\r' > /tmp/rudis_auth.txt || true
+exec 3<&-
+grep '^[-+%]' /tmp/rudis_auth.txt
+if [ "$(grep -c '^-NOAUTH' /tmp/rudis_auth.txt)" != "2" ] ||
+    ! grep -q '^-WRONGPASS' /tmp/rudis_auth.txt ||
+    [ "$(grep -c '^%7' /tmp/rudis_auth.txt)" != "1" ] ||
+    [ "$(tail -1 /tmp/rudis_auth.txt)" != "+OK" ]; then
+    echo "ERROR: HELLO AUTH did not authenticate only with the right password"
+    exit 1
+fi
+echo "✓ HELLO AUTH authenticates and switches protocol in one step"
+kill $AUTH_PID 2>/dev/null || true
+
+echo ""
+echo "=== Prometheus Metrics ==="
+RUDIS_ADDR=127.0.0.1:6385 RUDIS_METRICS_ADDR=127.0.0.1:9385 cargo run > /tmp/rudis_metrics.log 2>&1 &
+METRICS_PID=$!
+sleep 3
+redis-cli -p 6385 SET metric:key v
+redis-cli -p 6385 GET metric:key
+redis-cli -p 6385 GET metric:missing
+redis-cli -p 6385 GET metric:missing
+# Plain HTTP over a raw socket, so the test doesn't depend on curl
+scrape() {
+    exec 3<>/dev/tcp/127.0.0.1/9385
+    printf 'GET %s HTTP/1.1\r\nHost: localhost\r\n\r\n' "$1" >&3
+    timeout 2 cat <&3 | tr -d '\r'
+    exec 3<&-
+}
+scrape /metrics > /tmp/rudis_metrics.txt
+head -1 /tmp/rudis_metrics.txt
+for sample in \
+    'rudis_commands_processed_total{cmd="get"} 3' \
+    'rudis_commands_processed_total{cmd="set"} 1' \
+    'rudis_keyspace_hits_total 1' \
+    'rudis_keyspace_misses_total 2' \
+    'rudis_db_keys{db="0"} 1' \
+    '# TYPE rudis_connected_clients gauge'; do
+    if ! grep -qxF "$sample" /tmp/rudis_metrics.txt; then
+        echo "ERROR: /metrics is missing '$sample'"
+        exit 1
+    fi
+done
+echo "✓ /metrics exported command, keyspace and key counts"
+scrape /other | head -1
+redis-cli -p 6385 INFO | grep keyspace_
+kill $METRICS_PID 2>/dev/null || true
+
+echo ""
+echo "=== Replication ==="
+RUDIS_ADDR=127.0.0.1:6380 cargo run > /tmp/rudis_replica.log 2>&1 &
+REPLICA_PID=$!
+trap 'kill $REPLICA_PID 2>/dev/null || true; cleanup' EXIT
+sleep 3
+redis-cli -p 6379 FLUSHDB
+redis-cli -p 6379 SET before sync
+redis-cli -p 6380 REPLICAOF 127.0.0.1 6379
+sleep 1
+for i in {1..10}; do
+    redis-cli -p 6379 SET "r:$i" "v$i" > /dev/null
+done
+redis-cli -p 6379 DEL r:2 r:4 r:6
+redis-cli -p 6379 SET r:ttl "value" EX 100
+redis-cli -p 6379 EXPIRE r:1 100
+redis-cli -p 6379 HSET r:hash a 1 b 2
+redis-cli -p 6379 HEXPIRE r:hash 100 FIELDS 1 a
+redis-cli -p 6379 SETEX r:setex 100 v
+redis-cli -p 6379 PSETEX r:psetex 100000 v
+redis-cli -p 6379 GETEX r:3 PX 50000
+sleep 1
+echo "Replica keys:"
+redis-cli -p 6380 KEYS "*" | sort
+if [ "$(redis-cli -p 6379 KEYS '*' | sort)" != "$(redis-cli -p 6380 KEYS '*' | sort)" ]; then
+    echo "ERROR: replica diverged from master"
+    exit 1
+fi
+for key in before r:1 r:3 r:ttl; do
+    if [ "$(redis-cli -p 6379 GET $key)" != "$(redis-cli -p 6380 GET $key)" ]; then
+        echo "ERROR: replica value for $key differs"
+        exit 1
+    fi
+done
+if ! redis-cli -p 6380 HTTL r:hash FIELDS 2 a b | paste -sd' ' | grep -qE '^(9[0-9]|100) -1$'; then
+    echo "ERROR: replica hash field TTLs differ"
+    exit 1
+fi
+echo "✓ Replica consistent with master"
+# Relative expiries reach the replica as the absolute time the master stored
+for key in r:ttl r:1 r:setex r:psetex r:3; do
+    master_at=$(redis-cli -p 6379 PEXPIRETIME $key)
+    if [ "$master_at" -le 0 ] || [ "$master_at" != "$(redis-cli -p 6380 PEXPIRETIME $key)" ]; then
+        echo "ERROR: replica expiry of $key differs from the master's $master_at"
+        exit 1
+    fi
+done
+echo "✓ Replicated expiry times match the master's to the millisecond"
+redis-cli -p 6379 EXPIRETIME before
+redis-cli -p 6379 EXPIRETIME nosuchkey
+redis-cli -p 6379 INFO | grep -E "^(role|connected_slaves|master_repl_offset):"
+redis-cli -p 6380 INFO | grep -E "^(role|master_host|master_port):"
+REPLID=$(redis-cli -p 6379 INFO | grep master_replid)
+redis-cli -p 6379 DEBUG CHANGE-REPL-ID
+if [ "$(redis-cli -p 6379 INFO | grep master_replid)" == "$REPLID" ]; then
+    echo "ERROR: DEBUG CHANGE-REPL-ID kept the old replication id"
+    exit 1
+fi
+redis-cli -p 6380 SET r:write "rejected" 2>&1
+redis-cli -p 6380 FAILOVER
+redis-cli -p 6380 WAITAOF 0 0 0 2>&1
+redis-cli -p 6379 FAILOVER
+redis-cli -p 6380 REPLICAOF NO ONE
 
 echo ""
 echo "=========================================="
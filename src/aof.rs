@@ -0,0 +1,97 @@
+use crate::resp::RESPValue;
+use crate::store::{Snapshot, Value};
+use crate::zset;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Elements per RPUSH, SADD, ZADD or HSET when a big collection is split
+// over several commands, Redis' AOF_REWRITE_ITEMS_PER_CMD
+const ITEMS_PER_COMMAND: usize = 64;
+
+// The commands that rebuild a snapshot, as RESP: for each key, the fewest
+// writes that recreate its value, then its expiry as an absolute time, so
+// replaying the file later gives the same deadlines
+pub fn encode(snapshot: &Snapshot) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (key, value, expires_at) in snapshot.iter() {
+        let key = &key[..];
+        match value {
+            Value::String(val) => {
+                let value = val.to_bytes();
+                match expires_at {
+                    Some(at) => emit(&mut out, &[b"SET", key, &value, b"PXAT", &millis(at)]),
+                    None => emit(&mut out, &[b"SET", key, &value]),
+                }
+                // SET carried the expiry
+                continue;
+            }
+            Value::List(list) => {
+                let items: Vec<&[u8]> = list.iter().map(|item| &item[..]).collect();
+                emit_chunked(&mut out, b"RPUSH", key, &items, 1);
+            }
+            Value::Set(set) => {
+                let members: Vec<&[u8]> = set.iter().map(|member| &member[..]).collect();
+                emit_chunked(&mut out, b"SADD", key, &members, 1);
+            }
+            Value::ZSet(zset) => {
+                let scores: Vec<Vec<u8>> = zset
+                    .iter()
+                    .map(|(_, score)| zset::format_score(score).into_bytes())
+                    .collect();
+                let items: Vec<&[u8]> = zset
+                    .iter()
+                    .zip(&scores)
+                    .flat_map(|((member, _), score)| [&score[..], &member[..]])
+                    .collect();
+                emit_chunked(&mut out, b"ZADD", key, &items, 2);
+            }
+            Value::Hash(hash) => {
+                let items: Vec<&[u8]> = hash
+                    .fields()
+                    .flat_map(|(field, value, _)| [&field[..], &value[..]])
+                    .collect();
+                emit_chunked(&mut out, b"HSET", key, &items, 2);
+                for (field, _, field_expiry) in hash.fields() {
+                    if let Some(at) = field_expiry {
+                        emit(
+                            &mut out,
+                            &[b"HPEXPIREAT", key, &millis(at), b"FIELDS", b"1", field],
+                        );
+                    }
+                }
+            }
+        }
+        if let Some(at) = expires_at {
+            emit(&mut out, &[b"PEXPIREAT", key, &millis(at)]);
+        }
+    }
+    out
+}
+
+// `items` holds whole elements of `width` arguments each (a score and its
+// member, a field and its value), never split across commands
+fn emit_chunked(out: &mut Vec<u8>, name: &[u8], key: &[u8], items: &[&[u8]], width: usize) {
+    for chunk in items.chunks(ITEMS_PER_COMMAND * width) {
+        let mut parts = vec![name, key];
+        parts.extend_from_slice(chunk);
+        emit(out, &parts);
+    }
+}
+
+fn emit(out: &mut Vec<u8>, parts: &[&[u8]]) {
+    let command = RESPValue::Array(Some(
+        parts
+            .iter()
+            .map(|part| RESPValue::BulkString(Some(part.to_vec())))
+            .collect(),
+    ));
+    // Writing to a Vec can't fail
+    let _ = command.serialize_into(out);
+}
+
+fn millis(at: SystemTime) -> Vec<u8> {
+    let millis = at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    millis.to_string().into_bytes()
+}
@@ -0,0 +1,147 @@
+use crate::lock;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+// The key an element was popped from, and the element
+pub type Popped = (Vec<u8>, Vec<u8>);
+
+struct Waiter {
+    keys: Vec<Vec<u8>>,
+    from_left: bool,
+    reply: oneshot::Sender<Popped>,
+}
+
+#[derive(Default)]
+struct Queues {
+    // Key -> ids of the clients blocked on it, longest waiting first
+    keys: HashMap<Vec<u8>, VecDeque<u64>>,
+    waiters: HashMap<u64, Waiter>,
+}
+
+impl Queues {
+    fn remove(&mut self, id: u64) -> Option<Waiter> {
+        let waiter = self.waiters.remove(&id)?;
+        for key in &waiter.keys {
+            if let Some(queue) = self.keys.get_mut(key) {
+                queue.retain(|&waiting| waiting != id);
+                if queue.is_empty() {
+                    self.keys.remove(key);
+                }
+            }
+        }
+        Some(waiter)
+    }
+}
+
+// BLPOP/BRPOP wait queues. A push hands its elements to the clients blocked
+// on the key one at a time, in the order they blocked, so every element
+// reaches exactly one client. Popping, queueing and waking all happen under
+// one lock, and a new BLPOP never takes an element from a key other clients
+// are already waiting on: it queues up behind them instead.
+//
+// A push happens in a fixed order: the elements are added, the lpush or
+// rpush event is published, then wake serves the blocked clients, each
+// with an LPOP or RPOP (and its event) as if it had run right after the
+// push. Only a plain pop from another connection landing in between can
+// take an element first, in which case the blocked client keeps waiting.
+#[derive(Clone, Default)]
+pub struct Blocking {
+    queues: Arc<Mutex<Queues>>,
+}
+
+// A client's place in the queues until it is served or cancels
+pub struct Ticket {
+    id: u64,
+    reply: oneshot::Receiver<Popped>,
+}
+
+impl Ticket {
+    pub async fn served(&mut self) -> Option<Popped> {
+        (&mut self.reply).await.ok()
+    }
+}
+
+impl Blocking {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Pop from the first key that has an element and no one waiting on it
+    pub fn pop_now(
+        &self,
+        keys: &[Vec<u8>],
+        from_left: bool,
+        pop: impl Fn(&[u8], bool) -> Option<Vec<u8>>,
+    ) -> Option<Popped> {
+        let queues = lock::mutex(&self.queues);
+        Self::pop_first(&queues, keys, from_left, &pop)
+    }
+
+    // Like pop_now, but queues the client on every key when nothing can be popped
+    pub fn pop_or_wait(
+        &self,
+        id: u64,
+        keys: &[Vec<u8>],
+        from_left: bool,
+        pop: impl Fn(&[u8], bool) -> Option<Vec<u8>>,
+    ) -> Result<Popped, Ticket> {
+        let mut queues = lock::mutex(&self.queues);
+        if let Some(popped) = Self::pop_first(&queues, keys, from_left, &pop) {
+            return Ok(popped);
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        for key in keys {
+            queues.keys.entry(key.clone()).or_default().push_back(id);
+        }
+        queues.waiters.insert(
+            id,
+            Waiter {
+                keys: keys.to_vec(),
+                from_left,
+                reply: sender,
+            },
+        );
+        Err(Ticket {
+            id,
+            reply: receiver,
+        })
+    }
+
+    // Called after a write to `key`: serve the clients blocked on it while it
+    // holds a list with elements
+    pub fn wake(&self, key: &[u8], pop: impl Fn(&[u8], bool) -> Option<Vec<u8>>) {
+        let mut queues = lock::mutex(&self.queues);
+        while let Some(&id) = queues.keys.get(key).and_then(|queue| queue.front()) {
+            let from_left = queues.waiters[&id].from_left;
+            let Some(value) = pop(key, from_left) else {
+                break;
+            };
+            if let Some(waiter) = queues.remove(id) {
+                let _ = waiter.reply.send((key.to_vec(), value));
+            }
+        }
+    }
+
+    // Timed out or disconnected: leave the queues. Returns the element a
+    // push handed over before the client got the lock, if any.
+    pub fn cancel(&self, mut ticket: Ticket) -> Option<Popped> {
+        let mut queues = lock::mutex(&self.queues);
+        if queues.remove(ticket.id).is_some() {
+            return None;
+        }
+        ticket.reply.try_recv().ok()
+    }
+
+    fn pop_first(
+        queues: &Queues,
+        keys: &[Vec<u8>],
+        from_left: bool,
+        pop: &impl Fn(&[u8], bool) -> Option<Vec<u8>>,
+    ) -> Option<Popped> {
+        keys.iter()
+            .filter(|key| !queues.keys.contains_key(*key))
+            .find_map(|key| pop(key, from_left).map(|value| (key.clone(), value)))
+    }
+}
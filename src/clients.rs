@@ -0,0 +1,152 @@
+use crate::lock;
+use crate::pubsub::MessageSender;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+
+// What CLIENT LIST knows about one connection. Counters are updated by the
+// connection's own task and read by whoever runs CLIENT LIST.
+pub struct Client {
+    pub id: u64,
+    addr: String,
+    laddr: String,
+    created_at: Instant,
+    name: Mutex<String>,
+    last_command: Mutex<String>,
+    last_interaction: Mutex<Instant>,
+    total_commands: AtomicU64,
+    channels: AtomicUsize,
+    patterns: AtomicUsize,
+    tracking: AtomicBool,
+    // False until AUTH or HELLO AUTH succeeds when a password is required
+    authenticated: AtomicBool,
+    // The connection's pub/sub queue, also used for tracking invalidations
+    messages: MessageSender,
+}
+
+impl Client {
+    // Counted before the command runs, so commands that fail still show up
+    pub fn record_command(&self, name: &str) {
+        self.total_commands.fetch_add(1, Ordering::Relaxed);
+        *lock::mutex(&self.last_command) = name.to_string();
+        *lock::mutex(&self.last_interaction) = Instant::now();
+    }
+
+    pub fn set_subscriptions(&self, channels: usize, patterns: usize) {
+        self.channels.store(channels, Ordering::Relaxed);
+        self.patterns.store(patterns, Ordering::Relaxed);
+    }
+
+    pub fn is_tracking(&self) -> bool {
+        self.tracking.load(Ordering::Relaxed)
+    }
+
+    pub fn set_tracking(&self, tracking: bool) {
+        self.tracking.store(tracking, Ordering::Relaxed);
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.authenticated.load(Ordering::Relaxed)
+    }
+
+    pub fn set_authenticated(&self, authenticated: bool) {
+        self.authenticated.store(authenticated, Ordering::Relaxed);
+    }
+
+    // Set by HELLO, on the connection's message queue
+    pub fn is_resp3(&self) -> bool {
+        self.messages.is_resp3()
+    }
+
+    pub fn messages(&self) -> &MessageSender {
+        &self.messages
+    }
+
+    pub fn name(&self) -> String {
+        lock::mutex(&self.name).clone()
+    }
+
+    pub fn set_name(&self, name: String) {
+        *lock::mutex(&self.name) = name;
+    }
+
+    // One CLIENT LIST line, in Redis' field order for the fields Rudis tracks
+    pub fn info_line(&self) -> String {
+        let channels = self.channels.load(Ordering::Relaxed);
+        let patterns = self.patterns.load(Ordering::Relaxed);
+        let mut flags = String::new();
+        if channels + patterns > 0 {
+            flags.push('P');
+        }
+        if self.is_tracking() {
+            flags.push('t');
+        }
+        if flags.is_empty() {
+            flags.push('N');
+        }
+        format!(
+            "id={} addr={} laddr={} name={} age={} idle={} flags={} db=0 sub={} psub={} tot-cmds={} cmd={}",
+            self.id,
+            self.addr,
+            self.laddr,
+            self.name(),
+            self.created_at.elapsed().as_secs(),
+            lock::mutex(&self.last_interaction).elapsed().as_secs(),
+            flags,
+            channels,
+            patterns,
+            self.total_commands.load(Ordering::Relaxed),
+            lock::mutex(&self.last_command),
+        )
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct ClientRegistry {
+    clients: Arc<RwLock<HashMap<u64, Arc<Client>>>>,
+}
+
+impl ClientRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(
+        &self,
+        id: u64,
+        addr: String,
+        laddr: String,
+        messages: MessageSender,
+    ) -> Arc<Client> {
+        let now = Instant::now();
+        let client = Arc::new(Client {
+            id,
+            addr,
+            laddr,
+            created_at: now,
+            name: Mutex::new(String::new()),
+            last_command: Mutex::new("NULL".to_string()),
+            last_interaction: Mutex::new(now),
+            total_commands: AtomicU64::new(0),
+            channels: AtomicUsize::new(0),
+            patterns: AtomicUsize::new(0),
+            tracking: AtomicBool::new(false),
+            authenticated: AtomicBool::new(false),
+            messages,
+        });
+        lock::write(&self.clients).insert(id, client.clone());
+        client
+    }
+
+    pub fn unregister(&self, id: u64) {
+        lock::write(&self.clients).remove(&id);
+    }
+
+    // Every connected client, oldest first
+    pub fn list(&self) -> Vec<Arc<Client>> {
+        let mut clients: Vec<_> = lock::read(&self.clients).values().cloned().collect();
+        clients.sort_by_key(|c| c.id);
+        clients
+    }
+}
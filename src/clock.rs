@@ -0,0 +1,30 @@
+use std::sync::Arc;
+use std::time::SystemTime;
+
+// Where key and hash field expiry read the time: lookups, TTL and HTTL,
+// the reaper and relative expiry arguments like EX. The server runs on the
+// system clock; tests swap in one they move forward by hand instead of
+// sleeping. OBJECT IDLETIME still reads the system clock.
+#[derive(Clone)]
+pub struct Clock(Arc<dyn Fn() -> SystemTime + Send + Sync>);
+
+impl Clock {
+    pub fn system() -> Self {
+        Clock(Arc::new(SystemTime::now))
+    }
+
+    #[cfg(test)]
+    pub fn new(now: impl Fn() -> SystemTime + Send + Sync + 'static) -> Self {
+        Clock(Arc::new(now))
+    }
+
+    pub fn now(&self) -> SystemTime {
+        (self.0)()
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::system()
+    }
+}
@@ -1,7 +1,25 @@
+use crate::clients::{Client, ClientRegistry};
+use crate::command_table;
+use crate::config::Config;
+use crate::deadline::Deadline;
+use crate::json;
+use crate::latency::LatencyMonitor;
+use crate::memory;
+use crate::pause::Pause;
+use crate::persistence;
+use crate::pubsub::{PubSub, Subscriptions};
+use crate::replication::Replication;
 use crate::resp::RESPValue;
-use crate::store::Store;
-use std::time::Duration;
+use crate::snapshot;
+use crate::stats::Stats;
+use crate::store::{SortOptions, Store, ZAddOptions};
+use crate::tracking::Tracking;
+use crate::zset;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+#[derive(Clone)]
 pub struct Command {
     pub name: String,
     pub args: Vec<Vec<u8>>,
@@ -19,29 +37,395 @@ impl Command {
         }
     }
 
+    pub fn to_resp(&self) -> RESPValue {
+        let mut parts = vec![RESPValue::BulkString(Some(self.name.as_bytes().to_vec()))];
+        parts.extend(
+            self.args
+                .iter()
+                .map(|a| RESPValue::BulkString(Some(a.clone()))),
+        );
+        RESPValue::Array(Some(parts))
+    }
+
+    // How CLIENT LIST shows the command: lowercase, with the subcommand of
+    // container commands, e.g. `client|list`
+    pub fn display_name(&self) -> String {
+        let name = self.name.to_lowercase();
+        match (self.name.as_str(), self.args.first()) {
+            (
+                "CLIENT" | "CLUSTER" | "COMMAND" | "CONFIG" | "DEBUG" | "LATENCY" | "OBJECT"
+                | "PUBSUB",
+                Some(sub),
+            ) => {
+                format!("{}|{}", name, String::from_utf8_lossy(sub).to_lowercase())
+            }
+            _ => name,
+        }
+    }
+
+    // SORT ... STORE destination; SORT without it only reads
+    fn sort_destination(&self) -> Option<&[u8]> {
+        let at = self
+            .args
+            .iter()
+            .skip(1)
+            .position(|arg| arg.eq_ignore_ascii_case(b"STORE"))?;
+        self.args.get(at + 2).map(|arg| arg.as_slice())
+    }
+
+    // The key arguments: the positions the command table lists, plus the
+    // source keys that follow numkeys and SORT's STORE destination
+    pub fn keys(&self) -> Vec<&[u8]> {
+        let Some(spec) = command_table::lookup(&self.name) else {
+            return Vec::new();
+        };
+        let mut keys: Vec<&[u8]> = spec
+            .key_positions(self.args.len() + 1)
+            .into_iter()
+            .filter_map(|i| self.args.get(i - 1))
+            .map(|arg| arg.as_slice())
+            .collect();
+
+        let numkeys_at = match self.name.as_str() {
+            "SORT" => {
+                keys.extend(self.sort_destination());
+                return keys;
+            }
+            "ZDIFF" | "ZINTERCARD" | "SINTERCARD" => 0,
+            "ZUNIONSTORE" | "ZINTERSTORE" | "ZDIFFSTORE" => 1,
+            _ => return keys,
+        };
+        if let Some(numkeys) = self
+            .args
+            .get(numkeys_at)
+            .and_then(|n| String::from_utf8_lossy(n).parse::<usize>().ok())
+        {
+            keys.extend(
+                self.args
+                    .iter()
+                    .skip(numkeys_at + 1)
+                    .take(numkeys)
+                    .map(|arg| arg.as_slice()),
+            );
+        }
+        keys
+    }
+
+    // The keys a write changes, for WATCH, client tracking and blocked
+    // clients: keys() less the sources ZUNIONSTORE, ZINTERSTORE, ZDIFFSTORE
+    // and SORT ... STORE only read, which Redis doesn't touch either
+    pub fn written_keys(&self) -> Vec<&[u8]> {
+        match self.name.as_str() {
+            "SORT" => self.sort_destination().into_iter().collect(),
+            "ZUNIONSTORE" | "ZINTERSTORE" | "ZDIFFSTORE" => self
+                .args
+                .first()
+                .map(|arg| arg.as_slice())
+                .into_iter()
+                .collect(),
+            _ => self.keys(),
+        }
+    }
+
+    // Commands that modify the keyspace and must be forwarded to replicas
+    pub fn is_write(&self) -> bool {
+        if self.name == "SORT" {
+            return self.sort_destination().is_some();
+        }
+        matches!(
+            self.name.as_str(),
+            "SET"
+                | "SETEX"
+                | "PSETEX"
+                | "GETEX"
+                | "APPEND"
+                | "SETRANGE"
+                | "DEL"
+                | "INCR"
+                | "DECR"
+                | "INCRBY"
+                | "DECRBY"
+                | "FLUSHDB"
+                | "FLUSHALL"
+                | "EXPIRE"
+                | "PEXPIREAT"
+                | "PERSIST"
+                | "LPUSH"
+                | "RPUSH"
+                | "LPOP"
+                | "RPOP"
+                | "LREM"
+                | "LTRIM"
+                | "SADD"
+                | "SREM"
+                | "ZADD"
+                | "ZREM"
+                | "ZUNIONSTORE"
+                | "ZINTERSTORE"
+                | "ZDIFFSTORE"
+                | "HSET"
+                | "HMSET"
+                | "HDEL"
+                | "HEXPIRE"
+                | "HPEXPIRE"
+                | "HPEXPIREAT"
+                | "HPERSIST"
+                | "RESTORE"
+        )
+    }
+
+    // Writes that can only shrink the dataset or change expiries, which
+    // Redis still allows once over maxmemory with nothing left to evict
+    pub fn frees_memory(&self) -> bool {
+        matches!(
+            self.name.as_str(),
+            "DEL"
+                | "FLUSHDB"
+                | "FLUSHALL"
+                | "EXPIRE"
+                | "PEXPIREAT"
+                | "PERSIST"
+                | "LPOP"
+                | "RPOP"
+                | "LREM"
+                | "LTRIM"
+                | "SREM"
+                | "ZREM"
+                | "HDEL"
+                | "HEXPIRE"
+                | "HPEXPIRE"
+                | "HPEXPIREAT"
+                | "HPERSIST"
+        )
+    }
+
+    // Rewrite commands whose effect depends on the local clock into an
+    // absolute form, so replicas end up with the same state as the master:
+    //   EXPIRE key seconds        -> PEXPIREAT key <unix-ms>
+    //   SET key value EX seconds  -> SET key value PXAT <unix-ms>
+    //   SET key value PX millis   -> SET key value PXAT <unix-ms>
+    //   SETEX key seconds value   -> SET key value PXAT <unix-ms>
+    //   PSETEX key millis value   -> SET key value PXAT <unix-ms>
+    //   GETEX key EX|PX ttl       -> PEXPIREAT key <unix-ms>
+    //   HEXPIRE key seconds FIELDS ...  -> HPEXPIREAT key <unix-ms> FIELDS ...
+    //   HPEXPIRE key millis FIELDS ...  -> HPEXPIREAT key <unix-ms> FIELDS ...
+    //   RESTORE key millis value ...    -> RESTORE key <unix-ms> value ... ABSTTL
+    // Called after the command ran, so the time sent is the expiry the
+    // master stored rather than a second reading of its clock. Only when
+    // nothing was stored (a deleted field, say) is it computed from now.
+    pub fn for_propagation(&self, store: &Store) -> Command {
+        let now = unix_millis(store.now());
+        let arg = |i: usize| String::from_utf8_lossy(&self.args[i]).to_string();
+        let key_expiry = |ttl_ms: u64| {
+            store
+                .expiry(&self.args[0])
+                .flatten()
+                .map_or(now + ttl_ms, unix_millis)
+        };
+
+        match self.name.as_str() {
+            "RESTORE"
+                if arg(1) != "0"
+                    && !self.args[3..]
+                        .iter()
+                        .any(|a| a.eq_ignore_ascii_case(b"ABSTTL")) =>
+            {
+                let mut args = self.args.clone();
+                args[1] = key_expiry(0).to_string().into_bytes();
+                args.push(b"ABSTTL".to_vec());
+                Command {
+                    name: "RESTORE".to_string(),
+                    args,
+                }
+            }
+            "EXPIRE" if self.args.len() == 2 => {
+                let seconds = arg(1).parse::<u64>().unwrap_or(0);
+                Command {
+                    name: "PEXPIREAT".to_string(),
+                    args: vec![
+                        self.args[0].clone(),
+                        key_expiry(seconds * 1000).to_string().into_bytes(),
+                    ],
+                }
+            }
+            "SET" if self.args.len() == 4 => {
+                let ttl_ms = match arg(2).to_uppercase().as_str() {
+                    "EX" => arg(3).parse::<u64>().unwrap_or(0) * 1000,
+                    "PX" => arg(3).parse::<u64>().unwrap_or(0),
+                    _ => return self.clone(),
+                };
+                Command {
+                    name: "SET".to_string(),
+                    args: vec![
+                        self.args[0].clone(),
+                        self.args[1].clone(),
+                        b"PXAT".to_vec(),
+                        key_expiry(ttl_ms).to_string().into_bytes(),
+                    ],
+                }
+            }
+            "SETEX" | "PSETEX" if self.args.len() == 3 => {
+                let ttl = arg(1).parse::<u64>().unwrap_or(0);
+                let ttl_ms = if self.name == "SETEX" {
+                    ttl * 1000
+                } else {
+                    ttl
+                };
+                Command {
+                    name: "SET".to_string(),
+                    args: vec![
+                        self.args[0].clone(),
+                        self.args[2].clone(),
+                        b"PXAT".to_vec(),
+                        key_expiry(ttl_ms).to_string().into_bytes(),
+                    ],
+                }
+            }
+            "GETEX" if self.args.len() == 3 => {
+                let ttl_ms = match arg(1).to_uppercase().as_str() {
+                    "EX" => arg(2).parse::<u64>().unwrap_or(0) * 1000,
+                    "PX" => arg(2).parse::<u64>().unwrap_or(0),
+                    _ => return self.clone(),
+                };
+                Command {
+                    name: "PEXPIREAT".to_string(),
+                    args: vec![
+                        self.args[0].clone(),
+                        key_expiry(ttl_ms).to_string().into_bytes(),
+                    ],
+                }
+            }
+            // Every field listed gets the same time, so the first one's will do
+            "HEXPIRE" | "HPEXPIRE" if self.args.len() >= 5 => {
+                let ttl = arg(1).parse::<u64>().unwrap_or(0);
+                let ttl_ms = if self.name == "HEXPIRE" {
+                    ttl * 1000
+                } else {
+                    ttl
+                };
+                let expires_at = store
+                    .hash_field_expiry(&self.args[0], &self.args[4])
+                    .map_or(now + ttl_ms, unix_millis);
+                let mut args = self.args.clone();
+                args[1] = expires_at.to_string().into_bytes();
+                Command {
+                    name: "HPEXPIREAT".to_string(),
+                    args,
+                }
+            }
+            _ => self.clone(),
+        }
+    }
+
+    // default-ttl-seconds: a SET with no expiry option (KEEPTTL counts as
+    // one) is run as SET key value EX <seconds>, so it also reaches replicas
+    // with the expiry. None when the command is left as it is.
+    pub fn with_default_ttl(&self, seconds: u64) -> Option<Command> {
+        if seconds == 0 || self.name != "SET" || self.args.len() != 2 {
+            return None;
+        }
+        let mut args = self.args.clone();
+        args.push(b"EX".to_vec());
+        args.push(seconds.to_string().into_bytes());
+        Some(Command {
+            name: self.name.clone(),
+            args,
+        })
+    }
+
+    // For writes already checked elsewhere, such as the master's stream on
+    // a replica, which Redis doesn't hold to proto-max-bulk-len either
     pub fn execute(&self, store: &Store) -> RESPValue {
+        self.execute_within(store, Deadline::NONE, usize::MAX)
+    }
+
+    // execute, with the deadline command-timeout-ms gives SORT, KEYS,
+    // SMEMBERS and LRANGE, and the longest string APPEND and SETRANGE may
+    // build (proto-max-bulk-len)
+    pub fn execute_within(
+        &self,
+        store: &Store,
+        deadline: Deadline,
+        max_string_len: usize,
+    ) -> RESPValue {
         match self.name.as_str() {
             "PING" => self.handle_ping(),
-            "INFO" => self.handle_info(),
             "ECHO" => self.handle_echo(),
+            "SELECT" => self.handle_select(),
             "GET" => self.handle_get(store),
+            "APPEND" => self.handle_append(store, max_string_len),
+            "STRLEN" => self.handle_len(store, "string"),
+            "GETRANGE" | "SUBSTR" => self.handle_getrange(store),
+            "SETRANGE" => self.handle_setrange(store, max_string_len),
+            "LCS" => self.handle_lcs(store),
             "SET" => self.handle_set(store),
+            "SETEX" => self.handle_setex(store, ExpiryUnit::Seconds),
+            "PSETEX" => self.handle_setex(store, ExpiryUnit::Millis),
+            "GETEX" => self.handle_getex(store),
             "DEL" => self.handle_del(store),
             "EXISTS" => self.handle_exists(store),
-            "KEYS" => self.handle_keys(store),
+            "TYPE" => self.handle_type(store),
+            "DUMP" => self.handle_dump(store),
+            "RESTORE" => self.handle_restore(store),
+            "SCAN" => self.handle_scan(store),
+            "SORT" => self.handle_sort(store, deadline),
+            "KEYS" => self.handle_keys(store, deadline),
             "INCR" => self.handle_incr(store),
             "DECR" => self.handle_decr(store),
-            "FLUSHDB" => self.handle_flushdb(store),
+            "INCRBY" | "DECRBY" => self.handle_incr_by(store),
+            "FLUSHDB" | "FLUSHALL" => self.handle_flushdb(store),
             "DBSIZE" => self.handle_dbsize(store),
             "EXPIRE" => self.handle_expire(store),
-            "TTL" => self.handle_ttl(),
+            "PEXPIREAT" => self.handle_pexpireat(store),
+            "TTL" => self.handle_ttl(store, 1000),
+            "PTTL" => self.handle_ttl(store, 1),
+            "PERSIST" => self.handle_persist(store),
+            "EXPIRETIME" => self.handle_expiretime(store, 1000),
+            "PEXPIRETIME" => self.handle_expiretime(store, 1),
             "LPUSH" => self.handle_lpush(store),
             "RPUSH" => self.handle_rpush(store),
             "LPOP" => self.handle_lpop(store),
             "RPOP" => self.handle_rpop(store),
-            "LRANGE" => self.handle_lrange(store),
-            "LLEN" => self.handle_llen(store),
+            "LRANGE" => self.handle_lrange(store, deadline),
+            "LLEN" => self.handle_len(store, "list"),
             "LINDEX" => self.handle_lindex(store),
+            "LREM" => self.handle_lrem(store),
+            "LTRIM" => self.handle_ltrim(store),
+            "SADD" => self.handle_sadd(store),
+            "SREM" => self.handle_srem(store),
+            "SMEMBERS" => self.handle_smembers(store, deadline),
+            "SCARD" => self.handle_len(store, "set"),
+            "SISMEMBER" => self.handle_sismember(store),
+            "SMISMEMBER" => self.handle_smismember(store),
+            "SRANDMEMBER" => self.handle_srandmember(store),
+            "SINTERCARD" => self.handle_intercard(store, false),
+            "ZADD" => self.handle_zadd(store),
+            "ZREM" => self.handle_zrem(store),
+            "ZCARD" => self.handle_len(store, "zset"),
+            "ZSCORE" => self.handle_zscore(store),
+            "ZMSCORE" => self.handle_zmscore(store),
+            "ZRANGE" => self.handle_zrange(store),
+            "ZRANDMEMBER" => self.handle_zrandmember(store),
+            "ZUNIONSTORE" => self.handle_zstore(store, true),
+            "ZINTERSTORE" => self.handle_zstore(store, false),
+            "ZDIFF" => self.handle_zdiff(store),
+            "ZDIFFSTORE" => self.handle_zdiffstore(store),
+            "ZINTERCARD" => self.handle_intercard(store, true),
+            "HSET" | "HMSET" => self.handle_hset(store),
+            "HGET" => self.handle_hget(store),
+            "HDEL" => self.handle_hdel(store),
+            "HGETALL" => self.handle_hgetall(store),
+            "HKEYS" => self.handle_hkeys(store, false),
+            "HVALS" => self.handle_hkeys(store, true),
+            "HLEN" => self.handle_len(store, "hash"),
+            "HEXISTS" => self.handle_hexists(store),
+            "HRANDFIELD" => self.handle_hrandfield(store),
+            "HSCAN" => self.handle_hscan(store),
+            "HEXPIRE" => self.handle_hexpire(store, ExpiryUnit::Seconds),
+            "HPEXPIRE" => self.handle_hexpire(store, ExpiryUnit::Millis),
+            "HPEXPIREAT" => self.handle_hexpire(store, ExpiryUnit::UnixMillis),
+            "HTTL" => self.handle_httl(store),
+            "HPERSIST" => self.handle_hpersist(store),
             _ => RESPValue::Error(format!("ERR unknown command '{}'", self.name)),
         }
     }
@@ -54,12 +438,139 @@ impl Command {
         }
     }
 
-    fn handle_info(&self) -> RESPValue {
+    pub fn handle_info(
+        &self,
+        store: &Store,
+        stats: &Stats,
+        replication: &Replication,
+        config: &Config,
+        resp3: bool,
+    ) -> RESPValue {
         // return server information
         // # Server
         // rudis_version:0.1.0
-        let info = "# Server\r\nrudis_version:0.1.0\r\n";
-        RESPValue::BulkString(Some(info.as_bytes().to_vec()))
+        // # Clients
+        // connected_clients:1
+        // ...
+        // # Memory
+        // used_memory:1024
+        // ...
+        // # Persistence
+        // rdb_bgsave_in_progress:0
+        // ...
+        // # Stats
+        // total_connections_received:3
+        // ...
+        // # Replication
+        // role:master
+        // ...
+        // # Commandstats (INFO commandstats, all or everything)
+        // cmdstat_get:calls=2,usec=15,usec_per_call=7.50,rejected_calls=0,failed_calls=0
+        // # Cluster
+        // cluster_enabled:0
+        // # Keyspace
+        // db0:keys=2,expires=1,avg_ttl=0
+        let mut info = String::from("# Server\r\nrudis_version:0.1.0\r\n\r\n");
+        info.push_str(&format!(
+            "# Clients\r\nconnected_clients:{}\r\nblocked_clients:{}\r\nmaxclients:{}\r\ncluster_connections:0\r\n\r\n",
+            stats.connected_clients(),
+            stats.blocked_clients(),
+            config.maxclients
+        ));
+        // Without a way to read RSS it is taken to be the estimate. An empty
+        // keyspace reports a ratio of 1 rather than the RSS over nothing.
+        let used_memory = store.used_memory();
+        let rss = memory::rss().unwrap_or(used_memory);
+        let fragmentation = if used_memory == 0 {
+            1.0
+        } else {
+            rss as f64 / used_memory as f64
+        };
+        info.push_str(&format!(
+            "# Memory\r\nused_memory:{}\r\nused_memory_rss:{}\r\nmem_fragmentation_ratio:{:.2}\r\nmaxmemory:{}\r\n\r\n",
+            used_memory, rss, fragmentation, config.maxmemory
+        ));
+        let rewrite_status = if stats.aof_last_rewrite_ok() {
+            "ok"
+        } else {
+            "err"
+        };
+        info.push_str(&format!(
+            "# Persistence\r\nrdb_bgsave_in_progress:{}\r\nrdb_last_save_time:{}\r\naof_enabled:0\r\naof_rewrite_in_progress:{}\r\naof_last_bgrewrite_status:{}\r\n\r\n",
+            stats.bgsave_in_progress() as u8,
+            stats.last_save_time(),
+            stats.aof_rewrite_in_progress() as u8,
+            rewrite_status
+        ));
+        info.push_str(&format!(
+            "# Stats\r\ntotal_connections_received:{}\r\ntotal_commands_processed:{}\r\ntotal_net_output_bytes:{}\r\nkeyspace_hits:{}\r\nkeyspace_misses:{}\r\nexpired_keys:{}\r\nevicted_keys:{}\r\ntotal_writes_processed:{}\r\n\r\n# Replication\r\n",
+            stats.total_connections(),
+            stats.total_commands(),
+            stats.net_output_bytes(),
+            stats.keyspace_hits(),
+            stats.keyspace_misses(),
+            store.expired_keys(),
+            stats.evicted_keys(),
+            stats.writes_processed()
+        ));
+        match replication.master_addr() {
+            Some((host, port)) => {
+                info.push_str(&format!(
+                    "role:slave\r\nmaster_host:{}\r\nmaster_port:{}\r\n",
+                    host, port
+                ));
+            }
+            None => info.push_str("role:master\r\n"),
+        }
+        info.push_str(&format!(
+            "connected_slaves:{}\r\nmaster_replid:{}\r\nmaster_repl_offset:{}\r\n",
+            replication.connected_replicas(),
+            replication.replid(),
+            replication.offset()
+        ));
+        // Like Redis, only listed when asked for by name or with all/everything
+        let commandstats = self.args.iter().any(|arg| {
+            [&b"commandstats"[..], b"all", b"everything"]
+                .iter()
+                .any(|section| arg.eq_ignore_ascii_case(section))
+        });
+        if commandstats {
+            info.push_str("\r\n# Commandstats\r\n");
+            for (name, stat) in stats.command_stats() {
+                info.push_str(&format!(
+                    "cmdstat_{}:calls={},usec={},usec_per_call={:.2},rejected_calls={},failed_calls={}\r\n",
+                    name.to_lowercase(),
+                    stat.calls,
+                    stat.usec,
+                    stat.usec_per_call(),
+                    stat.rejected_calls,
+                    stat.failed_calls
+                ));
+            }
+        }
+        info.push_str("\r\n# Cluster\r\ncluster_enabled:0\r\n");
+        // Like Redis, an empty database isn't listed; avg_ttl isn't tracked
+        info.push_str("\r\n# Keyspace\r\n");
+        let keys = store.dbsize();
+        if keys > 0 {
+            info.push_str(&format!(
+                "db0:keys={},expires={},avg_ttl=0\r\n",
+                keys,
+                store.expires_count()
+            ));
+        }
+        RESPValue::text(info.into_bytes(), resp3)
+    }
+
+    // LOLWUT [VERSION version]: there is no art, only the version line
+    pub fn handle_lolwut(&self, resp3: bool) -> RESPValue {
+        match self.args.as_slice() {
+            [] => {}
+            [option, _] if option.eq_ignore_ascii_case(b"VERSION") => {}
+            _ => return RESPValue::Error("ERR syntax error".to_string()),
+        }
+        let text = format!("Rudis ver. {}\n", env!("CARGO_PKG_VERSION"));
+        RESPValue::text(text.into_bytes(), resp3)
     }
 
     fn handle_echo(&self) -> RESPValue {
@@ -70,49 +581,239 @@ impl Command {
         }
     }
 
+    // There is a single keyspace, so KEYS, DBSIZE and FLUSHDB always act
+    // on database 0; SELECT only accepts it, for clients that send SELECT 0
+    fn handle_select(&self) -> RESPValue {
+        if self.args.len() != 1 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'select' command".to_string(),
+            );
+        }
+
+        match String::from_utf8_lossy(&self.args[0]).parse::<i64>() {
+            Ok(0) => RESPValue::SimpleString("OK".to_string()),
+            Ok(_) => RESPValue::Error("ERR DB index is out of range".to_string()),
+            Err(_) => RESPValue::Error("ERR value is not an integer or out of range".to_string()),
+        }
+    }
+
     fn handle_get(&self, store: &Store) -> RESPValue {
         if self.args.len() != 1 {
             return RESPValue::Error("ERR wrong number of arguments for 'get' command".to_string());
         }
 
-        let key = String::from_utf8_lossy(&self.args[0]).to_string();
-        match store.get(&key) {
+        let key = &self.args[0];
+        match store.get(key) {
             Some(value) => RESPValue::BulkString(Some(value)),
             None => RESPValue::BulkString(None),
         }
     }
 
+    // GETRANGE key start end; SUBSTR is its old name
+    fn handle_getrange(&self, store: &Store) -> RESPValue {
+        if self.args.len() != 3 {
+            return RESPValue::Error(format!(
+                "ERR wrong number of arguments for '{}' command",
+                self.name.to_lowercase()
+            ));
+        }
+
+        let (start, end) = match (parse_i64(&self.args[1]), parse_i64(&self.args[2])) {
+            (Ok(start), Ok(end)) => (start, end),
+            (Err(e), _) | (_, Err(e)) => return e,
+        };
+        let key = &self.args[0];
+        match store.getrange(key, start, end) {
+            Ok(range) => RESPValue::BulkString(Some(range)),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    fn handle_append(&self, store: &Store, max_len: usize) -> RESPValue {
+        if self.args.len() != 2 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'append' command".to_string(),
+            );
+        }
+
+        let key = &self.args[0];
+        match store.append(key, &self.args[1], max_len) {
+            Ok(len) => RESPValue::Integer(len as i64),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    fn handle_setrange(&self, store: &Store, max_len: usize) -> RESPValue {
+        if self.args.len() != 3 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'setrange' command".to_string(),
+            );
+        }
+
+        let key = &self.args[0];
+        let offset = match String::from_utf8_lossy(&self.args[1]).parse::<i64>() {
+            Ok(offset) if offset >= 0 => offset as usize,
+            Ok(_) => return RESPValue::Error("ERR offset is out of range".to_string()),
+            Err(_) => {
+                return RESPValue::Error("ERR value is not an integer or out of range".to_string());
+            }
+        };
+        match store.setrange(key, offset, &self.args[2], max_len) {
+            Ok(len) => RESPValue::Integer(len as i64),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    pub fn handle_object(&self, store: &Store, config: &Config) -> RESPValue {
+        if self.args.is_empty() {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'object' command".to_string(),
+            );
+        }
+
+        let subcommand = String::from_utf8_lossy(&self.args[0]).to_uppercase();
+        match subcommand.as_str() {
+            "ENCODING" if self.args.len() == 2 => {
+                let key = &self.args[1];
+                let limits = config.encoding_limits();
+                RESPValue::BulkString(store.encoding(key, &limits).map(|e| e.as_bytes().to_vec()))
+            }
+            "IDLETIME" if self.args.len() == 2 => {
+                let key = &self.args[1];
+                match store.idle_time(key) {
+                    Some(idle) => RESPValue::Integer(idle as i64),
+                    None => RESPValue::BulkString(None),
+                }
+            }
+            _ => RESPValue::Error(format!(
+                "ERR unknown subcommand or wrong number of arguments for '{}'. Try OBJECT HELP.",
+                String::from_utf8_lossy(&self.args[0])
+            )),
+        }
+    }
+
+    fn handle_lcs(&self, store: &Store) -> RESPValue {
+        if self.args.len() < 2 {
+            return RESPValue::Error("ERR wrong number of arguments for 'lcs' command".to_string());
+        }
+
+        let mut get_len = false;
+        let mut get_idx = false;
+        let mut with_match_len = false;
+        let mut min_match_len: usize = 0;
+
+        let mut i = 2;
+        while i < self.args.len() {
+            let option = String::from_utf8_lossy(&self.args[i]).to_uppercase();
+            match option.as_str() {
+                "LEN" => get_len = true,
+                "IDX" => get_idx = true,
+                "WITHMATCHLEN" => with_match_len = true,
+                "MINMATCHLEN" if i + 1 < self.args.len() => {
+                    i += 1;
+                    match String::from_utf8_lossy(&self.args[i]).parse::<i64>() {
+                        Ok(n) => min_match_len = n.max(0) as usize,
+                        Err(_) => {
+                            return RESPValue::Error(
+                                "ERR value is not an integer or out of range".to_string(),
+                            );
+                        }
+                    }
+                }
+                _ => return RESPValue::Error("ERR syntax error".to_string()),
+            }
+            i += 1;
+        }
+
+        if get_len && get_idx {
+            return RESPValue::Error(
+                "ERR If you want both the length and indexes, please just use IDX.".to_string(),
+            );
+        }
+
+        let a = match store.get_string(&self.args[0]) {
+            Ok(value) => value.unwrap_or_default(),
+            Err(e) => return RESPValue::Error(e),
+        };
+        let b = match store.get_string(&self.args[1]) {
+            Ok(value) => value.unwrap_or_default(),
+            Err(e) => return RESPValue::Error(e),
+        };
+
+        let cells = (a.len() as u64 + 1) * (b.len() as u64 + 1);
+        if cells > LCS_MAX_CELLS {
+            return RESPValue::Error(
+                "ERR Insufficient memory, transient memory for LCS exceeds proto-max-bulk-len"
+                    .to_string(),
+            );
+        }
+
+        let lcs = Lcs::compute(&a, &b);
+        if get_len {
+            return RESPValue::Integer(lcs.len() as i64);
+        }
+        if !get_idx {
+            return RESPValue::BulkString(Some(lcs.subsequence()));
+        }
+
+        let matches = lcs
+            .ranges()
+            .into_iter()
+            .filter(|m| min_match_len == 0 || m.len >= min_match_len)
+            .map(|m| {
+                let range = |start: usize, end: usize| {
+                    RESPValue::Array(Some(vec![
+                        RESPValue::Integer(start as i64),
+                        RESPValue::Integer(end as i64),
+                    ]))
+                };
+                let mut entry = vec![
+                    range(m.a_start, m.a_start + m.len - 1),
+                    range(m.b_start, m.b_start + m.len - 1),
+                ];
+                if with_match_len {
+                    entry.push(RESPValue::Integer(m.len as i64));
+                }
+                RESPValue::Array(Some(entry))
+            })
+            .collect();
+
+        RESPValue::Array(Some(vec![
+            RESPValue::BulkString(Some(b"matches".to_vec())),
+            RESPValue::Array(Some(matches)),
+            RESPValue::BulkString(Some(b"len".to_vec())),
+            RESPValue::Integer(lcs.len() as i64),
+        ]))
+    }
+
     fn handle_set(&self, store: &Store) -> RESPValue {
         if self.args.len() < 2 {
             return RESPValue::Error("ERR wrong number of arguments for 'set' command".to_string());
         }
 
-        let key = String::from_utf8_lossy(&self.args[0]).to_string();
+        let key = &self.args[0];
         let value = self.args[1].clone();
 
-        // Check for EX, PX, EXAT, PXAT options
+        // Check for EX, PX, EXAT, PXAT and KEEPTTL options
         if self.args.len() > 2 {
             let option = String::from_utf8_lossy(&self.args[2]).to_uppercase();
-            match option.as_str() {
-                "EX" => {
-                    if self.args.len() < 4 {
-                        return RESPValue::Error("ERR syntax error".to_string());
-                    }
-                    let seconds = String::from_utf8_lossy(&self.args[3])
-                        .parse::<u64>()
-                        .unwrap_or(0);
-                    store.set_with_expiry(key, value, Duration::from_secs(seconds));
-                }
-                "PX" => {
-                    if self.args.len() < 4 {
-                        return RESPValue::Error("ERR syntax error".to_string());
-                    }
-                    let millis = String::from_utf8_lossy(&self.args[3])
-                        .parse::<u64>()
-                        .unwrap_or(0);
-                    store.set_with_expiry(key, value, Duration::from_millis(millis));
+            let unit = match option.as_str() {
+                "EX" => Some(ExpiryUnit::Seconds),
+                "PX" => Some(ExpiryUnit::Millis),
+                "EXAT" => Some(ExpiryUnit::UnixSeconds),
+                "PXAT" => Some(ExpiryUnit::UnixMillis),
+                _ => None,
+            };
+            match unit {
+                Some(_) if self.args.len() < 4 => {
+                    return RESPValue::Error("ERR syntax error".to_string());
                 }
-                _ => store.set(key, value),
+                Some(unit) => match parse_expiry(&self.args[3], unit, "set", store.now()) {
+                    Ok(expires_at) => store.set_with_expiry_at(key, value, expires_at),
+                    Err(e) => return e,
+                },
+                None if option == "KEEPTTL" => store.set_keep_ttl(key, value),
+                None => store.set(key, value),
             }
         } else {
             store.set(key, value);
@@ -121,18 +822,82 @@ impl Command {
         RESPValue::SimpleString("OK".to_string())
     }
 
-    fn handle_del(&self, store: &Store) -> RESPValue {
+    // SETEX key seconds value / PSETEX key milliseconds value
+    fn handle_setex(&self, store: &Store, unit: ExpiryUnit) -> RESPValue {
+        let name = self.name.to_lowercase();
+        if self.args.len() != 3 {
+            return RESPValue::Error(format!(
+                "ERR wrong number of arguments for '{}' command",
+                name
+            ));
+        }
+
+        let key = &self.args[0];
+        match parse_expiry(&self.args[1], unit, &name, store.now()) {
+            Ok(expires_at) => {
+                store.set_with_expiry_at(key, self.args[2].clone(), expires_at);
+                RESPValue::SimpleString("OK".to_string())
+            }
+            Err(e) => e,
+        }
+    }
+
+    // GETEX key [EX seconds | PX milliseconds | EXAT timestamp | PXAT timestamp | PERSIST]
+    fn handle_getex(&self, store: &Store) -> RESPValue {
         if self.args.is_empty() {
-            return RESPValue::Error("ERR wrong number of arguments for 'del' command".to_string());
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'getex' command".to_string(),
+            );
         }
 
-        let keys: Vec<String> = self
+        let key = &self.args[0];
+        let option = self
             .args
-            .iter()
-            .map(|k| String::from_utf8_lossy(k).to_string())
-            .collect();
+            .get(1)
+            .map(|o| String::from_utf8_lossy(o).to_uppercase());
+        let unit = match option.as_deref() {
+            None | Some("PERSIST") => None,
+            Some("EX") => Some(ExpiryUnit::Seconds),
+            Some("PX") => Some(ExpiryUnit::Millis),
+            Some("EXAT") => Some(ExpiryUnit::UnixSeconds),
+            Some("PXAT") => Some(ExpiryUnit::UnixMillis),
+            Some(_) => return RESPValue::Error("ERR syntax error".to_string()),
+        };
+        let expected_args = match (&option, unit) {
+            (None, _) => 1,
+            (Some(_), None) => 2,
+            (Some(_), Some(_)) => 3,
+        };
+        if self.args.len() != expected_args {
+            return RESPValue::Error("ERR syntax error".to_string());
+        }
+        let expires_at = match unit {
+            Some(unit) => match parse_expiry(&self.args[2], unit, "getex", store.now()) {
+                Ok(expires_at) => Some(expires_at),
+                Err(e) => return e,
+            },
+            None => None,
+        };
+
+        match store.get_string(key) {
+            Ok(Some(value)) => {
+                // With an option, EX/PX/EXAT/PXAT set the expiry and PERSIST clears it
+                if option.is_some() {
+                    store.set_expiry(key, expires_at);
+                }
+                RESPValue::BulkString(Some(value))
+            }
+            Ok(None) => RESPValue::BulkString(None),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    fn handle_del(&self, store: &Store) -> RESPValue {
+        if self.args.is_empty() {
+            return RESPValue::Error("ERR wrong number of arguments for 'del' command".to_string());
+        }
 
-        let count = store.del(&keys);
+        let count = store.del(&self.args);
         RESPValue::Integer(count as i64)
     }
 
@@ -143,34 +908,196 @@ impl Command {
             );
         }
 
-        let keys: Vec<String> = self
-            .args
-            .iter()
-            .map(|k| String::from_utf8_lossy(k).to_string())
-            .collect();
-
-        let count = store.exists(&keys);
+        let count = store.exists(&self.args);
         RESPValue::Integer(count as i64)
     }
 
-    fn handle_keys(&self, store: &Store) -> RESPValue {
+    fn handle_type(&self, store: &Store) -> RESPValue {
+        if self.args.len() != 1 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'type' command".to_string(),
+            );
+        }
+
+        let key = &self.args[0];
+        RESPValue::SimpleString(store.key_type(key).to_string())
+    }
+
+    fn handle_dump(&self, store: &Store) -> RESPValue {
+        if self.args.len() != 1 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'dump' command".to_string(),
+            );
+        }
+
+        let key = &self.args[0];
+        RESPValue::BulkString(store.dump(key))
+    }
+
+    // RESTORE key ttl serialized-value [REPLACE] [ABSTTL]. The TTL is in
+    // milliseconds, 0 for none; ABSTTL makes it a Unix time instead.
+    fn handle_restore(&self, store: &Store) -> RESPValue {
+        if self.args.len() < 3 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'restore' command".to_string(),
+            );
+        }
+
+        let key = &self.args[0];
+        let ttl = match parse_i64(&self.args[1]) {
+            Ok(ttl) if ttl < 0 => {
+                return RESPValue::Error("ERR Invalid TTL value, must be >= 0".to_string());
+            }
+            Ok(ttl) => ttl as u64,
+            Err(e) => return e,
+        };
+        let (mut replace, mut absolute) = (false, false);
+        for option in &self.args[3..] {
+            match String::from_utf8_lossy(option).to_uppercase().as_str() {
+                "REPLACE" => replace = true,
+                "ABSTTL" => absolute = true,
+                _ => return RESPValue::Error("ERR syntax error".to_string()),
+            }
+        }
+
+        let value = match snapshot::undump(&self.args[2]) {
+            Ok(value) => value,
+            Err(e) => return RESPValue::Error(e),
+        };
+        let expires_at = match (ttl, absolute) {
+            (0, _) => None,
+            (ms, true) => Some(UNIX_EPOCH + Duration::from_millis(ms)),
+            (ms, false) => Some(store.now() + Duration::from_millis(ms)),
+        };
+        match store.restore(key, value, expires_at, replace) {
+            Ok(()) => RESPValue::SimpleString("OK".to_string()),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    // SORT key [LIMIT offset count] [ASC|DESC] [ALPHA] [STORE destination].
+    // BY and GET patterns are not supported.
+    fn handle_sort(&self, store: &Store, deadline: Deadline) -> RESPValue {
+        if self.args.is_empty() {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'sort' command".to_string(),
+            );
+        }
+
+        let key = &self.args[0];
+        let mut options = SortOptions {
+            desc: false,
+            alpha: false,
+            limit: None,
+        };
+        let mut dest = None;
+        let mut i = 1;
+        while i < self.args.len() {
+            let option = String::from_utf8_lossy(&self.args[i]).to_uppercase();
+            match option.as_str() {
+                "ASC" => options.desc = false,
+                "DESC" => options.desc = true,
+                "ALPHA" => options.alpha = true,
+                "LIMIT" if i + 2 < self.args.len() => {
+                    match (parse_i64(&self.args[i + 1]), parse_i64(&self.args[i + 2])) {
+                        (Ok(offset), Ok(count)) => options.limit = Some((offset, count)),
+                        (Err(e), _) | (_, Err(e)) => return e,
+                    }
+                    i += 2;
+                }
+                "STORE" if i + 1 < self.args.len() => {
+                    dest = Some(&self.args[i + 1]);
+                    i += 1;
+                }
+                "BY" | "GET" => {
+                    return RESPValue::Error(format!(
+                        "ERR SORT {} patterns are not supported",
+                        option
+                    ));
+                }
+                _ => return RESPValue::Error("ERR syntax error".to_string()),
+            }
+            i += 1;
+        }
+
+        match dest {
+            Some(dest) => match store.sort_store(key, &options, dest, deadline) {
+                Ok(len) => RESPValue::Integer(len as i64),
+                Err(e) => RESPValue::Error(e),
+            },
+            None => match store.sort(key, &options, deadline) {
+                Ok(items) => RESPValue::Array(Some(
+                    items
+                        .into_iter()
+                        .map(|item| RESPValue::BulkString(Some(item.to_vec())))
+                        .collect(),
+                )),
+                Err(e) => RESPValue::Error(e),
+            },
+        }
+    }
+
+    fn handle_keys(&self, store: &Store, deadline: Deadline) -> RESPValue {
         if self.args.len() != 1 {
             return RESPValue::Error(
                 "ERR wrong number of arguments for 'keys' command".to_string(),
             );
         }
 
-        let pattern = String::from_utf8_lossy(&self.args[0]).to_string();
-        let keys = store.keys(&pattern);
+        let keys = match store.keys(&self.args[0], deadline) {
+            Ok(keys) => keys,
+            Err(e) => return RESPValue::Error(e),
+        };
 
         let resp_keys: Vec<RESPValue> = keys
             .into_iter()
-            .map(|k| RESPValue::BulkString(Some(k.into_bytes())))
+            .map(|k| RESPValue::BulkString(Some(k.to_vec())))
             .collect();
 
         RESPValue::Array(Some(resp_keys))
     }
 
+    // SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]
+    fn handle_scan(&self, store: &Store) -> RESPValue {
+        if self.args.is_empty() {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'scan' command".to_string(),
+            );
+        }
+        let Ok(cursor) = String::from_utf8_lossy(&self.args[0]).parse::<u64>() else {
+            return RESPValue::Error("ERR invalid cursor".to_string());
+        };
+
+        let mut count = SCAN_DEFAULT_COUNT;
+        let mut pattern = None;
+        let mut type_name = None;
+        for pair in self.args[1..].chunks(2) {
+            let [option, value] = pair else {
+                return RESPValue::Error("ERR syntax error".to_string());
+            };
+            match String::from_utf8_lossy(option).to_uppercase().as_str() {
+                "COUNT" => match parse_i64(value) {
+                    Ok(n) if n >= 1 => count = n as usize,
+                    Ok(_) => return RESPValue::Error("ERR syntax error".to_string()),
+                    Err(e) => return e,
+                },
+                "MATCH" => pattern = Some(value.as_slice()),
+                "TYPE" => type_name = Some(String::from_utf8_lossy(value).to_lowercase()),
+                _ => return RESPValue::Error("ERR syntax error".to_string()),
+            }
+        }
+
+        let (next, keys) = store.scan(cursor, count, pattern, type_name.as_deref());
+        RESPValue::Array(Some(vec![
+            RESPValue::BulkString(Some(next.to_string().into_bytes())),
+            RESPValue::Array(Some(
+                keys.into_iter()
+                    .map(|k| RESPValue::BulkString(Some(k.to_vec())))
+                    .collect(),
+            )),
+        ]))
+    }
+
     fn handle_incr(&self, store: &Store) -> RESPValue {
         if self.args.len() != 1 {
             return RESPValue::Error(
@@ -178,8 +1105,8 @@ impl Command {
             );
         }
 
-        let key = String::from_utf8_lossy(&self.args[0]).to_string();
-        match store.incr(&key) {
+        let key = &self.args[0];
+        match store.incr(key) {
             Ok(value) => RESPValue::Integer(value),
             Err(e) => RESPValue::Error(e),
         }
@@ -192,15 +1119,48 @@ impl Command {
             );
         }
 
-        let key = String::from_utf8_lossy(&self.args[0]).to_string();
-        match store.decr(&key) {
+        let key = &self.args[0];
+        match store.decr(key) {
+            Ok(value) => RESPValue::Integer(value),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    // INCRBY key increment / DECRBY key decrement
+    fn handle_incr_by(&self, store: &Store) -> RESPValue {
+        if self.args.len() != 2 {
+            return RESPValue::Error(format!(
+                "ERR wrong number of arguments for '{}' command",
+                self.name.to_lowercase()
+            ));
+        }
+
+        let key = &self.args[0];
+        let Ok(amount) = String::from_utf8_lossy(&self.args[1]).parse::<i64>() else {
+            return RESPValue::Error("ERR value is not an integer or out of range".to_string());
+        };
+        let result = if self.name == "INCRBY" {
+            store.incr_by(key, amount)
+        } else {
+            store.decr_by(key, amount)
+        };
+        match result {
             Ok(value) => RESPValue::Integer(value),
             Err(e) => RESPValue::Error(e),
         }
     }
 
+    // FLUSHDB and FLUSHALL [ASYNC|SYNC] are the same with one database.
+    // SYNC, the default, frees every value before replying; ASYNC empties
+    // the keyspace at once and frees the old one in the background.
     fn handle_flushdb(&self, store: &Store) -> RESPValue {
-        store.flush();
+        let lazy = match self.args.as_slice() {
+            [] => false,
+            [mode] if mode.eq_ignore_ascii_case(b"ASYNC") => true,
+            [mode] if mode.eq_ignore_ascii_case(b"SYNC") => false,
+            _ => return RESPValue::Error("ERR syntax error".to_string()),
+        };
+        store.flush(lazy);
         RESPValue::SimpleString("OK".to_string())
     }
 
@@ -216,67 +1176,160 @@ impl Command {
             );
         }
 
-        let key = String::from_utf8_lossy(&self.args[0]).to_string();
-        let seconds = String::from_utf8_lossy(&self.args[1])
-            .parse::<u64>()
-            .unwrap_or(0);
+        let key = &self.args[0];
+        let seconds = match parse_i64(&self.args[1]) {
+            Ok(seconds) => seconds,
+            Err(e) => return e,
+        };
 
-        if let Some(value) = store.get(&key) {
-            store.set_with_expiry(key, value, Duration::from_secs(seconds));
-            RESPValue::Integer(1)
-        } else {
-            RESPValue::Integer(0)
+        // A TTL that is already over deletes the key, like Redis
+        if seconds <= 0 {
+            return RESPValue::Integer(store.del(std::slice::from_ref(key)) as i64);
+        }
+        match parse_expiry(&self.args[1], ExpiryUnit::Seconds, "expire", store.now()) {
+            Ok(expires_at) => RESPValue::Integer(store.set_expiry(key, Some(expires_at)) as i64),
+            Err(e) => e,
         }
     }
 
-    fn handle_ttl(&self) -> RESPValue {
-        RESPValue::Integer(-1)
-    }
-
-    fn handle_lpush(&self, store: &Store) -> RESPValue {
-        if self.args.len() < 2 {
+    fn handle_pexpireat(&self, store: &Store) -> RESPValue {
+        if self.args.len() != 2 {
             return RESPValue::Error(
-                "ERR wrong number of arguments for 'lpush' command".to_string(),
+                "ERR wrong number of arguments for 'pexpireat' command".to_string(),
             );
         }
 
-        let key = String::from_utf8_lossy(&self.args[0]).to_string();
-        let values: Vec<Vec<u8>> = self.args[1..].to_vec();
+        let key = &self.args[0];
+        let millis = match parse_i64(&self.args[1]) {
+            // Timestamps before the epoch are simply in the past
+            Ok(millis) => millis.max(0) as u64,
+            Err(e) => return e,
+        };
 
-        let len = store.lpush(&key, values);
-        RESPValue::Integer(len as i64)
+        let expires_at = UNIX_EPOCH + Duration::from_millis(millis);
+        RESPValue::Integer(store.set_expiry(key, Some(expires_at)) as i64)
     }
 
-    fn handle_rpush(&self, store: &Store) -> RESPValue {
-        if self.args.len() < 2 {
-            return RESPValue::Error(
-                "ERR wrong number of arguments for 'rpush' command".to_string(),
-            );
+    // TTL / PTTL: the time left to live, -1 if the key has no expiry and -2
+    // if it doesn't exist
+    fn handle_ttl(&self, store: &Store, millis_per_unit: u64) -> RESPValue {
+        if self.args.len() != 1 {
+            return RESPValue::Error(format!(
+                "ERR wrong number of arguments for '{}' command",
+                self.name.to_lowercase()
+            ));
         }
 
-        let key = String::from_utf8_lossy(&self.args[0]).to_string();
-        let values: Vec<Vec<u8>> = self.args[1..].to_vec();
-
-        let len = store.rpush(&key, values);
-        RESPValue::Integer(len as i64)
+        let key = &self.args[0];
+        match store.get_ttl_ms(key) {
+            None => RESPValue::Integer(-2),
+            Some(None) => RESPValue::Integer(-1),
+            // Rounded to the nearest unit, like Redis
+            Some(Some(ms)) => {
+                RESPValue::Integer(((ms + millis_per_unit / 2) / millis_per_unit) as i64)
+            }
+        }
     }
 
-    fn handle_lpop(&self, store: &Store) -> RESPValue {
+    fn handle_persist(&self, store: &Store) -> RESPValue {
         if self.args.len() != 1 {
             return RESPValue::Error(
-                "ERR wrong number of arguments for 'lpop' command".to_string(),
+                "ERR wrong number of arguments for 'persist' command".to_string(),
             );
         }
 
-        let key = String::from_utf8_lossy(&self.args[0]).to_string();
+        let key = &self.args[0];
+        RESPValue::Integer(store.persist(key) as i64)
+    }
 
-        match store.lpop(&key) {
+    // EXPIRETIME / PEXPIRETIME: the absolute unix time the key expires at,
+    // -1 if it has no expiry and -2 if it doesn't exist
+    fn handle_expiretime(&self, store: &Store, millis_per_unit: u64) -> RESPValue {
+        if self.args.len() != 1 {
+            return RESPValue::Error(format!(
+                "ERR wrong number of arguments for '{}' command",
+                self.name.to_lowercase()
+            ));
+        }
+
+        let key = &self.args[0];
+        match store.expiry(key) {
+            None => RESPValue::Integer(-2),
+            Some(None) => RESPValue::Integer(-1),
+            Some(Some(at)) => RESPValue::Integer((unix_millis(at) / millis_per_unit) as i64),
+        }
+    }
+
+    fn handle_lpush(&self, store: &Store) -> RESPValue {
+        if self.args.len() < 2 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'lpush' command".to_string(),
+            );
+        }
+
+        let key = &self.args[0];
+        let values: Vec<Vec<u8>> = self.args[1..].to_vec();
+
+        let len = store.lpush(key, values);
+        RESPValue::Integer(len as i64)
+    }
+
+    fn handle_rpush(&self, store: &Store) -> RESPValue {
+        if self.args.len() < 2 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'rpush' command".to_string(),
+            );
+        }
+
+        let key = &self.args[0];
+        let values: Vec<Vec<u8>> = self.args[1..].to_vec();
+
+        let len = store.rpush(key, values);
+        RESPValue::Integer(len as i64)
+    }
+
+    fn handle_lpop(&self, store: &Store) -> RESPValue {
+        if self.args.len() != 1 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'lpop' command".to_string(),
+            );
+        }
+
+        let key = &self.args[0];
+
+        match store.lpop(key) {
             Ok(Some(value)) => RESPValue::BulkString(Some(value)),
             Ok(None) => RESPValue::BulkString(None),
             Err(e) => RESPValue::Error(e),
         }
     }
 
+    // BLPOP/BRPOP key [key ...] timeout: the keys, and how long to block
+    // (None meaning forever)
+    pub fn blocking_pop_args(&self) -> Result<(Vec<Vec<u8>>, Option<Duration>), RESPValue> {
+        if self.args.len() < 2 {
+            return Err(RESPValue::Error(format!(
+                "ERR wrong number of arguments for '{}' command",
+                self.name.to_lowercase()
+            )));
+        }
+
+        let (timeout, keys) = self.args.split_last().unwrap();
+        let timeout = match String::from_utf8_lossy(timeout).parse::<f64>() {
+            Ok(t) if t.is_finite() => t,
+            _ => {
+                return Err(RESPValue::Error(
+                    "ERR timeout is not a float or out of range".to_string(),
+                ));
+            }
+        };
+        if timeout < 0.0 {
+            return Err(RESPValue::Error("ERR timeout is negative".to_string()));
+        }
+        let timeout = (timeout > 0.0).then(|| Duration::from_secs_f64(timeout));
+        Ok((keys.to_vec(), timeout))
+    }
+
     fn handle_rpop(&self, store: &Store) -> RESPValue {
         if self.args.len() != 1 {
             return RESPValue::Error(
@@ -284,35 +1337,33 @@ impl Command {
             );
         }
 
-        let key = String::from_utf8_lossy(&self.args[0]).to_string();
+        let key = &self.args[0];
 
-        match store.rpop(&key) {
+        match store.rpop(key) {
             Ok(Some(value)) => RESPValue::BulkString(Some(value)),
             Ok(None) => RESPValue::BulkString(None),
             Err(e) => RESPValue::Error(e),
         }
     }
 
-    fn handle_lrange(&self, store: &Store) -> RESPValue {
+    fn handle_lrange(&self, store: &Store, deadline: Deadline) -> RESPValue {
         if self.args.len() != 3 {
             return RESPValue::Error(
                 "ERR wrong number of arguments for 'lrange' command".to_string(),
             );
         }
 
-        let key = String::from_utf8_lossy(&self.args[0]).to_string();
-        let start = String::from_utf8_lossy(&self.args[1])
-            .parse::<i64>()
-            .unwrap_or(0);
-        let stop = String::from_utf8_lossy(&self.args[2])
-            .parse::<i64>()
-            .unwrap_or(-1);
+        let key = &self.args[0];
+        let (start, stop) = match (parse_i64(&self.args[1]), parse_i64(&self.args[2])) {
+            (Ok(start), Ok(stop)) => (start, stop),
+            (Err(e), _) | (_, Err(e)) => return e,
+        };
 
-        match store.lrange(&key, start, stop) {
+        match store.lrange(key, start, stop, deadline) {
             Ok(values) => {
                 let resp_values: Vec<RESPValue> = values
                     .into_iter()
-                    .map(|v| RESPValue::BulkString(Some(v)))
+                    .map(|v| RESPValue::BulkString(Some(v.to_vec())))
                     .collect();
                 RESPValue::Array(Some(resp_values))
             }
@@ -320,16 +1371,18 @@ impl Command {
         }
     }
 
-    fn handle_llen(&self, store: &Store) -> RESPValue {
+    // STRLEN, LLEN, SCARD, ZCARD and HLEN, for a value of type `type_name`
+    fn handle_len(&self, store: &Store, type_name: &str) -> RESPValue {
         if self.args.len() != 1 {
-            return RESPValue::Error(
-                "ERR wrong number of arguments for 'llen' command".to_string(),
-            );
+            return RESPValue::Error(format!(
+                "ERR wrong number of arguments for '{}' command",
+                self.name.to_lowercase()
+            ));
         }
 
-        let key = String::from_utf8_lossy(&self.args[0]).to_string();
+        let key = &self.args[0];
 
-        match store.llen(&key) {
+        match store.len(key, type_name) {
             Ok(len) => RESPValue::Integer(len as i64),
             Err(e) => RESPValue::Error(e),
         }
@@ -342,15 +1395,2027 @@ impl Command {
             );
         }
 
-        let key = String::from_utf8_lossy(&self.args[0]).to_string();
-        let index = String::from_utf8_lossy(&self.args[1])
-            .parse::<i64>()
-            .unwrap_or(0);
+        let key = &self.args[0];
+        let index = match parse_i64(&self.args[1]) {
+            Ok(index) => index,
+            Err(e) => return e,
+        };
 
-        match store.lindex(&key, index) {
+        match store.lindex(key, index) {
             Ok(Some(value)) => RESPValue::BulkString(Some(value)),
             Ok(None) => RESPValue::BulkString(None),
             Err(e) => RESPValue::Error(e),
         }
     }
+
+    fn handle_lrem(&self, store: &Store) -> RESPValue {
+        if self.args.len() != 3 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'lrem' command".to_string(),
+            );
+        }
+
+        let key = &self.args[0];
+        let count = match parse_i64(&self.args[1]) {
+            Ok(count) => count,
+            Err(e) => return e,
+        };
+
+        match store.lrem(key, count, &self.args[2]) {
+            Ok(removed) => RESPValue::Integer(removed as i64),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    fn handle_ltrim(&self, store: &Store) -> RESPValue {
+        if self.args.len() != 3 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'ltrim' command".to_string(),
+            );
+        }
+
+        let key = &self.args[0];
+        let (start, stop) = match (parse_i64(&self.args[1]), parse_i64(&self.args[2])) {
+            (Ok(start), Ok(stop)) => (start, stop),
+            (Err(e), _) | (_, Err(e)) => return e,
+        };
+
+        match store.ltrim(key, start, stop) {
+            Ok(()) => RESPValue::SimpleString("OK".to_string()),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    fn handle_sadd(&self, store: &Store) -> RESPValue {
+        if self.args.len() < 2 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'sadd' command".to_string(),
+            );
+        }
+
+        let key = &self.args[0];
+
+        match store.sadd(key, self.args[1..].to_vec()) {
+            Ok(added) => RESPValue::Integer(added as i64),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    fn handle_srem(&self, store: &Store) -> RESPValue {
+        if self.args.len() < 2 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'srem' command".to_string(),
+            );
+        }
+
+        let key = &self.args[0];
+
+        match store.srem(key, &self.args[1..]) {
+            Ok(removed) => RESPValue::Integer(removed as i64),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    fn handle_smembers(&self, store: &Store, deadline: Deadline) -> RESPValue {
+        if self.args.len() != 1 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'smembers' command".to_string(),
+            );
+        }
+
+        let key = &self.args[0];
+
+        match store.smembers(key, deadline) {
+            Ok(members) => RESPValue::Array(Some(
+                members
+                    .into_iter()
+                    .map(|m| RESPValue::BulkString(Some(m.to_vec())))
+                    .collect(),
+            )),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    fn handle_srandmember(&self, store: &Store) -> RESPValue {
+        let count = match random_count_args(&self.args, None, "srandmember") {
+            Ok(count) => count,
+            Err(e) => return e,
+        };
+        let key = &self.args[0];
+
+        match store.srandmember(key, count.map_or(1, |(count, _)| count)) {
+            Ok(members) if count.is_none() => {
+                RESPValue::BulkString(members.into_iter().next().map(|m| m.to_vec()))
+            }
+            Ok(members) => RESPValue::Array(Some(
+                members
+                    .into_iter()
+                    .map(|m| RESPValue::BulkString(Some(m.to_vec())))
+                    .collect(),
+            )),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    fn handle_sismember(&self, store: &Store) -> RESPValue {
+        if self.args.len() != 2 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'sismember' command".to_string(),
+            );
+        }
+
+        let key = &self.args[0];
+
+        match store.sismember(key, &self.args[1]) {
+            Ok(found) => RESPValue::Integer(found as i64),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    fn handle_smismember(&self, store: &Store) -> RESPValue {
+        if self.args.len() < 2 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'smismember' command".to_string(),
+            );
+        }
+
+        let key = &self.args[0];
+
+        match store.smismember(key, &self.args[1..]) {
+            Ok(found) => RESPValue::Array(Some(
+                found
+                    .into_iter()
+                    .map(|f| RESPValue::Integer(f as i64))
+                    .collect(),
+            )),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    // ZADD key [NX | XX] [GT | LT] [CH] [INCR] score member [score member ...]
+    fn handle_zadd(&self, store: &Store) -> RESPValue {
+        if self.args.len() < 3 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'zadd' command".to_string(),
+            );
+        }
+
+        let key = &self.args[0];
+        let mut options = ZAddOptions::default();
+        let (mut ch, mut incr) = (false, false);
+        let mut i = 1;
+        while i < self.args.len() {
+            match String::from_utf8_lossy(&self.args[i])
+                .to_uppercase()
+                .as_str()
+            {
+                "NX" => options.nx = true,
+                "XX" => options.xx = true,
+                "GT" => options.gt = true,
+                "LT" => options.lt = true,
+                "CH" => ch = true,
+                "INCR" => incr = true,
+                _ => break,
+            }
+            i += 1;
+        }
+
+        let pairs = &self.args[i..];
+        if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+            return RESPValue::Error("ERR syntax error".to_string());
+        }
+        if options.nx && options.xx {
+            return RESPValue::Error(
+                "ERR XX and NX options at the same time are not compatible".to_string(),
+            );
+        }
+        if [options.nx, options.gt, options.lt]
+            .iter()
+            .filter(|&&set| set)
+            .count()
+            > 1
+        {
+            return RESPValue::Error(
+                "ERR GT, LT, and/or NX options at the same time are not compatible".to_string(),
+            );
+        }
+        if incr && pairs.len() > 2 {
+            return RESPValue::Error(
+                "ERR INCR option supports a single increment-element pair".to_string(),
+            );
+        }
+
+        let mut entries = Vec::with_capacity(pairs.len() / 2);
+        for pair in pairs.chunks(2) {
+            match zset::parse_score(&pair[0]) {
+                Some(score) => entries.push((score, pair[1].clone())),
+                None => return RESPValue::Error("ERR value is not a valid float".to_string()),
+            }
+        }
+
+        if incr {
+            let (increment, member) = entries.pop().unwrap();
+            return match store.zadd_incr(key, increment, &member, options) {
+                Ok(score) => score_reply(score),
+                Err(e) => RESPValue::Error(e),
+            };
+        }
+        match store.zadd(key, entries, options) {
+            Ok((added, changed)) => RESPValue::Integer(if ch { changed } else { added } as i64),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    fn handle_zrem(&self, store: &Store) -> RESPValue {
+        if self.args.len() < 2 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'zrem' command".to_string(),
+            );
+        }
+
+        let key = &self.args[0];
+
+        match store.zrem(key, &self.args[1..]) {
+            Ok(removed) => RESPValue::Integer(removed as i64),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    fn handle_zscore(&self, store: &Store) -> RESPValue {
+        if self.args.len() != 2 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'zscore' command".to_string(),
+            );
+        }
+
+        let key = &self.args[0];
+
+        match store.zscore(key, &self.args[1]) {
+            Ok(score) => score_reply(score),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    fn handle_zrandmember(&self, store: &Store) -> RESPValue {
+        let count = match random_count_args(&self.args, Some("WITHSCORES"), "zrandmember") {
+            Ok(count) => count,
+            Err(e) => return e,
+        };
+        let key = &self.args[0];
+
+        match store.zrandmember(key, count.map_or(1, |(count, _)| count)) {
+            Ok(members) => match count {
+                None => RESPValue::BulkString(members.into_iter().next().map(|(m, _)| m.to_vec())),
+                Some((_, with_scores)) => RESPValue::Array(Some(
+                    members
+                        .into_iter()
+                        .flat_map(|(member, score)| {
+                            let mut reply = vec![RESPValue::BulkString(Some(member.to_vec()))];
+                            if with_scores {
+                                reply.push(score_reply(Some(score)));
+                            }
+                            reply
+                        })
+                        .collect(),
+                )),
+            },
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    fn handle_zmscore(&self, store: &Store) -> RESPValue {
+        if self.args.len() < 2 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'zmscore' command".to_string(),
+            );
+        }
+
+        let key = &self.args[0];
+
+        match store.zmscore(key, &self.args[1..]) {
+            Ok(scores) => RESPValue::Array(Some(scores.into_iter().map(score_reply).collect())),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    fn handle_zrange(&self, store: &Store) -> RESPValue {
+        let with_scores = match self.args.len() {
+            3 => false,
+            4 if String::from_utf8_lossy(&self.args[3]).eq_ignore_ascii_case("WITHSCORES") => true,
+            4 => return RESPValue::Error("ERR syntax error".to_string()),
+            _ => {
+                return RESPValue::Error(
+                    "ERR wrong number of arguments for 'zrange' command".to_string(),
+                );
+            }
+        };
+
+        let key = &self.args[0];
+        let (start, stop) = match (parse_i64(&self.args[1]), parse_i64(&self.args[2])) {
+            (Ok(start), Ok(stop)) => (start, stop),
+            (Err(e), _) | (_, Err(e)) => return e,
+        };
+
+        match store.zrange(key, start, stop) {
+            Ok(entries) => {
+                let mut values = Vec::new();
+                for (member, score) in entries {
+                    values.push(RESPValue::BulkString(Some(member.to_vec())));
+                    if with_scores {
+                        values.push(score_reply(Some(score)));
+                    }
+                }
+                RESPValue::Array(Some(values))
+            }
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    // ZUNIONSTORE / ZINTERSTORE dest numkeys key [key ...] [WEIGHTS w ...] [AGGREGATE SUM|MIN|MAX]
+    fn handle_zstore(&self, store: &Store, union: bool) -> RESPValue {
+        let name = self.name.to_lowercase();
+        if self.args.len() < 3 {
+            return RESPValue::Error(format!(
+                "ERR wrong number of arguments for '{}' command",
+                name
+            ));
+        }
+
+        let dest = &self.args[0];
+        let keys = match parse_numkeys(&self.args[1..], &name) {
+            Ok(keys) => keys,
+            Err(e) => return e,
+        };
+        let numkeys = keys.len();
+        let mut weights = vec![1.0; numkeys];
+        let mut aggregate = zset::Aggregate::Sum;
+
+        let mut i = 2 + numkeys;
+        while i < self.args.len() {
+            let option = String::from_utf8_lossy(&self.args[i]).to_uppercase();
+            match option.as_str() {
+                "WEIGHTS" if i + numkeys < self.args.len() => {
+                    for (j, weight) in weights.iter_mut().enumerate() {
+                        match zset::parse_score(&self.args[i + 1 + j]) {
+                            Some(w) => *weight = w,
+                            None => {
+                                return RESPValue::Error(
+                                    "ERR weight value is not a float".to_string(),
+                                );
+                            }
+                        }
+                    }
+                    i += 1 + numkeys;
+                }
+                "AGGREGATE" if i + 1 < self.args.len() => {
+                    aggregate = match String::from_utf8_lossy(&self.args[i + 1])
+                        .to_uppercase()
+                        .as_str()
+                    {
+                        "SUM" => zset::Aggregate::Sum,
+                        "MIN" => zset::Aggregate::Min,
+                        "MAX" => zset::Aggregate::Max,
+                        _ => return RESPValue::Error("ERR syntax error".to_string()),
+                    };
+                    i += 2;
+                }
+                _ => return RESPValue::Error("ERR syntax error".to_string()),
+            }
+        }
+
+        match store.zstore(dest, keys, &weights, aggregate, union) {
+            Ok(len) => RESPValue::Integer(len as i64),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    // ZDIFF numkeys key [key ...] [WITHSCORES]
+    fn handle_zdiff(&self, store: &Store) -> RESPValue {
+        if self.args.len() < 2 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'zdiff' command".to_string(),
+            );
+        }
+
+        let keys = match parse_numkeys(&self.args, "zdiff") {
+            Ok(keys) => keys,
+            Err(e) => return e,
+        };
+        let with_scores = match &self.args[1 + keys.len()..] {
+            [] => false,
+            [option] if String::from_utf8_lossy(option).eq_ignore_ascii_case("WITHSCORES") => true,
+            _ => return RESPValue::Error("ERR syntax error".to_string()),
+        };
+
+        match store.zdiff(keys) {
+            Ok(entries) => {
+                let mut values = Vec::new();
+                for (member, score) in entries {
+                    values.push(RESPValue::BulkString(Some(member.to_vec())));
+                    if with_scores {
+                        values.push(score_reply(Some(score)));
+                    }
+                }
+                RESPValue::Array(Some(values))
+            }
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    // ZDIFFSTORE dest numkeys key [key ...]
+    fn handle_zdiffstore(&self, store: &Store) -> RESPValue {
+        if self.args.len() < 3 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'zdiffstore' command".to_string(),
+            );
+        }
+
+        let dest = &self.args[0];
+        let keys = match parse_numkeys(&self.args[1..], "zdiffstore") {
+            Ok(keys) => keys,
+            Err(e) => return e,
+        };
+        if self.args.len() > 2 + keys.len() {
+            return RESPValue::Error("ERR syntax error".to_string());
+        }
+
+        match store.zdiffstore(dest, keys) {
+            Ok(len) => RESPValue::Integer(len as i64),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    // SINTERCARD / ZINTERCARD numkeys key [key ...] [LIMIT limit]
+    fn handle_intercard(&self, store: &Store, sorted: bool) -> RESPValue {
+        let name = self.name.to_lowercase();
+        if self.args.len() < 2 {
+            return RESPValue::Error(format!(
+                "ERR wrong number of arguments for '{}' command",
+                name
+            ));
+        }
+
+        let numkeys = match String::from_utf8_lossy(&self.args[0]).parse::<i64>() {
+            Ok(n) if n > 0 => n as usize,
+            _ => return RESPValue::Error("ERR numkeys should be greater than 0".to_string()),
+        };
+        if self.args.len() < 1 + numkeys {
+            return RESPValue::Error(
+                "ERR Number of keys can't be greater than number of args".to_string(),
+            );
+        }
+        let keys = &self.args[1..1 + numkeys];
+
+        let limit = match &self.args[1 + numkeys..] {
+            [] => 0,
+            [option, limit] if String::from_utf8_lossy(option).eq_ignore_ascii_case("LIMIT") => {
+                match String::from_utf8_lossy(limit).parse::<i64>() {
+                    Ok(n) if n >= 0 => n as usize,
+                    Ok(_) => return RESPValue::Error("ERR LIMIT can't be negative".to_string()),
+                    Err(_) => {
+                        return RESPValue::Error(
+                            "ERR value is not an integer or out of range".to_string(),
+                        );
+                    }
+                }
+            }
+            _ => return RESPValue::Error("ERR syntax error".to_string()),
+        };
+
+        let result = if sorted {
+            store.zintercard(keys, limit)
+        } else {
+            store.sintercard(keys, limit)
+        };
+        match result {
+            Ok(count) => RESPValue::Integer(count as i64),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    // HSET replies with how many fields were new; the older HMSET with OK
+    fn handle_hset(&self, store: &Store) -> RESPValue {
+        if self.args.len() < 3 || self.args.len().is_multiple_of(2) {
+            return RESPValue::Error(format!(
+                "ERR wrong number of arguments for '{}' command",
+                self.name.to_lowercase()
+            ));
+        }
+
+        let key = &self.args[0];
+        let pairs = self.args[1..]
+            .chunks(2)
+            .map(|pair| (pair[0].clone(), pair[1].clone()))
+            .collect();
+
+        match store.hset(key, pairs) {
+            Ok(_) if self.name == "HMSET" => RESPValue::SimpleString("OK".to_string()),
+            Ok(added) => RESPValue::Integer(added as i64),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    fn handle_hget(&self, store: &Store) -> RESPValue {
+        if self.args.len() != 2 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'hget' command".to_string(),
+            );
+        }
+
+        let key = &self.args[0];
+
+        match store.hget(key, &self.args[1]) {
+            Ok(value) => RESPValue::BulkString(value),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    fn handle_hdel(&self, store: &Store) -> RESPValue {
+        if self.args.len() < 2 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'hdel' command".to_string(),
+            );
+        }
+
+        let key = &self.args[0];
+
+        match store.hdel(key, &self.args[1..]) {
+            Ok(removed) => RESPValue::Integer(removed as i64),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    fn handle_hgetall(&self, store: &Store) -> RESPValue {
+        if self.args.len() != 1 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'hgetall' command".to_string(),
+            );
+        }
+
+        let key = &self.args[0];
+
+        match store.hgetall(key) {
+            Ok(pairs) => RESPValue::Array(Some(
+                pairs
+                    .into_iter()
+                    .flat_map(|(field, value)| {
+                        [
+                            RESPValue::BulkString(Some(field.to_vec())),
+                            RESPValue::BulkString(Some(value.to_vec())),
+                        ]
+                    })
+                    .collect(),
+            )),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    // HSCAN key cursor [MATCH pattern] [COUNT count] [NOVALUES]
+    fn handle_hscan(&self, store: &Store) -> RESPValue {
+        if self.args.len() < 2 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'hscan' command".to_string(),
+            );
+        }
+        let key = &self.args[0];
+        let Ok(cursor) = String::from_utf8_lossy(&self.args[1]).parse::<u64>() else {
+            return RESPValue::Error("ERR invalid cursor".to_string());
+        };
+
+        let mut count = SCAN_DEFAULT_COUNT;
+        let mut pattern = None;
+        let mut no_values = false;
+        let mut options = self.args[2..].iter();
+        while let Some(option) = options.next() {
+            match String::from_utf8_lossy(option).to_uppercase().as_str() {
+                "NOVALUES" => no_values = true,
+                "COUNT" => match options.next().map(|value| parse_i64(value)) {
+                    Some(Ok(n)) if n >= 1 => count = n as usize,
+                    Some(Err(e)) => return e,
+                    _ => return RESPValue::Error("ERR syntax error".to_string()),
+                },
+                "MATCH" => match options.next() {
+                    Some(value) => pattern = Some(value.as_slice()),
+                    None => return RESPValue::Error("ERR syntax error".to_string()),
+                },
+                _ => return RESPValue::Error("ERR syntax error".to_string()),
+            }
+        }
+
+        match store.hscan(key, cursor, count, pattern) {
+            Ok((next, fields)) => RESPValue::Array(Some(vec![
+                RESPValue::BulkString(Some(next.to_string().into_bytes())),
+                RESPValue::Array(Some(
+                    fields
+                        .into_iter()
+                        .flat_map(|(field, value)| {
+                            let mut reply = vec![RESPValue::BulkString(Some(field.to_vec()))];
+                            if !no_values {
+                                reply.push(RESPValue::BulkString(Some(value.to_vec())));
+                            }
+                            reply
+                        })
+                        .collect(),
+                )),
+            ])),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    // HKEYS and HVALS, in insertion order like HGETALL
+    fn handle_hkeys(&self, store: &Store, values: bool) -> RESPValue {
+        if self.args.len() != 1 {
+            return RESPValue::Error(format!(
+                "ERR wrong number of arguments for '{}' command",
+                self.name.to_lowercase()
+            ));
+        }
+
+        let key = &self.args[0];
+        match store.hgetall(key) {
+            Ok(pairs) => RESPValue::Array(Some(
+                pairs
+                    .into_iter()
+                    .map(|(field, value)| {
+                        let item = if values { value } else { field };
+                        RESPValue::BulkString(Some(item.to_vec()))
+                    })
+                    .collect(),
+            )),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    fn handle_hrandfield(&self, store: &Store) -> RESPValue {
+        let count = match random_count_args(&self.args, Some("WITHVALUES"), "hrandfield") {
+            Ok(count) => count,
+            Err(e) => return e,
+        };
+        let key = &self.args[0];
+
+        match store.hrandfield(key, count.map_or(1, |(count, _)| count)) {
+            Ok(pairs) => match count {
+                None => RESPValue::BulkString(pairs.into_iter().next().map(|(f, _)| f.to_vec())),
+                Some((_, with_values)) => RESPValue::Array(Some(
+                    pairs
+                        .into_iter()
+                        .flat_map(|(field, value)| {
+                            let mut reply = vec![RESPValue::BulkString(Some(field.to_vec()))];
+                            if with_values {
+                                reply.push(RESPValue::BulkString(Some(value.to_vec())));
+                            }
+                            reply
+                        })
+                        .collect(),
+                )),
+            },
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    fn handle_hexists(&self, store: &Store) -> RESPValue {
+        if self.args.len() != 2 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'hexists' command".to_string(),
+            );
+        }
+
+        let key = &self.args[0];
+
+        match store.hexists(key, &self.args[1]) {
+            Ok(exists) => RESPValue::Integer(exists as i64),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    // HEXPIRE key seconds FIELDS numfields field [field ...], and the
+    // HPEXPIRE / HPEXPIREAT variants. A time of 0 deletes the fields.
+    fn handle_hexpire(&self, store: &Store, unit: ExpiryUnit) -> RESPValue {
+        let command = self.name.to_lowercase();
+        if self.args.len() < 5 {
+            return RESPValue::Error(format!(
+                "ERR wrong number of arguments for '{}' command",
+                command
+            ));
+        }
+
+        let key = &self.args[0];
+        let expires_at = match parse_i64(&self.args[1]) {
+            Ok(0) => match unit {
+                ExpiryUnit::UnixSeconds | ExpiryUnit::UnixMillis => UNIX_EPOCH,
+                ExpiryUnit::Seconds | ExpiryUnit::Millis => store.now(),
+            },
+            Ok(_) => match parse_expiry(&self.args[1], unit, &command, store.now()) {
+                Ok(expires_at) => expires_at,
+                Err(e) => return e,
+            },
+            Err(e) => return e,
+        };
+        let fields = match parse_fields(&self.args[2..]) {
+            Ok(fields) => fields,
+            Err(e) => return e,
+        };
+
+        match store.hexpire_at(key, fields, expires_at) {
+            Ok(codes) => integer_array(codes),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    // HTTL key FIELDS numfields field [field ...]
+    fn handle_httl(&self, store: &Store) -> RESPValue {
+        if self.args.len() < 4 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'httl' command".to_string(),
+            );
+        }
+
+        let key = &self.args[0];
+        let fields = match parse_fields(&self.args[1..]) {
+            Ok(fields) => fields,
+            Err(e) => return e,
+        };
+
+        match store.hpttl(key, fields) {
+            // Round to the nearest second, like TTL
+            Ok(ttls) => integer_array(
+                ttls.into_iter()
+                    .map(|ms| if ms < 0 { ms } else { (ms + 500) / 1000 })
+                    .collect(),
+            ),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    // HPERSIST key FIELDS numfields field [field ...]
+    fn handle_hpersist(&self, store: &Store) -> RESPValue {
+        if self.args.len() < 4 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'hpersist' command".to_string(),
+            );
+        }
+
+        let key = &self.args[0];
+        let fields = match parse_fields(&self.args[1..]) {
+            Ok(fields) => fields,
+            Err(e) => return e,
+        };
+
+        match store.hpersist(key, fields) {
+            Ok(codes) => integer_array(codes),
+            Err(e) => RESPValue::Error(e),
+        }
+    }
+
+    // WAITAOF numlocal numreplicas timeout. Rudis has no AOF, so appendonly
+    // is always off: numlocal must be 0, and no replica ever acknowledges an
+    // fsync. Returns how long to block before replying [0, 0]: not at all
+    // when numreplicas is 0, and None for a timeout of 0, which blocks until
+    // the client leaves.
+    pub fn waitaof_timeout(
+        &self,
+        replication: &Replication,
+    ) -> Result<Option<Duration>, RESPValue> {
+        if self.args.len() != 3 {
+            return Err(RESPValue::Error(
+                "ERR wrong number of arguments for 'waitaof' command".to_string(),
+            ));
+        }
+        if replication.is_replica() {
+            return Err(RESPValue::Error(
+                "ERR WAITAOF cannot be used with replica instances. Please also note that writes to replicas are just local and are not propagated.".to_string(),
+            ));
+        }
+        let numlocal = parse_i64(&self.args[0])?;
+        let numreplicas = parse_i64(&self.args[1])?;
+        let timeout = String::from_utf8_lossy(&self.args[2])
+            .parse::<i64>()
+            .map_err(|_| {
+                RESPValue::Error("ERR timeout is not an integer or out of range".to_string())
+            })?;
+        if timeout < 0 {
+            return Err(RESPValue::Error("ERR timeout is negative".to_string()));
+        }
+        if numlocal != 0 {
+            return Err(RESPValue::Error(
+                "ERR WAITAOF cannot be used when numlocal is set but appendonly is disabled."
+                    .to_string(),
+            ));
+        }
+
+        if numreplicas <= 0 {
+            Ok(Some(Duration::ZERO))
+        } else if timeout == 0 {
+            Ok(None)
+        } else {
+            Ok(Some(Duration::from_millis(timeout as u64)))
+        }
+    }
+
+    // Inside MULTI, WAITAOF replies at once, like Redis
+    pub fn handle_waitaof(&self, replication: &Replication) -> RESPValue {
+        match self.waitaof_timeout(replication) {
+            Ok(_) => waitaof_reply(),
+            Err(e) => e,
+        }
+    }
+
+    pub fn handle_replicaof(&self, replication: &Replication, store: &Store) -> RESPValue {
+        if self.args.len() != 2 {
+            return RESPValue::Error(format!(
+                "ERR wrong number of arguments for '{}' command",
+                self.name.to_lowercase()
+            ));
+        }
+
+        let host = String::from_utf8_lossy(&self.args[0]).to_string();
+        let port = String::from_utf8_lossy(&self.args[1]).to_string();
+
+        if host.eq_ignore_ascii_case("no") && port.eq_ignore_ascii_case("one") {
+            replication.promote();
+            return RESPValue::SimpleString("OK".to_string());
+        }
+
+        let port = match port.parse::<u16>() {
+            Ok(port) => port,
+            Err(_) => return RESPValue::Error("ERR Invalid master port".to_string()),
+        };
+
+        if replication.replicate_of(host, port, store) {
+            RESPValue::SimpleString("OK".to_string())
+        } else {
+            RESPValue::SimpleString("OK Already connected to specified master".to_string())
+        }
+    }
+
+    // DEBUG SLEEP seconds, which may be fractional. None for every other
+    // DEBUG subcommand.
+    pub fn debug_sleep(&self) -> Option<Result<Duration, RESPValue>> {
+        if !self.args.first()?.eq_ignore_ascii_case(b"SLEEP") {
+            return None;
+        }
+        if self.args.len() != 2 {
+            return Some(Err(RESPValue::Error(
+                "ERR wrong number of arguments for 'debug|sleep' command".to_string(),
+            )));
+        }
+        Some(
+            String::from_utf8_lossy(&self.args[1])
+                .parse::<f64>()
+                .ok()
+                .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                .ok_or_else(|| RESPValue::Error("ERR value is not a valid float".to_string())),
+        )
+    }
+
+    pub fn handle_debug(
+        &self,
+        store: &Store,
+        config: &Config,
+        stats: &Stats,
+        replication: &Replication,
+        resp3: bool,
+    ) -> RESPValue {
+        if self.args.is_empty() {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'debug' command".to_string(),
+            );
+        }
+
+        let subcommand = String::from_utf8_lossy(&self.args[0]).to_uppercase();
+        match subcommand.as_str() {
+            // A sample of the RESP3 types that have a RESP2 fallback, for
+            // testing clients
+            "PROTOCOL" if self.args.len() == 2 => {
+                match String::from_utf8_lossy(&self.args[1]).to_lowercase().as_str() {
+                    "bignum" => {
+                        let n = "1234567999999999999999999999999999999";
+                        if resp3 {
+                            RESPValue::BigNumber(n.to_string())
+                        } else {
+                            RESPValue::BulkString(Some(n.as_bytes().to_vec()))
+                        }
+                    }
+                    "verbatim" => RESPValue::text(b"This is a verbatim\nstring".to_vec(), resp3),
+                    _ => RESPValue::Error(
+                        "ERR Wrong protocol type name. Please use one of the following: bignum|verbatim"
+                            .to_string(),
+                    ),
+                }
+            }
+            // Redis' layout; there is no object address, refcount or LRU clock
+            // value to report, only the idle time, and the quicklist fields
+            // are computed, not measured
+            "OBJECT" if self.args.len() == 2 => {
+                let key = &self.args[1];
+                let limits = config.encoding_limits();
+                let (Some(encoding), Some(serialized_len), Some(idle)) = (
+                    store.encoding(key, &limits),
+                    store.serialized_len(key),
+                    store.idle_time(key),
+                ) else {
+                    return RESPValue::Error("ERR no such key".to_string());
+                };
+
+                let mut info = format!(
+                    "Value at:0x0 refcount:1 encoding:{} serializedlength:{} lru:0 lru_seconds_idle:{}",
+                    encoding, serialized_len, idle
+                );
+                if encoding == "quicklist"
+                    && let Some((nodes, len, bytes)) = store.quicklist_nodes(key, &limits)
+                {
+                    info.push_str(&format!(
+                        " ql_nodes:{} ql_avg_node:{:.2} ql_listpack_max:{} ql_compressed:0 ql_uncompressed_size:{}",
+                        nodes,
+                        len as f64 / nodes as f64,
+                        limits.list_max_listpack_size,
+                        bytes
+                    ));
+                }
+                RESPValue::SimpleString(info)
+            }
+            "OBJECT" => RESPValue::Error(
+                "ERR wrong number of arguments for 'debug|object' command".to_string(),
+            ),
+            // The [first, last] index of each node Redis' quicklist would
+            // split the list into; derived from the flat list like ql_nodes
+            "LISTPACK-ENTRIES" if self.args.len() == 2 => {
+                let key = &self.args[1];
+                match store.quicklist_chunks(key, &config.encoding_limits()) {
+                    None => RESPValue::Error("ERR no such key".to_string()),
+                    Some(Err(e)) => RESPValue::Error(e),
+                    Some(Ok(chunks)) => {
+                        let mut first = 0;
+                        let nodes = chunks
+                            .into_iter()
+                            .map(|len| {
+                                let node = RESPValue::Array(Some(vec![
+                                    RESPValue::Integer(first as i64),
+                                    RESPValue::Integer((first + len - 1) as i64),
+                                ]));
+                                first += len;
+                                node
+                            })
+                            .collect();
+                        RESPValue::Array(Some(nodes))
+                    }
+                }
+            }
+            "LISTPACK-ENTRIES" => RESPValue::Error(
+                "ERR wrong number of arguments for 'debug|listpack-entries' command".to_string(),
+            ),
+            // A connection sleeps in serve_client without blocking anyone else.
+            // Run by EXEC it replies at once, since EXEC holds the exec lock.
+            "SLEEP" => match self.debug_sleep() {
+                Some(Err(e)) => e,
+                _ => RESPValue::SimpleString("OK".to_string()),
+            },
+            "RELOAD" => {
+                let path = Path::new(&config.dbfilename);
+                let result = store.reload(|data| {
+                    let bytes = snapshot::encode_entries(data.iter());
+                    let saved = snapshot::save_to_file(&bytes, path);
+                    stats.record_save(saved.is_ok());
+                    saved?;
+                    snapshot::load_from_file(path)
+                });
+
+                match result {
+                    Ok(()) => RESPValue::SimpleString("OK".to_string()),
+                    Err(e) => {
+                        RESPValue::Error(format!("ERR Error trying to load the snapshot: {}", e))
+                    }
+                }
+            }
+            "DUMPJSON" => {
+                let mut offset = 0;
+                let mut count = DUMPJSON_DEFAULT_COUNT;
+                for pair in self.args[1..].chunks(2) {
+                    let [option, value] = pair else {
+                        return RESPValue::Error("ERR syntax error".to_string());
+                    };
+                    let value = match parse_i64(value) {
+                        Ok(value) if value >= 0 => value as usize,
+                        Ok(_) => {
+                            return RESPValue::Error(
+                                "ERR value is out of range, must be positive".to_string(),
+                            );
+                        }
+                        Err(e) => return e,
+                    };
+                    match String::from_utf8_lossy(option).to_uppercase().as_str() {
+                        "OFFSET" => offset = value,
+                        "COUNT" => count = value.min(DUMPJSON_MAX_COUNT),
+                        _ => return RESPValue::Error("ERR syntax error".to_string()),
+                    }
+                }
+                let (page, total) = store.page_by_name(offset, count);
+                let dump = json::dump(page, offset, count, total);
+                RESPValue::BulkString(Some(dump.into_bytes()))
+            }
+            // DEBUG POPULATE count [prefix] [size]
+            "POPULATE" if (2..=4).contains(&self.args.len()) => {
+                let positive = |arg: &[u8]| match parse_i64(arg) {
+                    Ok(n) if n >= 0 => Ok(n as usize),
+                    Ok(_) => Err(RESPValue::Error(
+                        "ERR value is out of range, must be positive".to_string(),
+                    )),
+                    Err(e) => Err(e),
+                };
+                let count = match positive(&self.args[1]) {
+                    Ok(count) => count,
+                    Err(e) => return e,
+                };
+                let prefix = self
+                    .args
+                    .get(2)
+                    .map(|p| String::from_utf8_lossy(p).to_string())
+                    .unwrap_or_else(|| "key:".to_string());
+                let size = match self.args.get(3).map(|s| positive(s)).transpose() {
+                    Ok(size) => size,
+                    Err(e) => return e,
+                };
+
+                let created = store.populate(count, &prefix, size);
+                stats.add_dirty(created as u64);
+                RESPValue::SimpleString("OK".to_string())
+            }
+            "POPULATE" => RESPValue::Error(
+                "ERR wrong number of arguments for 'debug|populate' command".to_string(),
+            ),
+            "SET-ACTIVE-EXPIRE" if self.args.len() == 2 => {
+                match String::from_utf8_lossy(&self.args[1]).as_ref() {
+                    "0" => config.set_active_expire(false),
+                    "1" => config.set_active_expire(true),
+                    _ => return RESPValue::Error("ERR syntax error".to_string()),
+                }
+                RESPValue::SimpleString("OK".to_string())
+            }
+            "SET-ACTIVE-EXPIRE" => RESPValue::Error(
+                "ERR wrong number of arguments for 'debug|set-active-expire' command".to_string(),
+            ),
+            // Redis' jemalloc heap dump; there is no allocator state to report
+            "JMAP" => RESPValue::SimpleString("OK".to_string()),
+            "CHANGE-REPL-ID" => {
+                replication.change_replid();
+                RESPValue::SimpleString("OK".to_string())
+            }
+            _ => RESPValue::Error(format!(
+                "ERR unknown subcommand '{}'. Try DEBUG HELP.",
+                String::from_utf8_lossy(&self.args[0])
+            )),
+        }
+    }
+
+    pub fn handle_save(&self, store: &Store, config: &Config, stats: &Stats) -> RESPValue {
+        if !self.args.is_empty() {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'save' command".to_string(),
+            );
+        }
+        if stats.bgsave_in_progress() {
+            return RESPValue::Error("ERR Background save already in progress".to_string());
+        }
+
+        match persistence::save(store, config, stats) {
+            Ok(()) => RESPValue::SimpleString("OK".to_string()),
+            Err(e) => RESPValue::Error(format!("ERR Error saving the snapshot: {}", e)),
+        }
+    }
+
+    pub fn handle_bgsave(
+        &self,
+        store: &Store,
+        config: &Arc<Config>,
+        stats: &Arc<Stats>,
+    ) -> RESPValue {
+        if !self.args.is_empty() {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'bgsave' command".to_string(),
+            );
+        }
+
+        if persistence::spawn_bgsave(store.clone(), config.clone(), stats.clone()) {
+            RESPValue::SimpleString("Background saving started".to_string())
+        } else {
+            RESPValue::Error("ERR Background save already in progress".to_string())
+        }
+    }
+
+    pub fn handle_bgrewriteaof(
+        &self,
+        store: &Store,
+        replication: &Replication,
+        config: &Arc<Config>,
+        stats: &Arc<Stats>,
+    ) -> RESPValue {
+        if !self.args.is_empty() {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'bgrewriteaof' command".to_string(),
+            );
+        }
+
+        if persistence::spawn_aof_rewrite(store, replication, config.clone(), stats.clone()) {
+            RESPValue::SimpleString("Background append only file rewriting started".to_string())
+        } else {
+            RESPValue::Error(
+                "ERR Background append only file rewriting already in progress".to_string(),
+            )
+        }
+    }
+
+    pub fn handle_lastsave(&self, stats: &Stats) -> RESPValue {
+        if !self.args.is_empty() {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'lastsave' command".to_string(),
+            );
+        }
+        RESPValue::Integer(stats.last_save_time() as i64)
+    }
+
+    // Only the parameters Rudis can report or change at runtime
+    pub fn handle_config(&self, config: &Config) -> RESPValue {
+        if self.args.is_empty() {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'config' command".to_string(),
+            );
+        }
+
+        let subcommand = String::from_utf8_lossy(&self.args[0]).to_uppercase();
+        match subcommand.as_str() {
+            "GET" if self.args.len() == 2 => {
+                let mut reply = Vec::new();
+                for (name, value) in config.get_parameters(&self.args[1]) {
+                    reply.push(RESPValue::BulkString(Some(name.into_bytes())));
+                    reply.push(RESPValue::BulkString(Some(value.into_bytes())));
+                }
+                RESPValue::Array(Some(reply))
+            }
+            "SET" if self.args.len() == 3 => {
+                let name = String::from_utf8_lossy(&self.args[1]).to_lowercase();
+                let value = String::from_utf8_lossy(&self.args[2]);
+                match config.set_parameter(&name, &value) {
+                    Ok(()) => RESPValue::SimpleString("OK".to_string()),
+                    Err(e) => RESPValue::Error(e),
+                }
+            }
+            "GET" | "SET" => RESPValue::Error(format!(
+                "ERR wrong number of arguments for 'config|{}' command",
+                subcommand.to_lowercase()
+            )),
+            _ => RESPValue::Error(format!(
+                "ERR unknown subcommand '{}'. Try CONFIG HELP.",
+                String::from_utf8_lossy(&self.args[0])
+            )),
+        }
+    }
+
+    // Liveness summary for monitoring sidecars, one key=value per line
+    pub fn handle_health(&self, stats: &Stats, replication: &Replication) -> RESPValue {
+        if !self.args.is_empty() {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'health' command".to_string(),
+            );
+        }
+
+        let role = if replication.is_replica() {
+            "replica"
+        } else {
+            "master"
+        };
+        let persistence = if stats.last_save_ok() { "ok" } else { "error" };
+
+        let health = format!(
+            "status=ok\nrole={}\nuptime_seconds={}\nconnected_clients={}\npersistence={}\nlast_save_time={}\n",
+            role,
+            stats.uptime_secs(),
+            stats.connected_clients(),
+            persistence,
+            stats.last_save_time()
+        );
+        RESPValue::BulkString(Some(health.into_bytes()))
+    }
+
+    // FAILOVER [TO host port [FORCE]] [ABORT] [TIMEOUT ms]. Coordinated
+    // failover isn't implemented, so this only reports why it can't start.
+    pub fn handle_failover(&self, replication: &Replication) -> RESPValue {
+        let mut abort = false;
+        let mut i = 0;
+        while i < self.args.len() {
+            let option = String::from_utf8_lossy(&self.args[i]).to_uppercase();
+            match option.as_str() {
+                "ABORT" => abort = true,
+                "FORCE" => {}
+                "TO" if i + 2 < self.args.len() => {
+                    if parse_i64(&self.args[i + 2]).is_err() {
+                        return RESPValue::Error("ERR syntax error".to_string());
+                    }
+                    i += 2;
+                }
+                "TIMEOUT" if i + 1 < self.args.len() => {
+                    match parse_i64(&self.args[i + 1]) {
+                        Ok(ms) if ms > 0 => {}
+                        _ => {
+                            return RESPValue::Error(
+                                "ERR FAILOVER timeout must be greater than 0".to_string(),
+                            );
+                        }
+                    }
+                    i += 1;
+                }
+                _ => return RESPValue::Error("ERR syntax error".to_string()),
+            }
+            i += 1;
+        }
+
+        if abort {
+            if self.args.len() != 1 {
+                return RESPValue::Error("ERR syntax error".to_string());
+            }
+            return RESPValue::Error("ERR No failover in progress.".to_string());
+        }
+        if replication.is_replica() {
+            return RESPValue::Error(
+                "ERR FAILOVER is not valid when server is a replica.".to_string(),
+            );
+        }
+        if replication.connected_replicas() == 0 {
+            return RESPValue::Error("ERR FAILOVER requires connected replicas.".to_string());
+        }
+        RESPValue::Error("ERR FAILOVER to a connected replica is not supported yet.".to_string())
+    }
+
+    pub fn handle_client(
+        &self,
+        clients: &ClientRegistry,
+        tracking: &Tracking,
+        pause: &Pause,
+        client: &Client,
+    ) -> RESPValue {
+        if self.args.is_empty() {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'client' command".to_string(),
+            );
+        }
+
+        let subcommand = String::from_utf8_lossy(&self.args[0]).to_uppercase();
+        match (subcommand.as_str(), self.args.len()) {
+            ("LIST", 1) => {
+                let mut list = String::new();
+                for c in clients.list() {
+                    list.push_str(&c.info_line());
+                    list.push('\n');
+                }
+                RESPValue::BulkString(Some(list.into_bytes()))
+            }
+            ("INFO", 1) => {
+                RESPValue::BulkString(Some(format!("{}\n", client.info_line()).into_bytes()))
+            }
+            ("ID", 1) => RESPValue::Integer(client.id as i64),
+            ("GETNAME", 1) => {
+                let name = client.name();
+                if name.is_empty() {
+                    RESPValue::BulkString(None)
+                } else {
+                    RESPValue::BulkString(Some(name.into_bytes()))
+                }
+            }
+            ("SETNAME", 2) => {
+                let name = String::from_utf8_lossy(&self.args[1]).to_string();
+                if let Err(e) = check_client_name(&name) {
+                    return e;
+                }
+                client.set_name(name);
+                RESPValue::SimpleString("OK".to_string())
+            }
+            // Only the default mode: no BCAST, PREFIX, OPTIN, OPTOUT, NOLOOP or REDIRECT
+            ("TRACKING", 2) => match String::from_utf8_lossy(&self.args[1])
+                .to_uppercase()
+                .as_str()
+            {
+                "ON" => {
+                    tracking.enable(client.id, client.messages().clone());
+                    client.set_tracking(true);
+                    RESPValue::SimpleString("OK".to_string())
+                }
+                "OFF" => {
+                    tracking.disable(client.id);
+                    client.set_tracking(false);
+                    RESPValue::SimpleString("OK".to_string())
+                }
+                _ => RESPValue::Error("ERR syntax error".to_string()),
+            },
+            // CLIENT PAUSE timeout [WRITE|ALL]
+            ("PAUSE", 2 | 3) => {
+                let timeout = match String::from_utf8_lossy(&self.args[1]).parse::<i64>() {
+                    Ok(ms) if ms < 0 => {
+                        return RESPValue::Error("ERR timeout is negative".to_string());
+                    }
+                    Ok(ms) => Duration::from_millis(ms as u64),
+                    Err(_) => {
+                        return RESPValue::Error(
+                            "ERR timeout is not an integer or out of range".to_string(),
+                        );
+                    }
+                };
+                let writes_only = match self
+                    .args
+                    .get(2)
+                    .map(|m| String::from_utf8_lossy(m).to_uppercase())
+                {
+                    None => false,
+                    Some(mode) if mode == "ALL" => false,
+                    Some(mode) if mode == "WRITE" => true,
+                    Some(_) => return RESPValue::Error("ERR syntax error".to_string()),
+                };
+                pause.pause(timeout, writes_only);
+                RESPValue::SimpleString("OK".to_string())
+            }
+            ("UNPAUSE", 1) => {
+                pause.unpause();
+                RESPValue::SimpleString("OK".to_string())
+            }
+            ("TRACKING", n) if n > 2 => RESPValue::Error(
+                "ERR CLIENT TRACKING options are not supported yet; use CLIENT TRACKING ON|OFF"
+                    .to_string(),
+            ),
+            (
+                "LIST" | "INFO" | "ID" | "GETNAME" | "SETNAME" | "TRACKING" | "PAUSE" | "UNPAUSE",
+                _,
+            ) => RESPValue::Error(format!(
+                "ERR wrong number of arguments for 'client|{}' command",
+                subcommand.to_lowercase()
+            )),
+            _ => RESPValue::Error(format!(
+                "ERR unknown subcommand '{}'. Try CLIENT HELP.",
+                String::from_utf8_lossy(&self.args[0])
+            )),
+        }
+    }
+
+    // LATENCY LATEST | HISTORY event | RESET [event ...]
+    pub fn handle_latency(&self, monitor: &LatencyMonitor) -> RESPValue {
+        if self.args.is_empty() {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'latency' command".to_string(),
+            );
+        }
+
+        let subcommand = String::from_utf8_lossy(&self.args[0]).to_uppercase();
+        match (subcommand.as_str(), self.args.len()) {
+            ("LATEST", 1) => RESPValue::Array(Some(
+                monitor
+                    .latest()
+                    .into_iter()
+                    .map(|(event, at, latest, max)| {
+                        RESPValue::Array(Some(vec![
+                            RESPValue::BulkString(Some(event.as_bytes().to_vec())),
+                            RESPValue::Integer(at as i64),
+                            RESPValue::Integer(latest as i64),
+                            RESPValue::Integer(max as i64),
+                        ]))
+                    })
+                    .collect(),
+            )),
+            ("HISTORY", 2) => {
+                let event = String::from_utf8_lossy(&self.args[1]);
+                RESPValue::Array(Some(
+                    monitor
+                        .history(&event)
+                        .into_iter()
+                        .map(|(at, millis)| {
+                            RESPValue::Array(Some(vec![
+                                RESPValue::Integer(at as i64),
+                                RESPValue::Integer(millis as i64),
+                            ]))
+                        })
+                        .collect(),
+                ))
+            }
+            ("RESET", _) => {
+                let events: Vec<String> = self.args[1..]
+                    .iter()
+                    .map(|a| String::from_utf8_lossy(a).to_string())
+                    .collect();
+                RESPValue::Integer(monitor.reset(&events) as i64)
+            }
+            ("LATEST" | "HISTORY", _) => RESPValue::Error(format!(
+                "ERR wrong number of arguments for 'latency|{}' command",
+                subcommand.to_lowercase()
+            )),
+            _ => RESPValue::Error(format!(
+                "ERR unknown subcommand '{}'. Try LATENCY HELP.",
+                String::from_utf8_lossy(&self.args[0])
+            )),
+        }
+    }
+
+    // Standalone-mode answers so cluster-aware clients fall back cleanly
+    pub fn handle_cluster(&self, node_id: &str) -> RESPValue {
+        if self.args.is_empty() {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'cluster' command".to_string(),
+            );
+        }
+
+        let subcommand = String::from_utf8_lossy(&self.args[0]).to_uppercase();
+        match subcommand.as_str() {
+            "INFO" => {
+                let info = "cluster_enabled:0\r\n\
+                            cluster_state:fail\r\n\
+                            cluster_slots_assigned:0\r\n\
+                            cluster_slots_ok:0\r\n\
+                            cluster_slots_pfail:0\r\n\
+                            cluster_slots_fail:0\r\n\
+                            cluster_known_nodes:1\r\n\
+                            cluster_size:0\r\n\
+                            cluster_current_epoch:0\r\n\
+                            cluster_my_epoch:0\r\n";
+                RESPValue::BulkString(Some(info.as_bytes().to_vec()))
+            }
+            "MYID" => RESPValue::BulkString(Some(node_id.as_bytes().to_vec())),
+            "SLOTS" | "SHARDS" => RESPValue::Array(Some(Vec::new())),
+            _ => RESPValue::Error(format!(
+                "ERR unknown subcommand '{}'. Try CLUSTER HELP.",
+                String::from_utf8_lossy(&self.args[0])
+            )),
+        }
+    }
+
+    pub fn handle_publish(&self, pubsub: &PubSub) -> RESPValue {
+        if self.args.len() != 2 {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'publish' command".to_string(),
+            );
+        }
+
+        let channel = String::from_utf8_lossy(&self.args[0]).to_string();
+        let receivers = pubsub.publish(&channel, &self.args[1]);
+        RESPValue::Integer(receivers as i64)
+    }
+
+    // PUBSUB CHANNELS [pattern] | NUMSUB [channel ...] | NUMPAT
+    pub fn handle_pubsub(&self, pubsub: &PubSub) -> RESPValue {
+        if self.args.is_empty() {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'pubsub' command".to_string(),
+            );
+        }
+
+        let subcommand = String::from_utf8_lossy(&self.args[0]).to_uppercase();
+        match (subcommand.as_str(), self.args.len()) {
+            ("CHANNELS", 1 | 2) => {
+                let pattern = self.args.get(1).map(|p| p.as_slice());
+                RESPValue::Array(Some(
+                    pubsub
+                        .channels(pattern)
+                        .into_iter()
+                        .map(|c| RESPValue::BulkString(Some(c.into_bytes())))
+                        .collect(),
+                ))
+            }
+            ("NUMSUB", _) => {
+                let mut reply = Vec::with_capacity(2 * (self.args.len() - 1));
+                for channel in &self.args[1..] {
+                    let count = pubsub.numsub(&String::from_utf8_lossy(channel));
+                    reply.push(RESPValue::BulkString(Some(channel.clone())));
+                    reply.push(RESPValue::Integer(count as i64));
+                }
+                RESPValue::Array(Some(reply))
+            }
+            ("NUMPAT", 1) => RESPValue::Integer(pubsub.numpat() as i64),
+            ("CHANNELS" | "NUMPAT", _) => RESPValue::Error(format!(
+                "ERR wrong number of arguments for 'pubsub|{}' command",
+                subcommand.to_lowercase()
+            )),
+            _ => RESPValue::Error(format!(
+                "ERR unknown subcommand '{}'. Try PUBSUB HELP.",
+                String::from_utf8_lossy(&self.args[0])
+            )),
+        }
+    }
+
+    // COMMAND GETKEYS command [arg ...]: the key arguments of a command,
+    // worked out from its key spec the same way the server finds them
+    pub fn handle_command(&self) -> RESPValue {
+        if self.args.is_empty() {
+            return RESPValue::Error(
+                "ERR wrong number of arguments for 'command' command".to_string(),
+            );
+        }
+
+        let subcommand = String::from_utf8_lossy(&self.args[0]).to_uppercase();
+        match (subcommand.as_str(), self.args.len()) {
+            ("GETKEYS", 1) => RESPValue::Error(
+                "ERR wrong number of arguments for 'command|getkeys' command".to_string(),
+            ),
+            ("GETKEYS", _) => {
+                let target = Command {
+                    name: String::from_utf8_lossy(&self.args[1]).to_uppercase(),
+                    args: self.args[2..].to_vec(),
+                };
+                let Some(spec) = command_table::lookup(&target.name) else {
+                    return RESPValue::Error("ERR Invalid command specified".to_string());
+                };
+                if !spec.accepts(target.args.len() + 1) {
+                    return RESPValue::Error(
+                        "ERR Invalid number of arguments specified for command".to_string(),
+                    );
+                }
+                let keys = target.keys();
+                if keys.is_empty() {
+                    return RESPValue::Error(
+                        "ERR Invalid arguments specified for command".to_string(),
+                    );
+                }
+                RESPValue::Array(Some(
+                    keys.into_iter()
+                        .map(|key| RESPValue::BulkString(Some(key.to_vec())))
+                        .collect(),
+                ))
+            }
+            // Names that aren't commands are left out, like Redis does
+            ("DOCS", 1) => command_docs(command_table::COMMANDS.iter()),
+            ("DOCS", _) => command_docs(self.args[1..].iter().filter_map(|name| {
+                command_table::lookup(&String::from_utf8_lossy(name).to_uppercase())
+            })),
+            _ => RESPValue::Error(format!(
+                "ERR unknown subcommand '{}'. Try COMMAND HELP.",
+                String::from_utf8_lossy(&self.args[0])
+            )),
+        }
+    }
+
+    // SUBSCRIBE, UNSUBSCRIBE, PSUBSCRIBE and PUNSUBSCRIBE reply with one frame per name
+    pub fn handle_subscribe(
+        &self,
+        subscriptions: &mut Subscriptions,
+        pubsub: &PubSub,
+    ) -> Vec<RESPValue> {
+        let names: Vec<String> = self
+            .args
+            .iter()
+            .map(|a| String::from_utf8_lossy(a).to_string())
+            .collect();
+
+        match self.name.as_str() {
+            "SUBSCRIBE" | "PSUBSCRIBE" if names.is_empty() => {
+                vec![RESPValue::Error(format!(
+                    "ERR wrong number of arguments for '{}' command",
+                    self.name.to_lowercase()
+                ))]
+            }
+            "SUBSCRIBE" => subscriptions.subscribe(pubsub, &names),
+            "PSUBSCRIBE" => subscriptions.psubscribe(pubsub, &names),
+            "UNSUBSCRIBE" => subscriptions.unsubscribe(pubsub, &names),
+            _ => subscriptions.punsubscribe(pubsub, &names),
+        }
+    }
+
+    // AUTH [username] password
+    pub fn handle_auth(&self, config: &Config, client: &Client) -> RESPValue {
+        let result = match self.args.as_slice() {
+            [_] if config.requirepass.is_none() => Err(RESPValue::Error(
+                "ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?".to_string(),
+            )),
+            [password] => check_credentials(config, b"default", password),
+            [username, password] => check_credentials(config, username, password),
+            _ => Err(RESPValue::Error("ERR syntax error".to_string())),
+        };
+        match result {
+            Ok(()) => {
+                client.set_authenticated(true);
+                RESPValue::SimpleString("OK".to_string())
+            }
+            Err(e) => e,
+        }
+    }
+
+    // HELLO [protover [AUTH username password] [SETNAME name]]: switches the
+    // connection between RESP2 and RESP3 and describes the server, as a map
+    // under RESP3
+    pub fn handle_hello(
+        &self,
+        subscriptions: &Subscriptions,
+        client: &Client,
+        config: &Config,
+        replication: &Replication,
+    ) -> RESPValue {
+        let mut resp3 = subscriptions.is_resp3();
+        let mut name = None;
+        let mut credentials = None;
+
+        if let Some(version) = self.args.first() {
+            resp3 = match String::from_utf8_lossy(version).parse::<i64>() {
+                Ok(2) => false,
+                Ok(3) => true,
+                Ok(_) => {
+                    return RESPValue::Error("NOPROTO unsupported protocol version".to_string());
+                }
+                Err(_) => {
+                    return RESPValue::Error(
+                        "ERR Protocol version is not an integer or out of range".to_string(),
+                    );
+                }
+            };
+
+            let mut i = 1;
+            while i < self.args.len() {
+                let option = String::from_utf8_lossy(&self.args[i]).to_string();
+                match option.to_uppercase().as_str() {
+                    "AUTH" if i + 2 < self.args.len() => {
+                        credentials = Some((&self.args[i + 1], &self.args[i + 2]));
+                        i += 3;
+                    }
+                    "SETNAME" if i + 1 < self.args.len() => {
+                        let value = String::from_utf8_lossy(&self.args[i + 1]).to_string();
+                        if let Err(e) = check_client_name(&value) {
+                            return e;
+                        }
+                        name = Some(value);
+                        i += 2;
+                    }
+                    _ => {
+                        return RESPValue::Error(format!(
+                            "ERR Syntax error in HELLO option '{}'",
+                            option
+                        ));
+                    }
+                }
+            }
+        }
+
+        // A failed AUTH fails the whole HELLO, leaving the protocol as it was
+        if let Some((username, password)) = credentials {
+            if let Err(e) = check_credentials(config, username, password) {
+                return e;
+            }
+            client.set_authenticated(true);
+        }
+        if !client.is_authenticated() {
+            return RESPValue::Error(
+                "NOAUTH HELLO must be called with the client already authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select the RESP protocol version at the same time".to_string(),
+            );
+        }
+
+        // Only applied once the whole command has been validated
+        subscriptions.set_resp3(resp3);
+        if let Some(name) = name {
+            client.set_name(name);
+        }
+
+        let role = if replication.is_replica() {
+            "replica"
+        } else {
+            "master"
+        };
+        let text = |s: &str| RESPValue::BulkString(Some(s.as_bytes().to_vec()));
+        let fields = vec![
+            (text("server"), text("rudis")),
+            (text("version"), text(env!("CARGO_PKG_VERSION"))),
+            (text("proto"), RESPValue::Integer(if resp3 { 3 } else { 2 })),
+            (text("id"), RESPValue::Integer(client.id as i64)),
+            (text("mode"), text("standalone")),
+            (text("role"), text(role)),
+            (text("modules"), RESPValue::Array(Some(Vec::new()))),
+        ];
+        if resp3 {
+            RESPValue::Map(fields)
+        } else {
+            RESPValue::Array(Some(fields.into_iter().flat_map(|(k, v)| [k, v]).collect()))
+        }
+    }
+}
+
+// Only the default user exists. Without requirepass it takes any
+// password, like Redis' nopass default user.
+fn check_credentials(config: &Config, username: &[u8], password: &[u8]) -> Result<(), RESPValue> {
+    let valid = username == b"default"
+        && config
+            .requirepass
+            .as_ref()
+            .is_none_or(|required| required.as_bytes() == password);
+    if valid {
+        Ok(())
+    } else {
+        Err(RESPValue::Error(
+            "WRONGPASS invalid username-password pair or user is disabled.".to_string(),
+        ))
+    }
+}
+
+// Scores are sent as bulk strings, nil when the member does not exist
+fn score_reply(score: Option<f64>) -> RESPValue {
+    RESPValue::BulkString(score.map(|s| zset::format_score(s).into_bytes()))
+}
+
+#[derive(Clone, Copy)]
+enum ExpiryUnit {
+    Seconds,
+    Millis,
+    UnixSeconds,
+    UnixMillis,
+}
+
+// Shared validation for every expiry argument: it must be an integer, and
+// relative TTLs (counted from `now`) and absolute timestamps alike must be
+// strictly positive
+fn parse_expiry(
+    arg: &[u8],
+    unit: ExpiryUnit,
+    command: &str,
+    now: SystemTime,
+) -> Result<SystemTime, RESPValue> {
+    let value = parse_i64(arg)?;
+    let invalid = || RESPValue::Error(format!("ERR invalid expire time in '{}' command", command));
+    if value <= 0 {
+        return Err(invalid());
+    }
+
+    let millis = match unit {
+        ExpiryUnit::Seconds | ExpiryUnit::UnixSeconds => {
+            (value as u64).checked_mul(1000).ok_or_else(invalid)?
+        }
+        ExpiryUnit::Millis | ExpiryUnit::UnixMillis => value as u64,
+    };
+    let base = match unit {
+        ExpiryUnit::Seconds | ExpiryUnit::Millis => now,
+        ExpiryUnit::UnixSeconds | ExpiryUnit::UnixMillis => UNIX_EPOCH,
+    };
+    base.checked_add(Duration::from_millis(millis))
+        .ok_or_else(invalid)
+}
+
+// The `FIELDS numfields field [field ...]` tail of the hash field expiry commands
+fn parse_fields(args: &[Vec<u8>]) -> Result<&[Vec<u8>], RESPValue> {
+    if !args[0].eq_ignore_ascii_case(b"FIELDS") {
+        return Err(RESPValue::Error(
+            "ERR Mandatory argument FIELDS is missing or not at the right position".to_string(),
+        ));
+    }
+    let count = String::from_utf8_lossy(&args[1])
+        .parse::<usize>()
+        .ok()
+        .filter(|&n| n > 0)
+        .ok_or_else(|| {
+            RESPValue::Error("ERR Parameter `numFields` should be greater than 0".to_string())
+        })?;
+    if count != args.len() - 2 {
+        return Err(RESPValue::Error(
+            "ERR The `numfields` parameter must match the number of arguments".to_string(),
+        ));
+    }
+    Ok(&args[2..])
+}
+
+// `numkeys key [key ...]` at the start of `args`, as taken by the
+// ZUNIONSTORE family and ZDIFF; anything after the keys is left to the caller
+fn parse_numkeys<'a>(args: &'a [Vec<u8>], command: &str) -> Result<&'a [Vec<u8>], RESPValue> {
+    let Ok(numkeys) = String::from_utf8_lossy(&args[0]).parse::<usize>() else {
+        return Err(RESPValue::Error(
+            "ERR value is not an integer or out of range".to_string(),
+        ));
+    };
+    if numkeys == 0 {
+        return Err(RESPValue::Error(format!(
+            "ERR at least 1 input key is needed for '{}' command",
+            command
+        )));
+    }
+    if args.len() < 1 + numkeys {
+        return Err(RESPValue::Error("ERR syntax error".to_string()));
+    }
+
+    Ok(&args[1..1 + numkeys])
+}
+
+// `key [count [modifier]]` for SRANDMEMBER, HRANDFIELD and ZRANDMEMBER.
+// None without a count, which asks for a single element rather than an
+// array; otherwise the count and whether the modifier (WITHSCORES or
+// WITHVALUES) followed it.
+fn random_count_args(
+    args: &[Vec<u8>],
+    modifier: Option<&str>,
+    command: &str,
+) -> Result<Option<(i64, bool)>, RESPValue> {
+    if args.is_empty() {
+        return Err(RESPValue::Error(format!(
+            "ERR wrong number of arguments for '{}' command",
+            command
+        )));
+    }
+    let Some(count) = args.get(1) else {
+        return Ok(None);
+    };
+    let count = parse_i64(count)?;
+
+    let with = match (&args[2..], modifier) {
+        ([], _) => false,
+        ([arg], Some(modifier)) if String::from_utf8_lossy(arg).eq_ignore_ascii_case(modifier) => {
+            true
+        }
+        _ => return Err(RESPValue::Error("ERR syntax error".to_string())),
+    };
+    // A negative count picks that many elements whatever the collection's
+    // size, and the reply is built in memory, so it is bounded up front
+    if count < -RANDOM_MAX_PICKS {
+        return Err(RESPValue::Error("ERR value is out of range".to_string()));
+    }
+    Ok(Some((count, with)))
+}
+
+fn integer_array(values: Vec<i64>) -> RESPValue {
+    RESPValue::Array(Some(values.into_iter().map(RESPValue::Integer).collect()))
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+// Local and replica AOF fsyncs acknowledged: always none
+pub fn waitaof_reply() -> RESPValue {
+    RESPValue::Array(Some(vec![RESPValue::Integer(0), RESPValue::Integer(0)]))
+}
+
+fn parse_i64(arg: &[u8]) -> Result<i64, RESPValue> {
+    String::from_utf8_lossy(arg)
+        .parse::<i64>()
+        .map_err(|_| RESPValue::Error("ERR value is not an integer or out of range".to_string()))
+}
+
+// Shared by CLIENT SETNAME and HELLO SETNAME
+fn check_client_name(name: &str) -> Result<(), RESPValue> {
+    if name.chars().any(|c| c == ' ' || c == '\n') {
+        return Err(RESPValue::Error(
+            "ERR Client names cannot contain spaces, newlines or special characters.".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// Keys per DEBUG DUMPJSON page unless COUNT says otherwise, and the most COUNT may ask for
+const DUMPJSON_DEFAULT_COUNT: usize = 1000;
+const DUMPJSON_MAX_COUNT: usize = 10_000;
+
+// The most elements a negative SRANDMEMBER, HRANDFIELD or ZRANDMEMBER count
+// may ask for
+const RANDOM_MAX_PICKS: i64 = 1024 * 1024;
+
+// Redis' default SCAN COUNT
+const SCAN_DEFAULT_COUNT: usize = 10;
+
+// Upper bound on the LCS dynamic-programming matrix (u32 cells, 512MB)
+const LCS_MAX_CELLS: u64 = 128 * 1024 * 1024;
+
+// A contiguous run shared by both strings, as reported by LCS IDX
+struct LcsMatch {
+    a_start: usize,
+    b_start: usize,
+    len: usize,
+}
+
+// Classic LCS matrix where table[i][j] is the LCS length of a[..i] and b[..j]
+struct Lcs<'a> {
+    a: &'a [u8],
+    b: &'a [u8],
+    table: Vec<u32>,
+}
+
+impl<'a> Lcs<'a> {
+    fn compute(a: &'a [u8], b: &'a [u8]) -> Self {
+        let width = b.len() + 1;
+        let mut table = vec![0u32; (a.len() + 1) * width];
+
+        for i in 1..=a.len() {
+            for j in 1..=b.len() {
+                table[i * width + j] = if a[i - 1] == b[j - 1] {
+                    table[(i - 1) * width + j - 1] + 1
+                } else {
+                    table[(i - 1) * width + j].max(table[i * width + j - 1])
+                };
+            }
+        }
+
+        Self { a, b, table }
+    }
+
+    fn at(&self, i: usize, j: usize) -> u32 {
+        self.table[i * (self.b.len() + 1) + j]
+    }
+
+    fn len(&self) -> usize {
+        self.at(self.a.len(), self.b.len()) as usize
+    }
+
+    fn subsequence(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.len());
+        let (mut i, mut j) = (self.a.len(), self.b.len());
+
+        while i > 0 && j > 0 {
+            if self.a[i - 1] == self.b[j - 1] {
+                result.push(self.a[i - 1]);
+                i -= 1;
+                j -= 1;
+            } else if self.at(i - 1, j) > self.at(i, j - 1) {
+                i -= 1;
+            } else {
+                j -= 1;
+            }
+        }
+
+        result.reverse();
+        result
+    }
+
+    // Walk back from the end collecting matched runs, last match first (as Redis does)
+    fn ranges(&self) -> Vec<LcsMatch> {
+        let mut ranges = Vec::new();
+        let mut current: Option<LcsMatch> = None;
+        let (mut i, mut j) = (self.a.len(), self.b.len());
+
+        while i > 0 && j > 0 {
+            if self.a[i - 1] == self.b[j - 1] {
+                match current.as_mut() {
+                    Some(m) if m.a_start == i && m.b_start == j => {
+                        m.a_start -= 1;
+                        m.b_start -= 1;
+                        m.len += 1;
+                    }
+                    _ => {
+                        ranges.extend(current.take());
+                        current = Some(LcsMatch {
+                            a_start: i - 1,
+                            b_start: j - 1,
+                            len: 1,
+                        });
+                    }
+                }
+                i -= 1;
+                j -= 1;
+            } else {
+                if self.at(i - 1, j) > self.at(i, j - 1) {
+                    i -= 1;
+                } else {
+                    j -= 1;
+                }
+                ranges.extend(current.take());
+            }
+        }
+
+        ranges.extend(current);
+        ranges
+    }
+}
+
+fn bulk_str(value: &str) -> RESPValue {
+    RESPValue::BulkString(Some(value.as_bytes().to_vec()))
+}
+
+// COMMAND DOCS' reply: the lowercase name of each command followed by its
+// summary, group and arguments, as flat field/value arrays
+fn command_docs<'a>(specs: impl Iterator<Item = &'a command_table::CommandSpec>) -> RESPValue {
+    let mut reply = Vec::new();
+    for spec in specs {
+        let arguments = spec
+            .arguments()
+            .into_iter()
+            .map(|argument| {
+                let mut fields = vec![
+                    bulk_str("name"),
+                    bulk_str(argument.name),
+                    bulk_str("type"),
+                    bulk_str(if argument.is_key { "key" } else { "string" }),
+                ];
+                if argument.is_key {
+                    fields.push(bulk_str("key_spec_index"));
+                    fields.push(RESPValue::Integer(0));
+                }
+                let flags: Vec<RESPValue> = [
+                    (argument.optional, "optional"),
+                    (argument.multiple, "multiple"),
+                ]
+                .into_iter()
+                .filter(|&(set, _)| set)
+                .map(|(_, flag)| RESPValue::SimpleString(flag.to_string()))
+                .collect();
+                if !flags.is_empty() {
+                    fields.push(bulk_str("flags"));
+                    fields.push(RESPValue::Array(Some(flags)));
+                }
+                RESPValue::Array(Some(fields))
+            })
+            .collect();
+        reply.push(bulk_str(&spec.name.to_lowercase()));
+        reply.push(RESPValue::Array(Some(vec![
+            bulk_str("summary"),
+            bulk_str(spec.summary),
+            bulk_str("group"),
+            bulk_str(spec.group),
+            bulk_str("arguments"),
+            RESPValue::Array(Some(arguments)),
+        ])));
+    }
+    RESPValue::Array(Some(reply))
 }
@@ -0,0 +1,384 @@
+// Every command Rudis knows, with its arity in Redis' convention: the
+// number of arguments including the command name, negative meaning "at
+// least". Used to reject bad commands when they are queued inside MULTI
+// and to answer COMMAND GETKEYS. Key positions are Redis' first/last/step
+// triple (last -1 meaning the final argument, all zero for commands without
+// keys). Commands taking numkeys (ZUNIONSTORE, ZDIFF, ZINTERCARD, ...) only
+// list their destination, if any; Command::keys adds the source keys that
+// follow numkeys. The group and summary are what COMMAND DOCS reports,
+// using Redis' group names.
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub arity: i32,
+    pub first_key: i32,
+    pub last_key: i32,
+    pub key_step: i32,
+    pub group: &'static str,
+    pub summary: &'static str,
+}
+
+const fn spec(
+    name: &'static str,
+    arity: i32,
+    first_key: i32,
+    last_key: i32,
+    key_step: i32,
+) -> CommandSpec {
+    CommandSpec {
+        name,
+        arity,
+        first_key,
+        last_key,
+        key_step,
+        group: "",
+        summary: "",
+    }
+}
+
+pub const COMMANDS: &[CommandSpec] = &[
+    // Connection and server
+    spec("PING", -1, 0, 0, 0).doc("connection", "Returns the server's liveliness response"),
+    spec("HELLO", -1, 0, 0, 0).doc(
+        "connection",
+        "Handshakes with the server and switches the protocol version",
+    ),
+    spec("AUTH", -2, 0, 0, 0).doc("connection", "Authenticates the connection"),
+    spec("ECHO", 2, 0, 0, 0).doc("connection", "Returns the given string"),
+    spec("SELECT", 2, 0, 0, 0).doc("connection", "Changes the selected database"),
+    spec("QUIT", -1, 0, 0, 0).doc("connection", "Closes the connection"),
+    spec("RESET", 1, 0, 0, 0).doc("connection", "Resets the connection"),
+    spec("INFO", -1, 0, 0, 0).doc(
+        "server",
+        "Returns information and statistics about the server",
+    ),
+    spec("LOLWUT", -1, 0, 0, 0).doc("server", "Displays the server's version"),
+    spec("HEALTH", 1, 0, 0, 0).doc("server", "Returns the server's health status"),
+    spec("CLIENT", -2, 0, 0, 0).doc("connection", "A container for client connection commands"),
+    spec("LATENCY", -2, 0, 0, 0).doc("server", "A container for latency diagnostics commands"),
+    spec("CLUSTER", -2, 0, 0, 0).doc("cluster", "A container for Redis Cluster commands"),
+    spec("COMMAND", -2, 0, 0, 0).doc("server", "Returns detailed information about commands"),
+    spec("CONFIG", -2, 0, 0, 0).doc("server", "A container for server configuration commands"),
+    spec("DEBUG", -2, 0, 0, 0).doc("server", "A container for debugging commands"),
+    spec("OBJECT", -2, 0, 0, 0).doc("generic", "A container for object introspection commands"),
+    spec("SAVE", 1, 0, 0, 0).doc("server", "Synchronously saves the database to disk"),
+    spec("BGSAVE", 1, 0, 0, 0).doc("server", "Asynchronously saves the database to disk"),
+    spec("BGREWRITEAOF", 1, 0, 0, 0).doc(
+        "server",
+        "Asynchronously rewrites the append-only file to disk",
+    ),
+    spec("LASTSAVE", 1, 0, 0, 0).doc(
+        "server",
+        "Returns the Unix timestamp of the last successful save",
+    ),
+    spec("DBSIZE", 1, 0, 0, 0).doc("server", "Returns the number of keys in the database"),
+    spec("FLUSHDB", -1, 0, 0, 0).doc("server", "Removes all keys from the database"),
+    spec("FLUSHALL", -1, 0, 0, 0).doc("server", "Removes all keys from all databases"),
+    spec("KEYS", 2, 0, 0, 0).doc("generic", "Returns all key names that match a pattern"),
+    spec("SCAN", -2, 0, 0, 0).doc("generic", "Iterates over the key names in the database"),
+    // Replication
+    spec("SYNC", 1, 0, 0, 0).doc("server", "An internal command used in replication"),
+    spec("REPLICAOF", 3, 0, 0, 0).doc(
+        "server",
+        "Configures the server as a replica of another, or promotes it to a master",
+    ),
+    spec("SLAVEOF", 3, 0, 0, 0).doc("server", "Deprecated alias of REPLICAOF"),
+    spec("FAILOVER", -1, 0, 0, 0).doc(
+        "server",
+        "Starts a coordinated failover from this server to one of its replicas",
+    ),
+    spec("WAITAOF", 4, 0, 0, 0).doc(
+        "generic",
+        "Blocks until writes are fsynced to the AOF of this and replica servers",
+    ),
+    // Transactions
+    spec("MULTI", 1, 0, 0, 0).doc("transactions", "Starts a transaction"),
+    spec("EXEC", 1, 0, 0, 0).doc("transactions", "Executes all commands in a transaction"),
+    spec("DISCARD", 1, 0, 0, 0).doc("transactions", "Discards a transaction"),
+    spec("WATCH", -2, 1, -1, 1).doc(
+        "transactions",
+        "Monitors changes to keys to determine the execution of a transaction",
+    ),
+    spec("UNWATCH", 1, 0, 0, 0).doc(
+        "transactions",
+        "Forgets about watched keys of a transaction",
+    ),
+    // Pub/Sub
+    spec("PUBLISH", 3, 0, 0, 0).doc("pubsub", "Posts a message to a channel"),
+    spec("PUBSUB", -2, 0, 0, 0).doc("pubsub", "A container for Pub/Sub introspection commands"),
+    spec("SUBSCRIBE", -2, 0, 0, 0).doc("pubsub", "Listens for messages published to channels"),
+    spec("UNSUBSCRIBE", -1, 0, 0, 0)
+        .doc("pubsub", "Stops listening to messages posted to channels"),
+    spec("PSUBSCRIBE", -2, 0, 0, 0).doc(
+        "pubsub",
+        "Listens for messages published to channels that match patterns",
+    ),
+    spec("PUNSUBSCRIBE", -1, 0, 0, 0).doc(
+        "pubsub",
+        "Stops listening to messages published to channels that match patterns",
+    ),
+    // Keys and strings
+    spec("GET", 2, 1, 1, 1).doc("string", "Returns the string value of a key"),
+    spec("SET", -3, 1, 1, 1).doc(
+        "string",
+        "Sets the string value of a key, creating the key if it doesn't exist",
+    ),
+    spec("SETEX", 4, 1, 1, 1).doc(
+        "string",
+        "Sets the string value and expiration time of a key",
+    ),
+    spec("PSETEX", 4, 1, 1, 1).doc(
+        "string",
+        "Sets both string value and expiration time in milliseconds of a key",
+    ),
+    spec("GETEX", -2, 1, 1, 1).doc(
+        "string",
+        "Returns the string value of a key after setting its expiration time",
+    ),
+    spec("APPEND", 3, 1, 1, 1).doc("string", "Appends a string to the value of a key"),
+    spec("STRLEN", 2, 1, 1, 1).doc("string", "Returns the length of a string value"),
+    spec("GETRANGE", 4, 1, 1, 1).doc(
+        "string",
+        "Returns a substring of the string stored at a key",
+    ),
+    spec("SUBSTR", 4, 1, 1, 1).doc("string", "Returns a substring from a string value"),
+    spec("SETRANGE", 4, 1, 1, 1).doc(
+        "string",
+        "Overwrites a part of a string value with another by an offset",
+    ),
+    spec("LCS", -3, 1, 2, 1).doc("string", "Finds the longest common substring"),
+    spec("DEL", -2, 1, -1, 1).doc("generic", "Deletes one or more keys"),
+    spec("EXISTS", -2, 1, -1, 1).doc("generic", "Determines whether one or more keys exist"),
+    spec("TYPE", 2, 1, 1, 1).doc("generic", "Determines the type of value stored at a key"),
+    spec("DUMP", 2, 1, 1, 1).doc(
+        "generic",
+        "Returns a serialized representation of the value stored at a key",
+    ),
+    spec("RESTORE", -4, 1, 1, 1).doc(
+        "generic",
+        "Creates a key from the serialized representation of a value",
+    ),
+    spec("SORT", -2, 1, 1, 1).doc(
+        "generic",
+        "Sorts the elements in a list, a set, or a sorted set, optionally storing the result",
+    ),
+    spec("INCR", 2, 1, 1, 1).doc("string", "Increments the integer value of a key by one"),
+    spec("DECR", 2, 1, 1, 1).doc("string", "Decrements the integer value of a key by one"),
+    spec("INCRBY", 3, 1, 1, 1).doc(
+        "string",
+        "Increments the integer value of a key by a number",
+    ),
+    spec("DECRBY", 3, 1, 1, 1).doc(
+        "string",
+        "Decrements a number from the integer value of a key",
+    ),
+    spec("EXPIRE", 3, 1, 1, 1).doc("generic", "Sets the expiration time of a key in seconds"),
+    spec("PEXPIREAT", 3, 1, 1, 1).doc(
+        "generic",
+        "Sets the expiration time of a key to a Unix milliseconds timestamp",
+    ),
+    spec("TTL", 2, 1, 1, 1).doc("generic", "Returns the expiration time in seconds of a key"),
+    spec("PTTL", 2, 1, 1, 1).doc(
+        "generic",
+        "Returns the expiration time in milliseconds of a key",
+    ),
+    spec("PERSIST", 2, 1, 1, 1).doc("generic", "Removes the expiration time of a key"),
+    spec("EXPIRETIME", 2, 1, 1, 1).doc(
+        "generic",
+        "Returns the expiration time of a key as a Unix timestamp",
+    ),
+    spec("PEXPIRETIME", 2, 1, 1, 1).doc(
+        "generic",
+        "Returns the expiration time of a key as a Unix milliseconds timestamp",
+    ),
+    // Lists
+    spec("LPUSH", -3, 1, 1, 1).doc("list", "Prepends one or more elements to a list"),
+    spec("RPUSH", -3, 1, 1, 1).doc("list", "Appends one or more elements to a list"),
+    spec("LPOP", 2, 1, 1, 1).doc(
+        "list",
+        "Returns the first element of a list after removing it",
+    ),
+    spec("RPOP", 2, 1, 1, 1).doc("list", "Returns and removes the last element of a list"),
+    spec("BLPOP", -3, 1, -2, 1).doc(
+        "list",
+        "Removes and returns the first element in a list, blocking until one is available",
+    ),
+    spec("BRPOP", -3, 1, -2, 1).doc(
+        "list",
+        "Removes and returns the last element in a list, blocking until one is available",
+    ),
+    spec("LRANGE", 4, 1, 1, 1).doc("list", "Returns a range of elements from a list"),
+    spec("LLEN", 2, 1, 1, 1).doc("list", "Returns the length of a list"),
+    spec("LINDEX", 3, 1, 1, 1).doc("list", "Returns an element from a list by its index"),
+    spec("LREM", 4, 1, 1, 1).doc("list", "Removes elements from a list"),
+    spec("LTRIM", 4, 1, 1, 1).doc("list", "Removes elements from both ends of a list"),
+    // Sets
+    spec("SADD", -3, 1, 1, 1).doc("set", "Adds one or more members to a set"),
+    spec("SREM", -3, 1, 1, 1).doc("set", "Removes one or more members from a set"),
+    spec("SMEMBERS", 2, 1, 1, 1).doc("set", "Returns all members of a set"),
+    spec("SCARD", 2, 1, 1, 1).doc("set", "Returns the number of members in a set"),
+    spec("SISMEMBER", 3, 1, 1, 1).doc("set", "Determines whether a member belongs to a set"),
+    spec("SMISMEMBER", -3, 1, 1, 1)
+        .doc("set", "Determines whether multiple members belong to a set"),
+    spec("SRANDMEMBER", -2, 1, 1, 1).doc("set", "Gets one or multiple random members from a set"),
+    spec("SINTERCARD", -3, 0, 0, 0).doc(
+        "set",
+        "Returns the number of members of the intersect of multiple sets",
+    ),
+    // Sorted sets
+    spec("ZADD", -4, 1, 1, 1).doc(
+        "sorted-set",
+        "Adds one or more members to a sorted set, or updates their scores",
+    ),
+    spec("ZREM", -3, 1, 1, 1).doc(
+        "sorted-set",
+        "Removes one or more members from a sorted set",
+    ),
+    spec("ZCARD", 2, 1, 1, 1).doc(
+        "sorted-set",
+        "Returns the number of members in a sorted set",
+    ),
+    spec("ZSCORE", 3, 1, 1, 1).doc(
+        "sorted-set",
+        "Returns the score of a member in a sorted set",
+    ),
+    spec("ZMSCORE", -3, 1, 1, 1).doc(
+        "sorted-set",
+        "Returns the score of one or more members in a sorted set",
+    ),
+    spec("ZRANGE", -4, 1, 1, 1).doc(
+        "sorted-set",
+        "Returns members in a sorted set within a range of indexes",
+    ),
+    spec("ZRANDMEMBER", -2, 1, 1, 1).doc(
+        "sorted-set",
+        "Returns one or more random members from a sorted set",
+    ),
+    spec("ZUNIONSTORE", -4, 1, 1, 1).doc(
+        "sorted-set",
+        "Stores the union of multiple sorted sets in a key",
+    ),
+    spec("ZINTERSTORE", -4, 1, 1, 1).doc(
+        "sorted-set",
+        "Stores the intersect of multiple sorted sets in a key",
+    ),
+    spec("ZDIFF", -3, 0, 0, 0).doc(
+        "sorted-set",
+        "Returns the difference between multiple sorted sets",
+    ),
+    spec("ZDIFFSTORE", -4, 1, 1, 1).doc(
+        "sorted-set",
+        "Stores the difference of multiple sorted sets in a key",
+    ),
+    spec("ZINTERCARD", -3, 0, 0, 0).doc(
+        "sorted-set",
+        "Returns the number of members of the intersect of multiple sorted sets",
+    ),
+    // Hashes
+    spec("HSET", -4, 1, 1, 1).doc("hash", "Creates or modifies the value of a field in a hash"),
+    spec("HMSET", -4, 1, 1, 1).doc("hash", "Sets the values of multiple fields"),
+    spec("HGET", 3, 1, 1, 1).doc("hash", "Returns the value of a field in a hash"),
+    spec("HDEL", -3, 1, 1, 1).doc(
+        "hash",
+        "Deletes one or more fields and their values from a hash",
+    ),
+    spec("HGETALL", 2, 1, 1, 1).doc("hash", "Returns all fields and values in a hash"),
+    spec("HKEYS", 2, 1, 1, 1).doc("hash", "Returns all fields in a hash"),
+    spec("HVALS", 2, 1, 1, 1).doc("hash", "Returns all values in a hash"),
+    spec("HLEN", 2, 1, 1, 1).doc("hash", "Returns the number of fields in a hash"),
+    spec("HEXISTS", 3, 1, 1, 1).doc("hash", "Determines whether a field exists in a hash"),
+    spec("HRANDFIELD", -2, 1, 1, 1).doc("hash", "Returns one or more random fields from a hash"),
+    spec("HSCAN", -3, 1, 1, 1).doc("hash", "Iterates over fields and values of a hash"),
+    spec("HEXPIRE", -6, 1, 1, 1).doc(
+        "hash",
+        "Set expiry for hash field using relative time to expire (seconds)",
+    ),
+    spec("HPEXPIRE", -6, 1, 1, 1).doc(
+        "hash",
+        "Set expiry for hash field using relative time to expire (milliseconds)",
+    ),
+    spec("HPEXPIREAT", -6, 1, 1, 1).doc(
+        "hash",
+        "Set expiry for hash field using an absolute Unix timestamp (milliseconds)",
+    ),
+    spec("HTTL", -5, 1, 1, 1).doc("hash", "Returns the TTL in seconds of a hash field"),
+    spec("HPERSIST", -5, 1, 1, 1).doc(
+        "hash",
+        "Removes the expiration time for each specified field",
+    ),
+];
+
+pub fn lookup(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS.iter().find(|spec| spec.name == name)
+}
+
+// One entry in COMMAND DOCS' arguments list
+pub struct ArgumentDoc {
+    pub name: &'static str,
+    pub is_key: bool,
+    // Repeats: the keys of DEL, or whatever an "at least" arity leaves open
+    pub multiple: bool,
+    pub optional: bool,
+}
+
+impl CommandSpec {
+    const fn doc(self, group: &'static str, summary: &'static str) -> Self {
+        CommandSpec {
+            group,
+            summary,
+            ..self
+        }
+    }
+
+    // `argc` counts the command name, like the arity
+    pub fn accepts(&self, argc: usize) -> bool {
+        let argc = argc as i32;
+        if self.arity < 0 {
+            argc >= -self.arity
+        } else {
+            argc == self.arity
+        }
+    }
+
+    // Indexes into the full argument list (command name at 0) that hold keys
+    pub fn key_positions(&self, argc: usize) -> Vec<usize> {
+        if self.first_key <= 0 {
+            return Vec::new();
+        }
+        let last = if self.last_key < 0 {
+            argc as i32 + self.last_key
+        } else {
+            self.last_key.min(argc as i32 - 1)
+        };
+        (self.first_key..=last)
+            .step_by(self.key_step.max(1) as usize)
+            .map(|i| i as usize)
+            .collect()
+    }
+
+    // The arguments as far as the arity and key positions describe them:
+    // the required ones, keys where the key spec puts them, then for an "at
+    // least" arity either the keys repeating (a key range running to the end)
+    // or an optional tail
+    pub fn arguments(&self) -> Vec<ArgumentDoc> {
+        let required = self.arity.unsigned_abs() as usize;
+        let keys = self.key_positions(required);
+        let keys_repeat = self.arity < 0 && self.last_key < 0 && !keys.is_empty();
+        let mut arguments: Vec<ArgumentDoc> = (1..required)
+            .map(|i| ArgumentDoc {
+                name: if keys.contains(&i) { "key" } else { "arg" },
+                is_key: keys.contains(&i),
+                multiple: keys_repeat && keys.last() == Some(&i),
+                optional: false,
+            })
+            .collect();
+        if self.arity < 0 && !keys_repeat {
+            arguments.push(ArgumentDoc {
+                name: "arg",
+                is_key: false,
+                multiple: true,
+                optional: true,
+            });
+        }
+        arguments
+    }
+}
@@ -0,0 +1,621 @@
+use crate::command_table;
+use crate::glob;
+use crate::lock;
+use crate::notify::EventFlags;
+use crate::resp::{DEFAULT_MAX_BULK_LEN, DEFAULT_MAX_DEPTH};
+use std::collections::HashMap;
+use std::env;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    // Every address to listen on, like Redis' bind with several addresses
+    pub addrs: Vec<String>,
+    // HTTP listener for Prometheus scrapes; off unless set
+    pub metrics_addr: Option<String>,
+    pub dbfilename: String,
+    // Where BGREWRITEAOF writes; nothing is appended to it otherwise yet
+    pub appendfilename: String,
+    pub max_nesting_depth: usize,
+    // Most bytes a connection may send without completing a command
+    pub client_query_buffer_limit: usize,
+    pub output_buffer_limit_normal: OutputBufferLimit,
+    pub output_buffer_limit_pubsub: OutputBufferLimit,
+    // output-flush-bytes: replies to pipelined commands are batched into
+    // one write, but written out early once the batch grows past this.
+    // Not a Redis setting.
+    pub output_flush_bytes: usize,
+    pub notify_keyspace_events: EventFlags,
+    // How many times per second the active-expiry reaper runs
+    pub hz: u64,
+    // TCP_NODELAY on accepted sockets, on by default like Redis
+    pub tcp_nodelay: bool,
+    // tcp-keepalive: idle seconds before the kernel probes a silent peer,
+    // so half-open connections are eventually dropped; 0 turns it off
+    pub tcp_keepalive: u64,
+    // Connections beyond this many are turned away on accept
+    pub maxclients: usize,
+    // maxmemory in bytes, checked against INFO's used_memory estimate
+    // before each write; 0 means no limit
+    pub maxmemory: usize,
+    // What to do once over maxmemory: evict keys or refuse writes
+    pub maxmemory_policy: MaxmemoryPolicy,
+    // default-ttl-seconds: the expiry a SET without EX/PX/EXAT/PXAT or
+    // KEEPTTL gets, so a cache can't grow forever when clients forget to
+    // set one; 0 (the default) leaves such keys persistent. Not a Redis
+    // setting.
+    pub default_ttl_seconds: u64,
+    // command-timeout-ms: how long SORT, KEYS, SMEMBERS and LRANGE may spend
+    // building their result before failing; 0 (the default) is no limit
+    pub command_timeout_ms: u64,
+    // latency-monitor-threshold: commands taking at least this many ms are
+    // recorded for LATENCY; 0 (the default, as in Redis) turns it off
+    pub latency_monitor_threshold: u64,
+    // Password of the default user; connections must AUTH first when set
+    pub requirepass: Option<String>,
+    // rename-command: fixed at startup, like Redis
+    pub rename_commands: CommandRenames,
+    // Background save points; changeable at runtime with CONFIG SET save
+    save: Arc<RwLock<Vec<SavePoint>>>,
+    // Size thresholds OBJECT ENCODING reports against; changeable with CONFIG SET
+    encoding_limits: Arc<RwLock<EncodingLimits>>,
+    // Largest bulk string the parser accepts and string commands may
+    // create; changeable with CONFIG SET proto-max-bulk-len
+    proto_max_bulk_len: Arc<AtomicUsize>,
+    // Test hook (DEBUG SET-ACTIVE-EXPIRE 0): pauses the reaper so expired
+    // keys only go away when accessed
+    active_expire: Arc<AtomicBool>,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Self {
+            addrs: match parse_addrs(
+                &env::var("RUDIS_ADDR").unwrap_or_else(|_| "127.0.0.1:6379".to_string()),
+            ) {
+                addrs if addrs.is_empty() => {
+                    tracing::error!("Invalid RUDIS_ADDR: no address to listen on");
+                    std::process::exit(1);
+                }
+                addrs => addrs,
+            },
+            metrics_addr: env::var("RUDIS_METRICS_ADDR")
+                .ok()
+                .filter(|v| !v.is_empty()),
+            dbfilename: env::var("RUDIS_DBFILENAME").unwrap_or_else(|_| "dump.rudis".to_string()),
+            appendfilename: env::var("RUDIS_APPENDFILENAME")
+                .unwrap_or_else(|_| "appendonly.aof".to_string()),
+            max_nesting_depth: env::var("RUDIS_MAX_NESTING_DEPTH")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_MAX_DEPTH),
+            client_query_buffer_limit: env::var("RUDIS_CLIENT_QUERY_BUFFER_LIMIT")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_CLIENT_QUERY_BUFFER_LIMIT),
+            output_buffer_limit_normal: env::var("RUDIS_CLIENT_OUTPUT_BUFFER_LIMIT_NORMAL")
+                .ok()
+                .and_then(|v| OutputBufferLimit::parse(&v))
+                .unwrap_or(OutputBufferLimit::NORMAL),
+            output_buffer_limit_pubsub: env::var("RUDIS_CLIENT_OUTPUT_BUFFER_LIMIT_PUBSUB")
+                .ok()
+                .and_then(|v| OutputBufferLimit::parse(&v))
+                .unwrap_or(OutputBufferLimit::PUBSUB),
+            output_flush_bytes: env::var("RUDIS_OUTPUT_FLUSH_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_OUTPUT_FLUSH_BYTES),
+            notify_keyspace_events: env::var("RUDIS_NOTIFY_KEYSPACE_EVENTS")
+                .ok()
+                .and_then(|v| EventFlags::parse(&v))
+                .unwrap_or_default(),
+            hz: env::var("RUDIS_HZ")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(10)
+                .clamp(1, 500),
+            tcp_nodelay: env::var("RUDIS_TCP_NODELAY")
+                .map(|v| !matches!(v.to_lowercase().as_str(), "no" | "0" | "false"))
+                .unwrap_or(true),
+            tcp_keepalive: env::var("RUDIS_TCP_KEEPALIVE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_TCP_KEEPALIVE),
+            maxclients: env::var("RUDIS_MAXCLIENTS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|&n| n > 0)
+                .unwrap_or(DEFAULT_MAXCLIENTS),
+            maxmemory: env::var("RUDIS_MAXMEMORY")
+                .ok()
+                .and_then(|v| parse_size(&v))
+                .unwrap_or(0),
+            maxmemory_policy: env::var("RUDIS_MAXMEMORY_POLICY")
+                .ok()
+                .and_then(|v| MaxmemoryPolicy::parse(&v))
+                .unwrap_or(MaxmemoryPolicy::NoEviction),
+            default_ttl_seconds: env::var("RUDIS_DEFAULT_TTL_SECONDS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+            command_timeout_ms: env::var("RUDIS_COMMAND_TIMEOUT_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+            latency_monitor_threshold: env::var("RUDIS_LATENCY_MONITOR_THRESHOLD")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+            requirepass: env::var("RUDIS_REQUIREPASS").ok().filter(|v| !v.is_empty()),
+            // A typo here must not leave a command enabled that was meant to
+            // be disabled, so unlike the other settings it stops the server
+            rename_commands: match CommandRenames::parse(
+                &env::var("RUDIS_RENAME_COMMAND").unwrap_or_default(),
+            ) {
+                Ok(renames) => renames,
+                Err(e) => {
+                    tracing::error!("Invalid RUDIS_RENAME_COMMAND: {}", e);
+                    std::process::exit(1);
+                }
+            },
+            save: Arc::new(RwLock::new(
+                env::var("RUDIS_SAVE")
+                    .ok()
+                    .and_then(|v| SavePoint::parse_all(&v))
+                    .unwrap_or_else(|| SavePoint::parse_all(DEFAULT_SAVE).unwrap()),
+            )),
+            encoding_limits: Arc::new(RwLock::new(EncodingLimits::DEFAULT)),
+            proto_max_bulk_len: Arc::new(AtomicUsize::new(
+                env::var("RUDIS_PROTO_MAX_BULK_LEN")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .filter(|&len| len >= MIN_PROTO_MAX_BULK_LEN)
+                    .unwrap_or(DEFAULT_MAX_BULK_LEN),
+            )),
+            active_expire: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    // CONFIG GET: every parameter whose name matches the glob pattern
+    pub fn get_parameters(&self, pattern: &[u8]) -> Vec<(String, String)> {
+        let limits = self.encoding_limits();
+        let mut parameters = vec![
+            ("dbfilename".to_string(), self.dbfilename.clone()),
+            ("appendfilename".to_string(), self.appendfilename.clone()),
+            (
+                "save".to_string(),
+                SavePoint::format_all(&self.save_points()),
+            ),
+            (
+                "proto-max-bulk-len".to_string(),
+                self.proto_max_bulk_len().to_string(),
+            ),
+            ("tcp-keepalive".to_string(), self.tcp_keepalive.to_string()),
+            ("maxclients".to_string(), self.maxclients.to_string()),
+            ("maxmemory".to_string(), self.maxmemory.to_string()),
+            (
+                "maxmemory-policy".to_string(),
+                self.maxmemory_policy.name().to_string(),
+            ),
+            (
+                "default-ttl-seconds".to_string(),
+                self.default_ttl_seconds.to_string(),
+            ),
+            (
+                "command-timeout-ms".to_string(),
+                self.command_timeout_ms.to_string(),
+            ),
+            (
+                "latency-monitor-threshold".to_string(),
+                self.latency_monitor_threshold.to_string(),
+            ),
+            (
+                "output-flush-bytes".to_string(),
+                self.output_flush_bytes.to_string(),
+            ),
+        ];
+        for name in EncodingLimits::PARAMETERS {
+            parameters.push((name.to_string(), limits.get(name).unwrap()));
+        }
+        parameters
+            .into_iter()
+            .filter(|(name, _)| glob::matches(&pattern.to_ascii_lowercase(), name.as_bytes()))
+            .collect()
+    }
+
+    // CONFIG SET for the parameters that can change at runtime
+    pub fn set_parameter(&self, name: &str, value: &str) -> Result<(), String> {
+        let invalid = || format!("ERR Invalid argument '{}' for CONFIG SET '{}'", value, name);
+        if name == "save" {
+            let points = SavePoint::parse_all(value).ok_or_else(invalid)?;
+            self.set_save_points(points);
+            return Ok(());
+        }
+        if name == "proto-max-bulk-len" {
+            let len = value
+                .parse::<usize>()
+                .ok()
+                .filter(|&len| len >= MIN_PROTO_MAX_BULK_LEN)
+                .ok_or_else(invalid)?;
+            self.proto_max_bulk_len.store(len, Ordering::Relaxed);
+            return Ok(());
+        }
+        if EncodingLimits::PARAMETERS.contains(&name) {
+            let number = value.parse::<i64>().map_err(|_| invalid())?;
+            let mut limits = lock::write(&self.encoding_limits);
+            return limits.set(name, number).ok_or_else(invalid);
+        }
+        Err(format!(
+            "ERR Unknown option or number of arguments for CONFIG SET - '{}'",
+            name
+        ))
+    }
+
+    pub fn encoding_limits(&self) -> EncodingLimits {
+        *lock::read(&self.encoding_limits)
+    }
+
+    pub fn proto_max_bulk_len(&self) -> usize {
+        self.proto_max_bulk_len.load(Ordering::Relaxed)
+    }
+
+    pub fn active_expire_enabled(&self) -> bool {
+        self.active_expire.load(Ordering::Relaxed)
+    }
+
+    pub fn set_active_expire(&self, enabled: bool) {
+        self.active_expire.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn save_points(&self) -> Vec<SavePoint> {
+        lock::read(&self.save).clone()
+    }
+
+    pub fn set_save_points(&self, points: Vec<SavePoint>) {
+        *lock::write(&self.save) = points;
+    }
+}
+
+// RUDIS_ADDR: comma-separated, e.g. "127.0.0.1:6379,[::1]:6379"
+fn parse_addrs(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// Redis' rename-command, from whitespace-separated OLD=NEW pairs: the
+// command is then only reachable as NEW, or not at all when NEW is empty.
+//   RUDIS_RENAME_COMMAND="FLUSHDB= CONFIG=ADMIN-CONFIG"
+#[derive(Clone, Debug, Default)]
+pub struct CommandRenames {
+    // Uppercase name clients send -> real name, or None when disabled
+    names: HashMap<String, Option<String>>,
+}
+
+impl CommandRenames {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut names = HashMap::new();
+        let mut renamed = Vec::new();
+        for pair in spec.split_whitespace() {
+            let Some((old, new)) = pair.split_once('=') else {
+                return Err(format!("expected OLD=NEW, got '{}'", pair));
+            };
+            let (old, new) = (old.to_uppercase(), new.to_uppercase());
+            if command_table::lookup(&old).is_none() {
+                return Err(format!("unknown command '{}'", old));
+            }
+            if renamed.contains(&old) {
+                return Err(format!("'{}' is renamed twice", old));
+            }
+            renamed.push(old.clone());
+            // The old name stops working whatever happens to the new one
+            names.entry(old.clone()).or_insert(None);
+            if !new.is_empty() {
+                if command_table::lookup(&new).is_some() && !renamed.contains(&new) {
+                    return Err(format!("'{}' is already a command", new));
+                }
+                if names.get(&new).is_some_and(|target| target.is_some()) {
+                    return Err(format!("'{}' is used twice", new));
+                }
+                names.insert(new, Some(old));
+            }
+        }
+        Ok(Self { names })
+    }
+
+    // The command to run for a name sent by a client, None if it's disabled
+    pub fn resolve<'a>(&'a self, name: &'a str) -> Option<&'a str> {
+        match self.names.get(name) {
+            Some(target) => target.as_deref(),
+            None => Some(name),
+        }
+    }
+}
+
+// Redis' *-max-listpack-* / set-max-intset-entries thresholds. Collections
+// are always stored the same way; these only decide what OBJECT ENCODING says.
+#[derive(Clone, Copy, Debug)]
+pub struct EncodingLimits {
+    // Positive: most entries per listpack; -1 to -5: most bytes (4kb to 64kb)
+    pub list_max_listpack_size: i64,
+    pub set_max_intset_entries: usize,
+    pub set_max_listpack_entries: usize,
+    pub set_max_listpack_value: usize,
+    pub zset_max_listpack_entries: usize,
+    pub zset_max_listpack_value: usize,
+    pub hash_max_listpack_entries: usize,
+    pub hash_max_listpack_value: usize,
+}
+
+impl EncodingLimits {
+    pub const DEFAULT: Self = Self {
+        list_max_listpack_size: -2,
+        set_max_intset_entries: 512,
+        set_max_listpack_entries: 128,
+        set_max_listpack_value: 64,
+        zset_max_listpack_entries: 128,
+        zset_max_listpack_value: 64,
+        hash_max_listpack_entries: 128,
+        hash_max_listpack_value: 64,
+    };
+
+    pub const PARAMETERS: [&str; 8] = [
+        "list-max-listpack-size",
+        "set-max-intset-entries",
+        "set-max-listpack-entries",
+        "set-max-listpack-value",
+        "zset-max-listpack-entries",
+        "zset-max-listpack-value",
+        "hash-max-listpack-entries",
+        "hash-max-listpack-value",
+    ];
+
+    fn get(&self, name: &str) -> Option<String> {
+        let value = match name {
+            "list-max-listpack-size" => return Some(self.list_max_listpack_size.to_string()),
+            "set-max-intset-entries" => self.set_max_intset_entries,
+            "set-max-listpack-entries" => self.set_max_listpack_entries,
+            "set-max-listpack-value" => self.set_max_listpack_value,
+            "zset-max-listpack-entries" => self.zset_max_listpack_entries,
+            "zset-max-listpack-value" => self.zset_max_listpack_value,
+            "hash-max-listpack-entries" => self.hash_max_listpack_entries,
+            "hash-max-listpack-value" => self.hash_max_listpack_value,
+            _ => return None,
+        };
+        Some(value.to_string())
+    }
+
+    // None if the value is out of range for the parameter
+    fn set(&mut self, name: &str, value: i64) -> Option<()> {
+        if name == "list-max-listpack-size" {
+            // Like Redis, sizes below -5 mean the 64kb limit
+            self.list_max_listpack_size = value.max(-5);
+            return Some(());
+        }
+        let value = usize::try_from(value).ok()?;
+        let field = match name {
+            "set-max-intset-entries" => &mut self.set_max_intset_entries,
+            "set-max-listpack-entries" => &mut self.set_max_listpack_entries,
+            "set-max-listpack-value" => &mut self.set_max_listpack_value,
+            "zset-max-listpack-entries" => &mut self.zset_max_listpack_entries,
+            "zset-max-listpack-value" => &mut self.zset_max_listpack_value,
+            "hash-max-listpack-entries" => &mut self.hash_max_listpack_entries,
+            "hash-max-listpack-value" => &mut self.hash_max_listpack_value,
+            _ => return None,
+        };
+        *field = value;
+        Some(())
+    }
+
+    // Whether a list of `len` elements totalling `bytes` fits in one listpack
+    pub fn list_fits_listpack(&self, len: usize, bytes: usize) -> bool {
+        match self.list_max_listpack_size {
+            size if size > 0 => len <= size as usize,
+            0 => false,
+            size => bytes <= 4096 << (-size - 1),
+        }
+    }
+
+    // Entries in each node Redis' quicklist would use for elements of these
+    // sizes: up to `list-max-listpack-size` elements per node, or as many as
+    // fit its byte limit when negative. An approximation, since Rudis keeps
+    // lists flat and per-entry listpack overhead isn't counted.
+    pub fn quicklist_chunks(&self, sizes: impl Iterator<Item = usize>) -> Vec<usize> {
+        let mut chunks: Vec<usize> = Vec::new();
+        let mut bytes = 0;
+        for size in sizes {
+            match chunks.last_mut() {
+                Some(len) if self.list_fits_listpack(*len + 1, bytes + size) => *len += 1,
+                _ => {
+                    chunks.push(1);
+                    bytes = 0;
+                }
+            }
+            bytes += size;
+        }
+        chunks
+    }
+
+    pub fn quicklist_nodes(&self, sizes: impl Iterator<Item = usize>) -> usize {
+        self.quicklist_chunks(sizes).len()
+    }
+}
+
+// Redis' default client-query-buffer-limit
+const DEFAULT_CLIENT_QUERY_BUFFER_LIMIT: usize = 1024 * 1024 * 1024;
+
+// Large enough to batch a pipeline of small replies into a few writes
+const DEFAULT_OUTPUT_FLUSH_BYTES: usize = 64 * 1024;
+
+// Redis refuses a proto-max-bulk-len below 1MB
+const MIN_PROTO_MAX_BULK_LEN: usize = 1024 * 1024;
+
+// Redis' default tcp-keepalive, in seconds
+const DEFAULT_TCP_KEEPALIVE: u64 = 300;
+
+// Redis' default maxclients
+const DEFAULT_MAXCLIENTS: usize = 10000;
+
+// Same defaults as Redis: after 1 hour if 1 key changed, 5 minutes if 100
+// changed, 1 minute if 10000 changed
+const DEFAULT_SAVE: &str = "3600 1 300 100 60 10000";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SavePoint {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+impl SavePoint {
+    // "<seconds> <changes> [<seconds> <changes> ...]"; an empty string disables saving
+    pub fn parse_all(value: &str) -> Option<Vec<Self>> {
+        let numbers: Vec<u64> = value
+            .split_whitespace()
+            .map(|n| n.parse().ok())
+            .collect::<Option<_>>()?;
+        if !numbers.len().is_multiple_of(2) {
+            return None;
+        }
+        Some(
+            numbers
+                .chunks(2)
+                .map(|pair| Self {
+                    seconds: pair[0],
+                    changes: pair[1],
+                })
+                .collect(),
+        )
+    }
+
+    pub fn format_all(points: &[Self]) -> String {
+        points
+            .iter()
+            .map(|p| format!("{} {}", p.seconds, p.changes))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+// maxmemory-policy. The allkeys-* policies evict from every key and the
+// volatile-* ones only from keys with an expiry; the LFU policies are not
+// supported.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MaxmemoryPolicy {
+    NoEviction,
+    AllKeysLru,
+    VolatileLru,
+    AllKeysRandom,
+    VolatileRandom,
+    VolatileTtl,
+}
+
+impl MaxmemoryPolicy {
+    const ALL: [Self; 6] = [
+        Self::NoEviction,
+        Self::AllKeysLru,
+        Self::VolatileLru,
+        Self::AllKeysRandom,
+        Self::VolatileRandom,
+        Self::VolatileTtl,
+    ];
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|policy| policy.name().eq_ignore_ascii_case(value))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::NoEviction => "noeviction",
+            Self::AllKeysLru => "allkeys-lru",
+            Self::VolatileLru => "volatile-lru",
+            Self::AllKeysRandom => "allkeys-random",
+            Self::VolatileRandom => "volatile-random",
+            Self::VolatileTtl => "volatile-ttl",
+        }
+    }
+
+    // Whether only keys with an expiry may be evicted
+    pub fn is_volatile(self) -> bool {
+        matches!(
+            self,
+            Self::VolatileLru | Self::VolatileRandom | Self::VolatileTtl
+        )
+    }
+}
+
+// Same shape as Redis' client-output-buffer-limit: a connection is closed
+// once its pending output reaches `hard` bytes, or stays at or above `soft`
+// bytes for `soft_seconds`. A limit of 0 disables that check.
+#[derive(Clone, Copy, Debug)]
+pub struct OutputBufferLimit {
+    pub hard: usize,
+    pub soft: usize,
+    pub soft_seconds: u64,
+}
+
+impl OutputBufferLimit {
+    pub const NORMAL: Self = Self {
+        hard: 0,
+        soft: 0,
+        soft_seconds: 0,
+    };
+
+    pub const PUBSUB: Self = Self {
+        hard: 32 * 1024 * 1024,
+        soft: 8 * 1024 * 1024,
+        soft_seconds: 60,
+    };
+
+    // "<hard> <soft> <soft seconds>", sizes may use k/kb/m/mb/g/gb suffixes
+    pub fn parse(value: &str) -> Option<Self> {
+        let parts: Vec<&str> = value.split_whitespace().collect();
+        if parts.len() != 3 {
+            return None;
+        }
+        Some(Self {
+            hard: parse_size(parts[0])?,
+            soft: parse_size(parts[1])?,
+            soft_seconds: parts[2].parse().ok()?,
+        })
+    }
+
+    // Check `pending` bytes against the limit; `soft_since` remembers when the
+    // soft limit was first crossed and is reset once the backlog drains below it
+    pub fn is_exceeded(&self, pending: usize, soft_since: &mut Option<Instant>) -> bool {
+        if self.hard > 0 && pending >= self.hard {
+            return true;
+        }
+        if self.soft == 0 || pending < self.soft {
+            *soft_since = None;
+            return false;
+        }
+        let since = *soft_since.get_or_insert_with(Instant::now);
+        since.elapsed() >= Duration::from_secs(self.soft_seconds)
+    }
+}
+
+fn parse_size(value: &str) -> Option<usize> {
+    let value = value.to_lowercase();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let multiplier = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    number.parse::<usize>().ok()?.checked_mul(multiplier)
+}
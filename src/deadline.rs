@@ -0,0 +1,49 @@
+use std::time::{Duration, Instant};
+
+// Elements handled between looks at the clock, which costs more than
+// copying one element out
+const CHECK_INTERVAL: usize = 1024;
+
+// command-timeout-ms: when a command building a large result gives up with
+// an error, instead of holding the keyspace lock for as long as it takes
+#[derive(Clone, Copy, Debug)]
+pub struct Deadline(Option<Instant>);
+
+impl Deadline {
+    pub const NONE: Self = Self(None);
+
+    // A zero timeout means no deadline
+    pub fn after(timeout: Duration) -> Self {
+        if timeout.is_zero() {
+            Self::NONE
+        } else {
+            Self(Some(Instant::now() + timeout))
+        }
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        match self.0 {
+            Some(at) if Instant::now() > at => Err("ERR command exceeded time limit".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    // check, but only once every CHECK_INTERVAL elements of a loop
+    pub fn tick(&self, done: usize) -> Result<(), String> {
+        if done.is_multiple_of(CHECK_INTERVAL) {
+            self.check()
+        } else {
+            Ok(())
+        }
+    }
+
+    // Collect the items, giving up once the deadline passes
+    pub fn collect<T>(&self, items: impl Iterator<Item = T>) -> Result<Vec<T>, String> {
+        let mut collected = Vec::with_capacity(items.size_hint().0);
+        for (done, item) in items.enumerate() {
+            self.tick(done)?;
+            collected.push(item);
+        }
+        Ok(collected)
+    }
+}
@@ -0,0 +1,85 @@
+// Redis-style glob matching: `*`, `?`, `[abc]`, `[^a-z]` and `\` escapes
+pub fn matches(pattern: &[u8], string: &[u8]) -> bool {
+    let (mut p, mut s) = (pattern, string);
+
+    while let Some(&c) = p.first() {
+        match c {
+            b'*' => {
+                // Collapse consecutive stars, then try every possible split
+                while p.len() > 1 && p[1] == b'*' {
+                    p = &p[1..];
+                }
+                if p.len() == 1 {
+                    return true;
+                }
+                return (0..=s.len()).any(|i| matches(&p[1..], &s[i..]));
+            }
+            b'?' => {
+                if s.is_empty() {
+                    return false;
+                }
+                s = &s[1..];
+            }
+            b'[' => {
+                let Some(&ch) = s.first() else {
+                    return false;
+                };
+                let (matched, consumed) = match_class(&p[1..], ch);
+                if !matched {
+                    return false;
+                }
+                p = &p[consumed..];
+                s = &s[1..];
+            }
+            b'\\' if p.len() >= 2 => {
+                if s.first() != Some(&p[1]) {
+                    return false;
+                }
+                p = &p[1..];
+                s = &s[1..];
+            }
+            _ => {
+                if s.first() != Some(&c) {
+                    return false;
+                }
+                s = &s[1..];
+            }
+        }
+        p = &p[1..];
+    }
+
+    s.is_empty()
+}
+
+// Match `ch` against the class body following `[`; returns whether it
+// matched and how many pattern bytes were used, up to (not past) the `]`
+fn match_class(class: &[u8], ch: u8) -> (bool, usize) {
+    let mut i = 0;
+    let negate = class.first() == Some(&b'^');
+    if negate {
+        i += 1;
+    }
+
+    let mut matched = false;
+    while i < class.len() && class[i] != b']' {
+        if class[i] == b'\\' && i + 1 < class.len() {
+            i += 1;
+            matched |= class[i] == ch;
+        } else if i + 2 < class.len() && class[i + 1] == b'-' && class[i + 2] != b']' {
+            let (start, end) = if class[i] <= class[i + 2] {
+                (class[i], class[i + 2])
+            } else {
+                (class[i + 2], class[i])
+            };
+            matched |= (start..=end).contains(&ch);
+            i += 2;
+        } else {
+            matched |= class[i] == ch;
+        }
+        i += 1;
+    }
+
+    // An unterminated class runs to the end of the pattern
+    let consumed = i.min(class.len().saturating_sub(1)) + 1;
+    (matched != negate, consumed)
+}
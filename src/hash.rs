@@ -0,0 +1,209 @@
+use bytes::Bytes;
+use std::collections::{BTreeMap, HashMap};
+use std::time::SystemTime;
+
+#[derive(Clone, Debug)]
+struct Field {
+    value: Bytes,
+    expires_at: Option<SystemTime>,
+    // Position in insertion order; the key of the field in `Hash::order`
+    seq: u64,
+}
+
+impl Field {
+    fn is_expired(&self, now: SystemTime) -> bool {
+        matches!(self.expires_at, Some(expires_at) if now > expires_at)
+    }
+}
+
+// Hash fields can expire individually (HEXPIRE). Expired fields are hidden
+// from reads straight away and dropped by the next write or reaper pass.
+// Whatever depends on expiry takes the time it is asked at, the store's
+// clock, so field TTLs move with key TTLs.
+// Fields iterate in insertion order, like Redis' listpack-encoded hashes,
+// and overwriting a field keeps its place.
+#[derive(Clone, Debug, Default)]
+pub struct Hash {
+    fields: HashMap<Bytes, Field>,
+    order: BTreeMap<u64, Bytes>,
+    next_seq: u64,
+}
+
+impl Hash {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns true if the field was not present before. Like Redis,
+    // overwriting a field clears its expiry.
+    pub fn insert(&mut self, field: Bytes, value: Bytes, now: SystemTime) -> bool {
+        if let Some(old) = self.fields.get_mut(&field)
+            && !old.is_expired(now)
+        {
+            old.value = value;
+            old.expires_at = None;
+            return false;
+        }
+
+        // New, or replacing a field that had expired: it goes to the end
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.order.insert(seq, field.clone());
+        let previous = self.fields.insert(
+            field,
+            Field {
+                value,
+                expires_at: None,
+                seq,
+            },
+        );
+        if let Some(old) = previous {
+            self.order.remove(&old.seq);
+        }
+        true
+    }
+
+    pub fn get(&self, field: &[u8], now: SystemTime) -> Option<&Bytes> {
+        self.live(field, now).map(|f| &f.value)
+    }
+
+    pub fn remove(&mut self, field: &[u8], now: SystemTime) -> bool {
+        let Some(old) = self.fields.remove(field) else {
+            return false;
+        };
+        self.order.remove(&old.seq);
+        !old.is_expired(now)
+    }
+
+    // None if the field doesn't exist, Some(None) if it never expires
+    pub fn expires_at(&self, field: &[u8], now: SystemTime) -> Option<Option<SystemTime>> {
+        self.live(field, now).map(|f| f.expires_at)
+    }
+
+    // Returns false if the field doesn't exist
+    pub fn set_expiry(
+        &mut self,
+        field: &[u8],
+        expires_at: Option<SystemTime>,
+        now: SystemTime,
+    ) -> bool {
+        match self.fields.get_mut(field) {
+            Some(f) if !f.is_expired(now) => {
+                f.expires_at = expires_at;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn len(&self, now: SystemTime) -> usize {
+        self.iter(now).count()
+    }
+
+    pub fn is_empty(&self, now: SystemTime) -> bool {
+        self.len(now) == 0
+    }
+
+    // Live fields with their values and expiry times, in insertion order
+    pub fn iter(
+        &self,
+        now: SystemTime,
+    ) -> impl Iterator<Item = (&Bytes, &Bytes, Option<SystemTime>)> {
+        self.order
+            .values()
+            .filter_map(|name| Some((name, self.fields.get(name)?)))
+            .filter(move |(_, f)| !f.is_expired(now))
+            .map(|(name, f)| (name, &f.value, f.expires_at))
+    }
+
+    // Every field, expired ones not purged yet included, in insertion order:
+    // what snapshots and the AOF save, so they don't need a clock and a
+    // field that has expired is dropped wherever it is loaded
+    pub fn fields(&self) -> impl Iterator<Item = (&Bytes, &Bytes, Option<SystemTime>)> {
+        self.order
+            .values()
+            .filter_map(|name| Some((name, self.fields.get(name)?)))
+            .map(|(name, f)| (name, &f.value, f.expires_at))
+    }
+
+    // A field as fields() gave it, for loading a snapshot: appended with its
+    // expiry, whether or not that has passed
+    pub fn load_field(&mut self, field: Bytes, value: Bytes, expires_at: Option<SystemTime>) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.order.insert(seq, field.clone());
+        let previous = self.fields.insert(
+            field,
+            Field {
+                value,
+                expires_at,
+                seq,
+            },
+        );
+        if let Some(old) = previous {
+            self.order.remove(&old.seq);
+        }
+    }
+
+    // HSCAN: up to count live fields from the cursor on, with the cursor to
+    // continue from (0 once done). The cursor is a position in insertion
+    // order, which an overwrite doesn't change, so a field present for the
+    // whole iteration is returned exactly once.
+    pub fn scan(&self, cursor: u64, count: usize, now: SystemTime) -> (u64, Vec<(&Bytes, &Bytes)>) {
+        let mut live = self
+            .order
+            .range(cursor..)
+            .filter_map(|(&seq, name)| Some((seq, name, self.fields.get(name)?)))
+            .filter(|(_, _, f)| !f.is_expired(now));
+        let fields = live
+            .by_ref()
+            .take(count.max(1))
+            .map(|(_, name, f)| (name, &f.value))
+            .collect();
+        // Fields are numbered from 0 but the next one always follows a
+        // returned field, so 0 can still mean done
+        let next = live.next().map_or(0, |(seq, _, _)| seq);
+        (next, fields)
+    }
+
+    // Estimated bytes held, for INFO's used_memory: every field including
+    // expired ones not purged yet, each with its map entry and order slot
+    pub fn memory_usage(&self) -> usize {
+        self.fields
+            .iter()
+            .map(|(name, field)| {
+                name.len()
+                    + field.value.len()
+                    + 2 * std::mem::size_of::<Bytes>()
+                    + std::mem::size_of::<Field>()
+                    + std::mem::size_of::<u64>()
+            })
+            .sum()
+    }
+
+    pub fn has_field_expiry(&self) -> bool {
+        self.fields.values().any(|f| f.expires_at.is_some())
+    }
+
+    pub fn has_expired_fields(&self, now: SystemTime) -> bool {
+        self.fields.values().any(|f| f.is_expired(now))
+    }
+
+    // Drop expired fields, returning how many were removed
+    pub fn purge_expired(&mut self, now: SystemTime) -> usize {
+        let before = self.fields.len();
+        let order = &mut self.order;
+        self.fields.retain(|_, f| {
+            let live = !f.is_expired(now);
+            if !live {
+                order.remove(&f.seq);
+            }
+            live
+        });
+        before - self.fields.len()
+    }
+
+    fn live(&self, field: &[u8], now: SystemTime) -> Option<&Field> {
+        self.fields.get(field).filter(|f| !f.is_expired(now))
+    }
+}
@@ -0,0 +1,137 @@
+use crate::store::Value;
+use crate::zset;
+use bytes::Bytes;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// DEBUG DUMPJSON output: keys sorted by name, one page at a time.
+//   {"keys":[{"key":"<base64>","type":"string","expires_at_ms":null,"value":"<base64>"}, ...],
+//    "total":<n>,"next":<offset of the next page, or null>}
+// Keys and values are base64, so binary ones survive. Lists and sets hold
+// arrays of base64 strings, sorted sets {"member","score"} objects (scores
+// as strings, so inf survives) and hashes {"field","value","expires_at_ms"}
+// objects. `page` is the keys from `offset` on, as Store::page_by_name
+// returns them, out of `total`.
+pub fn dump(
+    page: Vec<(Bytes, Value, Option<SystemTime>)>,
+    offset: usize,
+    count: usize,
+    total: usize,
+) -> String {
+    let keys: Vec<String> = page
+        .iter()
+        .map(|(key, value, expires_at)| encode_entry(key, value, *expires_at))
+        .collect();
+    let next = if offset.saturating_add(count) < total {
+        (offset + count).to_string()
+    } else {
+        "null".to_string()
+    };
+
+    format!(
+        "{{\"keys\":[{}],\"total\":{},\"next\":{}}}",
+        keys.join(","),
+        total,
+        next
+    )
+}
+
+fn encode_entry(key: &[u8], value: &Value, expires_at: Option<SystemTime>) -> String {
+    let (kind, payload) = match value {
+        Value::String(val) => ("string", string(&base64(&val.to_bytes()))),
+        Value::List(list) => ("list", array(list.iter().map(|v| string(&base64(v))))),
+        Value::Set(set) => {
+            let mut members: Vec<_> = set.iter().collect();
+            members.sort();
+            (
+                "set",
+                array(members.into_iter().map(|m| string(&base64(m)))),
+            )
+        }
+        Value::ZSet(zset) => (
+            "zset",
+            array(zset.iter().map(|(member, score)| {
+                format!(
+                    "{{\"member\":{},\"score\":{}}}",
+                    string(&base64(member)),
+                    string(&zset::format_score(score))
+                )
+            })),
+        ),
+        Value::Hash(hash) => {
+            let mut fields: Vec<_> = hash.fields().collect();
+            fields.sort_by(|a, b| a.0.cmp(b.0));
+            (
+                "hash",
+                array(fields.into_iter().map(|(field, value, expires_at)| {
+                    format!(
+                        "{{\"field\":{},\"value\":{},\"expires_at_ms\":{}}}",
+                        string(&base64(field)),
+                        string(&base64(value)),
+                        millis(expires_at)
+                    )
+                })),
+            )
+        }
+    };
+
+    format!(
+        "{{\"key\":{},\"type\":\"{}\",\"expires_at_ms\":{},\"value\":{}}}",
+        string(&base64(key)),
+        kind,
+        millis(expires_at),
+        payload
+    )
+}
+
+fn array(items: impl Iterator<Item = String>) -> String {
+    format!("[{}]", items.collect::<Vec<_>>().join(","))
+}
+
+fn millis(at: Option<SystemTime>) -> String {
+    match at.and_then(|t| t.duration_since(UNIX_EPOCH).ok()) {
+        Some(d) => d.as_millis().to_string(),
+        None => "null".to_string(),
+    }
+}
+
+// JSON string literal with the escapes RFC 8259 requires
+fn string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// Standard base64 with padding
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,295 @@
+use crate::clock::Clock;
+use crate::scan::ScanTable;
+use crate::store::Value;
+use bytes::Bytes;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// A value and when it was last accessed, in Unix seconds like Redis' LRU
+// clock. The access time is atomic so lookups under the read lock can
+// update it. `size` is the key and value's estimated memory use as of the
+// last time it was measured.
+struct Entry {
+    value: Value,
+    accessed: AtomicU64,
+    size: usize,
+}
+
+impl Entry {
+    fn new(key: &[u8], value: Value) -> Self {
+        Self {
+            size: key.len() + value.memory_usage(),
+            value,
+            accessed: AtomicU64::new(unix_secs()),
+        }
+    }
+
+    fn touch(&self) {
+        self.accessed.store(unix_secs(), Ordering::Relaxed);
+    }
+}
+
+// The key -> value map together with Redis' "expires" dict: the expiry of
+// every key that has one, whatever its type. Active expiry walks only those
+// keys, and INFO counts them without a scan. Every change to a key goes
+// through here so the two stay in step. A key whose expiry has passed is
+// hidden from lookups and iter until it is removed, so it reads as missing
+// even before lazy or active expiry gets to it. get and get_mut count as an
+// access for OBJECT IDLETIME and LRU eviction; peek and iter don't.
+//
+// The estimated memory use is kept as a running total of the entry sizes,
+// so checking it against maxmemory doesn't walk the keyspace. Values handed
+// out by get_mut may change in place, so those keys are measured again the
+// next time the total is read.
+//
+// Every key is also in a ScanTable, the buckets SCAN's cursor walks.
+#[derive(Default)]
+pub struct Keyspace {
+    entries: HashMap<Bytes, Entry>,
+    scan_table: ScanTable,
+    used_memory: usize,
+    resized: HashSet<Bytes>,
+    expires: HashMap<Bytes, SystemTime>,
+    // Hashes that have had a field expiry set; a superset, pruned by the
+    // reaper once a hash no longer has any
+    field_expires: HashSet<Bytes>,
+    // What "passed" means for an expiry
+    clock: Clock,
+}
+
+impl Keyspace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_clock(clock: Clock) -> Self {
+        Self {
+            clock,
+            ..Self::default()
+        }
+    }
+
+    // For a keyspace built elsewhere (a loaded snapshot) before it goes live
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&Value> {
+        let entry = self.live(key)?;
+        entry.touch();
+        Some(&entry.value)
+    }
+
+    pub fn get_mut(&mut self, key: &[u8]) -> Option<&mut Value> {
+        if self.is_expired(key) {
+            return None;
+        }
+        let entry = self.entries.get_mut(key)?;
+        entry.touch();
+        if !self.resized.contains(key) {
+            self.resized.insert(Bytes::copy_from_slice(key));
+        }
+        Some(&mut entry.value)
+    }
+
+    // A lookup that leaves the access time alone, for introspection
+    pub fn peek(&self, key: &[u8]) -> Option<&Value> {
+        self.live(key).map(|entry| &entry.value)
+    }
+
+    fn live(&self, key: &[u8]) -> Option<&Entry> {
+        let entry = self.entries.get(key)?;
+        (!self.is_expired(key)).then_some(entry)
+    }
+
+    // Whether `key` has an expiry that has passed; it is still in the
+    // keyspace until lazy or active expiry removes it
+    pub fn is_expired(&self, key: &[u8]) -> bool {
+        !self.expires.is_empty()
+            && self
+                .expires
+                .get(key)
+                .is_some_and(|&at| self.clock.now() > at)
+    }
+
+    // When the key expires, None if it never does
+    pub fn expires_at(&self, key: &[u8]) -> Option<SystemTime> {
+        self.expires.get(key).copied()
+    }
+
+    // expires_at for a live key; None if there is no such key
+    pub fn expiry(&self, key: &[u8]) -> Option<Option<SystemTime>> {
+        self.contains_key(key).then(|| self.expires_at(key))
+    }
+
+    // Seconds since the key was last accessed
+    pub fn idle_secs(&self, key: &[u8]) -> Option<u64> {
+        let accessed = self.entries.get(key)?.accessed.load(Ordering::Relaxed);
+        Some(unix_secs().saturating_sub(accessed))
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.live(key).is_some()
+    }
+
+    // A new value replaces the old one and its expiry, like SET
+    pub fn insert(&mut self, key: Bytes, value: Value) -> Option<Value> {
+        self.index(&key, &value);
+        let entry = Entry::new(&key, value);
+        self.used_memory += entry.size;
+        let old = match self.entries.insert(key.clone(), entry) {
+            Some(old) => old,
+            None => {
+                self.scan_table.insert(key);
+                return None;
+            }
+        };
+        self.used_memory -= old.size;
+        Some(old.value)
+    }
+
+    // The value at `key`, first inserting the one `default` makes
+    pub fn get_or_insert_with(
+        &mut self,
+        key: &[u8],
+        default: impl FnOnce() -> Value,
+    ) -> &mut Value {
+        if !self.contains_key(key) {
+            self.insert(Bytes::copy_from_slice(key), default());
+        }
+        self.get_mut(key).unwrap()
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<Value> {
+        self.expires.remove(key);
+        self.field_expires.remove(key);
+        self.resized.remove(key);
+        let entry = self.entries.remove(key)?;
+        self.scan_table.remove(key);
+        self.used_memory -= entry.size;
+        Some(entry.value)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.scan_table.clear();
+        self.expires.clear();
+        self.field_expires.clear();
+        self.resized.clear();
+        self.used_memory = 0;
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.entries.reserve(additional);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    // INFO's used_memory: the allocated entry slots and SCAN buckets plus
+    // what every key and value holds on the heap, expired ones included.
+    // Only the keys changed in place since the last call are measured again.
+    pub fn used_memory(&mut self) -> usize {
+        for key in std::mem::take(&mut self.resized) {
+            if let Some(entry) = self.entries.get_mut(&key) {
+                let size = key.len() + entry.value.memory_usage();
+                self.used_memory = self.used_memory - entry.size + size;
+                entry.size = size;
+            }
+        }
+        self.entries.capacity() * std::mem::size_of::<(Bytes, Entry)>()
+            + self.scan_table.memory_usage()
+            + self.used_memory
+    }
+
+    // The key and value's share of used_memory, as of its last call
+    pub fn memory_usage(&self, key: &[u8]) -> Option<usize> {
+        self.entries.get(key).map(|entry| entry.size)
+    }
+
+    // Every live key with its value and expiry
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, &Value, Option<SystemTime>)> {
+        self.entries
+            .iter()
+            .filter(|(key, _)| !self.is_expired(key))
+            .map(|(key, entry)| (key, &entry.value, self.expires_at(key)))
+    }
+
+    // Every key, expired ones included, for the walks that remove them or
+    // check is_expired themselves
+    pub fn keys(&self) -> impl Iterator<Item = &Bytes> {
+        self.entries.keys()
+    }
+
+    // The keys in the SCAN buckets from `cursor` on, as ScanTable::scan
+    // gathers them; expired ones included, like keys
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<&Bytes>) {
+        self.scan_table.scan(cursor, count)
+    }
+
+    // Change the expiry of a live key of any type in place; None removes
+    // it. Returns false if there is no such key.
+    pub fn set_expiry(&mut self, key: &[u8], expires_at: Option<SystemTime>) -> bool {
+        if !self.contains_key(key) {
+            return false;
+        }
+        match expires_at {
+            Some(at) => self.expires.insert(Bytes::copy_from_slice(key), at),
+            None => self.expires.remove(key),
+        };
+        true
+    }
+
+    // Note that the hash at `key` now has a field with an expiry
+    pub fn track_field_expiry(&mut self, key: &[u8]) {
+        if !self.field_expires.contains(key) {
+            self.field_expires.insert(Bytes::copy_from_slice(key));
+        }
+    }
+
+    // Keys with an expiry that has passed
+    pub fn expired_keys(&self, now: SystemTime) -> Vec<Bytes> {
+        self.expires
+            .iter()
+            .filter(|&(_, &at)| now > at)
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    // Hashes that may have expiring fields, forgetting the ones that no
+    // longer do
+    pub fn hashes_with_field_expiry(&mut self) -> Vec<Bytes> {
+        let entries = &self.entries;
+        self.field_expires.retain(
+            |key| matches!(entries.get(key), Some(Entry { value: Value::Hash(hash), .. }) if hash.has_field_expiry()),
+        );
+        self.field_expires.iter().cloned().collect()
+    }
+
+    // Keys with an expiry and when they expire
+    pub fn expiring(&self) -> impl Iterator<Item = (&Bytes, SystemTime)> {
+        self.expires.iter().map(|(key, &at)| (key, at))
+    }
+
+    // INFO's expires= count
+    pub fn expires_len(&self) -> usize {
+        self.expires.len()
+    }
+
+    fn index(&mut self, key: &[u8], value: &Value) {
+        self.expires.remove(key);
+        if matches!(value, Value::Hash(hash) if hash.has_field_expiry()) {
+            self.track_field_expiry(key);
+        } else {
+            self.field_expires.remove(key);
+        }
+    }
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
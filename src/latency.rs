@@ -0,0 +1,89 @@
+use crate::lock;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Samples kept per event, Redis' LATENCY_TS_LEN
+const HISTORY_LEN: usize = 160;
+
+#[derive(Default)]
+struct History {
+    // (unix seconds, milliseconds), oldest first; one per second, the worst
+    samples: VecDeque<(u64, u64)>,
+    max: u64,
+}
+
+// The latency monitor behind LATENCY: spikes of at least
+// latency-monitor-threshold ms, per event class ("command" for slow
+// commands), kept as a short time series like Redis does
+#[derive(Clone, Default)]
+pub struct LatencyMonitor {
+    events: Arc<Mutex<HashMap<&'static str, History>>>,
+}
+
+impl LatencyMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, event: &'static str, millis: u64) {
+        let now = unix_now();
+        let mut events = lock::mutex(&self.events);
+        let history = events.entry(event).or_default();
+        history.max = history.max.max(millis);
+        match history.samples.back_mut() {
+            Some((at, worst)) if *at == now => *worst = (*worst).max(millis),
+            _ => {
+                if history.samples.len() == HISTORY_LEN {
+                    history.samples.pop_front();
+                }
+                history.samples.push_back((now, millis));
+            }
+        }
+    }
+
+    // LATENCY HISTORY: (unix seconds, milliseconds), oldest first
+    pub fn history(&self, event: &str) -> Vec<(u64, u64)> {
+        let events = lock::mutex(&self.events);
+        events
+            .get(event)
+            .map(|history| history.samples.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    // LATENCY LATEST: each event's last sample and all-time worst, by name
+    pub fn latest(&self) -> Vec<(&'static str, u64, u64, u64)> {
+        let events = lock::mutex(&self.events);
+        let mut latest: Vec<_> = events
+            .iter()
+            .filter_map(|(event, history)| {
+                let (at, millis) = *history.samples.back()?;
+                Some((*event, at, millis, history.max))
+            })
+            .collect();
+        latest.sort();
+        latest
+    }
+
+    // LATENCY RESET: the named events, or all of them; returns how many
+    // event series were dropped
+    pub fn reset(&self, events: &[String]) -> usize {
+        let mut all = lock::mutex(&self.events);
+        if events.is_empty() {
+            let count = all.len();
+            all.clear();
+            return count;
+        }
+        events
+            .iter()
+            .filter(|event| all.remove(event.as_str()).is_some())
+            .count()
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -0,0 +1,18 @@
+use std::sync::{Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+// Lock acquisition that survives poisoning. A command that panics while
+// holding a lock would otherwise make every later user of it panic too,
+// taking the whole server down with one bad request. What these locks guard
+// (registries, queues, counters) is still usable after a panic part way
+// through an update, so the lock is taken over instead.
+pub fn mutex<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
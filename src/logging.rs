@@ -0,0 +1,218 @@
+use crate::lock;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::{self, Write as _};
+use std::io::Write as _;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+
+// Install the logger for `tracing` events. The level comes from RUDIS_LOG,
+// falling back to RUST_LOG: error, warn, info (the default), debug or trace.
+// Only a plain level is understood, not per-module directives.
+pub fn init() {
+    let level = ["RUDIS_LOG", "RUST_LOG"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find_map(|value| value.trim().parse::<Level>().ok())
+        .unwrap_or(Level::INFO);
+    let _ = tracing::subscriber::set_global_default(Logger::new(level));
+}
+
+// One line per event: time, level, the spans it happened in with their
+// fields, then the message. Warnings and errors go to stderr, the rest to
+// stdout.
+struct Logger {
+    level: Level,
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, Span>>,
+}
+
+struct Span {
+    name: &'static str,
+    fields: String,
+    // Handles to the span still alive; it is forgotten when this reaches 0
+    refs: usize,
+}
+
+thread_local! {
+    // Spans entered on this thread, innermost last. Instrumented futures
+    // enter and exit their span around every poll, on the polling thread.
+    static ENTERED: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+impl Logger {
+    fn new(level: Level) -> Self {
+        Self {
+            level,
+            next_id: AtomicU64::new(1),
+            spans: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn context(&self) -> String {
+        let spans = lock::mutex(&self.spans);
+        ENTERED.with(|entered| {
+            let mut context = String::new();
+            for id in entered.borrow().iter() {
+                if let Some(span) = spans.get(id) {
+                    let _ = write!(context, "{}{{{}}}: ", span.name, span.fields.trim_start());
+                }
+            }
+            context
+        })
+    }
+}
+
+impl Subscriber for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        *metadata.level() <= self.level
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Some(LevelFilter::from_level(self.level))
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut fields = String::new();
+        attrs.record(&mut FieldWriter {
+            message: &mut String::new(),
+            fields: &mut fields,
+        });
+        lock::mutex(&self.spans).insert(
+            id,
+            Span {
+                name: attrs.metadata().name(),
+                fields,
+                refs: 1,
+            },
+        );
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        if let Some(span) = lock::mutex(&self.spans).get_mut(&span.into_u64()) {
+            values.record(&mut FieldWriter {
+                message: &mut String::new(),
+                fields: &mut span.fields,
+            });
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut message = String::new();
+        let mut fields = String::new();
+        event.record(&mut FieldWriter {
+            message: &mut message,
+            fields: &mut fields,
+        });
+
+        let level = *event.metadata().level();
+        let line = format!(
+            "{} {:>5} {}{}{}",
+            timestamp(SystemTime::now()),
+            level,
+            self.context(),
+            message,
+            fields
+        );
+        if level <= Level::WARN {
+            let _ = writeln!(std::io::stderr().lock(), "{}", line);
+        } else {
+            let _ = writeln!(std::io::stdout().lock(), "{}", line);
+        }
+    }
+
+    fn enter(&self, span: &Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.into_u64()));
+    }
+
+    fn exit(&self, span: &Id) {
+        let id = span.into_u64();
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(pos) = entered.iter().rposition(|&e| e == id) {
+                entered.remove(pos);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(span) = lock::mutex(&self.spans).get_mut(&span.into_u64()) {
+            span.refs += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let mut spans = lock::mutex(&self.spans);
+        let id = span.into_u64();
+        let Some(data) = spans.get_mut(&id) else {
+            return false;
+        };
+        data.refs -= 1;
+        if data.refs == 0 {
+            spans.remove(&id);
+            return true;
+        }
+        false
+    }
+}
+
+// The `message` field is the text of the event; every other field is
+// appended as ` name=value`
+struct FieldWriter<'a> {
+    message: &'a mut String,
+    fields: &'a mut String,
+}
+
+impl Visit for FieldWriter<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record_debug(field, &format_args!("{}", value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+// RFC 3339 in UTC with milliseconds, e.g. 2026-10-14T09:30:00.123Z
+fn timestamp(now: SystemTime) -> String {
+    let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = ((secs / 86_400) as i64, secs % 86_400);
+
+    // Howard Hinnant's days-to-civil algorithm
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
@@ -1,17 +1,52 @@
+mod aof;
+mod blocking;
+mod clients;
+mod clock;
 mod command;
+mod command_table;
+mod config;
+mod deadline;
+mod glob;
+mod hash;
+mod id;
+mod json;
+mod keyspace;
+mod latency;
+mod lock;
+mod logging;
+mod memory;
+mod metrics;
+mod notify;
+mod pause;
+mod persistence;
+mod pubsub;
+mod random;
+mod replication;
 mod resp;
+mod scan;
 mod server;
+mod snapshot;
+mod stats;
 mod store;
+#[cfg(test)]
+mod tests;
+mod tracking;
+mod transaction;
+mod watch;
+mod zset;
 
+use config::Config;
 use server::Server;
 
 #[tokio::main]
-async fn main() -> std::io::Result<()> {
-    let addr = std::env::var("RUDIS_ADDR").unwrap_or_else(|_| "127.0.0.1:6379".to_string());
+async fn main() {
+    logging::init();
+    tracing::info!("Starting Rudis (Rust Redis) server, version 0.1.0");
+    let config = Config::from_env();
 
-    println!("Starting Rudis (Rust Redis) server...");
-    println!("Version: 0.1.0");
-
-    let server = Server::new(addr);
-    server.run().await
+    let server = Server::new(config);
+    if let Err(e) = server.run().await {
+        tracing::error!("{}", e);
+        std::process::exit(1);
+    }
 }
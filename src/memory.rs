@@ -0,0 +1,17 @@
+// Resident set size of the process, for INFO's used_memory_rss and
+// mem_fragmentation_ratio. Only Linux has a cheap way to read it; elsewhere
+// callers fall back to the estimated used memory.
+#[cfg(target_os = "linux")]
+pub fn rss() -> Option<usize> {
+    // statm is "size resident shared ..." in pages
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: usize = statm.split_whitespace().nth(1)?.parse().ok()?;
+    // SAFETY: sysconf only reads a system constant
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Some(pages * usize::try_from(page_size).ok()?)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn rss() -> Option<usize> {
+    None
+}
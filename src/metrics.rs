@@ -0,0 +1,160 @@
+use crate::stats::Stats;
+use crate::store::Store;
+use std::fmt::Write as _;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+// Largest request head read before giving up on a scrape
+const MAX_REQUEST_SIZE: usize = 8192;
+
+// Optional HTTP listener serving GET /metrics in the Prometheus text format.
+// One response per connection; anything but a complete GET /metrics gets a
+// plain 4xx.
+pub async fn run(addr: String, store: Store, stats: Arc<Stats>) -> std::io::Result<()> {
+    let listener = TcpListener::bind(&addr).await?;
+    tracing::info!("Metrics listening on http://{}/metrics", addr);
+
+    loop {
+        let (socket, peer) = listener.accept().await?;
+        let store = store.clone();
+        let stats = stats.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve(socket, &store, &stats).await {
+                tracing::error!("Error serving metrics to {}: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn serve(mut socket: TcpStream, store: &Store, stats: &Stats) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = socket.read(&mut buffer).await?;
+        if n == 0 || request.len() + n > MAX_REQUEST_SIZE {
+            return Ok(());
+        }
+        request.extend_from_slice(&buffer[..n]);
+    }
+
+    let head = String::from_utf8_lossy(&request);
+    let mut request_line = head.lines().next().unwrap_or_default().split(' ');
+    let response = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/metrics")) => response("200 OK", &render(store, stats)),
+        (Some("GET"), Some(_)) => response("404 Not Found", "Not Found\n"),
+        _ => response("405 Method Not Allowed", "Method Not Allowed\n"),
+    };
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await
+}
+
+fn response(status: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+fn render(store: &Store, stats: &Stats) -> String {
+    let mut out = String::new();
+
+    metric(
+        &mut out,
+        "rudis_commands_processed_total",
+        "counter",
+        "Commands processed, by command name.",
+    );
+    for (name, stat) in stats.command_stats() {
+        let _ = writeln!(
+            out,
+            "rudis_commands_processed_total{{cmd=\"{}\"}} {}",
+            name.to_lowercase(),
+            stat.calls
+        );
+    }
+
+    let samples = [
+        (
+            "rudis_connections_received_total",
+            "counter",
+            "Client connections accepted.",
+            stats.total_connections(),
+        ),
+        (
+            "rudis_connected_clients",
+            "gauge",
+            "Clients currently connected.",
+            stats.connected_clients() as u64,
+        ),
+        (
+            "rudis_keyspace_hits_total",
+            "counter",
+            "Key lookups by read commands that found the key.",
+            stats.keyspace_hits(),
+        ),
+        (
+            "rudis_keyspace_misses_total",
+            "counter",
+            "Key lookups by read commands that found nothing.",
+            stats.keyspace_misses(),
+        ),
+        (
+            "rudis_expired_keys_total",
+            "counter",
+            "Keys removed because their expiry passed.",
+            store.expired_keys(),
+        ),
+        (
+            "rudis_evicted_keys_total",
+            "counter",
+            "Keys evicted to free memory.",
+            stats.evicted_keys(),
+        ),
+        (
+            "rudis_uptime_seconds",
+            "gauge",
+            "Seconds since the server started.",
+            stats.uptime_secs(),
+        ),
+    ];
+    for (name, kind, help, value) in samples {
+        metric(&mut out, name, kind, help);
+        let _ = writeln!(out, "{} {}", name, value);
+    }
+
+    metric(
+        &mut out,
+        "rudis_db_keys",
+        "gauge",
+        "Keys per database, including expired keys not removed yet.",
+    );
+    let _ = writeln!(out, "rudis_db_keys{{db=\"0\"}} {}", store.dbsize());
+
+    if let Some(rss) = resident_memory() {
+        metric(
+            &mut out,
+            "rudis_memory_rss_bytes",
+            "gauge",
+            "Resident memory of the server process.",
+        );
+        let _ = writeln!(out, "rudis_memory_rss_bytes {}", rss);
+    }
+
+    out
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+// VmRSS from /proc; None where there is no procfs
+fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
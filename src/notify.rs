@@ -0,0 +1,86 @@
+use crate::pubsub::PubSub;
+use crate::tracking::Tracking;
+use crate::watch::Watches;
+
+// Which keyspace notifications to publish, parsed from the same flag string
+// as Redis' notify-keyspace-events (only the classes rudis emits are tracked)
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EventFlags {
+    pub keyspace: bool,
+    pub keyevent: bool,
+    pub expired: bool,
+    pub list: bool,
+}
+
+impl EventFlags {
+    pub fn parse(flags: &str) -> Option<Self> {
+        let mut parsed = Self::default();
+        for flag in flags.chars() {
+            match flag {
+                'K' => parsed.keyspace = true,
+                'E' => parsed.keyevent = true,
+                'x' => parsed.expired = true,
+                'l' => parsed.list = true,
+                'A' => {
+                    parsed.expired = true;
+                    parsed.list = true;
+                }
+                // Classes rudis has no events for yet
+                'g' | '$' | 's' | 'h' | 'z' | 'e' | 't' | 'd' | 'm' | 'n' => {}
+                _ => return None,
+            }
+        }
+        Some(parsed)
+    }
+}
+
+#[derive(Clone)]
+pub struct KeyspaceEvents {
+    pubsub: PubSub,
+    flags: EventFlags,
+    tracking: Tracking,
+    watches: Watches,
+}
+
+impl KeyspaceEvents {
+    pub fn new(pubsub: PubSub, flags: EventFlags, tracking: Tracking, watches: Watches) -> Self {
+        Self {
+            pubsub,
+            flags,
+            tracking,
+            watches,
+        }
+    }
+
+    // Expired keys also invalidate client-side caches and fail the EXEC of
+    // connections watching them, whatever the flags
+    pub fn expired(&self, key: &[u8]) {
+        self.tracking.invalidate(&[key]);
+        self.watches.touch(&[key]);
+        if self.flags.expired {
+            self.notify("expired", key);
+        }
+    }
+
+    // lpush, rpush, lpop and rpop: fired once the list has changed and its
+    // lock is released, and before a push wakes any blocked client, so a
+    // subscriber sees a push before the pop that serves a BLPOP
+    pub fn list(&self, event: &str, key: &[u8]) {
+        if self.flags.list {
+            self.notify(event, key);
+        }
+    }
+
+    // Channel names are text, so a key that isn't UTF-8 is named lossily in
+    // __keyspace@0__ channels; __keyevent@0__ messages carry its exact bytes
+    fn notify(&self, event: &str, key: &[u8]) {
+        if self.flags.keyspace {
+            let channel = format!("__keyspace@0__:{}", String::from_utf8_lossy(key));
+            self.pubsub.publish(&channel, event.as_bytes());
+        }
+        if self.flags.keyevent {
+            self.pubsub
+                .publish(&format!("__keyevent@0__:{}", event), key);
+        }
+    }
+}
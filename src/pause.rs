@@ -0,0 +1,69 @@
+use crate::lock;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+#[derive(Clone, Copy)]
+struct PauseUntil {
+    until: Instant,
+    writes_only: bool,
+}
+
+// CLIENT PAUSE, shared by every connection. Paused commands wait rather than
+// fail, and run once the pause ends or CLIENT UNPAUSE lifts it.
+#[derive(Clone, Default)]
+pub struct Pause {
+    state: Arc<RwLock<Option<PauseUntil>>>,
+    lifted: Arc<Notify>,
+}
+
+impl Pause {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Like Redis, a pause already in place is only ever extended: the later
+    // end time wins, and ALL wins over WRITE
+    pub fn pause(&self, duration: Duration, writes_only: bool) {
+        let until = Instant::now() + duration;
+        let mut state = lock::write(&self.state);
+        *state = Some(match *state {
+            Some(current) if current.until > Instant::now() => PauseUntil {
+                until: current.until.max(until),
+                writes_only: current.writes_only && writes_only,
+            },
+            _ => PauseUntil { until, writes_only },
+        });
+    }
+
+    pub fn unpause(&self) {
+        *lock::write(&self.state) = None;
+        self.lifted.notify_waiters();
+    }
+
+    // Whether a command of this kind would have to wait right now
+    pub fn holds(&self, is_write: bool) -> bool {
+        self.current(is_write).is_some()
+    }
+
+    // Returns once a command of this kind may run
+    pub async fn wait(&self, is_write: bool) {
+        loop {
+            let lifted = self.lifted.notified();
+            let Some(pause) = self.current(is_write) else {
+                return;
+            };
+            tokio::select! {
+                _ = tokio::time::sleep_until(pause.until) => {}
+                _ = lifted => {}
+            }
+        }
+    }
+
+    fn current(&self, is_write: bool) -> Option<PauseUntil> {
+        let pause = (*lock::read(&self.state))?;
+        let holds = pause.until > Instant::now() && (is_write || !pause.writes_only);
+        holds.then_some(pause)
+    }
+}
@@ -0,0 +1,124 @@
+use crate::aof;
+use crate::config::Config;
+use crate::replication::Replication;
+use crate::snapshot;
+use crate::stats::Stats;
+use crate::store::{Snapshot, Store};
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SCHEDULE_INTERVAL: Duration = Duration::from_secs(1);
+// After a failed background save, wait this long before trying again
+const RETRY_DELAY_SECS: u64 = 5;
+
+// Write the dataset to the snapshot file. Only the changes made before the
+// save started are cleared from the dirty counter.
+pub fn save(store: &Store, config: &Config, stats: &Stats) -> io::Result<()> {
+    let dirty = stats.dirty();
+    write_snapshot(&store.snapshot(), dirty, config, stats)
+}
+
+// BGSAVE: the snapshot is taken before this returns, so the file holds the
+// dataset as of the command; it is serialized and written on a blocking
+// thread while the server keeps serving. Returns false if a background
+// save is already running.
+pub fn spawn_bgsave(store: Store, config: Arc<Config>, stats: Arc<Stats>) -> bool {
+    if !stats.begin_bgsave() {
+        return false;
+    }
+    let dirty = stats.dirty();
+    let snapshot = store.snapshot();
+
+    tokio::task::spawn_blocking(move || {
+        match write_snapshot(&snapshot, dirty, &config, &stats) {
+            Ok(()) => tracing::info!("Background saving terminated with success"),
+            Err(e) => tracing::error!("Background saving error: {}", e),
+        }
+        stats.end_bgsave();
+    });
+    true
+}
+
+// BGREWRITEAOF: write the commands that rebuild the dataset, then the writes
+// that ran while they were being written, to the append-only file. Returns
+// false if a rewrite is already running.
+pub fn spawn_aof_rewrite(
+    store: &Store,
+    replication: &Replication,
+    config: Arc<Config>,
+    stats: Arc<Stats>,
+) -> bool {
+    if !stats.begin_aof_rewrite() {
+        return false;
+    }
+    let snapshot = replication.begin_rewrite(store);
+    let replication = replication.clone();
+
+    tokio::task::spawn_blocking(move || {
+        let mut bytes = aof::encode(&snapshot);
+        drop(snapshot);
+        bytes.extend_from_slice(&replication.end_rewrite());
+        let result = snapshot::save_to_file(&bytes, Path::new(&config.appendfilename));
+        match &result {
+            Ok(()) => tracing::info!("Background AOF rewrite terminated with success"),
+            Err(e) => tracing::error!("Background AOF rewrite error: {}", e),
+        }
+        stats.end_aof_rewrite(result.is_ok());
+    });
+    true
+}
+
+fn write_snapshot(
+    snapshot: &Snapshot,
+    dirty: u64,
+    config: &Config,
+    stats: &Stats,
+) -> io::Result<()> {
+    let bytes = snapshot::encode(snapshot);
+    let result = snapshot::save_to_file(&bytes, Path::new(&config.dbfilename));
+    stats.record_save(result.is_ok());
+    if result.is_ok() {
+        stats.clear_dirty(dirty);
+    }
+    result
+}
+
+// Trigger a background save whenever one of the configured `save` points is
+// reached: at least `changes` writes and `seconds` since the last save
+pub async fn run_save_schedule(store: Store, config: Arc<Config>, stats: Arc<Stats>) {
+    let mut ticker = tokio::time::interval(SCHEDULE_INTERVAL);
+    loop {
+        ticker.tick().await;
+
+        let dirty = stats.dirty();
+        if dirty == 0 {
+            continue;
+        }
+        let since_save = unix_now().saturating_sub(stats.last_save_attempt());
+        if !stats.last_save_ok() && since_save < RETRY_DELAY_SECS {
+            continue;
+        }
+
+        let point = config
+            .save_points()
+            .into_iter()
+            .find(|p| dirty >= p.changes && since_save >= p.seconds);
+        if let Some(point) = point {
+            tracing::info!(
+                "{} changes in {} seconds. Saving...",
+                point.changes,
+                point.seconds
+            );
+            spawn_bgsave(store.clone(), config.clone(), stats.clone());
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -0,0 +1,399 @@
+use crate::config::OutputBufferLimit;
+use crate::glob;
+use crate::lock;
+use crate::resp::RESPValue;
+use bytes::Bytes;
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+use tokio::sync::{Notify, mpsc};
+
+// Bytes queued for a connection but not yet handed to its socket
+struct Backlog {
+    bytes: usize,
+    soft_since: Option<Instant>,
+    overflowed: bool,
+}
+
+// Each connection owns one receiver; the registry holds a sender per subscription.
+// Messages are serialized once per publish and protocol and shared between subscribers.
+#[derive(Clone)]
+pub struct MessageSender {
+    tx: mpsc::UnboundedSender<Bytes>,
+    backlog: Arc<Mutex<Backlog>>,
+    overflow: Arc<Notify>,
+    limit: OutputBufferLimit,
+    // Set by HELLO 3: messages go out as push frames instead of arrays
+    resp3: Arc<AtomicBool>,
+}
+
+pub struct MessageReceiver {
+    rx: mpsc::UnboundedReceiver<Bytes>,
+    backlog: Arc<Mutex<Backlog>>,
+    overflow: Arc<Notify>,
+}
+
+pub fn message_channel(limit: OutputBufferLimit) -> (MessageSender, MessageReceiver) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let backlog = Arc::new(Mutex::new(Backlog {
+        bytes: 0,
+        soft_since: None,
+        overflowed: false,
+    }));
+    let overflow = Arc::new(Notify::new());
+    let sender = MessageSender {
+        tx,
+        backlog: backlog.clone(),
+        overflow: overflow.clone(),
+        limit,
+        resp3: Arc::new(AtomicBool::new(false)),
+    };
+    (
+        sender,
+        MessageReceiver {
+            rx,
+            backlog,
+            overflow,
+        },
+    )
+}
+
+impl MessageSender {
+    // Queue a frame, refusing it once the connection's backlog is over its
+    // output buffer limit; the receiver is told to close the connection
+    fn send(&self, frame: &Bytes) -> bool {
+        let mut backlog = lock::mutex(&self.backlog);
+        if backlog.overflowed {
+            return false;
+        }
+
+        let pending = backlog.bytes + frame.len();
+        if self.limit.is_exceeded(pending, &mut backlog.soft_since) {
+            backlog.overflowed = true;
+            self.overflow.notify_one();
+            return false;
+        }
+
+        if self.tx.send(frame.clone()).is_err() {
+            return false;
+        }
+        backlog.bytes = pending;
+        true
+    }
+
+    pub fn is_resp3(&self) -> bool {
+        self.resp3.load(Ordering::Relaxed)
+    }
+
+    // Out-of-band frames outside pub/sub (tracking invalidations), which
+    // only RESP3 connections can receive
+    pub fn push(&self, items: Vec<RESPValue>) -> bool {
+        self.is_resp3() && self.send(&RESPValue::Push(items).serialize().into())
+    }
+}
+
+// A message serialized on first use for each protocol some subscriber speaks
+struct Frame {
+    items: Vec<RESPValue>,
+    array: OnceCell<Bytes>,
+    push: OnceCell<Bytes>,
+}
+
+impl Frame {
+    fn new(items: Vec<RESPValue>) -> Self {
+        Self {
+            items,
+            array: OnceCell::new(),
+            push: OnceCell::new(),
+        }
+    }
+
+    fn send(&self, sender: &MessageSender) -> bool {
+        let frame = if sender.is_resp3() {
+            self.push
+                .get_or_init(|| RESPValue::Push(self.items.clone()).serialize().into())
+        } else {
+            self.array.get_or_init(|| {
+                RESPValue::Array(Some(self.items.clone()))
+                    .serialize()
+                    .into()
+            })
+        };
+        sender.send(frame)
+    }
+}
+
+impl MessageReceiver {
+    pub async fn recv(&mut self) -> Option<Bytes> {
+        let frame = self.rx.recv().await?;
+        let mut backlog = lock::mutex(&self.backlog);
+        backlog.bytes -= frame.len();
+        Some(frame)
+    }
+
+    // Notified once a publish finds this connection over its output buffer limit
+    pub fn overflow(&self) -> Arc<Notify> {
+        self.overflow.clone()
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Channel,
+    Pattern,
+}
+
+impl Kind {
+    fn subscribe_reply(self) -> &'static str {
+        match self {
+            Kind::Channel => "subscribe",
+            Kind::Pattern => "psubscribe",
+        }
+    }
+
+    fn unsubscribe_reply(self) -> &'static str {
+        match self {
+            Kind::Channel => "unsubscribe",
+            Kind::Pattern => "punsubscribe",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    channels: HashMap<String, HashMap<u64, MessageSender>>,
+    patterns: HashMap<String, HashMap<u64, MessageSender>>,
+}
+
+impl Registry {
+    fn map(&mut self, kind: Kind) -> &mut HashMap<String, HashMap<u64, MessageSender>> {
+        match kind {
+            Kind::Channel => &mut self.channels,
+            Kind::Pattern => &mut self.patterns,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PubSub {
+    registry: Arc<RwLock<Registry>>,
+}
+
+impl PubSub {
+    pub fn new() -> Self {
+        Self {
+            registry: Arc::new(RwLock::new(Registry::default())),
+        }
+    }
+
+    fn add(&self, kind: Kind, name: &str, client_id: u64, sender: MessageSender) {
+        let mut registry = lock::write(&self.registry);
+        registry
+            .map(kind)
+            .entry(name.to_string())
+            .or_default()
+            .insert(client_id, sender);
+    }
+
+    fn remove(&self, kind: Kind, name: &str, client_id: u64) {
+        let mut registry = lock::write(&self.registry);
+        let map = registry.map(kind);
+        if let Some(subscribers) = map.get_mut(name) {
+            subscribers.remove(&client_id);
+            if subscribers.is_empty() {
+                map.remove(name);
+            }
+        }
+    }
+
+    // Channels with at least one subscriber (the registry drops empty
+    // ones), optionally filtered by a glob pattern, sorted by name
+    pub fn channels(&self, pattern: Option<&[u8]>) -> Vec<String> {
+        let registry = lock::read(&self.registry);
+        let mut channels: Vec<String> = registry
+            .channels
+            .keys()
+            .filter(|c| pattern.is_none_or(|p| glob::matches(p, c.as_bytes())))
+            .cloned()
+            .collect();
+        channels.sort();
+        channels
+    }
+
+    // Channel subscribers only; pattern subscriptions aren't counted, like Redis
+    pub fn numsub(&self, channel: &str) -> usize {
+        let registry = lock::read(&self.registry);
+        registry.channels.get(channel).map_or(0, |s| s.len())
+    }
+
+    // Distinct patterns with at least one subscriber
+    pub fn numpat(&self) -> usize {
+        lock::read(&self.registry).patterns.len()
+    }
+
+    // Deliver a message to every channel and pattern subscriber, returning
+    // how many clients received it. The write lock serializes concurrent
+    // publishes so every subscriber sees the same order; a subscriber over
+    // its backlog limit is disconnected rather than skipping messages.
+    pub fn publish(&self, channel: &str, message: &[u8]) -> usize {
+        let registry = lock::write(&self.registry);
+        let mut receivers = 0;
+
+        if let Some(subscribers) = registry.channels.get(channel) {
+            let frame = Frame::new(vec![
+                bulk(b"message"),
+                bulk(channel.as_bytes()),
+                bulk(message),
+            ]);
+            for sender in subscribers.values() {
+                if frame.send(sender) {
+                    receivers += 1;
+                }
+            }
+        }
+
+        for (pattern, subscribers) in &registry.patterns {
+            if !glob::matches(pattern.as_bytes(), channel.as_bytes()) {
+                continue;
+            }
+            let frame = Frame::new(vec![
+                bulk(b"pmessage"),
+                bulk(pattern.as_bytes()),
+                bulk(channel.as_bytes()),
+                bulk(message),
+            ]);
+            for sender in subscribers.values() {
+                if frame.send(sender) {
+                    receivers += 1;
+                }
+            }
+        }
+
+        receivers
+    }
+}
+
+// The channels and patterns a single connection is subscribed to
+pub struct Subscriptions {
+    client_id: u64,
+    sender: MessageSender,
+    channels: Vec<String>,
+    patterns: Vec<String>,
+}
+
+impl Subscriptions {
+    pub fn new(client_id: u64, sender: MessageSender) -> Self {
+        Self {
+            client_id,
+            sender,
+            channels: Vec::new(),
+            patterns: Vec::new(),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    pub fn channel_count(&self) -> usize {
+        self.channels.len()
+    }
+
+    pub fn pattern_count(&self) -> usize {
+        self.patterns.len()
+    }
+
+    // The protocol negotiated with HELLO decides how messages and
+    // subscribe confirmations are framed
+    pub fn is_resp3(&self) -> bool {
+        self.sender.is_resp3()
+    }
+
+    pub fn set_resp3(&self, resp3: bool) {
+        self.sender.resp3.store(resp3, Ordering::Relaxed);
+    }
+
+    pub fn subscribe(&mut self, pubsub: &PubSub, channels: &[String]) -> Vec<RESPValue> {
+        self.add(pubsub, Kind::Channel, channels)
+    }
+
+    pub fn psubscribe(&mut self, pubsub: &PubSub, patterns: &[String]) -> Vec<RESPValue> {
+        self.add(pubsub, Kind::Pattern, patterns)
+    }
+
+    // With no names, drop every current subscription of that kind
+    pub fn unsubscribe(&mut self, pubsub: &PubSub, channels: &[String]) -> Vec<RESPValue> {
+        self.remove(pubsub, Kind::Channel, channels)
+    }
+
+    pub fn punsubscribe(&mut self, pubsub: &PubSub, patterns: &[String]) -> Vec<RESPValue> {
+        self.remove(pubsub, Kind::Pattern, patterns)
+    }
+
+    // Called when the connection goes away
+    pub fn clear(&mut self, pubsub: &PubSub) {
+        self.remove(pubsub, Kind::Channel, &[]);
+        self.remove(pubsub, Kind::Pattern, &[]);
+    }
+
+    fn list(&mut self, kind: Kind) -> &mut Vec<String> {
+        match kind {
+            Kind::Channel => &mut self.channels,
+            Kind::Pattern => &mut self.patterns,
+        }
+    }
+
+    fn add(&mut self, pubsub: &PubSub, kind: Kind, names: &[String]) -> Vec<RESPValue> {
+        let mut replies = Vec::with_capacity(names.len());
+        for name in names {
+            if !self.list(kind).contains(name) {
+                self.list(kind).push(name.clone());
+                pubsub.add(kind, name, self.client_id, self.sender.clone());
+            }
+            replies.push(self.reply(kind.subscribe_reply(), Some(name)));
+        }
+        replies
+    }
+
+    fn remove(&mut self, pubsub: &PubSub, kind: Kind, names: &[String]) -> Vec<RESPValue> {
+        let targets = if names.is_empty() {
+            self.list(kind).clone()
+        } else {
+            names.to_vec()
+        };
+
+        if targets.is_empty() {
+            return vec![self.reply(kind.unsubscribe_reply(), None)];
+        }
+
+        let mut replies = Vec::with_capacity(targets.len());
+        for name in &targets {
+            let list = self.list(kind);
+            if let Some(pos) = list.iter().position(|n| n == name) {
+                list.remove(pos);
+                pubsub.remove(kind, name, self.client_id);
+            }
+            replies.push(self.reply(kind.unsubscribe_reply(), Some(name)));
+        }
+        replies
+    }
+
+    fn reply(&self, kind: &str, name: Option<&str>) -> RESPValue {
+        let items = vec![
+            bulk(kind.as_bytes()),
+            RESPValue::BulkString(name.map(|n| n.as_bytes().to_vec())),
+            RESPValue::Integer(self.count() as i64),
+        ];
+        if self.is_resp3() {
+            RESPValue::Push(items)
+        } else {
+            RESPValue::Array(Some(items))
+        }
+    }
+}
+
+fn bulk(bytes: &[u8]) -> RESPValue {
+    RESPValue::BulkString(Some(bytes.to_vec()))
+}
@@ -0,0 +1,245 @@
+use crate::command::Command;
+use crate::deadline::Deadline;
+use crate::id;
+use crate::lock;
+use crate::resp::RESPValue;
+use crate::snapshot;
+use crate::store::{Snapshot, Store};
+use std::io::{self, BufReader, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+const FEED_CAPACITY: usize = 10_000;
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone)]
+pub struct Replication {
+    master: Arc<Mutex<Option<MasterLink>>>,
+    feed: broadcast::Sender<Vec<u8>>,
+    // Serializes write execution with replica attachment so a new replica
+    // never sees a write both in its snapshot and in its command stream
+    write_lock: Arc<Mutex<()>>,
+    // Identifies this server's replication history; generated at startup
+    replid: Arc<Mutex<String>>,
+    // Bytes of write commands propagated so far
+    offset: Arc<AtomicU64>,
+    // While BGREWRITEAOF runs: the writes executed since its snapshot, as
+    // propagated, to go at the end of the new file
+    rewrite_buffer: Arc<Mutex<Option<Vec<u8>>>>,
+}
+
+struct MasterLink {
+    host: String,
+    port: u16,
+    stopped: Arc<AtomicBool>,
+    stream: Arc<Mutex<Option<TcpStream>>>,
+}
+
+impl MasterLink {
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(stream) = lock::mutex(&self.stream).take() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl Replication {
+    pub fn new() -> Self {
+        let (feed, _) = broadcast::channel(FEED_CAPACITY);
+        Self {
+            master: Arc::new(Mutex::new(None)),
+            feed,
+            write_lock: Arc::new(Mutex::new(())),
+            replid: Arc::new(Mutex::new(id::generate())),
+            offset: Arc::new(AtomicU64::new(0)),
+            rewrite_buffer: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn is_replica(&self) -> bool {
+        lock::mutex(&self.master).is_some()
+    }
+
+    pub fn master_addr(&self) -> Option<(String, u16)> {
+        let master = lock::mutex(&self.master);
+        master.as_ref().map(|link| (link.host.clone(), link.port))
+    }
+
+    pub fn replid(&self) -> String {
+        lock::mutex(&self.replid).clone()
+    }
+
+    pub fn change_replid(&self) {
+        *lock::mutex(&self.replid) = id::generate();
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset.load(Ordering::SeqCst)
+    }
+
+    pub fn connected_replicas(&self) -> usize {
+        self.feed.receiver_count()
+    }
+
+    // Write commands run under this lock, so a panic in one would poison it;
+    // the lock guards no data, so it is taken over rather than failing
+    // every write after it
+    fn lock_writes(&self) -> MutexGuard<'_, ()> {
+        lock::mutex(&self.write_lock)
+    }
+
+    // Execute a write command and forward it to every attached replica.
+    // `max_string_len` is proto-max-bulk-len, as Command::execute_within takes it.
+    pub fn execute_write(&self, cmd: &Command, store: &Store, max_string_len: usize) -> RESPValue {
+        let _guard = self.lock_writes();
+        let response = cmd.execute_within(store, Deadline::NONE, max_string_len);
+
+        if !matches!(response, RESPValue::Error(_)) {
+            let payload = cmd.for_propagation(store).to_resp().serialize();
+            self.offset
+                .fetch_add(payload.len() as u64, Ordering::SeqCst);
+            if let Some(buffer) = lock::mutex(&self.rewrite_buffer).as_mut() {
+                buffer.extend_from_slice(&payload);
+            }
+            if self.feed.receiver_count() > 0 {
+                let _ = self.feed.send(payload);
+            }
+        }
+        response
+    }
+
+    // Register a new replica: returns the full-sync payload and a receiver
+    // for every write executed after that payload was taken
+    pub fn attach(&self, store: &Store) -> (Vec<u8>, broadcast::Receiver<Vec<u8>>) {
+        let _guard = self.lock_writes();
+        let rx = self.feed.subscribe();
+        (snapshot::encode(&store.snapshot()), rx)
+    }
+
+    // Start an AOF rewrite: the snapshot to rewrite from, with every write
+    // after it buffered until end_rewrite
+    pub fn begin_rewrite(&self, store: &Store) -> Snapshot {
+        let _guard = self.lock_writes();
+        *lock::mutex(&self.rewrite_buffer) = Some(Vec::new());
+        store.snapshot()
+    }
+
+    // The writes buffered since begin_rewrite; buffering stops here
+    pub fn end_rewrite(&self) -> Vec<u8> {
+        lock::mutex(&self.rewrite_buffer).take().unwrap_or_default()
+    }
+
+    // Start following the given master, dropping any previous link
+    pub fn replicate_of(&self, host: String, port: u16, store: &Store) -> bool {
+        let mut master = lock::mutex(&self.master);
+
+        if let Some(link) = master.as_ref() {
+            if link.host == host && link.port == port {
+                return false;
+            }
+            link.stop();
+        }
+
+        let link = MasterLink {
+            host: host.clone(),
+            port,
+            stopped: Arc::new(AtomicBool::new(false)),
+            stream: Arc::new(Mutex::new(None)),
+        };
+
+        let stopped = link.stopped.clone();
+        let stream = link.stream.clone();
+        let store = store.clone();
+        thread::spawn(move || run_link(host, port, store, stopped, stream));
+
+        *master = Some(link);
+        true
+    }
+
+    // Stop replicating and keep the current dataset as a master
+    pub fn promote(&self) {
+        if let Some(link) = lock::mutex(&self.master).take() {
+            link.stop();
+        }
+    }
+}
+
+fn run_link(
+    host: String,
+    port: u16,
+    store: Store,
+    stopped: Arc<AtomicBool>,
+    stream: Arc<Mutex<Option<TcpStream>>>,
+) {
+    while !stopped.load(Ordering::SeqCst) {
+        let result = sync_with_master(&host, port, &store, &stopped, &stream);
+        if stopped.load(Ordering::SeqCst) {
+            break;
+        }
+
+        match result {
+            Ok(()) => tracing::info!("Connection with master {}:{} closed", host, port),
+            Err(e) => tracing::error!("Replication error with master {}:{}: {}", host, port, e),
+        }
+        thread::sleep(RECONNECT_DELAY);
+    }
+}
+
+fn sync_with_master(
+    host: &str,
+    port: u16,
+    store: &Store,
+    stopped: &AtomicBool,
+    link_stream: &Mutex<Option<TcpStream>>,
+) -> io::Result<()> {
+    let addr = (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "unable to resolve master"))?;
+    let mut socket = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+
+    // Publish the socket so promote() can interrupt the blocking reads below
+    *lock::mutex(link_stream) = Some(socket.try_clone()?);
+    if stopped.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    let sync = RESPValue::Array(Some(vec![RESPValue::BulkString(Some(b"SYNC".to_vec()))]));
+    socket.write_all(&sync.serialize())?;
+
+    let mut reader = BufReader::new(socket);
+    match RESPValue::parse(&mut reader)? {
+        RESPValue::BulkString(Some(payload)) => {
+            store.replace(snapshot::decode(&payload)?);
+            tracing::info!(
+                "MASTER <-> REPLICA sync: loaded {} bytes from {}:{}",
+                payload.len(),
+                host,
+                port
+            );
+        }
+        RESPValue::Error(e) => return Err(io::Error::other(e)),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected reply to SYNC",
+            ));
+        }
+    }
+
+    loop {
+        let value = RESPValue::parse(&mut reader)?;
+        if stopped.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        if let Some(cmd) = Command::from_resp(value) {
+            cmd.execute(store);
+        }
+    }
+}
@@ -1,4 +1,5 @@
-use std::io::{self, BufRead, BufReader, Read};
+use std::io::{self, BufRead, BufReader, Cursor, Read, Write};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum RESPValue {
@@ -7,10 +8,115 @@ pub enum RESPValue {
     Integer(i64),
     BulkString(Option<Vec<u8>>),
     Array(Option<Vec<RESPValue>>),
+    // RESP3 only: out-of-band frames (pub/sub messages) and key/value maps.
+    // Sent to connections that negotiated protocol 3 with HELLO; never parsed.
+    Push(Vec<RESPValue>),
+    Map(Vec<(RESPValue, RESPValue)>),
+    // Text with a three-letter format hint ("txt", "mkd"), and an integer
+    // too large for Integer, in decimal
+    VerbatimString { format: [u8; 3], data: Vec<u8> },
+    BigNumber(String),
 }
 
+// Maximum array nesting accepted from clients, matching Redis
+pub const DEFAULT_MAX_DEPTH: usize = 128;
+
+// Largest bulk string accepted from clients, Redis' default proto-max-bulk-len
+pub const DEFAULT_MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+// How much of a reply write_to gathers before writing it to the socket
+const WRITE_CHUNK: usize = 16 * 1024;
+
 impl RESPValue {
+    // For the replication link: the master's snapshot may be any size
     pub fn parse<R: Read>(reader: &mut BufReader<R>) -> io::Result<RESPValue> {
+        Self::parse_with_limits(reader, DEFAULT_MAX_DEPTH, usize::MAX)
+    }
+
+    // The first complete frame in some input and how many bytes it took, or
+    // None while it is still arriving; for tests reading replies
+    #[cfg(test)]
+    pub fn parse_frame(
+        input: &[u8],
+        max_depth: usize,
+        max_bulk_len: usize,
+    ) -> io::Result<Option<(RESPValue, usize)>> {
+        Self::resume_frame(input, &mut FrameScan::default(), max_depth, max_bulk_len)
+    }
+
+    // parse_frame for input that grows between calls: `scan` keeps how far
+    // the frame has been walked, so each call only looks at the bytes added
+    // since the last one rather than walking a large multibulk from the
+    // start on every read. It is reset once a frame is returned.
+    fn resume_frame(
+        input: &[u8],
+        scan: &mut FrameScan,
+        max_depth: usize,
+        max_bulk_len: usize,
+    ) -> io::Result<Option<(RESPValue, usize)>> {
+        let Some(end) = scan.frame_end(input, max_depth, max_bulk_len) else {
+            return Ok(None);
+        };
+        *scan = FrameScan::default();
+        let mut reader = BufReader::new(Cursor::new(&input[..end]));
+        match Self::parse_with_limits(&mut reader, max_depth, max_bulk_len) {
+            Ok(value) => Ok(Some((value, end))),
+            // frame_end found the whole frame, so running out of bytes means
+            // the headers didn't describe it
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                Err(protocol_error("unbalanced frame"))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    // A client request: a multibulk frame, or an inline command (a line of
+    // arguments, as typed into telnet) as the array it stands for. Blank
+    // inline lines are skipped, like Redis. `scan` is the connection's
+    // progress through a multibulk still arriving, as resume_frame keeps it.
+    pub fn parse_request(
+        input: &[u8],
+        scan: &mut FrameScan,
+        max_depth: usize,
+        max_bulk_len: usize,
+    ) -> io::Result<Option<(RESPValue, usize)>> {
+        let mut start = 0;
+        loop {
+            let rest = &input[start..];
+            match rest.first() {
+                None => return Ok(None),
+                Some(b'*') => {
+                    return Ok(Self::resume_frame(rest, scan, max_depth, max_bulk_len)?
+                        .map(|(value, used)| (value, start + used)));
+                }
+                Some(_) => {
+                    let Some(len) = rest.iter().position(|&b| b == b'\n') else {
+                        return Ok(None);
+                    };
+                    let line = &rest[..len];
+                    start += len + 1;
+                    let args = split_inline(line.strip_suffix(b"\r").unwrap_or(line))
+                        .ok_or_else(|| protocol_error("unbalanced quotes in request"))?;
+                    if !args.is_empty() {
+                        let args = args
+                            .into_iter()
+                            .map(|arg| RESPValue::BulkString(Some(arg)))
+                            .collect();
+                        return Ok(Some((RESPValue::Array(Some(args)), start)));
+                    }
+                }
+            }
+        }
+    }
+
+    // Nested arrays beyond max_depth are rejected instead of recursing
+    // until the stack overflows, and bulk strings longer than max_bulk_len
+    // before their buffer is allocated
+    pub fn parse_with_limits<R: Read>(
+        reader: &mut BufReader<R>,
+        max_depth: usize,
+        max_bulk_len: usize,
+    ) -> io::Result<RESPValue> {
         let mut line = String::new();
         reader.read_line(&mut line)?;
 
@@ -18,8 +124,19 @@ impl RESPValue {
             return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Empty line"));
         }
 
+        // Accept both \r\n and a bare \n so hand-written clients interoperate
+        let line = line
+            .strip_suffix("\r\n")
+            .or_else(|| line.strip_suffix('\n'))
+            .unwrap_or(&line);
+        if line.is_empty() {
+            return Err(protocol_error("empty line"));
+        }
+
         let first_byte = line.as_bytes()[0];
-        let content = &line[1..line.len() - 2]; // Remove prefix and \r\n
+        let content = line
+            .get(1..)
+            .ok_or_else(|| protocol_error("invalid type prefix"))?;
 
         match first_byte {
             b'+' => Ok(RESPValue::SimpleString(content.to_string())),
@@ -27,68 +144,183 @@ impl RESPValue {
             b':' => {
                 let num = content
                     .parse::<i64>()
-                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                    .map_err(|_| protocol_error("invalid integer"))?;
                 Ok(RESPValue::Integer(num))
             }
             b'$' => {
                 let len = content
                     .parse::<i64>()
-                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                    .map_err(|_| protocol_error("invalid bulk length"))?;
 
                 if len == -1 {
                     return Ok(RESPValue::BulkString(None));
                 }
+                if len < 0 || len as u64 > max_bulk_len as u64 {
+                    return Err(protocol_error("invalid bulk length"));
+                }
 
                 let mut buffer = vec![0u8; len as usize];
                 reader.read_exact(&mut buffer)?;
 
-                let mut crlf = [0u8; 2];
-                reader.read_exact(&mut crlf)?;
+                // Consume the terminator, again tolerating a bare \n
+                let mut terminator = [0u8; 1];
+                reader.read_exact(&mut terminator)?;
+                if terminator[0] == b'\r' {
+                    reader.read_exact(&mut terminator)?;
+                }
+                if terminator[0] != b'\n' {
+                    return Err(protocol_error("expected line terminator after bulk string"));
+                }
 
                 Ok(RESPValue::BulkString(Some(buffer)))
             }
             b'*' => {
                 let count = content
                     .parse::<i64>()
-                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                    .map_err(|_| protocol_error("invalid multibulk length"))?;
 
                 if count == -1 {
                     return Ok(RESPValue::Array(None));
                 }
+                if max_depth == 0 {
+                    return Err(protocol_error("nesting depth limit exceeded"));
+                }
 
                 let mut array = Vec::new();
                 for _ in 0..count {
-                    array.push(RESPValue::parse(reader)?);
+                    array.push(RESPValue::parse_with_limits(
+                        reader,
+                        max_depth - 1,
+                        max_bulk_len,
+                    )?);
                 }
                 Ok(RESPValue::Array(Some(array)))
             }
-            _ => Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("Unknown RESP type: {}", first_byte as char),
-            )),
+            _ => Err(protocol_error(&format!(
+                "unknown RESP type '{}'",
+                first_byte as char
+            ))),
         }
     }
 
     pub fn serialize(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.serialized_len());
+        self.serialize_into(&mut out)
+            .expect("writing to a Vec cannot fail");
+        out
+    }
+
+    pub fn serialize_into<W: Write>(&self, w: &mut W) -> io::Result<()> {
         match self {
-            RESPValue::SimpleString(s) => format!("+{}\r\n", s).into_bytes(),
-            RESPValue::Error(e) => format!("-{}\r\n", e).into_bytes(),
-            RESPValue::Integer(i) => format!(":{}\r\n", i).into_bytes(),
-            RESPValue::BulkString(None) => b"$-1\r\n".to_vec(),
+            RESPValue::SimpleString(s) => write!(w, "+{}\r\n", s),
+            RESPValue::Error(e) => write!(w, "-{}\r\n", e),
+            RESPValue::Integer(i) => write!(w, ":{}\r\n", i),
+            RESPValue::BulkString(None) => w.write_all(b"$-1\r\n"),
             RESPValue::BulkString(Some(data)) => {
-                let mut result = format!("${}\r\n", data.len()).into_bytes();
-                result.extend_from_slice(data);
-                result.extend_from_slice(b"\r\n");
-                result
+                write!(w, "${}\r\n", data.len())?;
+                w.write_all(data)?;
+                w.write_all(b"\r\n")
+            }
+            RESPValue::Array(None) => w.write_all(b"*-1\r\n"),
+            RESPValue::Array(Some(items)) => {
+                write!(w, "*{}\r\n", items.len())?;
+                items.iter().try_for_each(|item| item.serialize_into(w))
+            }
+            RESPValue::Push(items) => {
+                write!(w, ">{}\r\n", items.len())?;
+                items.iter().try_for_each(|item| item.serialize_into(w))
+            }
+            RESPValue::Map(pairs) => {
+                write!(w, "%{}\r\n", pairs.len())?;
+                for (key, value) in pairs {
+                    key.serialize_into(w)?;
+                    value.serialize_into(w)?;
+                }
+                Ok(())
+            }
+            RESPValue::VerbatimString { format, data } => {
+                write!(w, "={}\r\n", data.len() + 4)?;
+                w.write_all(format)?;
+                w.write_all(b":")?;
+                w.write_all(data)?;
+                w.write_all(b"\r\n")
+            }
+            RESPValue::BigNumber(n) => write!(w, "({}\r\n", n),
+        }
+    }
+
+    // Bytes serialize() would produce, without producing them; checked
+    // against the output buffer limits before a reply is written
+    pub fn serialized_len(&self) -> usize {
+        match self {
+            RESPValue::SimpleString(s) | RESPValue::Error(s) | RESPValue::BigNumber(s) => {
+                s.len() + 3
+            }
+            RESPValue::Integer(i) => decimal_len(*i) + 3,
+            RESPValue::BulkString(None) | RESPValue::Array(None) => 5,
+            RESPValue::BulkString(Some(data)) => header_len(data.len()) + data.len() + 2,
+            RESPValue::Array(Some(items)) | RESPValue::Push(items) => {
+                header_len(items.len()) + items.iter().map(Self::serialized_len).sum::<usize>()
+            }
+            RESPValue::Map(pairs) => {
+                header_len(pairs.len())
+                    + pairs
+                        .iter()
+                        .map(|(key, value)| key.serialized_len() + value.serialized_len())
+                        .sum::<usize>()
             }
-            RESPValue::Array(None) => b"*-1\r\n".to_vec(),
-            RESPValue::Array(Some(arr)) => {
-                let mut result = format!("*{}\r\n", arr.len()).into_bytes();
-                for item in arr {
-                    result.extend_from_slice(&item.serialize());
+            RESPValue::VerbatimString { data, .. } => header_len(data.len() + 4) + data.len() + 6,
+        }
+    }
+
+    // Write the reply to a client as it is serialized, a chunk at a time,
+    // so a large array (LRANGE, KEYS) is never copied out in full first.
+    // Bulk strings bigger than a chunk go straight from the value.
+    pub async fn write_to<W: AsyncWrite + Unpin>(&self, w: &mut W) -> io::Result<()> {
+        let mut chunk = Vec::with_capacity(WRITE_CHUNK);
+        // Arrays still being written, innermost last
+        let mut open = vec![std::slice::from_ref(self).iter()];
+        while let Some(items) = open.last_mut() {
+            let Some(value) = items.next() else {
+                open.pop();
+                continue;
+            };
+            match value {
+                RESPValue::Array(Some(items)) => {
+                    write!(chunk, "*{}\r\n", items.len())?;
+                    open.push(items.iter());
+                }
+                RESPValue::Push(items) => {
+                    write!(chunk, ">{}\r\n", items.len())?;
+                    open.push(items.iter());
+                }
+                RESPValue::BulkString(Some(data)) if data.len() >= WRITE_CHUNK => {
+                    write!(chunk, "${}\r\n", data.len())?;
+                    w.write_all(&chunk).await?;
+                    chunk.clear();
+                    w.write_all(data).await?;
+                    chunk.extend_from_slice(b"\r\n");
                 }
-                result
+                _ => value.serialize_into(&mut chunk)?,
+            }
+            if chunk.len() >= WRITE_CHUNK {
+                w.write_all(&chunk).await?;
+                chunk.clear();
+            }
+        }
+        w.write_all(&chunk).await
+    }
+
+    // Plain text for a human reader: a verbatim string under RESP3, a bulk
+    // string under RESP2
+    pub fn text(data: Vec<u8>, resp3: bool) -> RESPValue {
+        if resp3 {
+            RESPValue::VerbatimString {
+                format: *b"txt",
+                data,
             }
+        } else {
+            RESPValue::BulkString(Some(data))
         }
     }
 
@@ -104,3 +336,147 @@ impl RESPValue {
             .and_then(|bytes| String::from_utf8(bytes).ok())
     }
 }
+
+// Length of a `*<n>\r\n`-style header
+fn header_len(n: usize) -> usize {
+    decimal_len(n as i64) + 3
+}
+
+fn decimal_len(n: i64) -> usize {
+    let digits = n
+        .unsigned_abs()
+        .checked_ilog10()
+        .map_or(1, |d| d as usize + 1);
+    digits + usize::from(n < 0)
+}
+
+// Splits an inline command into arguments the way redis-cli does: tokens
+// are separated by whitespace, "double quotes" take \n \r \t \b \a, \xHH and
+// \ followed by any other byte for itself, and 'single quotes' take only
+// \'. A closing quote must end its argument. None if a quote is unbalanced
+// or followed by something other than whitespace.
+pub fn split_inline(line: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let hex = |b: u8| (b as char).to_digit(16).map(|d| d as u8);
+    let mut args = Vec::new();
+    let mut i = 0;
+    loop {
+        while line.get(i).is_some_and(|b| b.is_ascii_whitespace()) {
+            i += 1;
+        }
+        if i == line.len() {
+            return Some(args);
+        }
+
+        let mut arg = Vec::new();
+        let mut quote = None;
+        loop {
+            let b = line.get(i).copied();
+            match (quote, b) {
+                // End of the line: fine unless a quote is still open
+                (Some(_), None) => return None,
+                (None, None) => break,
+                (None, Some(b)) if b.is_ascii_whitespace() => break,
+                (None, Some(q @ (b'"' | b'\''))) => quote = Some(q),
+                (None, Some(b)) => arg.push(b),
+                (Some(q), Some(b)) if b == q => {
+                    // The closing quote must end the argument
+                    if line.get(i + 1).is_some_and(|b| !b.is_ascii_whitespace()) {
+                        return None;
+                    }
+                    i += 1;
+                    break;
+                }
+                (Some(b'"'), Some(b'\\')) => match line.get(i + 1..i + 4) {
+                    Some([b'x', hi, lo]) if hex(*hi).is_some() && hex(*lo).is_some() => {
+                        arg.push((hex(*hi)? << 4) | hex(*lo)?);
+                        i += 3;
+                    }
+                    _ => {
+                        let escaped = *line.get(i + 1)?;
+                        arg.push(match escaped {
+                            b'n' => b'\n',
+                            b'r' => b'\r',
+                            b't' => b'\t',
+                            b'b' => 0x08,
+                            b'a' => 0x07,
+                            other => other,
+                        });
+                        i += 1;
+                    }
+                },
+                (Some(b'\''), Some(b'\\')) if line.get(i + 1) == Some(&b'\'') => {
+                    arg.push(b'\'');
+                    i += 1;
+                }
+                (Some(_), Some(b)) => arg.push(b),
+            }
+            i += 1;
+        }
+        args.push(arg);
+    }
+}
+
+// How far frame_end has walked the frame at the start of a connection's
+// input. Blank lines skipped before a multibulk stay in the input until a
+// request is returned, so the frame starts at the same offset on every call.
+#[derive(Default)]
+pub struct FrameScan {
+    // Where the next header starts
+    pos: usize,
+    // Elements still to come in each array the walk is inside, outermost
+    // first
+    remaining: Vec<i64>,
+}
+
+impl FrameScan {
+    // Where the frame ends, or None while it is incomplete, in which case
+    // the walk picks up from the last whole header next time. Only headers
+    // are read, so a large bulk string isn't copied again on every read
+    // while it arrives. Malformed headers end their element right away and
+    // are left for the parser to report.
+    fn frame_end(&mut self, input: &[u8], max_depth: usize, max_bulk_len: usize) -> Option<usize> {
+        loop {
+            let pos = self.pos;
+            let line_end = pos + input.get(pos..)?.iter().position(|&b| b == b'\n')? + 1;
+            let header = &input[pos..line_end];
+            let number = std::str::from_utf8(header.get(1..)?)
+                .ok()
+                .and_then(|n| n.trim_end().parse::<i64>().ok());
+
+            self.pos = match (header[0], number) {
+                (b'$', Some(len)) if len >= 0 && len as u64 <= max_bulk_len as u64 => {
+                    let data_end = line_end.checked_add(len as usize)?;
+                    match input.get(data_end)? {
+                        b'\r' => (input.len() > data_end + 1).then_some(data_end + 2)?,
+                        _ => data_end + 1,
+                    }
+                }
+                (b'*', Some(count)) if count > 0 && self.remaining.len() < max_depth => {
+                    self.pos = line_end;
+                    self.remaining.push(count);
+                    continue;
+                }
+                _ => line_end,
+            };
+
+            // That element is done, and with it every array it completes
+            loop {
+                let Some(left) = self.remaining.last_mut() else {
+                    return Some(self.pos);
+                };
+                *left -= 1;
+                if *left > 0 {
+                    break;
+                }
+                self.remaining.pop();
+            }
+        }
+    }
+}
+
+fn protocol_error(msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Protocol error: {}", msg),
+    )
+}
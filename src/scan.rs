@@ -0,0 +1,109 @@
+use bytes::Bytes;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::LazyLock;
+
+// Fixed for the process, so SCAN cursors stay valid between calls and
+// across the keyspaces that replace one another on load and flush
+static HASHER: LazyLock<RandomState> = LazyLock::new(RandomState::new);
+
+// Fewest buckets a table has once it holds a key, like Redis' dict
+const MIN_BUCKETS: usize = 4;
+
+// The keys again, bucketed by hash the way Redis' dict holds them, for
+// SCAN's reverse-binary cursor. std's HashMap doesn't expose its buckets,
+// so this table mirrors them: a power of two of them, doubled once there
+// are more keys than buckets and halved once under an eighth are used, all
+// at once like the HashMap it shadows.
+//
+// The cursor is a bucket index with its bits reversed and incremented from
+// the top, so it walks the buckets in an order that stays valid whatever
+// the table grows or shrinks to between calls: a key present for the whole
+// iteration is returned at least once. Only a shrink can return one twice.
+#[derive(Default)]
+pub struct ScanTable {
+    buckets: Vec<Vec<Bytes>>,
+    len: usize,
+}
+
+impl ScanTable {
+    // `key` must not be in the table already
+    pub fn insert(&mut self, key: Bytes) {
+        if self.len >= self.buckets.len() {
+            self.resize((self.len + 1).next_power_of_two().max(MIN_BUCKETS));
+        }
+        let bucket = self.bucket(&key);
+        self.buckets[bucket].push(key);
+        self.len += 1;
+    }
+
+    pub fn remove(&mut self, key: &[u8]) {
+        if self.buckets.is_empty() {
+            return;
+        }
+        let bucket = self.bucket(key);
+        let keys = &mut self.buckets[bucket];
+        let Some(at) = keys.iter().position(|k| k == key) else {
+            return;
+        };
+        keys.swap_remove(at);
+        self.len -= 1;
+        if self.buckets.len() > MIN_BUCKETS && self.len * 8 < self.buckets.len() {
+            self.resize(self.len.next_power_of_two().max(MIN_BUCKETS));
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.buckets = Vec::new();
+        self.len = 0;
+    }
+
+    // One SCAN call: every key in the buckets from `cursor` on until at
+    // least `count` are gathered, like Redis a bucket at a time, so a call
+    // may return a few more. At most ten empty buckets per key asked for
+    // are visited, so a sparse table can return fewer. Returns the cursor
+    // to continue from, 0 once every bucket has been visited.
+    pub fn scan(&self, mut cursor: u64, count: usize) -> (u64, Vec<&Bytes>) {
+        if self.buckets.is_empty() {
+            return (0, Vec::new());
+        }
+        let mask = self.buckets.len() as u64 - 1;
+        let mut empty_visits = count.saturating_mul(10);
+        let mut keys = Vec::new();
+        loop {
+            let bucket = &self.buckets[(cursor & mask) as usize];
+            keys.extend(bucket);
+            if bucket.is_empty() {
+                empty_visits = empty_visits.saturating_sub(1);
+            }
+            // Add one to the reversed index, carrying into the bits above
+            // the mask so the walk wraps to 0 after the last bucket
+            cursor = (cursor | !mask)
+                .reverse_bits()
+                .wrapping_add(1)
+                .reverse_bits();
+            if cursor == 0 || keys.len() >= count || empty_visits == 0 {
+                return (cursor, keys);
+            }
+        }
+    }
+
+    // The bucket array and the key handles in it, for used_memory; the key
+    // bytes are shared with the keyspace's own map
+    pub fn memory_usage(&self) -> usize {
+        self.buckets.capacity() * std::mem::size_of::<Vec<Bytes>>()
+            + self.len * std::mem::size_of::<Bytes>()
+    }
+
+    fn bucket(&self, key: &[u8]) -> usize {
+        (HASHER.hash_one(key) & (self.buckets.len() as u64 - 1)) as usize
+    }
+
+    fn resize(&mut self, size: usize) {
+        let old = std::mem::replace(&mut self.buckets, vec![Vec::new(); size]);
+        for key in old.into_iter().flatten() {
+            let bucket = self.bucket(&key);
+            self.buckets[bucket].push(key);
+        }
+    }
+}
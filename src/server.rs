@@ -1,68 +1,934 @@
-use crate::command::Command;
-use crate::resp::RESPValue;
+use crate::blocking::{Blocking, Popped};
+use crate::clients::{Client, ClientRegistry};
+use crate::clock::Clock;
+use crate::command::{Command, waitaof_reply};
+use crate::command_table::{self, CommandSpec};
+use crate::config::Config;
+use crate::deadline::Deadline;
+use crate::id;
+use crate::latency::LatencyMonitor;
+use crate::lock;
+use crate::metrics;
+use crate::notify::KeyspaceEvents;
+use crate::pause::Pause;
+use crate::persistence;
+use crate::pubsub::{MessageReceiver, PubSub, Subscriptions, message_channel};
+use crate::replication::Replication;
+use crate::resp::{FrameScan, RESPValue};
+use crate::stats::Stats;
 use crate::store::Store;
-use std::io::BufReader;
+use crate::tracking::Tracking;
+use crate::transaction::Transaction;
+use crate::watch::Watches;
+use socket2::{SockRef, TcpKeepalive};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::Instrument;
 
+// Cheap to clone: every connection task gets its own handle to the shared state
+#[derive(Clone)]
 pub struct Server {
     store: Store,
-    addr: String,
+    replication: Replication,
+    pubsub: PubSub,
+    clients: ClientRegistry,
+    tracking: Tracking,
+    watches: Watches,
+    blocking: Blocking,
+    pause: Pause,
+    latency: LatencyMonitor,
+    stats: Arc<Stats>,
+    config: Arc<Config>,
+    // Generated once at startup and stable for the process lifetime
+    node_id: Arc<String>,
+    // Commands run under the read side; EXEC takes the write side so no other
+    // command interleaves with a transaction
+    exec_lock: Arc<RwLock<()>>,
 }
 
 impl Server {
-    pub fn new(addr: String) -> Self {
+    pub fn new(config: Config) -> Self {
+        Self::with_clock(config, Clock::system())
+    }
+
+    // A server whose key expiry runs on `clock`, so tests can move time
+    // forward instead of sleeping
+    pub fn with_clock(config: Config, clock: Clock) -> Self {
+        let pubsub = PubSub::new();
+        let tracking = Tracking::new();
+        let watches = Watches::new();
+        let events = KeyspaceEvents::new(
+            pubsub.clone(),
+            config.notify_keyspace_events,
+            tracking.clone(),
+            watches.clone(),
+        );
         Self {
-            store: Store::new(),
-            addr,
+            store: Store::new(events, clock),
+            replication: Replication::new(),
+            pubsub,
+            clients: ClientRegistry::new(),
+            tracking,
+            watches,
+            blocking: Blocking::new(),
+            pause: Pause::new(),
+            latency: LatencyMonitor::new(),
+            stats: Arc::new(Stats::new()),
+            config: Arc::new(config),
+            node_id: Arc::new(id::generate()),
+            exec_lock: Arc::new(RwLock::new(())),
         }
     }
 
     pub async fn run(&self) -> std::io::Result<()> {
-        let listener = TcpListener::bind(&self.addr).await?;
-        println!("Rudis server listening on {}", self.addr);
+        // Bind everything before serving anything, so a bad address stops
+        // startup instead of leaving the server up on only some of them
+        let mut listeners = Vec::new();
+        for addr in &self.config.addrs {
+            let listener = TcpListener::bind(addr).await.map_err(|e| {
+                std::io::Error::new(e.kind(), format!("Could not bind {}: {}", addr, e))
+            })?;
+            tracing::info!("Rudis server listening on {}", addr);
+            listeners.push(listener);
+        }
+        self.serve(listeners).await
+    }
 
-        loop {
-            let (socket, addr) = listener.accept().await?;
-            println!("New connection from: {}", addr);
+    #[cfg(test)]
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
 
+    // Serve clients on listeners that are already bound, with the
+    // background tasks (active expiry, metrics, save points) alongside
+    pub async fn serve(&self, listeners: Vec<TcpListener>) -> std::io::Result<()> {
+        // Active expiry: keys nobody reads again are still removed, and fire
+        // the same expired events as lazy expiry on access. Checked every
+        // cycle so DEBUG SET-ACTIVE-EXPIRE takes effect on the next tick.
+        let store = self.store.clone();
+        let config = self.config.clone();
+        let period = Duration::from_millis(1000 / self.config.hz);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(period);
+            loop {
+                ticker.tick().await;
+                if config.active_expire_enabled() {
+                    store.reap_expired();
+                }
+            }
+        });
+
+        if let Some(addr) = self.config.metrics_addr.clone() {
             let store = self.store.clone();
+            let stats = self.stats.clone();
             tokio::spawn(async move {
-                if let Err(e) = Self::handle_client(socket, store).await {
-                    eprintln!("Error handling client {}: {}", addr, e);
+                if let Err(e) = metrics::run(addr, store, stats).await {
+                    tracing::error!("Metrics listener stopped: {}", e);
                 }
             });
         }
+
+        tokio::spawn(persistence::run_save_schedule(
+            self.store.clone(),
+            self.config.clone(),
+            self.stats.clone(),
+        ));
+
+        // All listeners feed the same store; the first accept error stops the server
+        let mut accept_loops = tokio::task::JoinSet::new();
+        for listener in listeners {
+            let server = self.clone();
+            accept_loops.spawn(async move { server.accept(listener).await });
+        }
+        match accept_loops.join_next().await {
+            Some(Ok(result)) => result,
+            Some(Err(e)) => Err(std::io::Error::other(e)),
+            None => Ok(()),
+        }
+    }
+
+    async fn accept(&self, listener: TcpListener) -> std::io::Result<()> {
+        loop {
+            let (socket, addr) = listener.accept().await?;
+            let id = self.stats.next_client_id();
+            // Everything logged for the connection carries its id and address
+            let span = tracing::info_span!("client", id, addr = %addr);
+            if let Err(e) = self.configure_socket(&socket) {
+                tracing::warn!(parent: &span, "Could not set socket options: {}", e);
+            }
+
+            // Like Redis, the client is told why before being disconnected
+            if self.stats.connected_clients() >= self.config.maxclients {
+                tracing::warn!(parent: &span, "Rejected connection: maxclients reached");
+                tokio::spawn(async move {
+                    let mut socket = socket;
+                    let _ = socket
+                        .write_all(b"-ERR max number of clients reached\r\n")
+                        .await;
+                });
+                continue;
+            }
+
+            let server = self.clone();
+            server.stats.client_connected();
+            tokio::spawn(
+                async move {
+                    tracing::info!("Client connected");
+                    match server.handle_client(socket, id).await {
+                        Ok(()) => tracing::info!("Client disconnected"),
+                        Err(e) => tracing::error!("Error handling client: {}", e),
+                    }
+                    server.stats.client_disconnected();
+                }
+                .instrument(span),
+            );
+        }
+    }
+
+    // TCP_NODELAY and tcp-keepalive, as configured
+    pub fn configure_socket(&self, socket: &TcpStream) -> std::io::Result<()> {
+        socket.set_nodelay(self.config.tcp_nodelay)?;
+        if self.config.tcp_keepalive > 0 {
+            let keepalive =
+                TcpKeepalive::new().with_time(Duration::from_secs(self.config.tcp_keepalive));
+            SockRef::from(socket).set_tcp_keepalive(&keepalive)?;
+        }
+        Ok(())
+    }
+
+    async fn handle_client(&self, mut socket: TcpStream, id: u64) -> std::io::Result<()> {
+        let address = |addr: std::io::Result<std::net::SocketAddr>| {
+            addr.map(|a| a.to_string()).unwrap_or_default()
+        };
+        let (sender, mut messages) = message_channel(self.config.output_buffer_limit_pubsub);
+        let client = self.clients.register(
+            id,
+            address(socket.peer_addr()),
+            address(socket.local_addr()),
+            sender.clone(),
+        );
+        client.set_authenticated(self.config.requirepass.is_none());
+        let mut subscriptions = Subscriptions::new(id, sender);
+
+        let result = self
+            .serve_client(&mut socket, &client, &mut subscriptions, &mut messages)
+            .await;
+        subscriptions.clear(&self.pubsub);
+        self.tracking.disable(id);
+        self.watches.unwatch(id);
+        self.clients.unregister(id);
+        result
     }
 
-    async fn handle_client(mut socket: TcpStream, store: Store) -> std::io::Result<()> {
+    async fn serve_client(
+        &self,
+        socket: &mut TcpStream,
+        client: &Client,
+        subscriptions: &mut Subscriptions,
+        messages: &mut MessageReceiver,
+    ) -> std::io::Result<()> {
         let mut buffer = vec![0u8; 4096];
+        // Bytes read but not parsed yet: pipelined commands and the start of
+        // one that hasn't fully arrived
+        let mut input: Vec<u8> = Vec::new();
+        let mut scan = FrameScan::default();
+        // Replies not written yet: a pipeline's replies go out in one write
+        // once it runs dry, or in several once they pass output-flush-bytes
+        let mut output: Vec<u8> = Vec::new();
+        let mut soft_since = None;
+        let mut transaction: Option<Transaction> = None;
+        let overflow = messages.overflow();
 
         loop {
-            let n = socket.read(&mut buffer).await?;
+            // Pub/sub messages are written as soon as they arrive, in between commands
+            let n = tokio::select! {
+                n = socket.read(&mut buffer) => n?,
+                Some(message) = messages.recv() => {
+                    tokio::select! {
+                        result = socket.write_all(&message) => result?,
+                        _ = overflow.notified() => return self.close_overflowed("pubsub"),
+                    }
+                    continue;
+                }
+                _ = overflow.notified() => return self.close_overflowed("pubsub"),
+            };
             if n == 0 {
+                // A clean close lands on a frame boundary; anything left over
+                // is a command the client never finished sending
+                if !input.is_empty() {
+                    tracing::info!(
+                        pending = input.len(),
+                        "Client closed the connection in the middle of a command"
+                    );
+                }
                 return Ok(());
             }
+            input.extend_from_slice(&buffer[..n]);
 
-            let cursor = std::io::Cursor::new(&buffer[..n]);
-            let mut reader = BufReader::new(cursor);
+            loop {
+                let value = match RESPValue::parse_request(
+                    &input,
+                    &mut scan,
+                    self.config.max_nesting_depth,
+                    self.config.proto_max_bulk_len(),
+                ) {
+                    Ok(Some((value, used))) => {
+                        input.drain(..used);
+                        value
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        // Like Redis: after malformed framing the rest of the
+                        // stream can't be trusted, so reply and close
+                        self.flush(socket, &mut output).await?;
+                        socket
+                            .write_all(&RESPValue::Error(format!("ERR {}", e)).serialize())
+                            .await?;
+                        return Ok(());
+                    }
+                };
 
-            match RESPValue::parse(&mut reader) {
-                Ok(value) => {
-                    let response = if let Some(cmd) = Command::from_resp(value) {
-                        println!("Executing command: {}", cmd.name);
-                        cmd.execute(&store)
-                    } else {
-                        RESPValue::Error("ERR invalid command format".to_string())
-                    };
+                let parsed = self.parse_command(value);
+                let spec = parsed
+                    .as_ref()
+                    .ok()
+                    .and_then(|cmd| command_table::lookup(&cmd.name));
+                let replies = match parsed
+                    .and_then(|cmd| require_auth(cmd, client))
+                    .and_then(|cmd| require_subscribe_context(cmd, subscriptions))
+                {
+                    Ok(cmd) => {
+                        self.wait_if_paused(&cmd, transaction.as_ref(), socket, &mut output)
+                            .await?;
+                        tracing::debug!(command = %cmd.name, "Executing command");
+                        client.record_command(&cmd.display_name());
+                        let argc = cmd.args.len() + 1;
+                        let started = Instant::now();
+                        let replies = match cmd.name.as_str() {
+                            "MULTI" | "EXEC" | "DISCARD" => {
+                                vec![self.handle_transaction(&cmd, &mut transaction, client)]
+                            }
+                            "QUIT" => {
+                                RESPValue::SimpleString("OK".to_string())
+                                    .serialize_into(&mut output)?;
+                                return self.flush(socket, &mut output).await;
+                            }
+                            "RESET" => {
+                                // Back to the state of a freshly accepted connection
+                                transaction = None;
+                                subscriptions.clear(&self.pubsub);
+                                subscriptions.set_resp3(false);
+                                client.set_subscriptions(0, 0);
+                                client.set_tracking(false);
+                                client.set_name(String::new());
+                                client.set_authenticated(self.config.requirepass.is_none());
+                                self.tracking.disable(client.id);
+                                self.watches.unwatch(client.id);
+                                vec![RESPValue::SimpleString("RESET".to_string())]
+                            }
+                            _ if transaction.is_some() => {
+                                vec![transaction.as_mut().unwrap().queue(cmd)]
+                            }
+                            "SYNC" => {
+                                // The connection becomes a replication link from here on
+                                self.flush(socket, &mut output).await?;
+                                return self.serve_replica(socket).await;
+                            }
+                            "SUBSCRIBE" | "UNSUBSCRIBE" | "PSUBSCRIBE" | "PUNSUBSCRIBE" => {
+                                // One reply frame per channel or pattern
+                                let replies = cmd.handle_subscribe(subscriptions, &self.pubsub);
+                                client.set_subscriptions(
+                                    subscriptions.channel_count(),
+                                    subscriptions.pattern_count(),
+                                );
+                                replies
+                            }
+                            "PING" if subscriptions.count() > 0 && !subscriptions.is_resp3() => {
+                                // Subscribed RESP2 replies are all arrays, so PING
+                                // answers in the same shape as a message
+                                let message = cmd.args.first().cloned().unwrap_or_default();
+                                vec![RESPValue::Array(Some(vec![
+                                    RESPValue::BulkString(Some(b"pong".to_vec())),
+                                    RESPValue::BulkString(Some(message)),
+                                ]))]
+                            }
+                            "HELLO" => vec![cmd.handle_hello(
+                                subscriptions,
+                                client,
+                                &self.config,
+                                &self.replication,
+                            )],
+                            "DEBUG" if cmd.debug_sleep().is_some() => {
+                                match cmd.debug_sleep().unwrap() {
+                                    Ok(duration) => {
+                                        // Nothing is locked while this connection sleeps
+                                        self.flush(socket, &mut output).await?;
+                                        tokio::time::sleep(duration).await;
+                                        vec![RESPValue::SimpleString("OK".to_string())]
+                                    }
+                                    Err(e) => vec![e],
+                                }
+                            }
+                            "WAITAOF" => {
+                                self.flush(socket, &mut output).await?;
+                                match self.waitaof(&cmd, socket).await {
+                                    Some(reply) => vec![reply],
+                                    // Disconnected while waiting
+                                    None => return Ok(()),
+                                }
+                            }
+                            "BLPOP" | "BRPOP" => {
+                                // Earlier replies must not wait on the pop
+                                self.flush(socket, &mut output).await?;
+                                match self.blocking_pop(&cmd, client, socket).await {
+                                    Some(reply) => vec![reply],
+                                    // Disconnected while blocked
+                                    None => return Ok(()),
+                                }
+                            }
+                            _ => vec![self.dispatch(&cmd, client)],
+                        };
+                        if let Some(spec) = spec {
+                            self.record_command(spec, argc, &replies, started.elapsed());
+                        }
+                        replies
+                    }
+                    Err(e) => {
+                        if let Some(spec) = spec {
+                            self.stats.record_rejected(spec.name);
+                        }
+                        // Rejected before queueing, but still fatal to an open MULTI
+                        if let Some(transaction) = transaction.as_mut() {
+                            transaction.abort();
+                        }
+                        vec![e]
+                    }
+                };
 
-                    socket.write_all(&response.serialize()).await?;
+                let pending =
+                    output.len() + replies.iter().map(RESPValue::serialized_len).sum::<usize>();
+                if self
+                    .config
+                    .output_buffer_limit_normal
+                    .is_exceeded(pending, &mut soft_since)
+                {
+                    return self.close_overflowed("normal");
+                }
+                for reply in &replies {
+                    let len = reply.serialized_len();
+                    if len < self.config.output_flush_bytes {
+                        reply.serialize_into(&mut output)?;
+                        continue;
+                    }
+                    // Too big to batch: streamed as it is serialized, after
+                    // the replies queued before it
+                    self.flush(socket, &mut output).await?;
+                    reply.write_to(socket).await?;
+                    self.stats.record_write(len);
+                }
+                if output.len() >= self.config.output_flush_bytes {
+                    self.flush(socket, &mut output).await?;
                 }
-                Err(e) => {
-                    let error = RESPValue::Error(format!("ERR parse error: {}", e));
-                    socket.write_all(&error.serialize()).await?;
+                soft_since = None;
+            }
+            self.flush(socket, &mut output).await?;
+
+            // High-water mark for a command that keeps arriving without end
+            if input.len() > self.config.client_query_buffer_limit {
+                tracing::warn!("Closing client: query buffer limit reached");
+                return Ok(());
+            }
+        }
+    }
+
+    // Commands are looked up by the name clients must use after
+    // rename-command, then run under their real name
+    fn parse_command(&self, value: RESPValue) -> Result<Command, RESPValue> {
+        let Some(mut cmd) = Command::from_resp(value) else {
+            return Err(RESPValue::Error("ERR invalid command format".to_string()));
+        };
+        match self.config.rename_commands.resolve(&cmd.name) {
+            Some(name) => {
+                cmd.name = name.to_string();
+                Ok(cmd)
+            }
+            None => Err(RESPValue::Error(format!(
+                "ERR unknown command '{}'",
+                cmd.name
+            ))),
+        }
+    }
+
+    // INFO commandstats and the latency monitor. Arity is checked by each
+    // command as it runs, so an error for a call with the wrong argument
+    // count is a rejection rather than a failure, as in Redis. Time a
+    // blocking command spent waiting is not a latency spike.
+    fn record_command(
+        &self,
+        spec: &'static CommandSpec,
+        argc: usize,
+        replies: &[RESPValue],
+        took: Duration,
+    ) {
+        let failed = replies.iter().any(|r| matches!(r, RESPValue::Error(_)));
+        if failed && !spec.accepts(argc) {
+            self.stats.record_rejected(spec.name);
+        } else {
+            self.stats.record_command(spec.name, took, failed);
+        }
+        let threshold = self.config.latency_monitor_threshold;
+        let millis = took.as_millis() as u64;
+        if threshold > 0
+            && millis >= threshold
+            && !matches!(spec.name, "BLPOP" | "BRPOP" | "WAITAOF")
+        {
+            self.latency.record("command", millis);
+        }
+    }
+
+    async fn flush(&self, socket: &mut TcpStream, output: &mut Vec<u8>) -> std::io::Result<()> {
+        if output.is_empty() {
+            return Ok(());
+        }
+        socket.write_all(output).await?;
+        self.stats.record_write(output.len());
+        output.clear();
+        Ok(())
+    }
+
+    fn close_overflowed(&self, class: &str) -> std::io::Result<()> {
+        tracing::warn!("Closing client: {} output buffer limit reached", class);
+        Ok(())
+    }
+
+    // MULTI starts queueing, EXEC runs the queue atomically (unless a command
+    // was rejected while queueing) and DISCARD drops it
+    fn handle_transaction(
+        &self,
+        cmd: &Command,
+        transaction: &mut Option<Transaction>,
+        client: &Client,
+    ) -> RESPValue {
+        if !cmd.args.is_empty() {
+            return RESPValue::Error(format!(
+                "ERR wrong number of arguments for '{}' command",
+                cmd.name.to_lowercase()
+            ));
+        }
+
+        match cmd.name.as_str() {
+            "MULTI" => {
+                if transaction.is_some() {
+                    return RESPValue::Error("ERR MULTI calls can not be nested".to_string());
                 }
+                *transaction = Some(Transaction::new());
+                RESPValue::SimpleString("OK".to_string())
             }
+            "DISCARD" => match transaction.take() {
+                Some(_) => {
+                    self.watches.unwatch(client.id);
+                    RESPValue::SimpleString("OK".to_string())
+                }
+                None => RESPValue::Error("ERR DISCARD without MULTI".to_string()),
+            },
+            _ => match transaction.take() {
+                None => RESPValue::Error("ERR EXEC without MULTI".to_string()),
+                Some(queued) if queued.is_aborted() => {
+                    self.watches.unwatch(client.id);
+                    RESPValue::Error(
+                        "EXECABORT Transaction discarded because of previous errors.".to_string(),
+                    )
+                }
+                // Runtime errors are just replies: the remaining commands still run
+                Some(queued) => {
+                    let _guard = self.lock_exclusive();
+                    // A watched key that has expired by now changed too, even
+                    // if nothing has removed it yet
+                    for key in self.watches.keys(client.id) {
+                        self.store.remove_if_expired(&key);
+                    }
+                    if self.watches.unwatch(client.id) {
+                        return RESPValue::Array(None);
+                    }
+                    RESPValue::Array(Some(
+                        queued
+                            .into_commands()
+                            .iter()
+                            .map(|cmd| self.execute(cmd, client))
+                            .collect(),
+                    ))
+                }
+            },
         }
     }
+
+    // CLIENT PAUSE: hold the command back until the pause ends. Commands
+    // queued inside MULTI are not held, their EXEC is; CLIENT itself never
+    // is, so CLIENT UNPAUSE can always get through.
+    async fn wait_if_paused(
+        &self,
+        cmd: &Command,
+        transaction: Option<&Transaction>,
+        socket: &mut TcpStream,
+        output: &mut Vec<u8>,
+    ) -> std::io::Result<()> {
+        let is_write = match cmd.name.as_str() {
+            "CLIENT" => return Ok(()),
+            "EXEC" => transaction.is_some_and(|t| t.has_writes()),
+            _ if transaction.is_some() => return Ok(()),
+            "BLPOP" | "BRPOP" => true,
+            _ => cmd.is_write(),
+        };
+        // Replies to the commands before this one aren't held with it
+        if self.pause.holds(is_write) {
+            self.flush(socket, output).await?;
+        }
+        self.pause.wait(is_write).await;
+        Ok(())
+    }
+
+    fn dispatch(&self, cmd: &Command, client: &Client) -> RESPValue {
+        let _guard = self.lock_shared();
+        self.execute(cmd, client)
+    }
+
+    // Commands run holding exec_lock, so one that panics poisons it. The
+    // lock guards no data, so later commands take it over instead of
+    // panicking in turn.
+    fn lock_shared(&self) -> RwLockReadGuard<'_, ()> {
+        lock::read(&self.exec_lock)
+    }
+
+    fn lock_exclusive(&self) -> RwLockWriteGuard<'_, ()> {
+        lock::write(&self.exec_lock)
+    }
+
+    fn execute(&self, cmd: &Command, client: &Client) -> RESPValue {
+        match cmd.name.as_str() {
+            "REPLICAOF" | "SLAVEOF" => cmd.handle_replicaof(&self.replication, &self.store),
+            "WAITAOF" => cmd.handle_waitaof(&self.replication),
+            "INFO" => cmd.handle_info(
+                &self.store,
+                &self.stats,
+                &self.replication,
+                &self.config,
+                client.is_resp3(),
+            ),
+            "LOLWUT" => cmd.handle_lolwut(client.is_resp3()),
+            "DEBUG" => cmd.handle_debug(
+                &self.store,
+                &self.config,
+                &self.stats,
+                &self.replication,
+                client.is_resp3(),
+            ),
+            "CLUSTER" => cmd.handle_cluster(&self.node_id),
+            "COMMAND" => cmd.handle_command(),
+            "AUTH" => cmd.handle_auth(&self.config, client),
+            "CLIENT" => cmd.handle_client(&self.clients, &self.tracking, &self.pause, client),
+            "FAILOVER" => cmd.handle_failover(&self.replication),
+            "LATENCY" => cmd.handle_latency(&self.latency),
+            "WATCH" if cmd.args.is_empty() => {
+                RESPValue::Error("ERR wrong number of arguments for 'watch' command".to_string())
+            }
+            "WATCH" => {
+                self.watches.watch(client.id, &cmd.args);
+                RESPValue::SimpleString("OK".to_string())
+            }
+            "UNWATCH" if !cmd.args.is_empty() => {
+                RESPValue::Error("ERR wrong number of arguments for 'unwatch' command".to_string())
+            }
+            "UNWATCH" => {
+                self.watches.unwatch(client.id);
+                RESPValue::SimpleString("OK".to_string())
+            }
+            "HEALTH" => cmd.handle_health(&self.stats, &self.replication),
+            "SAVE" => cmd.handle_save(&self.store, &self.config, &self.stats),
+            "BGSAVE" => cmd.handle_bgsave(&self.store, &self.config, &self.stats),
+            "BGREWRITEAOF" => {
+                cmd.handle_bgrewriteaof(&self.store, &self.replication, &self.config, &self.stats)
+            }
+            "LASTSAVE" => cmd.handle_lastsave(&self.stats),
+            "CONFIG" => cmd.handle_config(&self.config),
+            "OBJECT" => cmd.handle_object(&self.store, &self.config),
+            "PUBLISH" => cmd.handle_publish(&self.pubsub),
+            "PUBSUB" => cmd.handle_pubsub(&self.pubsub),
+            // Inside MULTI: pop if possible, never block
+            "BLPOP" | "BRPOP" => match cmd.blocking_pop_args() {
+                Err(e) => e,
+                Ok(_) if self.replication.is_replica() => RESPValue::Error(
+                    "READONLY You can't write against a read only replica.".to_string(),
+                ),
+                Ok((keys, _)) => popped_reply(self.blocking.pop_now(
+                    &keys,
+                    cmd.name == "BLPOP",
+                    |key, from_left| self.pop_list(key, from_left),
+                )),
+            },
+            _ if cmd.is_write() => {
+                let defaulted = cmd.with_default_ttl(self.config.default_ttl_seconds);
+                let cmd = defaulted.as_ref().unwrap_or(cmd);
+                if self.replication.is_replica() {
+                    RESPValue::Error(
+                        "READONLY You can't write against a read only replica.".to_string(),
+                    )
+                } else if !self.make_room() && !cmd.frees_memory() {
+                    RESPValue::Error(
+                        "OOM command not allowed when used memory > 'maxmemory'.".to_string(),
+                    )
+                } else {
+                    let reply = self.replication.execute_write(
+                        cmd,
+                        &self.store,
+                        self.config.proto_max_bulk_len(),
+                    );
+                    if !matches!(reply, RESPValue::Error(_)) {
+                        self.stats.add_dirty(1);
+                        if matches!(cmd.name.as_str(), "FLUSHDB" | "FLUSHALL") {
+                            self.tracking.invalidate_all();
+                            self.watches.touch_all();
+                        } else {
+                            self.tracking.invalidate(&cmd.written_keys());
+                            self.watches.touch(&cmd.written_keys());
+                        }
+                        // The write and its events are done; now the clients
+                        // blocked on the keys it wrote, whether a push, SORT
+                        // STORE or RESTORE left the list there
+                        for key in cmd.written_keys() {
+                            self.blocking
+                                .wake(key, |key, from_left| self.pop_list(key, from_left));
+                        }
+                    }
+                    reply
+                }
+            }
+            _ => {
+                let deadline =
+                    Deadline::after(Duration::from_millis(self.config.command_timeout_ms));
+                let reply =
+                    cmd.execute_within(&self.store, deadline, self.config.proto_max_bulk_len());
+                // Errors (WRONGTYPE included) count as neither a hit nor a miss
+                if !matches!(reply, RESPValue::Error(_)) {
+                    let keys = cmd.keys();
+                    for key in &keys {
+                        self.stats.record_lookup(self.store.contains(key));
+                    }
+                    if client.is_tracking() {
+                        self.tracking.remember(client.id, &keys);
+                    }
+                }
+                reply
+            }
+        }
+    }
+
+    // BLPOP/BRPOP outside a transaction. Returns None if the client
+    // disconnected while blocked.
+    async fn blocking_pop(
+        &self,
+        cmd: &Command,
+        client: &Client,
+        socket: &TcpStream,
+    ) -> Option<RESPValue> {
+        let (keys, timeout) = match cmd.blocking_pop_args() {
+            Ok(args) => args,
+            Err(e) => return Some(e),
+        };
+        if self.replication.is_replica() {
+            return Some(RESPValue::Error(
+                "READONLY You can't write against a read only replica.".to_string(),
+            ));
+        }
+
+        let from_left = cmd.name == "BLPOP";
+        let mut ticket = {
+            let _guard = self.lock_shared();
+            match self
+                .blocking
+                .pop_or_wait(client.id, &keys, from_left, |key, from_left| {
+                    self.pop_list(key, from_left)
+                }) {
+                Ok(popped) => return Some(popped_reply(Some(popped))),
+                Err(ticket) => ticket,
+            }
+        };
+        let _blocked = self.stats.client_blocked();
+
+        let deadline = timeout.map(|t| tokio::time::Instant::now() + t);
+        // Pipelined commands wait until the pop is done; only EOF matters here
+        let mut watch_socket = true;
+        loop {
+            let mut probe = [0u8; 1];
+            tokio::select! {
+                popped = ticket.served() => return Some(popped_reply(popped)),
+                _ = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now)),
+                    if deadline.is_some() => break,
+                n = socket.peek(&mut probe), if watch_socket => match n {
+                    Ok(0) | Err(_) => {
+                        // Put back an element that was handed over as the client left
+                        if let Some((key, value)) = self.blocking.cancel(ticket) {
+                            let push = if from_left { "LPUSH" } else { "RPUSH" };
+                            self.dispatch(
+                                &Command { name: push.to_string(), args: vec![key, value] },
+                                client,
+                            );
+                        }
+                        return None;
+                    }
+                    Ok(_) => watch_socket = false,
+                },
+            }
+        }
+        Some(popped_reply(self.blocking.cancel(ticket)))
+    }
+
+    // No fsync is ever acknowledged, so this only waits out the timeout,
+    // watching for the client leaving like a blocking pop
+    async fn waitaof(&self, cmd: &Command, socket: &TcpStream) -> Option<RESPValue> {
+        let timeout = match cmd.waitaof_timeout(&self.replication) {
+            Ok(timeout) => timeout,
+            Err(e) => return Some(e),
+        };
+        let _blocked = self.stats.client_blocked();
+        let deadline = timeout.map(|t| tokio::time::Instant::now() + t);
+        let mut watch_socket = true;
+        loop {
+            let mut probe = [0u8; 1];
+            tokio::select! {
+                _ = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now)),
+                    if deadline.is_some() => return Some(waitaof_reply()),
+                n = socket.peek(&mut probe), if watch_socket => match n {
+                    Ok(0) | Err(_) => return None,
+                    Ok(_) => watch_socket = false,
+                },
+                // Pipelined input arrived behind a WAITAOF without a timeout
+                else => std::future::pending::<()>().await,
+            }
+        }
+    }
+
+    // Evict keys by the maxmemory policy until the estimated memory use is
+    // within maxmemory, deleting them with a DEL so replicas and tracking
+    // see it. Returns false if it's still over.
+    fn make_room(&self) -> bool {
+        if self.config.maxmemory == 0 {
+            return true;
+        }
+        let (victims, fits) = self
+            .store
+            .eviction_victims(self.config.maxmemory, self.config.maxmemory_policy);
+        if !victims.is_empty() {
+            let del = Command {
+                name: "DEL".to_string(),
+                args: victims.into_iter().map(Vec::from).collect(),
+            };
+            if let RESPValue::Integer(deleted) =
+                self.replication
+                    .execute_write(&del, &self.store, self.config.proto_max_bulk_len())
+            {
+                self.stats.add_evicted(deleted as usize);
+                self.stats.add_dirty(1);
+            }
+            self.tracking.invalidate(&del.keys());
+            self.watches.touch(&del.keys());
+        }
+        fits
+    }
+
+    // One element popped for a blocked or blocking client, run as a plain
+    // LPOP/RPOP so replicas, tracking and the dirty counter see it
+    fn pop_list(&self, key: &[u8], from_left: bool) -> Option<Vec<u8>> {
+        let cmd = Command {
+            name: if from_left { "LPOP" } else { "RPOP" }.to_string(),
+            args: vec![key.to_vec()],
+        };
+        match self
+            .replication
+            .execute_write(&cmd, &self.store, self.config.proto_max_bulk_len())
+        {
+            RESPValue::BulkString(Some(value)) => {
+                self.stats.add_dirty(1);
+                self.tracking.invalidate(&[key]);
+                self.watches.touch(&[key]);
+                Some(value)
+            }
+            _ => None,
+        }
+    }
+
+    async fn serve_replica(&self, socket: &mut TcpStream) -> std::io::Result<()> {
+        let (payload, mut feed) = self.replication.attach(&self.store);
+        tracing::info!("Starting full sync with replica ({} bytes)", payload.len());
+        socket
+            .write_all(&RESPValue::BulkString(Some(payload)).serialize())
+            .await?;
+
+        let mut buffer = [0u8; 512];
+        loop {
+            tokio::select! {
+                command = feed.recv() => match command {
+                    Ok(bytes) => socket.write_all(&bytes).await?,
+                    Err(RecvError::Lagged(skipped)) => {
+                        return Err(std::io::Error::other(format!(
+                            "replica fell behind by {} commands",
+                            skipped
+                        )));
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                n = socket.read(&mut buffer) => {
+                    if n? == 0 {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Until a required password is given only AUTH and HELLO (which can carry
+// AUTH) run; anything else is rejected like an unknown command
+fn require_auth(cmd: Command, client: &Client) -> Result<Command, RESPValue> {
+    if client.is_authenticated() || matches!(cmd.name.as_str(), "AUTH" | "HELLO" | "QUIT" | "RESET")
+    {
+        Ok(cmd)
+    } else {
+        Err(RESPValue::Error(
+            "NOAUTH Authentication required.".to_string(),
+        ))
+    }
+}
+
+// A RESP2 connection with subscriptions only takes the commands that manage
+// them; RESP3 tells messages apart from replies, so anything goes there
+fn require_subscribe_context(
+    cmd: Command,
+    subscriptions: &Subscriptions,
+) -> Result<Command, RESPValue> {
+    let allowed = matches!(
+        cmd.name.as_str(),
+        "SUBSCRIBE" | "UNSUBSCRIBE" | "PSUBSCRIBE" | "PUNSUBSCRIBE" | "PING" | "QUIT" | "RESET"
+    );
+    if allowed || subscriptions.count() == 0 || subscriptions.is_resp3() {
+        Ok(cmd)
+    } else {
+        Err(RESPValue::Error(format!(
+            "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+            cmd.name.to_lowercase()
+        )))
+    }
+}
+
+// [key, element], or a nil array when nothing was popped
+fn popped_reply(popped: Option<Popped>) -> RESPValue {
+    match popped {
+        Some((key, value)) => RESPValue::Array(Some(vec![
+            RESPValue::BulkString(Some(key)),
+            RESPValue::BulkString(Some(value)),
+        ])),
+        None => RESPValue::Array(None),
+    }
 }
@@ -0,0 +1,301 @@
+use crate::hash::Hash;
+use crate::keyspace::Keyspace;
+use crate::store::{Snapshot, StringValue, Value};
+use crate::zset::SortedSet;
+use bytes::Bytes;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Snapshot layout:
+//   "RUDIS" <version:u8>
+//   { <type:u8> <key> <expires_at_ms:u64, 0 = none> <payload> }*
+//   0xFF
+// where every byte string is encoded as <len:u32 BE> <bytes>, sorted set
+// scores as the big-endian bits of an f64 and hash fields as
+// <field> <value> <expires_at_ms:u64, 0 = none>.
+const MAGIC: &[u8] = b"RUDIS";
+const VERSION: u8 = 1;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+const EOF: u8 = 0xFF;
+
+pub fn encode(snapshot: &Snapshot) -> Vec<u8> {
+    encode_entries(snapshot.iter())
+}
+
+pub fn encode_entries<'a>(
+    entries: impl Iterator<Item = (&'a Bytes, &'a Value, Option<SystemTime>)>,
+) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+
+    for (key, value, expires_at) in entries {
+        out.push(value_type(value));
+        write_bytes(&mut out, key);
+        write_expiry(&mut out, expires_at);
+        write_payload(&mut out, value);
+    }
+
+    out.push(EOF);
+    out
+}
+
+// DEBUG OBJECT serializedlength: the snapshot bytes of one value, leaving
+// out the entry's type, key and expiry
+pub fn serialized_len(value: &Value) -> usize {
+    let mut out = Vec::new();
+    write_payload(&mut out, value);
+    out.len()
+}
+
+// DUMP's payload: <type:u8> <payload> <version:u8> <checksum:u64 BE>, the
+// value as a snapshot stores it, with the FNV-1a hash of everything before
+// the checksum. It carries the type and contents only, so RESTORE derives
+// the encoding afresh. This is not Redis' RDB format: payloads only move
+// between rudis servers.
+pub fn dump(value: &Value) -> Vec<u8> {
+    let mut out = vec![value_type(value)];
+    write_payload(&mut out, value);
+    out.push(VERSION);
+    let checksum = fnv1a(&out);
+    out.extend_from_slice(&checksum.to_be_bytes());
+    out
+}
+
+// RESTORE: the value a DUMP payload holds, built the way loading a snapshot
+// builds it. The errors are replies, as in Redis.
+pub fn undump(bytes: &[u8]) -> Result<Value, String> {
+    let (body, checksum) = bytes.split_at(bytes.len().saturating_sub(8));
+    if body.len() < 2 || body.last() != Some(&VERSION) || fnv1a(body).to_be_bytes() != checksum {
+        return Err("ERR DUMP payload version or checksum are wrong".to_string());
+    }
+
+    let mut reader = Reader {
+        bytes: &body[1..body.len() - 1],
+        pos: 0,
+    };
+    match read_payload(&mut reader, body[0]) {
+        Ok(value) if reader.pos == reader.bytes.len() => Ok(value),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+            Err("ERR DUMP payload version or checksum are wrong".to_string())
+        }
+        _ => Err("ERR Bad data format".to_string()),
+    }
+}
+
+pub fn decode(bytes: &[u8]) -> io::Result<Keyspace> {
+    let mut reader = Reader { bytes, pos: 0 };
+
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(invalid("bad snapshot header"));
+    }
+    let version = reader.u8()?;
+    if version != VERSION {
+        return Err(invalid(&format!(
+            "unsupported snapshot version {}",
+            version
+        )));
+    }
+
+    let mut data = Keyspace::new();
+    loop {
+        let kind = reader.u8()?;
+        if kind == EOF {
+            break;
+        }
+
+        let key = Bytes::copy_from_slice(reader.bytes()?);
+        let expires_at = reader.expiry()?;
+
+        let value = read_payload(&mut reader, kind)?;
+        data.insert(key.clone(), value);
+        data.set_expiry(&key, expires_at);
+    }
+
+    Ok(data)
+}
+
+// Write via a temporary file so a crash mid-save never truncates the previous snapshot.
+// The temporary name includes the pid so servers sharing a directory never collide.
+pub fn save_to_file(bytes: &[u8], path: &Path) -> io::Result<()> {
+    let tmp = path.with_extension(format!("tmp.{}", std::process::id()));
+    fs::write(&tmp, bytes)?;
+    fs::rename(&tmp, path)
+}
+
+pub fn load_from_file(path: &Path) -> io::Result<Keyspace> {
+    decode(&fs::read(path)?)
+}
+
+fn value_type(value: &Value) -> u8 {
+    match value {
+        Value::String(_) => TYPE_STRING,
+        Value::List(_) => TYPE_LIST,
+        Value::Set(_) => TYPE_SET,
+        Value::ZSet(_) => TYPE_ZSET,
+        Value::Hash(_) => TYPE_HASH,
+    }
+}
+
+fn write_payload(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::String(val) => write_bytes(out, &val.to_bytes()),
+        Value::List(list) => {
+            out.extend_from_slice(&(list.len() as u32).to_be_bytes());
+            for item in list {
+                write_bytes(out, item);
+            }
+        }
+        Value::Set(set) => {
+            out.extend_from_slice(&(set.len() as u32).to_be_bytes());
+            for member in set {
+                write_bytes(out, member);
+            }
+        }
+        Value::ZSet(zset) => {
+            out.extend_from_slice(&(zset.len() as u32).to_be_bytes());
+            for (member, score) in zset.iter() {
+                write_bytes(out, member);
+                out.extend_from_slice(&score.to_bits().to_be_bytes());
+            }
+        }
+        Value::Hash(hash) => {
+            let fields: Vec<_> = hash.fields().collect();
+            out.extend_from_slice(&(fields.len() as u32).to_be_bytes());
+            for (field, value, expires_at) in fields {
+                write_bytes(out, field);
+                write_bytes(out, value);
+                write_expiry(out, expires_at);
+            }
+        }
+    }
+}
+
+fn read_payload(reader: &mut Reader, kind: u8) -> io::Result<Value> {
+    Ok(match kind {
+        TYPE_STRING => Value::String(StringValue::from_bytes(reader.bytes()?.to_vec())),
+        TYPE_LIST => {
+            let count = reader.u32()?;
+            let mut list = Vec::with_capacity(reader.capacity(count, 4));
+            for _ in 0..count {
+                list.push(Bytes::copy_from_slice(reader.bytes()?));
+            }
+            Value::List(list)
+        }
+        TYPE_SET => {
+            let count = reader.u32()?;
+            let mut set = HashSet::with_capacity(reader.capacity(count, 4));
+            for _ in 0..count {
+                set.insert(Bytes::copy_from_slice(reader.bytes()?));
+            }
+            Value::Set(set)
+        }
+        TYPE_ZSET => {
+            let count = reader.u32()?;
+            let mut zset = SortedSet::new();
+            for _ in 0..count {
+                let member = Bytes::copy_from_slice(reader.bytes()?);
+                zset.insert(member, f64::from_bits(reader.u64()?));
+            }
+            Value::ZSet(zset)
+        }
+        TYPE_HASH => {
+            let count = reader.u32()?;
+            let mut hash = Hash::new();
+            for _ in 0..count {
+                let field = Bytes::copy_from_slice(reader.bytes()?);
+                let value = Bytes::copy_from_slice(reader.bytes()?);
+                hash.load_field(field, value, reader.expiry()?);
+            }
+            Value::Hash(hash)
+        }
+        _ => return Err(invalid(&format!("unknown snapshot value type {}", kind))),
+    })
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
+
+fn write_expiry(out: &mut Vec<u8>, expires_at: Option<SystemTime>) {
+    let millis = expires_at
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    out.extend_from_slice(&millis.to_be_bytes());
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() - self.pos < n {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "truncated snapshot",
+            ));
+        }
+        let slice = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    // Room to reserve for `count` elements of at least `min_size` bytes each.
+    // The count comes from the input, so it is only trusted as far as the
+    // bytes left could hold that many elements.
+    fn capacity(&self, count: u32, min_size: usize) -> usize {
+        (count as usize).min((self.bytes.len() - self.pos) / min_size)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let mut buf = [0u8; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(u32::from_be_bytes(buf))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(buf))
+    }
+
+    fn bytes(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn expiry(&mut self) -> io::Result<Option<SystemTime>> {
+        let millis = self.u64()?;
+        if millis == 0 {
+            Ok(None)
+        } else {
+            Ok(Some(UNIX_EPOCH + Duration::from_millis(millis)))
+        }
+    }
+}
@@ -0,0 +1,270 @@
+use crate::lock;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Server-wide counters shared by every connection
+pub struct Stats {
+    started_at: Instant,
+    next_client_id: AtomicU64,
+    connected_clients: AtomicUsize,
+    // Connections waiting in BLPOP, BRPOP or WAITAOF
+    blocked_clients: AtomicUsize,
+    total_connections: AtomicU64,
+    // INFO commandstats, keyed by command table name
+    command_stats: Mutex<HashMap<&'static str, CommandStats>>,
+    keyspace_hits: AtomicU64,
+    keyspace_misses: AtomicU64,
+    // Keys removed by the maxmemory policy
+    evicted_keys: AtomicU64,
+    // Bytes of replies written to clients, and the socket writes it took
+    net_output_bytes: AtomicU64,
+    writes_processed: AtomicU64,
+    last_save_time: AtomicU64,
+    last_save_attempt: AtomicU64,
+    last_save_ok: AtomicBool,
+    bgsave_in_progress: AtomicBool,
+    aof_rewrite_in_progress: AtomicBool,
+    aof_last_rewrite_ok: AtomicBool,
+    // Write commands executed since the last successful save
+    dirty: AtomicU64,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            next_client_id: AtomicU64::new(1),
+            connected_clients: AtomicUsize::new(0),
+            blocked_clients: AtomicUsize::new(0),
+            total_connections: AtomicU64::new(0),
+            command_stats: Mutex::new(HashMap::new()),
+            keyspace_hits: AtomicU64::new(0),
+            keyspace_misses: AtomicU64::new(0),
+            evicted_keys: AtomicU64::new(0),
+            net_output_bytes: AtomicU64::new(0),
+            writes_processed: AtomicU64::new(0),
+            last_save_time: AtomicU64::new(0),
+            last_save_attempt: AtomicU64::new(unix_now()),
+            last_save_ok: AtomicBool::new(true),
+            bgsave_in_progress: AtomicBool::new(false),
+            aof_rewrite_in_progress: AtomicBool::new(false),
+            aof_last_rewrite_ok: AtomicBool::new(true),
+            dirty: AtomicU64::new(0),
+        }
+    }
+
+    pub fn uptime_secs(&self) -> u64 {
+        self.started_at.elapsed().as_secs()
+    }
+
+    pub fn next_client_id(&self) -> u64 {
+        self.next_client_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn client_connected(&self) {
+        self.connected_clients.fetch_add(1, Ordering::Relaxed);
+        self.total_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn client_disconnected(&self) {
+        self.connected_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn connected_clients(&self) -> usize {
+        self.connected_clients.load(Ordering::Relaxed)
+    }
+
+    // Counts the connection as blocked until the guard is dropped, however
+    // the wait ends
+    pub fn client_blocked(&self) -> BlockedClient<'_> {
+        self.blocked_clients.fetch_add(1, Ordering::Relaxed);
+        BlockedClient { stats: self }
+    }
+
+    pub fn blocked_clients(&self) -> usize {
+        self.blocked_clients.load(Ordering::Relaxed)
+    }
+
+    pub fn total_connections(&self) -> u64 {
+        self.total_connections.load(Ordering::Relaxed)
+    }
+
+    // Only names from the command table are counted, so clients sending
+    // garbage can't grow the map. A call that replied with an error counts
+    // as failed as well.
+    pub fn record_command(&self, name: &'static str, took: Duration, failed: bool) {
+        let mut stats = lock::mutex(&self.command_stats);
+        let stat = stats.entry(name).or_default();
+        stat.calls += 1;
+        stat.usec += took.as_micros() as u64;
+        stat.failed_calls += failed as u64;
+    }
+
+    // Refused before running: wrong arity, NOAUTH, or not allowed while
+    // subscribed. Not counted as a call.
+    pub fn record_rejected(&self, name: &'static str) {
+        let mut stats = lock::mutex(&self.command_stats);
+        stats.entry(name).or_default().rejected_calls += 1;
+    }
+
+    // Per-command stats, sorted by name
+    pub fn command_stats(&self) -> Vec<(&'static str, CommandStats)> {
+        let mut stats: Vec<_> = lock::mutex(&self.command_stats)
+            .iter()
+            .map(|(name, stat)| (*name, *stat))
+            .collect();
+        stats.sort_by_key(|&(name, _)| name);
+        stats
+    }
+
+    pub fn total_commands(&self) -> u64 {
+        lock::mutex(&self.command_stats)
+            .values()
+            .map(|stat| stat.calls)
+            .sum()
+    }
+
+    pub fn record_lookup(&self, hit: bool) {
+        if hit {
+            self.keyspace_hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.keyspace_misses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn keyspace_hits(&self) -> u64 {
+        self.keyspace_hits.load(Ordering::Relaxed)
+    }
+
+    pub fn keyspace_misses(&self) -> u64 {
+        self.keyspace_misses.load(Ordering::Relaxed)
+    }
+
+    pub fn add_evicted(&self, keys: usize) {
+        self.evicted_keys.fetch_add(keys as u64, Ordering::Relaxed);
+    }
+
+    pub fn evicted_keys(&self) -> u64 {
+        self.evicted_keys.load(Ordering::Relaxed)
+    }
+
+    pub fn record_write(&self, bytes: usize) {
+        self.net_output_bytes
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        self.writes_processed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn net_output_bytes(&self) -> u64 {
+        self.net_output_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn writes_processed(&self) -> u64 {
+        self.writes_processed.load(Ordering::Relaxed)
+    }
+
+    pub fn record_save(&self, ok: bool) {
+        let now = unix_now();
+        if ok {
+            self.last_save_time.store(now, Ordering::Relaxed);
+        }
+        self.last_save_attempt.store(now, Ordering::Relaxed);
+        self.last_save_ok.store(ok, Ordering::Relaxed);
+    }
+
+    // Unix time of the last save attempt, or of startup if there was none
+    pub fn last_save_attempt(&self) -> u64 {
+        self.last_save_attempt.load(Ordering::Relaxed)
+    }
+
+    // Unix time of the last successful save, 0 if nothing was saved yet
+    pub fn last_save_time(&self) -> u64 {
+        self.last_save_time.load(Ordering::Relaxed)
+    }
+
+    pub fn last_save_ok(&self) -> bool {
+        self.last_save_ok.load(Ordering::Relaxed)
+    }
+
+    // Returns false if a background save is already running
+    pub fn begin_bgsave(&self) -> bool {
+        !self.bgsave_in_progress.swap(true, Ordering::SeqCst)
+    }
+
+    pub fn end_bgsave(&self) {
+        self.bgsave_in_progress.store(false, Ordering::SeqCst);
+    }
+
+    pub fn bgsave_in_progress(&self) -> bool {
+        self.bgsave_in_progress.load(Ordering::SeqCst)
+    }
+
+    // Returns false if an AOF rewrite is already running
+    pub fn begin_aof_rewrite(&self) -> bool {
+        !self.aof_rewrite_in_progress.swap(true, Ordering::SeqCst)
+    }
+
+    pub fn end_aof_rewrite(&self, ok: bool) {
+        self.aof_last_rewrite_ok.store(ok, Ordering::SeqCst);
+        self.aof_rewrite_in_progress.store(false, Ordering::SeqCst);
+    }
+
+    pub fn aof_rewrite_in_progress(&self) -> bool {
+        self.aof_rewrite_in_progress.load(Ordering::SeqCst)
+    }
+
+    pub fn aof_last_rewrite_ok(&self) -> bool {
+        self.aof_last_rewrite_ok.load(Ordering::SeqCst)
+    }
+
+    pub fn add_dirty(&self, changes: u64) {
+        self.dirty.fetch_add(changes, Ordering::Relaxed);
+    }
+
+    // Forget the changes a completed save captured, keeping any made since
+    pub fn clear_dirty(&self, saved: u64) {
+        self.dirty.fetch_sub(saved, Ordering::Relaxed);
+    }
+
+    pub fn dirty(&self) -> u64 {
+        self.dirty.load(Ordering::Relaxed)
+    }
+}
+
+// One command's INFO commandstats line
+#[derive(Clone, Copy, Default)]
+pub struct CommandStats {
+    pub calls: u64,
+    // Time spent running the command, including any time it spent blocked
+    pub usec: u64,
+    pub rejected_calls: u64,
+    pub failed_calls: u64,
+}
+
+impl CommandStats {
+    pub fn usec_per_call(&self) -> f64 {
+        if self.calls == 0 {
+            0.0
+        } else {
+            self.usec as f64 / self.calls as f64
+        }
+    }
+}
+
+pub struct BlockedClient<'a> {
+    stats: &'a Stats,
+}
+
+impl Drop for BlockedClient<'_> {
+    fn drop(&mut self) {
+        self.stats.blocked_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -1,289 +1,1257 @@
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
-use std::time::{Duration, SystemTime};
+use crate::clock::Clock;
+use crate::config::{EncodingLimits, MaxmemoryPolicy};
+use crate::deadline::Deadline;
+use crate::glob;
+use crate::hash::Hash;
+use crate::keyspace::Keyspace;
+use crate::lock;
+use crate::notify::KeyspaceEvents;
+use crate::random;
+use crate::snapshot;
+use crate::zset::{self, Aggregate, SortedSet};
+use bytes::Bytes;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::SystemTime;
 
+// Strings that look like canonical integers are kept as i64, so counters are
+// not re-parsed on every INCR/DECR; anything else is stored as raw bytes,
+// reference-counted so snapshots share them instead of copying
 #[derive(Clone, Debug)]
-pub struct ValueWithExpiry {
-    pub data: Vec<u8>,
-    pub expires_at: Option<SystemTime>,
+pub enum StringValue {
+    Int(i64),
+    Raw(Bytes),
+    // Edited in place by APPEND or SETRANGE. Redis gives such a string a
+    // buffer of its own, so it reports raw rather than embstr however short
+    Edited(Bytes),
 }
 
-impl ValueWithExpiry {
-    pub fn new(data: Vec<u8>) -> Self {
-        Self {
-            data,
-            expires_at: None,
+impl StringValue {
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        match parse_int(&bytes) {
+            Some(n) => StringValue::Int(n),
+            None => StringValue::Raw(Bytes::from(bytes)),
         }
     }
 
-    pub fn with_expiry(data: Vec<u8>, ttl: Duration) -> Self {
-        Self {
-            data,
-            expires_at: Some(SystemTime::now() + ttl),
+    // Raw values still count as integers if they are spelled canonically,
+    // e.g. after APPEND demoted "42" and appended "0"
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            StringValue::Int(n) => Some(*n),
+            StringValue::Raw(bytes) | StringValue::Edited(bytes) => parse_int(bytes),
         }
     }
 
-    pub fn is_expired(&self) -> bool {
-        if let Some(expires_at) = self.expires_at {
-            SystemTime::now() > expires_at
-        } else {
-            false
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            StringValue::Int(n) => n.to_string().into_bytes(),
+            StringValue::Raw(bytes) | StringValue::Edited(bytes) => bytes.to_vec(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            StringValue::Int(n) => n.to_string().len(),
+            StringValue::Raw(bytes) | StringValue::Edited(bytes) => bytes.len(),
+        }
+    }
+
+    // Edit the bytes in their raw form, for commands like APPEND and
+    // SETRANGE. Bytes no snapshot still shares are edited without a copy.
+    pub fn edit_raw<R>(&mut self, edit: impl FnOnce(&mut Vec<u8>) -> R) -> R {
+        let mut bytes = match std::mem::replace(self, StringValue::Raw(Bytes::new())) {
+            StringValue::Int(n) => n.to_string().into_bytes(),
+            StringValue::Raw(bytes) | StringValue::Edited(bytes) => Vec::from(bytes),
+        };
+        let result = edit(&mut bytes);
+        *self = StringValue::Edited(Bytes::from(bytes));
+        result
+    }
+
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            StringValue::Int(_) => "int",
+            StringValue::Raw(bytes) if bytes.len() <= 44 => "embstr",
+            StringValue::Raw(_) | StringValue::Edited(_) => "raw",
         }
     }
 }
 
 #[derive(Clone, Debug)]
 pub enum Value {
-    String(ValueWithExpiry),
-    List(Vec<Vec<u8>>),
+    String(StringValue),
+    // Elements are reference-counted so readers can copy a range out of the
+    // list without holding the lock for the duration of the byte copy
+    List(Vec<Bytes>),
+    Set(HashSet<Bytes>),
+    ZSet(SortedSet),
+    Hash(Hash),
+}
+
+impl Value {
+    pub fn string(bytes: Vec<u8>) -> Self {
+        Value::String(StringValue::from_bytes(bytes))
+    }
+
+    // Redis never keeps an empty collection; strings may be empty
+    fn is_empty_collection(&self, now: SystemTime) -> bool {
+        match self {
+            Value::String(_) => false,
+            Value::List(list) => list.is_empty(),
+            Value::Set(set) => set.is_empty(),
+            Value::ZSet(zset) => zset.is_empty(),
+            Value::Hash(hash) => hash.is_empty(now),
+        }
+    }
+
+    // Estimated bytes the value holds on the heap: the element bytes plus
+    // what the collections spend per element. Only an estimate; allocator
+    // overhead is what mem_fragmentation_ratio shows.
+    pub fn memory_usage(&self) -> usize {
+        match self {
+            Value::String(val) => match val {
+                StringValue::Int(_) => 0,
+                StringValue::Raw(bytes) | StringValue::Edited(bytes) => bytes.len(),
+            },
+            Value::List(list) => list
+                .iter()
+                .map(|item| item.len() + std::mem::size_of::<Bytes>())
+                .sum(),
+            Value::Set(set) => set
+                .iter()
+                .map(|member| member.len() + std::mem::size_of::<Bytes>())
+                .sum(),
+            Value::ZSet(zset) => zset.memory_usage(),
+            Value::Hash(hash) => hash.memory_usage(),
+        }
+    }
+
+    // As reported by TYPE and matched by SCAN's TYPE option
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Set(_) => "set",
+            Value::ZSet(_) => "zset",
+            Value::Hash(_) => "hash",
+        }
+    }
+}
+
+// The options of SORT other than STORE
+pub struct SortOptions {
+    pub desc: bool,
+    pub alpha: bool,
+    // LIMIT offset count; a negative count runs to the end
+    pub limit: Option<(i64, i64)>,
+}
+
+// ZADD's NX, XX, GT and LT: which members may be added or updated
+#[derive(Clone, Copy, Default)]
+pub struct ZAddOptions {
+    pub nx: bool,
+    pub xx: bool,
+    pub gt: bool,
+    pub lt: bool,
+}
+
+impl ZAddOptions {
+    // The score to give member, if the options allow the change
+    fn apply(&self, current: Option<f64>, score: f64) -> Option<f64> {
+        match current {
+            None if self.xx => None,
+            None => Some(score),
+            Some(_) if self.nx => None,
+            Some(old) if (self.gt && score <= old) || (self.lt && score >= old) => None,
+            Some(_) => Some(score),
+        }
+    }
+}
+
+// Candidates sampled per eviction, Redis' default maxmemory-samples
+const EVICTION_SAMPLES: usize = 5;
+
+// What Store::snapshot captured: the live keys with their values and
+// expiries, as of one moment
+pub struct Snapshot {
+    entries: Vec<(Bytes, Value, Option<SystemTime>)>,
+}
+
+impl Snapshot {
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, &Value, Option<SystemTime>)> {
+        self.entries
+            .iter()
+            .map(|(k, v, expires_at)| (k, v, *expires_at))
+    }
 }
 
 #[derive(Clone)]
 pub struct Store {
-    data: Arc<RwLock<HashMap<String, Value>>>,
+    data: Arc<RwLock<Keyspace>>,
+    events: KeyspaceEvents,
+    // Shared with every keyspace this store holds, including the ones that
+    // replace it on load and flush
+    clock: Clock,
+    // Keys removed because they expired, lazily or by the reaper
+    expired_keys: Arc<AtomicU64>,
 }
 
 impl Store {
-    pub fn new() -> Self {
+    pub fn new(events: KeyspaceEvents, clock: Clock) -> Self {
         Self {
-            data: Arc::new(RwLock::new(HashMap::new())),
+            data: Arc::new(RwLock::new(Keyspace::with_clock(clock.clone()))),
+            events,
+            clock,
+            expired_keys: Arc::new(AtomicU64::new(0)),
         }
     }
 
-    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
-        let data = self.data.read().unwrap();
-        data.get(key).and_then(|v| match v {
-            Value::String(val) => {
-                if val.is_expired() {
-                    None
-                } else {
-                    Some(val.data.clone())
+    // Every access to the keyspace locks it through these. A command that
+    // panics while holding the lock poisons it; the keyspace is still
+    // usable, so later commands take it over instead of panicking in turn.
+    fn read(&self) -> RwLockReadGuard<'_, Keyspace> {
+        lock::read(&self.data)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Keyspace> {
+        lock::write(&self.data)
+    }
+
+    // For tests of the above: panics with the write lock held
+    #[cfg(test)]
+    pub fn panic_holding_lock(&self) {
+        let _data = self.write();
+        panic!("panicked while holding the keyspace lock");
+    }
+
+    // The time key expiries are measured against
+    pub fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
+
+    // The single place expired keys leave the keyspace, shared by lazy expiry
+    // on access and the active reaper so both fire the same side effects
+    pub fn remove_if_expired(&self, key: &[u8]) -> bool {
+        let removed = {
+            let mut data = self.write();
+            take_expired(&mut data, key)
+        };
+        if removed {
+            self.expired(key);
+        }
+        removed
+    }
+
+    // INFO's expired_keys
+    pub fn expired_keys(&self) -> u64 {
+        self.expired_keys.load(Ordering::Relaxed)
+    }
+
+    // Every removal of an expired key ends here, whichever path found it
+    fn expired(&self, key: &[u8]) {
+        self.expired_keys.fetch_add(1, Ordering::Relaxed);
+        self.events.expired(key);
+    }
+
+    // One active-expiry pass over the keys that have an expiry; returns how
+    // many keys were removed
+    pub fn reap_expired(&self) -> usize {
+        let expired = self.read().expired_keys(self.now());
+        let removed = expired
+            .iter()
+            .filter(|key| self.remove_if_expired(key))
+            .count();
+        removed + self.reap_hash_fields()
+    }
+
+    // Drop expired hash fields, deleting hashes left empty; returns how many
+    // hashes were deleted
+    fn reap_hash_fields(&self) -> usize {
+        let mut data = self.write();
+        let now = self.now();
+        let candidates = data.hashes_with_field_expiry();
+        let mut deleted = 0;
+        for key in candidates {
+            if let Some(Value::Hash(hash)) = data.get_mut(&key)
+                && hash.has_expired_fields(now)
+            {
+                hash.purge_expired(now);
+                if hash.is_empty(now) {
+                    data.remove(&key);
+                    deleted += 1;
                 }
             }
-            Value::List(_) => None,
-        })
+        }
+        deleted
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.get_string(key).ok().flatten()
+    }
+
+    // Like get, but reports WRONGTYPE instead of hiding non-string values
+    pub fn get_string(&self, key: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        let data = self.read();
+        if data.is_expired(key) {
+            drop(data);
+            self.remove_if_expired(key);
+            return Ok(None);
+        }
+        match data.get(key) {
+            Some(Value::String(val)) => Ok(Some(val.to_bytes())),
+            Some(_) => {
+                Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+            }
+            None => Ok(None),
+        }
+    }
+
+    // GETRANGE with Redis' index rules: negative indexes count from the end,
+    // both are clamped to the string, and anything left empty (including a
+    // missing key) is an empty string. Only the range is copied.
+    pub fn getrange(&self, key: &[u8], start: i64, end: i64) -> Result<Vec<u8>, String> {
+        let data = self.read();
+        let rendered;
+        let bytes: &[u8] = match data.get(key) {
+            Some(Value::String(val)) => match val {
+                StringValue::Raw(bytes) | StringValue::Edited(bytes) => bytes,
+                StringValue::Int(n) => {
+                    rendered = n.to_string();
+                    rendered.as_bytes()
+                }
+            },
+            None => return Ok(Vec::new()),
+            Some(_) => {
+                return Err(
+                    "WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+                );
+            }
+        };
+
+        let len = bytes.len() as i64;
+        if start < 0 && end < 0 && start > end {
+            return Ok(Vec::new());
+        }
+        let start = if start < 0 {
+            (len + start).max(0)
+        } else {
+            start
+        };
+        let end = if end < 0 {
+            (len + end).max(0)
+        } else {
+            end.min(len - 1)
+        };
+        if len == 0 || start > end {
+            return Ok(Vec::new());
+        }
+        Ok(bytes[start as usize..=end as usize].to_vec())
+    }
+
+    // STRLEN, LLEN, SCARD, ZCARD and HLEN: the length of the value at `key`,
+    // which must be of type `type_name` (as TYPE names it), read under the
+    // read lock without copying anything out. A missing key has length 0.
+    pub fn len(&self, key: &[u8], type_name: &str) -> Result<usize, String> {
+        let data = self.read();
+        let now = self.now();
+        match data.get(key) {
+            None => Ok(0),
+            Some(value) if value.type_name() != type_name => {
+                Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+            }
+            Some(Value::String(val)) => Ok(val.len()),
+            Some(Value::List(list)) => Ok(list.len()),
+            Some(Value::Set(set)) => Ok(set.len()),
+            Some(Value::ZSet(zset)) => Ok(zset.len()),
+            Some(Value::Hash(hash)) => Ok(hash.len(now)),
+        }
+    }
+
+    // SET replaces whatever the key held, whatever its type. The replaced
+    // value is freed after the lock is released, so overwriting a large
+    // collection doesn't hold up other clients.
+    pub fn set(&self, key: &[u8], value: Vec<u8>) {
+        let mut data = self.write();
+        let expired = take_expired(&mut data, key);
+        let replaced = data.insert(Bytes::copy_from_slice(key), Value::string(value));
+        drop(data);
+        drop(replaced);
+
+        if expired {
+            self.expired(key);
+        }
+    }
+
+    // DUMP: the value at key serialized, or None if there is none
+    pub fn dump(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.read().get(key).map(snapshot::dump)
     }
 
-    pub fn set(&self, key: String, value: Vec<u8>) {
-        let mut data = self.data.write().unwrap();
-        data.insert(key, Value::String(ValueWithExpiry::new(value)));
+    // RESTORE: key takes value, failing if it exists unless replace is set.
+    // An expiry already in the past only deletes what was there.
+    pub fn restore(
+        &self,
+        key: &[u8],
+        value: Value,
+        expires_at: Option<SystemTime>,
+        replace: bool,
+    ) -> Result<(), String> {
+        let mut data = self.write();
+        let expired = take_expired(&mut data, key);
+        if !replace && data.contains_key(key) {
+            return Err("BUSYKEY Target key name already exists.".to_string());
+        }
+
+        let replaced = if expires_at.is_some_and(|at| at <= self.now()) {
+            data.remove(key)
+        } else {
+            let replaced = data.insert(Bytes::copy_from_slice(key), value);
+            data.set_expiry(key, expires_at);
+            replaced
+        };
+        drop(data);
+        drop(replaced);
+
+        if expired {
+            self.expired(key);
+        }
+        Ok(())
+    }
+
+    // SET ... KEEPTTL: like set, but the expiry of whatever was at the key
+    // carries over to the new value
+    pub fn set_keep_ttl(&self, key: &[u8], value: Vec<u8>) {
+        let mut data = self.write();
+        let expired = take_expired(&mut data, key);
+        let expires_at = data.expiry(key).flatten();
+        let replaced = data.insert(Bytes::copy_from_slice(key), Value::string(value));
+        data.set_expiry(key, expires_at);
+        drop(data);
+        drop(replaced);
+
+        if expired {
+            self.expired(key);
+        }
+    }
+
+    // DEBUG POPULATE: creates <prefix><n> for n in 0..count in one pass under
+    // the write lock, leaving existing keys alone. Values are "value:<n>",
+    // padded with zero bytes or cut to `size` when one is given. Returns how
+    // many keys were created.
+    pub fn populate(&self, count: usize, prefix: &str, size: Option<usize>) -> usize {
+        let mut data = self.write();
+        data.reserve(count);
+
+        let mut created = 0;
+        for n in 0..count {
+            let key = Bytes::from(format!("{}{}", prefix, n));
+            if take_expired(&mut data, &key) {
+                self.expired(&key);
+            }
+            if data.contains_key(&key) {
+                continue;
+            }
+            let mut value = format!("value:{}", n).into_bytes();
+            if let Some(size) = size {
+                value.resize(size, 0);
+            }
+            data.insert(key, Value::string(value));
+            created += 1;
+        }
+        created
+    }
+
+    pub fn set_with_expiry_at(&self, key: &[u8], value: Vec<u8>, expires_at: SystemTime) {
+        let mut data = self.write();
+        let expired = take_expired(&mut data, key);
+        let replaced = data.insert(Bytes::copy_from_slice(key), Value::string(value));
+        data.set_expiry(key, Some(expires_at));
+        drop(data);
+        drop(replaced);
+
+        if expired {
+            self.expired(key);
+        }
+    }
+
+    // EXPIRE, PEXPIREAT, PERSIST and GETEX: change only the expiry of a key
+    // of any type, in place, leaving its value and encoding alone. None
+    // removes the expiry. Returns false if there is no live key at `key`.
+    pub fn set_expiry(&self, key: &[u8], expires_at: Option<SystemTime>) -> bool {
+        self.write().set_expiry(key, expires_at)
     }
 
-    pub fn set_with_expiry(&self, key: String, value: Vec<u8>, ttl: Duration) {
-        let mut data = self.data.write().unwrap();
-        data.insert(key, Value::String(ValueWithExpiry::with_expiry(value, ttl)));
+    // PERSIST: true if the key had an expiry to remove
+    pub fn persist(&self, key: &[u8]) -> bool {
+        let mut data = self.write();
+        data.expiry(key).flatten().is_some() && data.set_expiry(key, None)
     }
 
-    pub fn del(&self, keys: &[String]) -> usize {
-        let mut data = self.data.write().unwrap();
+    // A key that has expired counts as missing, like Redis, and is removed
+    // as expired rather than deleted
+    pub fn del(&self, keys: &[Vec<u8>]) -> usize {
+        let mut data = self.write();
         let mut count = 0;
+        let mut expired = Vec::new();
         for key in keys {
-            if data.remove(key).is_some() {
+            if take_expired(&mut data, key) {
+                expired.push(key);
+            } else if data.remove(key).is_some() {
                 count += 1;
             }
         }
+        drop(data);
+
+        for key in expired {
+            self.expired(key);
+        }
         count
     }
 
-    pub fn exists(&self, keys: &[String]) -> usize {
-        let data = self.data.read().unwrap();
-        keys.iter()
-            .filter(|key| data.get(key.as_str()).is_some())
-            .count()
+    pub fn exists(&self, keys: &[Vec<u8>]) -> usize {
+        let data = self.read();
+        let mut expired = Vec::new();
+        let mut count = 0;
+        for key in keys {
+            if data.is_expired(key) {
+                expired.push(key);
+            } else if data.contains_key(key) {
+                count += 1;
+            }
+        }
+        drop(data);
+
+        for key in expired {
+            self.remove_if_expired(key);
+        }
+        count
     }
 
-    pub fn keys(&self, pattern: &str) -> Vec<String> {
-        let data = self.data.read().unwrap();
+    // KEYS: the pattern is matched against the key bytes as given, never
+    // decoded, like SCAN's MATCH
+    pub fn keys(&self, pattern: &[u8], deadline: Deadline) -> Result<Vec<Bytes>, String> {
+        let data = self.read();
+        let mut expired = Vec::new();
+        let mut matched = Vec::new();
+        for (done, key) in data.keys().enumerate() {
+            deadline.tick(done)?;
+            if pattern != b"*" && !glob::matches(pattern, key) {
+                continue;
+            }
+            if data.is_expired(key) {
+                expired.push(key.clone());
+            } else {
+                matched.push(key.clone());
+            }
+        }
+        drop(data);
 
-        if pattern == "*" {
-            data.keys().cloned().collect()
-        } else {
-            let prefix = pattern.trim_end_matches('*');
-            data.keys()
-                .filter(|k| k.starts_with(prefix))
-                .cloned()
-                .collect()
+        for key in &expired {
+            self.remove_if_expired(key);
         }
+        Ok(matched)
     }
 
-    pub fn incr(&self, key: &str) -> Result<i64, String> {
-        let mut data = self.data.write().unwrap();
+    // SCAN walks the keyspace's buckets with Redis' reverse-binary cursor
+    // (see ScanTable), so a key present for the whole iteration is returned
+    // at least once however the keyspace changes between calls, and a call
+    // costs only the buckets it visits. MATCH and TYPE filter the keys a
+    // call gathers, like Redis, so it may return fewer. Returns the next
+    // cursor, 0 once the iteration is complete.
+    pub fn scan(
+        &self,
+        cursor: u64,
+        count: usize,
+        pattern: Option<&[u8]>,
+        type_name: Option<&str>,
+    ) -> (u64, Vec<Bytes>) {
+        let data = self.read();
+        let (next, keys) = data.scan(cursor, count.max(1));
+        let keys = keys
+            .into_iter()
+            .filter(|key| pattern.is_none_or(|p| glob::matches(p, key)))
+            .filter(|key| {
+                data.peek(key)
+                    .is_some_and(|value| type_name.is_none_or(|t| value.type_name() == t))
+            })
+            .cloned()
+            .collect();
+        (next, keys)
+    }
 
-        let current = if let Some(Value::String(val)) = data.get(key) {
-            if val.is_expired() {
-                0
-            } else {
-                String::from_utf8(val.data.clone())
-                    .map_err(|_| "ERR value is not an integer or out of range")?
-                    .parse::<i64>()
-                    .map_err(|_| "ERR value is not an integer or out of range")?
+    pub fn incr(&self, key: &[u8]) -> Result<i64, String> {
+        self.apply_int_delta(key, 1)
+    }
+
+    pub fn decr(&self, key: &[u8]) -> Result<i64, String> {
+        self.apply_int_delta(key, -1)
+    }
+
+    pub fn incr_by(&self, key: &[u8], increment: i64) -> Result<i64, String> {
+        self.apply_int_delta(key, increment)
+    }
+
+    pub fn decr_by(&self, key: &[u8], decrement: i64) -> Result<i64, String> {
+        let delta = decrement
+            .checked_neg()
+            .ok_or("ERR decrement would overflow")?;
+        self.apply_int_delta(key, delta)
+    }
+
+    // Shared by INCR, DECR, INCRBY and DECRBY: type, integer and overflow
+    // checks all happen here. The value is changed in place, so like APPEND
+    // it keeps the key's expiry.
+    fn apply_int_delta(&self, key: &[u8], delta: i64) -> Result<i64, String> {
+        let mut data = self.write();
+        let expired = take_expired(&mut data, key);
+
+        let val = match data.get_or_insert_with(key, || Value::String(StringValue::Int(0))) {
+            Value::String(val) => val,
+            _ => {
+                return Err(
+                    "WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+                );
             }
-        } else {
-            0
         };
+        let new_value = val
+            .as_int()
+            .ok_or("ERR value is not an integer or out of range")?
+            .checked_add(delta)
+            .ok_or("ERR increment or decrement would overflow")?;
+        *val = StringValue::Int(new_value);
+        drop(data);
 
-        let new_value = current + 1;
-        data.insert(
-            key.to_string(),
-            Value::String(ValueWithExpiry::new(new_value.to_string().into_bytes())),
-        );
+        if expired {
+            self.expired(key);
+        }
         Ok(new_value)
     }
 
-    pub fn decr(&self, key: &str) -> Result<i64, String> {
-        let mut data = self.data.write().unwrap();
+    // APPEND: creates the key if needed and returns the new length
+    pub fn append(&self, key: &[u8], value: &[u8], max_len: usize) -> Result<usize, String> {
+        self.edit_string(
+            key,
+            |len| len.saturating_add(value.len()),
+            max_len,
+            |bytes| {
+                bytes.extend_from_slice(value);
+                Ok(())
+            },
+        )
+    }
+
+    // SETRANGE: overwrite from `offset`, zero-padding any gap; returns the new length
+    pub fn setrange(
+        &self,
+        key: &[u8],
+        offset: usize,
+        value: &[u8],
+        max_len: usize,
+    ) -> Result<usize, String> {
+        if value.is_empty() {
+            // Nothing to write: report the current length without creating the key
+            return match self.get_string(key)? {
+                Some(bytes) => Ok(bytes.len()),
+                None => Ok(0),
+            };
+        }
 
-        let current = if let Some(Value::String(val)) = data.get(key) {
-            if val.is_expired() {
-                0
-            } else {
-                String::from_utf8(val.data.clone())
-                    .map_err(|_| "ERR value is not an integer or out of range")?
-                    .parse::<i64>()
-                    .map_err(|_| "ERR value is not an integer or out of range")?
-            }
+        self.edit_string(
+            key,
+            |len| len.max(offset.saturating_add(value.len())),
+            max_len,
+            |bytes| {
+                let end = offset + value.len();
+                if bytes.len() < end {
+                    bytes.resize(end, 0);
+                }
+                bytes[offset..end].copy_from_slice(value);
+                Ok(())
+            },
+        )
+    }
+
+    // Edit a string value in its raw form, keeping any expiry; int-encoded
+    // values are demoted first and a missing key starts out empty. The
+    // length `grown` gives from the current one is checked against
+    // `max_len` (proto-max-bulk-len) under the same write lock, before the
+    // key is created or anything allocated, so concurrent edits can't
+    // together pass it.
+    fn edit_string<F>(
+        &self,
+        key: &[u8],
+        grown: impl FnOnce(usize) -> usize,
+        max_len: usize,
+        edit: F,
+    ) -> Result<usize, String>
+    where
+        F: FnOnce(&mut Vec<u8>) -> Result<(), String>,
+    {
+        let mut data = self.write();
+        let expired = take_expired(&mut data, key);
+
+        let current = match data.peek(key) {
+            Some(Value::String(val)) => val.len(),
+            _ => 0,
+        };
+        let result = if grown(current) > max_len {
+            Err("ERR string exceeds maximum allowed size (proto-max-bulk-len)".to_string())
         } else {
-            0
+            match data.get_or_insert_with(key, || Value::string(Vec::new())) {
+                Value::String(val) => val.edit_raw(|bytes| edit(bytes).map(|()| bytes.len())),
+                _ => Err(
+                    "WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+                ),
+            }
         };
+        drop(data);
 
-        let new_value = current - 1;
-        data.insert(
-            key.to_string(),
-            Value::String(ValueWithExpiry::new(new_value.to_string().into_bytes())),
-        );
-        Ok(new_value)
+        if expired {
+            self.expired(key);
+        }
+        result
+    }
+
+    // When the key expires: None if it doesn't exist, Some(None) if it never
+    // does
+    pub fn expiry(&self, key: &[u8]) -> Option<Option<SystemTime>> {
+        self.read().expiry(key)
+    }
+
+    // TTL and PTTL: milliseconds left to live, with expiry's None and
+    // Some(None)
+    pub fn get_ttl_ms(&self, key: &[u8]) -> Option<Option<u64>> {
+        let expiry = self.expiry(key)?;
+        Some(expiry.map(|at| {
+            at.duration_since(self.now())
+                .map_or(0, |left| left.as_millis() as u64)
+        }))
+    }
+
+    pub fn hash_field_expiry(&self, key: &[u8], field: &[u8]) -> Option<SystemTime> {
+        let data = self.read();
+        let now = self.now();
+        match data.peek(key)? {
+            Value::Hash(hash) => hash.expires_at(field, now).flatten(),
+            _ => None,
+        }
+    }
+
+    // TYPE: "none" for missing and expired keys
+    pub fn key_type(&self, key: &[u8]) -> &'static str {
+        let data = self.read();
+        match data.get(key) {
+            Some(value) => value.type_name(),
+            None => "none",
+        }
+    }
+
+    // SORT: the elements of a list, set or sorted set, as numbers or, with
+    // ALPHA, as byte strings
+    pub fn sort(
+        &self,
+        key: &[u8],
+        options: &SortOptions,
+        deadline: Deadline,
+    ) -> Result<Vec<Bytes>, String> {
+        let data = self.read();
+        sorted(&data, key, options, deadline)
+    }
+
+    // SORT ... STORE: the result always becomes a list at `dest`, whatever
+    // type `key` had. Returns its length.
+    pub fn sort_store(
+        &self,
+        key: &[u8],
+        options: &SortOptions,
+        dest: &[u8],
+        deadline: Deadline,
+    ) -> Result<usize, String> {
+        let mut data = self.write();
+        let items = sorted(&data, key, options, deadline)?;
+        let len = items.len();
+        set_or_delete(&mut data, dest, Value::List(items), self.now());
+        Ok(len)
+    }
+
+    // OBJECT ENCODING: the encoding Redis would use for a value of this shape
+    pub fn encoding(&self, key: &[u8], limits: &EncodingLimits) -> Option<&'static str> {
+        let data = self.read();
+        let now = self.now();
+        let encoding = match data.peek(key)? {
+            Value::String(val) => val.encoding(),
+            Value::List(list) => {
+                let bytes = list.iter().map(|item| item.len()).sum();
+                if limits.list_fits_listpack(list.len(), bytes) {
+                    "listpack"
+                } else {
+                    "quicklist"
+                }
+            }
+            Value::Set(set) => {
+                if set.len() <= limits.set_max_intset_entries
+                    && set.iter().all(|m| parse_int(m).is_some())
+                {
+                    "intset"
+                } else if set.len() <= limits.set_max_listpack_entries
+                    && set.iter().all(|m| m.len() <= limits.set_max_listpack_value)
+                {
+                    "listpack"
+                } else {
+                    "hashtable"
+                }
+            }
+            Value::ZSet(zset) => {
+                if zset.len() <= limits.zset_max_listpack_entries
+                    && zset
+                        .iter()
+                        .all(|(m, _)| m.len() <= limits.zset_max_listpack_value)
+                {
+                    "listpack"
+                } else {
+                    "skiplist"
+                }
+            }
+            Value::Hash(hash) => {
+                if hash.len(now) <= limits.hash_max_listpack_entries
+                    && hash.iter(now).all(|(field, value, _)| {
+                        field.len() <= limits.hash_max_listpack_value
+                            && value.len() <= limits.hash_max_listpack_value
+                    })
+                {
+                    "listpack"
+                } else {
+                    "hashtable"
+                }
+            }
+        };
+        Some(encoding)
+    }
+
+    // OBJECT IDLETIME: seconds since the key was last read or written;
+    // None for missing and expired keys
+    pub fn idle_time(&self, key: &[u8]) -> Option<u64> {
+        let data = self.read();
+        data.peek(key)?;
+        data.idle_secs(key)
     }
 
-    pub fn flush(&self) {
-        let mut data = self.data.write().unwrap();
-        data.clear();
+    // DEBUG OBJECT: None for missing and expired keys
+    pub fn serialized_len(&self, key: &[u8]) -> Option<usize> {
+        let data = self.read();
+        data.peek(key).map(snapshot::serialized_len)
     }
 
+    // DEBUG OBJECT on a list: its quicklist node count, length and total
+    // element bytes
+    pub fn quicklist_nodes(
+        &self,
+        key: &[u8],
+        limits: &EncodingLimits,
+    ) -> Option<(usize, usize, usize)> {
+        let data = self.read();
+        let Value::List(list) = data.peek(key)? else {
+            return None;
+        };
+        let nodes = limits.quicklist_nodes(list.iter().map(|item| item.len()));
+        Some((nodes, list.len(), list.iter().map(|item| item.len()).sum()))
+    }
+
+    // DEBUG LISTPACK-ENTRIES: the entries in each quicklist node of a list
+    pub fn quicklist_chunks(
+        &self,
+        key: &[u8],
+        limits: &EncodingLimits,
+    ) -> Option<Result<Vec<usize>, String>> {
+        let data = self.read();
+        match data.peek(key)? {
+            Value::List(list) => Some(Ok(
+                limits.quicklist_chunks(list.iter().map(|item| item.len()))
+            )),
+            _ => Some(Err(
+                "WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+            )),
+        }
+    }
+
+    // With `lazy` the keyspace is swapped for an empty one and the old one
+    // dropped on a thread of its own, so freeing millions of keys holds
+    // neither the lock nor the caller. A plain thread rather than a Tokio
+    // blocking task, as the replication link calls this outside any runtime.
+    pub fn flush(&self, lazy: bool) {
+        let mut data = self.write();
+        if lazy {
+            let old = std::mem::replace(&mut *data, Keyspace::with_clock(self.clock.clone()));
+            drop(data);
+            std::thread::spawn(move || drop(old));
+        } else {
+            data.clear();
+        }
+    }
+
+    // Whether a live key exists, without expiring anything
+    pub fn contains(&self, key: &[u8]) -> bool {
+        let data = self.read();
+        data.contains_key(key)
+    }
+
+    // Like Redis, keys that expired but weren't removed yet still count
     pub fn dbsize(&self) -> usize {
-        let data = self.data.read().unwrap();
-        data.len()
+        self.read().len()
+    }
+
+    // Keys with an expiry, counted like DBSIZE: expired keys stay until
+    // they are accessed or reaped
+    pub fn expires_count(&self) -> usize {
+        self.read().expires_len()
+    }
+
+    // INFO's used_memory, as Keyspace::used_memory estimates it
+    pub fn used_memory(&self) -> usize {
+        self.write().used_memory()
+    }
+
+    // The keys to evict for the estimated memory use to fit in maxmemory,
+    // and whether it then fits. Like Redis, each victim is the best of a
+    // few sampled candidates: the longest idle for the LRU policies, the
+    // soonest to expire for volatile-ttl, any for the random ones. Checking
+    // the estimate only measures the values changed since the last write;
+    // the candidates are gathered from the whole keyspace, but only while
+    // it is over the limit.
+    pub fn eviction_victims(
+        &self,
+        maxmemory: usize,
+        policy: MaxmemoryPolicy,
+    ) -> (Vec<Bytes>, bool) {
+        let mut data = self.write();
+        let mut used = data.used_memory();
+        if used <= maxmemory || policy == MaxmemoryPolicy::NoEviction {
+            return (Vec::new(), used <= maxmemory);
+        }
+
+        let mut candidates: Vec<(&Bytes, Option<SystemTime>)> = if policy.is_volatile() {
+            data.expiring().map(|(key, at)| (key, Some(at))).collect()
+        } else {
+            data.keys().map(|key| (key, None)).collect()
+        };
+        let mut victims = Vec::new();
+        while used > maxmemory && !candidates.is_empty() {
+            // With few enough candidates, all of them
+            let sample: Vec<usize> = if candidates.len() <= EVICTION_SAMPLES {
+                (0..candidates.len()).collect()
+            } else {
+                (0..EVICTION_SAMPLES)
+                    .map(|_| random::below(candidates.len()))
+                    .collect()
+            };
+            let best = match policy {
+                MaxmemoryPolicy::AllKeysLru | MaxmemoryPolicy::VolatileLru => sample
+                    .into_iter()
+                    .max_by_key(|&i| data.idle_secs(candidates[i].0)),
+                MaxmemoryPolicy::VolatileTtl => sample.into_iter().min_by_key(|&i| candidates[i].1),
+                _ => sample.into_iter().next(),
+            }
+            .unwrap();
+            let (key, _) = candidates.swap_remove(best);
+            if data.contains_key(key) {
+                used = used.saturating_sub(data.memory_usage(key).unwrap_or(0));
+            }
+            victims.push(key.clone());
+        }
+        (victims, used <= maxmemory)
+    }
+
+    // Point-in-time copy of every live key and its expiry. Writers only wait
+    // while the entries are cloned, which shares the element bytes rather
+    // than copying them; serializing the copy happens outside the lock.
+    pub fn snapshot(&self) -> Snapshot {
+        let data = self.read();
+        Snapshot {
+            entries: data
+                .iter()
+                .map(|(k, v, expires_at)| (k.clone(), v.clone(), expires_at))
+                .collect(),
+        }
+    }
+
+    // DEBUG DUMPJSON: up to `count` live keys in name order from `offset`,
+    // with their values and expiries, and how many live keys there are.
+    // Only the keys are sorted, and only the page's values are cloned; hash
+    // fields that have expired are left out of the copies.
+    pub fn page_by_name(
+        &self,
+        offset: usize,
+        count: usize,
+    ) -> (Vec<(Bytes, Value, Option<SystemTime>)>, usize) {
+        let data = self.read();
+        let mut entries: Vec<_> = data.iter().collect();
+        entries.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        let page = entries
+            .iter()
+            .skip(offset)
+            .take(count)
+            .map(|&(k, v, expires_at)| {
+                let mut value = v.clone();
+                if let Value::Hash(hash) = &mut value {
+                    hash.purge_expired(self.now());
+                }
+                (k.clone(), value, expires_at)
+            })
+            .collect();
+        (page, entries.len())
+    }
+
+    // Rebuild the keyspace from its current contents while holding the write
+    // lock; the live data is only replaced if the rebuild succeeds
+    pub fn reload<F>(&self, rebuild: F) -> io::Result<()>
+    where
+        F: FnOnce(&Keyspace) -> io::Result<Keyspace>,
+    {
+        let mut data = self.write();
+        let mut keyspace = rebuild(&data)?;
+        keyspace.set_clock(self.clock.clone());
+        *data = keyspace;
+        Ok(())
+    }
+
+    // Swap the whole keyspace for a freshly loaded one
+    pub fn replace(&self, mut keyspace: Keyspace) {
+        keyspace.set_clock(self.clock.clone());
+        *self.write() = keyspace;
     }
 
     // List operations
-    pub fn lpush(&self, key: &str, values: Vec<Vec<u8>>) -> usize {
-        let mut data = self.data.write().unwrap();
+    pub fn lpush(&self, key: &[u8], values: Vec<Vec<u8>>) -> usize {
+        let mut data = self.write();
+        let expired = take_expired(&mut data, key);
 
-        match data.get_mut(key) {
+        // Each value in turn goes to the head, so the last argument ends up
+        // first: LPUSH k a b c gives c b a. One splice shifts the existing
+        // elements once instead of once per value.
+        let pushed = values.into_iter().rev().map(Bytes::from);
+        let len = match data.get_mut(key) {
             Some(Value::List(list)) => {
-                for value in values.into_iter().rev() {
-                    list.insert(0, value);
-                }
+                list.splice(0..0, pushed);
                 list.len()
             }
-            Some(Value::String(_)) => {
+            Some(_) => {
                 // Key exists but is not a list - error handled in command layer
-                0
+                return 0;
             }
             None => {
-                let mut list: Vec<Vec<u8>> = Vec::new();
-                for value in values.into_iter().rev() {
-                    list.insert(0, value);
-                }
+                let list: Vec<Bytes> = pushed.collect();
                 let len = list.len();
-                data.insert(key.to_string(), Value::List(list));
+                data.insert(Bytes::copy_from_slice(key), Value::List(list));
                 len
             }
+        };
+        drop(data);
+        if expired {
+            self.expired(key);
         }
+        self.events.list("lpush", key);
+        len
     }
 
-    pub fn rpush(&self, key: &str, values: Vec<Vec<u8>>) -> usize {
-        let mut data = self.data.write().unwrap();
+    pub fn rpush(&self, key: &[u8], values: Vec<Vec<u8>>) -> usize {
+        let mut data = self.write();
+        let expired = take_expired(&mut data, key);
 
-        match data.get_mut(key) {
+        let len = match data.get_mut(key) {
             Some(Value::List(list)) => {
-                list.extend(values);
+                list.extend(values.into_iter().map(Bytes::from));
                 list.len()
             }
-            Some(Value::String(_)) => 0,
+            Some(_) => return 0,
             None => {
-                let len = values.len();
-                data.insert(key.to_string(), Value::List(values));
+                let list: Vec<Bytes> = values.into_iter().map(Bytes::from).collect();
+                let len = list.len();
+                data.insert(Bytes::copy_from_slice(key), Value::List(list));
                 len
             }
+        };
+        drop(data);
+        if expired {
+            self.expired(key);
         }
+        self.events.list("rpush", key);
+        len
     }
 
-    pub fn lpop(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
-        let mut data = self.data.write().unwrap();
+    pub fn lpop(&self, key: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        let mut data = self.write();
 
-        match data.get_mut(key) {
+        let value = match data.get_mut(key) {
             Some(Value::List(list)) => {
-                if list.is_empty() {
-                    Ok(None)
+                let value = if list.is_empty() {
+                    None
                 } else {
-                    Ok(Some(list.remove(0)))
+                    Some(Vec::from(list.remove(0)))
+                };
+                if list.is_empty() {
+                    data.remove(key);
                 }
+                value
             }
-            Some(Value::String(_)) => {
-                Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+            Some(_) => {
+                return Err(
+                    "WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+                );
             }
-            None => Ok(None),
+            None => None,
+        };
+        drop(data);
+        if value.is_some() {
+            self.events.list("lpop", key);
         }
+        Ok(value)
     }
 
-    pub fn rpop(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
-        let mut data = self.data.write().unwrap();
+    pub fn rpop(&self, key: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        let mut data = self.write();
 
-        match data.get_mut(key) {
-            Some(Value::List(list)) => Ok(list.pop()),
-            Some(Value::String(_)) => {
-                Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+        let value = match data.get_mut(key) {
+            Some(Value::List(list)) => {
+                let value = list.pop().map(Vec::from);
+                if list.is_empty() {
+                    data.remove(key);
+                }
+                value
             }
-            None => Ok(None),
+            Some(_) => {
+                return Err(
+                    "WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+                );
+            }
+            None => None,
+        };
+        drop(data);
+        if value.is_some() {
+            self.events.list("rpop", key);
         }
+        Ok(value)
     }
 
-    pub fn lrange(&self, key: &str, start: i64, stop: i64) -> Result<Vec<Vec<u8>>, String> {
-        let data = self.data.read().unwrap();
+    // Remove up to |count| occurrences of element, scanning from the tail when
+    // count is negative; 0 removes them all
+    pub fn lrem(&self, key: &[u8], count: i64, element: &[u8]) -> Result<usize, String> {
+        let mut data = self.write();
 
-        match data.get(key) {
+        match data.get_mut(key) {
             Some(Value::List(list)) => {
-                let len = list.len() as i64;
-
-                // Convert negative indices
+                let limit = if count == 0 {
+                    usize::MAX
+                } else {
+                    count.unsigned_abs() as usize
+                };
+                let mut removed = 0;
+                if count < 0 {
+                    let mut i = list.len();
+                    while i > 0 && removed < limit {
+                        i -= 1;
+                        if list[i] == element {
+                            list.remove(i);
+                            removed += 1;
+                        }
+                    }
+                } else {
+                    list.retain(|item| {
+                        if removed < limit && item == element {
+                            removed += 1;
+                            false
+                        } else {
+                            true
+                        }
+                    });
+                }
+                if list.is_empty() {
+                    data.remove(key);
+                }
+                Ok(removed)
+            }
+            Some(_) => {
+                Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+            }
+            None => Ok(0),
+        }
+    }
+
+    // Keep only the inclusive range, with the same index rules as LRANGE
+    pub fn ltrim(&self, key: &[u8], start: i64, stop: i64) -> Result<(), String> {
+        let mut data = self.write();
+
+        match data.get_mut(key) {
+            Some(Value::List(list)) => {
+                let len = list.len() as i64;
                 let start_idx = if start < 0 {
                     (len + start).max(0)
                 } else {
                     start
                 };
                 let stop_idx = if stop < 0 { (len + stop).max(-1) } else { stop };
-
-                // Clamp to valid range
                 let start_idx = (start_idx as usize).min(list.len());
                 let stop_idx = ((stop_idx + 1) as usize).min(list.len());
 
                 if start_idx >= stop_idx {
-                    Ok(Vec::new())
+                    data.remove(key);
                 } else {
-                    Ok(list[start_idx..stop_idx].to_vec())
+                    list.truncate(stop_idx);
+                    list.drain(..start_idx);
                 }
+                Ok(())
             }
-            Some(Value::String(_)) => {
+            Some(_) => {
                 Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
             }
-            None => Ok(Vec::new()),
+            None => Ok(()),
         }
     }
 
-    pub fn llen(&self, key: &str) -> Result<usize, String> {
-        let data = self.data.read().unwrap();
+    // Returns shared handles to the elements; callers copy the bytes after the
+    // read lock is released, so a huge range only blocks writers briefly
+    pub fn lrange(
+        &self,
+        key: &[u8],
+        start: i64,
+        stop: i64,
+        deadline: Deadline,
+    ) -> Result<Vec<Bytes>, String> {
+        let data = self.read();
 
         match data.get(key) {
-            Some(Value::List(list)) => Ok(list.len()),
-            Some(Value::String(_)) => {
+            Some(Value::List(list)) => {
+                let len = list.len() as i64;
+
+                // Convert negative indices
+                let start_idx = if start < 0 {
+                    (len + start).max(0)
+                } else {
+                    start
+                };
+                let stop_idx = if stop < 0 { (len + stop).max(-1) } else { stop };
+
+                // Clamp to valid range
+                let start_idx = (start_idx as usize).min(list.len());
+                let stop_idx = ((stop_idx + 1) as usize).min(list.len());
+
+                if start_idx >= stop_idx {
+                    Ok(Vec::new())
+                } else {
+                    deadline.collect(list[start_idx..stop_idx].iter().cloned())
+                }
+            }
+            Some(_) => {
                 Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
             }
-            None => Ok(0),
+            None => Ok(Vec::new()),
         }
     }
 
-    pub fn lindex(&self, key: &str, index: i64) -> Result<Option<Vec<u8>>, String> {
-        let data = self.data.read().unwrap();
+    pub fn lindex(&self, key: &[u8], index: i64) -> Result<Option<Vec<u8>>, String> {
+        let data = self.read();
 
         match data.get(key) {
             Some(Value::List(list)) => {
@@ -293,13 +1261,715 @@ impl Store {
                 if idx < 0 || idx >= len {
                     Ok(None)
                 } else {
-                    Ok(Some(list[idx as usize].clone()))
+                    Ok(Some(list[idx as usize].to_vec()))
                 }
             }
-            Some(Value::String(_)) => {
+            Some(_) => {
                 Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
             }
             None => Ok(None),
         }
     }
+
+    // Set operations
+    pub fn sadd(&self, key: &[u8], members: Vec<Vec<u8>>) -> Result<usize, String> {
+        let mut data = self.write();
+        if take_expired(&mut data, key) {
+            self.expired(key);
+        }
+
+        let set = match data.get_or_insert_with(key, || Value::Set(HashSet::new())) {
+            Value::Set(set) => set,
+            _ => {
+                return Err(
+                    "WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+                );
+            }
+        };
+        Ok(members
+            .into_iter()
+            .filter(|m| set.insert(Bytes::from(m.clone())))
+            .count())
+    }
+
+    pub fn srem(&self, key: &[u8], members: &[Vec<u8>]) -> Result<usize, String> {
+        let mut data = self.write();
+
+        match data.get_mut(key) {
+            Some(Value::Set(set)) => {
+                let removed = members.iter().filter(|m| set.remove(m.as_slice())).count();
+                if set.is_empty() {
+                    data.remove(key);
+                }
+                Ok(removed)
+            }
+            Some(_) => {
+                Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+            }
+            None => Ok(0),
+        }
+    }
+
+    pub fn smembers(&self, key: &[u8], deadline: Deadline) -> Result<Vec<Bytes>, String> {
+        let data = self.read();
+
+        match data.get(key) {
+            Some(Value::Set(set)) => deadline.collect(set.iter().cloned()),
+            Some(_) => {
+                Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // SRANDMEMBER, HRANDFIELD and ZRANDMEMBER share sample()'s count rules
+    pub fn srandmember(&self, key: &[u8], count: i64) -> Result<Vec<Bytes>, String> {
+        let data = self.read();
+
+        match data.get(key) {
+            Some(Value::Set(set)) => Ok(sample(set.iter().cloned().collect(), count)),
+            Some(_) => {
+                Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // SINTERCARD: like ZINTERCARD, but only plain sets are accepted
+    pub fn sintercard(&self, keys: &[Vec<u8>], limit: usize) -> Result<usize, String> {
+        let data = self.read();
+
+        let mut sets = Vec::with_capacity(keys.len());
+        let mut missing = false;
+        for key in keys {
+            match data.get(key) {
+                Some(Value::Set(set)) => sets.push(set),
+                Some(_) => {
+                    return Err(
+                        "WRONGTYPE Operation against a key holding the wrong kind of value"
+                            .to_string(),
+                    );
+                }
+                None => missing = true,
+            }
+        }
+        if missing {
+            return Ok(0);
+        }
+        Ok(intersection_card(&sets, limit))
+    }
+
+    pub fn sismember(&self, key: &[u8], member: &[u8]) -> Result<bool, String> {
+        Ok(self.smismember(key, &[member.to_vec()])?[0])
+    }
+
+    // One lookup per member under a single read lock; a missing key has no members
+    pub fn smismember(&self, key: &[u8], members: &[Vec<u8>]) -> Result<Vec<bool>, String> {
+        let data = self.read();
+
+        match data.get(key) {
+            Some(Value::Set(set)) => {
+                Ok(members.iter().map(|m| set.contains(m.as_slice())).collect())
+            }
+            Some(_) => {
+                Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+            }
+            None => Ok(vec![false; members.len()]),
+        }
+    }
+
+    // Sorted set operations
+
+    // The number of members added, and of members added or given a new score
+    pub fn zadd(
+        &self,
+        key: &[u8],
+        entries: Vec<(f64, Vec<u8>)>,
+        options: ZAddOptions,
+    ) -> Result<(usize, usize), String> {
+        self.update_zset(key, |zset| {
+            let (mut added, mut changed) = (0, 0);
+            for (score, member) in entries {
+                let current = zset.score(&member);
+                if let Some(score) = options.apply(current, score) {
+                    if current != Some(score) {
+                        changed += 1;
+                    }
+                    if zset.insert(Bytes::from(member), score) {
+                        added += 1;
+                    }
+                }
+            }
+            Ok((added, changed))
+        })
+    }
+
+    // ZADD INCR: the member's new score, or None when the options refused it
+    pub fn zadd_incr(
+        &self,
+        key: &[u8],
+        increment: f64,
+        member: &[u8],
+        options: ZAddOptions,
+    ) -> Result<Option<f64>, String> {
+        self.update_zset(key, |zset| {
+            let current = zset.score(member);
+            let score = current.unwrap_or(0.0) + increment;
+            if score.is_nan() {
+                return Err("ERR resulting score is not a number (NaN)".to_string());
+            }
+            let score = options.apply(current, score);
+            if let Some(score) = score {
+                zset.insert(Bytes::copy_from_slice(member), score);
+            }
+            Ok(score)
+        })
+    }
+
+    // Runs update on the sorted set at key, created empty if missing, and
+    // drops the key again if it is left with no members
+    fn update_zset<T>(
+        &self,
+        key: &[u8],
+        update: impl FnOnce(&mut SortedSet) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let mut data = self.write();
+        if take_expired(&mut data, key) {
+            self.expired(key);
+        }
+
+        let zset = match data.get_or_insert_with(key, || Value::ZSet(SortedSet::new())) {
+            Value::ZSet(zset) => zset,
+            _ => {
+                return Err(
+                    "WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+                );
+            }
+        };
+        let result = update(zset);
+        if zset.is_empty() {
+            data.remove(key);
+        }
+        result
+    }
+
+    pub fn zrem(&self, key: &[u8], members: &[Vec<u8>]) -> Result<usize, String> {
+        let mut data = self.write();
+
+        match data.get_mut(key) {
+            Some(Value::ZSet(zset)) => {
+                let removed = members.iter().filter(|m| zset.remove(m)).count();
+                if zset.is_empty() {
+                    data.remove(key);
+                }
+                Ok(removed)
+            }
+            Some(_) => {
+                Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+            }
+            None => Ok(0),
+        }
+    }
+
+    pub fn zscore(&self, key: &[u8], member: &[u8]) -> Result<Option<f64>, String> {
+        Ok(self.zmscore(key, &[member.to_vec()])?[0])
+    }
+
+    // Scores for several members under one read lock; a missing key has none
+    pub fn zmscore(&self, key: &[u8], members: &[Vec<u8>]) -> Result<Vec<Option<f64>>, String> {
+        let data = self.read();
+
+        match data.get(key) {
+            Some(Value::ZSet(zset)) => Ok(members.iter().map(|m| zset.score(m)).collect()),
+            Some(_) => {
+                Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+            }
+            None => Ok(vec![None; members.len()]),
+        }
+    }
+
+    pub fn zrange(&self, key: &[u8], start: i64, stop: i64) -> Result<Vec<(Bytes, f64)>, String> {
+        let data = self.read();
+
+        match data.get(key) {
+            Some(Value::ZSet(zset)) => Ok(zset.range(start, stop)),
+            Some(_) => {
+                Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn zrandmember(&self, key: &[u8], count: i64) -> Result<Vec<(Bytes, f64)>, String> {
+        let data = self.read();
+
+        match data.get(key) {
+            Some(Value::ZSet(zset)) => Ok(sample(
+                zset.iter()
+                    .map(|(member, score)| (member.clone(), score))
+                    .collect(),
+                count,
+            )),
+            Some(_) => {
+                Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // ZUNIONSTORE / ZINTERSTORE: plain sets take part with a score of 1 and
+    // missing keys count as empty. Returns the cardinality of `dest`, which is
+    // deleted when the result is empty.
+    pub fn zstore(
+        &self,
+        dest: &[u8],
+        keys: &[Vec<u8>],
+        weights: &[f64],
+        aggregate: Aggregate,
+        union: bool,
+    ) -> Result<usize, String> {
+        let mut data = self.write();
+        let sources = zsources(&data, keys)?;
+
+        let mut combined: HashMap<Bytes, f64> = HashMap::new();
+        for (i, members) in sources.into_iter().enumerate() {
+            let weighted: HashMap<Bytes, f64> = members
+                .into_iter()
+                .map(|(m, s)| (m, zset::weighted(s, weights[i])))
+                .collect();
+
+            if union {
+                for (member, score) in weighted {
+                    combined
+                        .entry(member)
+                        .and_modify(|acc| *acc = aggregate.combine(*acc, score))
+                        .or_insert(score);
+                }
+            } else if i == 0 {
+                combined = weighted;
+            } else {
+                combined.retain(|member, acc| match weighted.get(member) {
+                    Some(&score) => {
+                        *acc = aggregate.combine(*acc, score);
+                        true
+                    }
+                    None => false,
+                });
+            }
+        }
+
+        let mut zset = SortedSet::new();
+        for (member, score) in combined {
+            zset.insert(member, score);
+        }
+        let len = zset.len();
+        set_or_delete(&mut data, dest, Value::ZSet(zset), self.now());
+        Ok(len)
+    }
+
+    // ZDIFF: members of the first key missing from every other key, with
+    // their scores from the first key, in score order
+    pub fn zdiff(&self, keys: &[Vec<u8>]) -> Result<Vec<(Bytes, f64)>, String> {
+        let data = self.read();
+        Ok(zdiff_of(zsources(&data, keys)?)
+            .iter()
+            .map(|(m, s)| (m.clone(), s))
+            .collect())
+    }
+
+    // ZDIFFSTORE: like ZUNIONSTORE, `dest` is deleted when the result is empty
+    pub fn zdiffstore(&self, dest: &[u8], keys: &[Vec<u8>]) -> Result<usize, String> {
+        let mut data = self.write();
+        let diff = zdiff_of(zsources(&data, keys)?);
+
+        let len = diff.len();
+        set_or_delete(&mut data, dest, Value::ZSet(diff), self.now());
+        Ok(len)
+    }
+
+    // ZINTERCARD: size of the intersection, counting stops at `limit` (0 = no limit)
+    pub fn zintercard(&self, keys: &[Vec<u8>], limit: usize) -> Result<usize, String> {
+        let data = self.read();
+        let sources: Vec<HashSet<Bytes>> = zsources(&data, keys)?
+            .into_iter()
+            .map(|members| members.into_iter().map(|(m, _)| m).collect())
+            .collect();
+        Ok(intersection_card(
+            &sources.iter().collect::<Vec<_>>(),
+            limit,
+        ))
+    }
+    // Hash operations
+    pub fn hset(&self, key: &[u8], pairs: Vec<(Vec<u8>, Vec<u8>)>) -> Result<usize, String> {
+        let mut data = self.write();
+        let now = self.now();
+        if take_expired(&mut data, key) {
+            self.expired(key);
+        }
+
+        let hash = match data.get_or_insert_with(key, || Value::Hash(Hash::new())) {
+            Value::Hash(hash) => hash,
+            _ => {
+                return Err(
+                    "WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+                );
+            }
+        };
+        hash.purge_expired(now);
+        Ok(pairs
+            .into_iter()
+            .filter(|(field, value)| {
+                hash.insert(Bytes::from(field.clone()), Bytes::from(value.clone()), now)
+            })
+            .count())
+    }
+
+    pub fn hget(&self, key: &[u8], field: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        let data = self.read();
+        let now = self.now();
+
+        match data.get(key) {
+            Some(Value::Hash(hash)) => Ok(hash.get(field, now).map(|v| v.to_vec())),
+            Some(_) => {
+                Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn hdel(&self, key: &[u8], fields: &[Vec<u8>]) -> Result<usize, String> {
+        let mut data = self.write();
+        let now = self.now();
+
+        match data.get_mut(key) {
+            Some(Value::Hash(hash)) => {
+                let removed = fields.iter().filter(|f| hash.remove(f, now)).count();
+                if hash.is_empty(now) {
+                    data.remove(key);
+                }
+                Ok(removed)
+            }
+            Some(_) => {
+                Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+            }
+            None => Ok(0),
+        }
+    }
+
+    pub fn hgetall(&self, key: &[u8]) -> Result<Vec<(Bytes, Bytes)>, String> {
+        let data = self.read();
+        let now = self.now();
+
+        match data.get(key) {
+            Some(Value::Hash(hash)) => Ok(hash
+                .iter(now)
+                .map(|(field, value, _)| (field.clone(), value.clone()))
+                .collect()),
+            Some(_) => {
+                Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // MATCH filters the fields a call visits, like SCAN's
+    pub fn hscan(
+        &self,
+        key: &[u8],
+        cursor: u64,
+        count: usize,
+        pattern: Option<&[u8]>,
+    ) -> Result<(u64, Vec<(Bytes, Bytes)>), String> {
+        let data = self.read();
+        let now = self.now();
+
+        match data.get(key) {
+            Some(Value::Hash(hash)) => {
+                let (next, fields) = hash.scan(cursor, count, now);
+                Ok((
+                    next,
+                    fields
+                        .into_iter()
+                        .filter(|(field, _)| pattern.is_none_or(|p| glob::matches(p, field)))
+                        .map(|(field, value)| (field.clone(), value.clone()))
+                        .collect(),
+                ))
+            }
+            Some(_) => {
+                Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+            }
+            None => Ok((0, Vec::new())),
+        }
+    }
+
+    pub fn hrandfield(&self, key: &[u8], count: i64) -> Result<Vec<(Bytes, Bytes)>, String> {
+        let data = self.read();
+        let now = self.now();
+
+        match data.get(key) {
+            Some(Value::Hash(hash)) => Ok(sample(
+                hash.iter(now)
+                    .map(|(field, value, _)| (field.clone(), value.clone()))
+                    .collect(),
+                count,
+            )),
+            Some(_) => {
+                Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn hexists(&self, key: &[u8], field: &[u8]) -> Result<bool, String> {
+        Ok(self.hget(key, field)?.is_some())
+    }
+
+    // HEXPIRE family, one code per field: -2 no such field, 1 expiry set,
+    // 2 deleted because the time is not in the future
+    pub fn hexpire_at(
+        &self,
+        key: &[u8],
+        fields: &[Vec<u8>],
+        expires_at: SystemTime,
+    ) -> Result<Vec<i64>, String> {
+        let mut data = self.write();
+        let now = self.now();
+
+        let codes = match data.get_mut(key) {
+            Some(Value::Hash(hash)) => {
+                let delete = expires_at <= now;
+                let codes: Vec<i64> = fields
+                    .iter()
+                    .map(|field| {
+                        if delete {
+                            if hash.remove(field, now) { 2 } else { -2 }
+                        } else if hash.set_expiry(field, Some(expires_at), now) {
+                            1
+                        } else {
+                            -2
+                        }
+                    })
+                    .collect();
+                if hash.is_empty(now) {
+                    data.remove(key);
+                } else if codes.contains(&1) {
+                    data.track_field_expiry(key);
+                }
+                codes
+            }
+            Some(_) => {
+                return Err(
+                    "WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+                );
+            }
+            None => vec![-2; fields.len()],
+        };
+        Ok(codes)
+    }
+
+    // Remaining time to live in milliseconds per field, -1 for fields
+    // without an expiry and -2 for missing ones
+    pub fn hpttl(&self, key: &[u8], fields: &[Vec<u8>]) -> Result<Vec<i64>, String> {
+        let data = self.read();
+        let now = self.now();
+
+        match data.get(key) {
+            Some(Value::Hash(hash)) => Ok(fields
+                .iter()
+                .map(|field| match hash.expires_at(field, now) {
+                    None => -2,
+                    Some(None) => -1,
+                    Some(Some(at)) => at
+                        .duration_since(now)
+                        .map(|d| d.as_millis() as i64)
+                        .unwrap_or(0),
+                })
+                .collect()),
+            Some(_) => {
+                Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+            }
+            None => Ok(vec![-2; fields.len()]),
+        }
+    }
+
+    // -2 for missing fields, -1 if the field had no expiry, 1 if it was removed
+    pub fn hpersist(&self, key: &[u8], fields: &[Vec<u8>]) -> Result<Vec<i64>, String> {
+        let mut data = self.write();
+        let now = self.now();
+
+        match data.get_mut(key) {
+            Some(Value::Hash(hash)) => Ok(fields
+                .iter()
+                .map(|field| match hash.expires_at(field, now) {
+                    None => -2,
+                    Some(None) => -1,
+                    Some(Some(_)) => {
+                        hash.set_expiry(field, None, now);
+                        1
+                    }
+                })
+                .collect()),
+            Some(_) => {
+                Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+            }
+            None => Ok(vec![-2; fields.len()]),
+        }
+    }
+}
+
+// Only exact round-trips qualify: "007", "+1" and " 1" are not integers
+fn parse_int(bytes: &[u8]) -> Option<i64> {
+    std::str::from_utf8(bytes)
+        .ok()
+        .filter(|s| s.len() <= 20)
+        .and_then(|s| s.parse::<i64>().ok())
+        .filter(|n| n.to_string().as_bytes() == bytes)
+}
+
+// The members of each key for sorted-set algebra: plain sets take part
+// with a score of 1 and missing keys count as empty
+fn zsources(data: &Keyspace, keys: &[Vec<u8>]) -> Result<Vec<Vec<(Bytes, f64)>>, String> {
+    let mut sources = Vec::with_capacity(keys.len());
+    for key in keys {
+        let members = match data.get(key) {
+            Some(Value::ZSet(zset)) => zset.iter().map(|(m, s)| (m.clone(), s)).collect(),
+            Some(Value::Set(set)) => set.iter().map(|m| (m.clone(), 1.0)).collect(),
+            Some(_) => {
+                return Err(
+                    "WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+                );
+            }
+            None => Vec::new(),
+        };
+        sources.push(members);
+    }
+    Ok(sources)
+}
+
+fn zdiff_of(sources: Vec<Vec<(Bytes, f64)>>) -> SortedSet {
+    let mut sources = sources.into_iter();
+    let first = sources.next().unwrap_or_default();
+    let excluded: HashSet<Bytes> = sources.flatten().map(|(m, _)| m).collect();
+
+    let mut diff = SortedSet::new();
+    for (member, score) in first {
+        if !excluded.contains(&member) {
+            diff.insert(member, score);
+        }
+    }
+    diff
+}
+
+// Walks the smallest set and stops once `limit` common members are found
+fn intersection_card(sets: &[&HashSet<Bytes>], limit: usize) -> usize {
+    let Some(smallest) = sets.iter().min_by_key(|set| set.len()) else {
+        return 0;
+    };
+    let mut count = 0;
+    for member in smallest.iter() {
+        if sets.iter().all(|set| set.contains(member)) {
+            count += 1;
+            if count == limit {
+                break;
+            }
+        }
+    }
+    count
+}
+
+// Redis' random-member count rules: 0 gives nothing, a positive count up to
+// that many distinct elements (all of them if it is larger) and a negative
+// count exactly |count| independent picks, so elements may repeat
+fn sample<T: Clone>(mut items: Vec<T>, count: i64) -> Vec<T> {
+    if items.is_empty() || count == 0 {
+        return Vec::new();
+    }
+    if count < 0 {
+        return (0..count.unsigned_abs())
+            .map(|_| items[random::below(items.len())].clone())
+            .collect();
+    }
+
+    // Partial Fisher-Yates: the first `count` slots become a uniform sample
+    let count = (count as usize).min(items.len());
+    for i in 0..count {
+        let j = i + random::below(items.len() - i);
+        items.swap(i, j);
+    }
+    items.truncate(count);
+    items
+}
+
+// The deadline is checked while the elements are copied and parsed and once
+// more after sorting; the sort itself can't be interrupted
+fn sorted(
+    data: &Keyspace,
+    key: &[u8],
+    options: &SortOptions,
+    deadline: Deadline,
+) -> Result<Vec<Bytes>, String> {
+    let mut items: Vec<Bytes> = match data.get(key) {
+        Some(Value::List(list)) => deadline.collect(list.iter().cloned())?,
+        Some(Value::Set(set)) => deadline.collect(set.iter().cloned())?,
+        Some(Value::ZSet(zset)) => deadline.collect(zset.iter().map(|(m, _)| m.clone()))?,
+        Some(_) => {
+            return Err(
+                "WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+            );
+        }
+        None => Vec::new(),
+    };
+
+    if options.alpha {
+        items.sort();
+    } else {
+        let mut scored = Vec::with_capacity(items.len());
+        for (done, item) in items.into_iter().enumerate() {
+            deadline.tick(done)?;
+            let Some(score) = zset::parse_score(&item) else {
+                return Err("ERR One or more scores can't be converted into double".to_string());
+            };
+            scored.push((score, item));
+        }
+        // Equal numbers fall back to comparing the elements, so the order
+        // doesn't depend on how a set happens to iterate
+        scored.sort_by(|(a, x), (b, y)| a.total_cmp(b).then_with(|| x.cmp(y)));
+        items = scored.into_iter().map(|(_, item)| item).collect();
+    }
+    deadline.check()?;
+    if options.desc {
+        items.reverse();
+    }
+
+    if let Some((offset, count)) = options.limit {
+        let start = (offset.max(0) as usize).min(items.len());
+        let end = if count < 0 {
+            items.len()
+        } else {
+            start.saturating_add(count as usize).min(items.len())
+        };
+        items = items[start..end].to_vec();
+    }
+    Ok(items)
+}
+
+// Write the result of a *STORE command to its destination, replacing
+// whatever was there. An empty result deletes the destination instead.
+fn set_or_delete(data: &mut Keyspace, dest: &[u8], value: Value, now: SystemTime) {
+    if value.is_empty_collection(now) {
+        data.remove(dest);
+    } else {
+        data.insert(Bytes::copy_from_slice(dest), value);
+    }
+}
+
+// Drop `key` if it has expired; callers hold the write lock and fire the
+// expired event once it is released
+fn take_expired(data: &mut Keyspace, key: &[u8]) -> bool {
+    if data.is_expired(key) {
+        data.remove(key);
+        true
+    } else {
+        false
+    }
 }
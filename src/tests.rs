@@ -0,0 +1,2222 @@
+// End-to-end tests: a real server on an ephemeral loopback port, driven over
+// the wire with RESP bytes, so the buffering and pipelining in serve_client
+// are exercised along with the commands themselves
+use crate::clock::Clock;
+use crate::config::{Config, MaxmemoryPolicy};
+use crate::notify::EventFlags;
+use crate::resp::{DEFAULT_MAX_BULK_LEN, DEFAULT_MAX_DEPTH, RESPValue};
+use crate::server::Server;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+// A client connection and the bytes read from it but not parsed yet
+struct Connection {
+    stream: TcpStream,
+    input: Vec<u8>,
+}
+
+// Start a server on 127.0.0.1:0, returning its address. The server lives
+// until the test's runtime shuts down.
+async fn start(config: Config) -> SocketAddr {
+    start_with_clock(config, Clock::system()).await
+}
+
+async fn start_with_clock(config: Config, clock: Clock) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::with_clock(config, clock);
+    tokio::spawn(async move { server.serve(vec![listener]).await });
+    addr
+}
+
+async fn open(addr: SocketAddr) -> Connection {
+    Connection {
+        stream: TcpStream::connect(addr).await.unwrap(),
+        input: Vec::new(),
+    }
+}
+
+// A server with the default configuration and one connection to it
+async fn connect() -> Connection {
+    open(start(Config::from_env()).await).await
+}
+
+fn encode_command(args: &[&str]) -> Vec<u8> {
+    RESPValue::Array(Some(
+        args.iter()
+            .map(|arg| RESPValue::BulkString(Some(arg.as_bytes().to_vec())))
+            .collect(),
+    ))
+    .serialize()
+}
+
+async fn send_command(conn: &mut Connection, args: &[&str]) {
+    conn.stream.write_all(&encode_command(args)).await.unwrap();
+}
+
+// The next reply, reading more from the socket until a whole frame is in
+async fn read_reply(conn: &mut Connection) -> RESPValue {
+    loop {
+        if let Some((value, used)) =
+            RESPValue::parse_frame(&conn.input, DEFAULT_MAX_DEPTH, DEFAULT_MAX_BULK_LEN).unwrap()
+        {
+            conn.input.drain(..used);
+            return value;
+        }
+        let mut buffer = [0u8; 4096];
+        let n = conn.stream.read(&mut buffer).await.unwrap();
+        assert!(n > 0, "server closed the connection");
+        conn.input.extend_from_slice(&buffer[..n]);
+    }
+}
+
+async fn command(conn: &mut Connection, args: &[&str]) -> RESPValue {
+    send_command(conn, args).await;
+    read_reply(conn).await
+}
+
+fn bulk(value: &str) -> RESPValue {
+    RESPValue::BulkString(Some(value.as_bytes().to_vec()))
+}
+
+fn ok() -> RESPValue {
+    RESPValue::SimpleString("OK".to_string())
+}
+
+fn array(items: Vec<RESPValue>) -> RESPValue {
+    RESPValue::Array(Some(items))
+}
+
+#[tokio::test]
+async fn ping() {
+    let mut conn = connect().await;
+    assert_eq!(
+        command(&mut conn, &["PING"]).await,
+        RESPValue::SimpleString("PONG".to_string())
+    );
+    assert_eq!(command(&mut conn, &["PING", "hello"]).await, bulk("hello"));
+}
+
+#[tokio::test]
+async fn set_then_get() {
+    let mut conn = connect().await;
+    assert_eq!(command(&mut conn, &["SET", "k", "v"]).await, ok());
+    assert_eq!(command(&mut conn, &["GET", "k"]).await, bulk("v"));
+    assert_eq!(
+        command(&mut conn, &["GET", "missing"]).await,
+        RESPValue::BulkString(None)
+    );
+}
+
+#[tokio::test]
+async fn pipelined_commands_reply_in_order() {
+    let mut conn = connect().await;
+    // Both commands in one write: the server must parse them out of a single
+    // read and answer each
+    let mut pipeline = encode_command(&["SET", "k", "v"]);
+    pipeline.extend(encode_command(&["GET", "k"]));
+    conn.stream.write_all(&pipeline).await.unwrap();
+
+    assert_eq!(read_reply(&mut conn).await, ok());
+    assert_eq!(read_reply(&mut conn).await, bulk("v"));
+}
+
+#[tokio::test]
+async fn unknown_command_is_an_error() {
+    let mut conn = connect().await;
+    assert_eq!(
+        command(&mut conn, &["NOSUCHCOMMAND"]).await,
+        RESPValue::Error("ERR unknown command 'NOSUCHCOMMAND'".to_string())
+    );
+    // The connection stays usable
+    assert_eq!(
+        command(&mut conn, &["PING"]).await,
+        RESPValue::SimpleString("PONG".to_string())
+    );
+}
+
+#[tokio::test]
+async fn command_sent_one_byte_at_a_time() {
+    let mut conn = connect().await;
+    // Every byte in its own segment: each read ends mid-frame and the
+    // server must keep what it has and wait for the rest
+    conn.stream.set_nodelay(true).unwrap();
+    for byte in encode_command(&["SET", "k", "split"]) {
+        conn.stream.write_all(&[byte]).await.unwrap();
+        conn.stream.flush().await.unwrap();
+        tokio::task::yield_now().await;
+    }
+    assert_eq!(read_reply(&mut conn).await, ok());
+    assert_eq!(command(&mut conn, &["GET", "k"]).await, bulk("split"));
+}
+
+#[tokio::test]
+async fn command_completed_after_a_pause() {
+    let mut conn = connect().await;
+    let frame = encode_command(&["SET", "k", "v"]);
+    let (head, tail) = frame.split_at(frame.len() / 2);
+    conn.stream.write_all(head).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    conn.stream.write_all(tail).await.unwrap();
+
+    assert_eq!(read_reply(&mut conn).await, ok());
+    assert_eq!(command(&mut conn, &["GET", "k"]).await, bulk("v"));
+}
+
+#[tokio::test]
+async fn accepted_sockets_get_nodelay_and_keepalive() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let _client = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (accepted, _) = listener.accept().await.unwrap();
+
+    Server::new(Config::from_env())
+        .configure_socket(&accepted)
+        .unwrap();
+    assert!(accepted.nodelay().unwrap());
+    assert!(socket2::SockRef::from(&accepted).keepalive().unwrap());
+}
+
+// Returns once another connection's BLPOP has been read and had time to block
+async fn wait_for_blpop(conn: &mut Connection) {
+    loop {
+        let RESPValue::BulkString(Some(list)) = command(conn, &["CLIENT", "LIST"]).await else {
+            panic!("CLIENT LIST should reply with a bulk string");
+        };
+        if String::from_utf8_lossy(&list).contains("cmd=blpop") {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+}
+
+#[tokio::test]
+async fn push_publishes_its_event_before_serving_a_blocked_pop() {
+    let mut config = Config::from_env();
+    config.notify_keyspace_events = EventFlags::parse("Kl").unwrap();
+    let addr = start(config).await;
+
+    let mut subscriber = open(addr).await;
+    command(&mut subscriber, &["SUBSCRIBE", "__keyspace@0__:queue"]).await;
+    let mut popper = open(addr).await;
+    send_command(&mut popper, &["BLPOP", "queue", "0"]).await;
+
+    // Push once the BLPOP has been read and had time to block. Were it
+    // not blocked yet it would pop straight away, in the same order.
+    let mut pusher = open(addr).await;
+    wait_for_blpop(&mut pusher).await;
+    assert_eq!(
+        command(&mut pusher, &["RPUSH", "queue", "job"]).await,
+        RESPValue::Integer(1)
+    );
+
+    // Served as if by an LPOP right after the push: the element is gone
+    assert_eq!(
+        read_reply(&mut popper).await,
+        array(vec![bulk("queue"), bulk("job")])
+    );
+    assert_eq!(
+        command(&mut pusher, &["LLEN", "queue"]).await,
+        RESPValue::Integer(0)
+    );
+    for event in ["rpush", "lpop"] {
+        assert_eq!(
+            read_reply(&mut subscriber).await,
+            array(vec![
+                bulk("message"),
+                bulk("__keyspace@0__:queue"),
+                bulk(event)
+            ])
+        );
+    }
+}
+
+// One field of INFO, as a string
+async fn info_field(conn: &mut Connection, field: &str) -> String {
+    let RESPValue::BulkString(Some(info)) = command(conn, &["INFO"]).await else {
+        panic!("INFO should reply with a bulk string");
+    };
+    let prefix = format!("{}:", field);
+    String::from_utf8_lossy(&info)
+        .lines()
+        .find_map(|line| line.strip_prefix(&prefix).map(str::to_string))
+        .unwrap_or_else(|| panic!("INFO has no {} field", field))
+}
+
+// Poll INFO until the field has the value; the other connection changes it
+// asynchronously
+async fn wait_for_info(conn: &mut Connection, field: &str, value: &str) {
+    for _ in 0..200 {
+        if info_field(conn, field).await == value {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("INFO {} never became {}", field, value);
+}
+
+// Repeat a command until it replies with `expected`, for state that
+// arrives asynchronously such as a replica applying the master's stream
+async fn wait_for_reply(conn: &mut Connection, args: &[&str], expected: RESPValue) {
+    for _ in 0..200 {
+        if command(conn, args).await == expected {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("{:?} never replied {:?}", args, expected);
+}
+
+#[tokio::test]
+async fn replica_keeps_applying_writes_after_flushall_async() {
+    let master_addr = start(Config::from_env()).await;
+    let mut master = open(master_addr).await;
+    let mut replica = connect().await;
+    command(&mut master, &["SET", "before", "1"]).await;
+    let port = master_addr.port().to_string();
+    assert_eq!(
+        command(&mut replica, &["REPLICAOF", "127.0.0.1", &port]).await,
+        ok()
+    );
+    wait_for_reply(&mut replica, &["GET", "before"], bulk("1")).await;
+
+    // The link applies the flush on its own thread, outside any runtime
+    assert_eq!(command(&mut master, &["FLUSHALL", "ASYNC"]).await, ok());
+    command(&mut master, &["SET", "after", "2"]).await;
+    wait_for_reply(&mut replica, &["GET", "after"], bulk("2")).await;
+    assert_eq!(
+        command(&mut replica, &["GET", "before"]).await,
+        RESPValue::BulkString(None)
+    );
+}
+
+#[tokio::test]
+async fn blocked_clients_counts_waiting_pops() {
+    let addr = start(Config::from_env()).await;
+    let mut observer = open(addr).await;
+    assert_eq!(info_field(&mut observer, "blocked_clients").await, "0");
+
+    // Served by a push
+    let mut popper = open(addr).await;
+    send_command(&mut popper, &["BLPOP", "jobs", "0"]).await;
+    wait_for_info(&mut observer, "blocked_clients", "1").await;
+    command(&mut observer, &["RPUSH", "jobs", "a"]).await;
+    assert_eq!(
+        read_reply(&mut popper).await,
+        array(vec![bulk("jobs"), bulk("a")])
+    );
+    wait_for_info(&mut observer, "blocked_clients", "0").await;
+
+    // Timed out
+    send_command(&mut popper, &["BRPOP", "jobs", "0.2"]).await;
+    wait_for_info(&mut observer, "blocked_clients", "1").await;
+    assert_eq!(read_reply(&mut popper).await, RESPValue::Array(None));
+    wait_for_info(&mut observer, "blocked_clients", "0").await;
+
+    // A pop that doesn't have to wait never counts
+    command(&mut observer, &["RPUSH", "jobs", "b"]).await;
+    command(&mut popper, &["BLPOP", "jobs", "0"]).await;
+    assert_eq!(info_field(&mut observer, "blocked_clients").await, "0");
+}
+
+#[tokio::test]
+async fn connections_over_maxclients_are_refused() {
+    let mut config = Config::from_env();
+    config.maxclients = 1;
+    let addr = start(config).await;
+    let mut first = open(addr).await;
+    assert_eq!(info_field(&mut first, "maxclients").await, "1");
+
+    let mut second = open(addr).await;
+    assert_eq!(
+        read_reply(&mut second).await,
+        RESPValue::Error("ERR max number of clients reached".to_string())
+    );
+    assert_eq!(
+        command(&mut first, &["PING"]).await,
+        RESPValue::SimpleString("PONG".to_string())
+    );
+}
+
+// Values of 10000 bytes, so three keys take about 30kb by INFO's estimate
+// and the per-key overheads don't matter next to a 25kb or 35kb maxmemory
+const BIG: usize = 10_000;
+
+async fn start_with_maxmemory(policy: &str, maxmemory: usize) -> Connection {
+    let mut config = Config::from_env();
+    config.maxmemory = maxmemory;
+    config.maxmemory_policy = MaxmemoryPolicy::parse(policy).unwrap();
+    open(start(config).await).await
+}
+
+async fn set_big(conn: &mut Connection, key: &str, ttl: Option<&str>) -> RESPValue {
+    let value = "x".repeat(BIG);
+    match ttl {
+        Some(ttl) => command(conn, &["SET", key, &value, "EX", ttl]).await,
+        None => command(conn, &["SET", key, &value]).await,
+    }
+}
+
+async fn exists(conn: &mut Connection, key: &str) -> bool {
+    command(conn, &["EXISTS", key]).await == RESPValue::Integer(1)
+}
+
+fn oom() -> RESPValue {
+    RESPValue::Error("OOM command not allowed when used memory > 'maxmemory'.".to_string())
+}
+
+#[tokio::test]
+async fn noeviction_refuses_writes_over_maxmemory() {
+    let mut conn = start_with_maxmemory("noeviction", 25_000).await;
+    for key in ["a", "b", "c"] {
+        assert_eq!(set_big(&mut conn, key, None).await, ok());
+    }
+    assert_eq!(command(&mut conn, &["SET", "d", "v"]).await, oom());
+    assert_eq!(command(&mut conn, &["RPUSH", "l", "v"]).await, oom());
+    // Reads and writes that free memory still work
+    assert!(exists(&mut conn, "a").await);
+    assert_eq!(
+        command(&mut conn, &["DEL", "a"]).await,
+        RESPValue::Integer(1)
+    );
+    assert_eq!(command(&mut conn, &["SET", "d", "v"]).await, ok());
+    assert_eq!(info_field(&mut conn, "evicted_keys").await, "0");
+}
+
+#[tokio::test]
+async fn allkeys_random_evicts_until_writes_fit() {
+    let mut conn = start_with_maxmemory("allkeys-random", 25_000).await;
+    for key in ["a", "b", "c"] {
+        assert_eq!(set_big(&mut conn, key, None).await, ok());
+    }
+    // Over by one value: the next write evicts a key to make room
+    assert_eq!(command(&mut conn, &["SET", "d", "v"]).await, ok());
+    assert_eq!(info_field(&mut conn, "evicted_keys").await, "1");
+    assert_eq!(command(&mut conn, &["DBSIZE"]).await, RESPValue::Integer(3));
+}
+
+#[tokio::test]
+async fn allkeys_lru_evicts_the_longest_idle_key() {
+    let mut conn = start_with_maxmemory("allkeys-lru", 25_000).await;
+    set_big(&mut conn, "old", None).await;
+    set_big(&mut conn, "read", None).await;
+    // Access times are in seconds
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    command(&mut conn, &["GET", "read"]).await;
+    assert!(matches!(
+        command(&mut conn, &["OBJECT", "IDLETIME", "old"]).await,
+        RESPValue::Integer(idle) if idle >= 1
+    ));
+    set_big(&mut conn, "new", None).await;
+
+    assert_eq!(command(&mut conn, &["SET", "d", "v"]).await, ok());
+    assert!(!exists(&mut conn, "old").await);
+    assert!(exists(&mut conn, "read").await);
+    assert!(exists(&mut conn, "new").await);
+}
+
+#[tokio::test]
+async fn volatile_ttl_evicts_the_soonest_to_expire() {
+    let mut conn = start_with_maxmemory("volatile-ttl", 35_000).await;
+    set_big(&mut conn, "persistent", None).await;
+    set_big(&mut conn, "later", Some("200")).await;
+    set_big(&mut conn, "soon", Some("50")).await;
+    set_big(&mut conn, "latest", Some("300")).await;
+
+    assert_eq!(command(&mut conn, &["SET", "d", "v"]).await, ok());
+    assert!(!exists(&mut conn, "soon").await);
+    for key in ["persistent", "later", "latest"] {
+        assert!(exists(&mut conn, key).await, "{} was evicted", key);
+    }
+}
+
+#[tokio::test]
+async fn volatile_random_never_evicts_keys_without_an_expiry() {
+    let mut conn = start_with_maxmemory("volatile-random", 25_000).await;
+    set_big(&mut conn, "p1", None).await;
+    set_big(&mut conn, "p2", None).await;
+    set_big(&mut conn, "v", Some("100")).await;
+
+    assert_eq!(command(&mut conn, &["SET", "d", "v"]).await, ok());
+    assert!(!exists(&mut conn, "v").await);
+
+    // Over again with only persistent keys left: writes are refused
+    set_big(&mut conn, "p3", None).await;
+    assert_eq!(command(&mut conn, &["SET", "e", "v"]).await, oom());
+    for key in ["p1", "p2", "p3"] {
+        assert!(exists(&mut conn, key).await, "{} was evicted", key);
+    }
+}
+
+// Raw reply bytes up to and including the needle; for RESP3 frames the test
+// parser doesn't read
+async fn read_through(conn: &mut Connection, needle: &[u8]) -> Vec<u8> {
+    loop {
+        if let Some(at) = conn.input.windows(needle.len()).position(|w| w == needle) {
+            return conn.input.drain(..at + needle.len()).collect();
+        }
+        let mut buffer = [0u8; 4096];
+        let n = conn.stream.read(&mut buffer).await.unwrap();
+        assert!(n > 0, "server closed the connection");
+        conn.input.extend_from_slice(&buffer[..n]);
+    }
+}
+
+#[tokio::test]
+async fn info_is_a_verbatim_string_under_resp3() {
+    let mut conn = connect().await;
+    let RESPValue::BulkString(Some(info)) = command(&mut conn, &["INFO", "server"]).await else {
+        panic!("INFO should reply with a bulk string under RESP2");
+    };
+    assert!(info.starts_with(b"# Server"));
+
+    // The HELLO map is skipped along with everything before the INFO reply
+    send_command(&mut conn, &["HELLO", "3"]).await;
+    send_command(&mut conn, &["INFO", "server"]).await;
+    let reply = read_through(&mut conn, b"txt:# Server\r\n").await;
+    let frame = &reply[reply.iter().rposition(|&b| b == b'=').unwrap()..];
+    let len: usize = std::str::from_utf8(&frame[1..frame.len() - b"\r\ntxt:# Server\r\n".len()])
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(len > "txt:# Server\r\n".len());
+
+    send_command(&mut conn, &["LOLWUT"]).await;
+    let expected = format!("Rudis ver. {}\n", env!("CARGO_PKG_VERSION"));
+    let frame = format!("={}\r\ntxt:{}\r\n", expected.len() + 4, expected);
+    let reply = read_through(&mut conn, frame.as_bytes()).await;
+    assert!(reply.ends_with(frame.as_bytes()));
+}
+
+#[tokio::test]
+async fn debug_protocol_bignum_follows_the_protocol() {
+    let mut conn = connect().await;
+    assert_eq!(
+        command(&mut conn, &["DEBUG", "PROTOCOL", "bignum"]).await,
+        bulk("1234567999999999999999999999999999999")
+    );
+    send_command(&mut conn, &["HELLO", "3"]).await;
+    send_command(&mut conn, &["DEBUG", "PROTOCOL", "bignum"]).await;
+    read_through(&mut conn, b"(1234567999999999999999999999999999999\r\n").await;
+}
+
+#[tokio::test]
+async fn debug_listpack_entries_chunks_by_list_max_listpack_size() {
+    let mut conn = connect().await;
+    command(&mut conn, &["CONFIG", "SET", "list-max-listpack-size", "4"]).await;
+    command(
+        &mut conn,
+        &[
+            "RPUSH", "list", "1", "2", "3", "4", "5", "6", "7", "8", "9", "10",
+        ],
+    )
+    .await;
+    let node = |first, last| array(vec![RESPValue::Integer(first), RESPValue::Integer(last)]);
+    assert_eq!(
+        command(&mut conn, &["DEBUG", "LISTPACK-ENTRIES", "list"]).await,
+        array(vec![node(0, 3), node(4, 7), node(8, 9)])
+    );
+
+    // A negative size limits bytes: two 2500-byte elements don't share 4kb
+    command(
+        &mut conn,
+        &["CONFIG", "SET", "list-max-listpack-size", "-1"],
+    )
+    .await;
+    let big = "x".repeat(2500);
+    command(&mut conn, &["RPUSH", "big", "a", &big, &big]).await;
+    assert_eq!(
+        command(&mut conn, &["DEBUG", "LISTPACK-ENTRIES", "big"]).await,
+        array(vec![node(0, 1), node(2, 2)])
+    );
+
+    assert_eq!(
+        command(&mut conn, &["DEBUG", "LISTPACK-ENTRIES", "missing"]).await,
+        RESPValue::Error("ERR no such key".to_string())
+    );
+    command(&mut conn, &["SET", "string", "value"]).await;
+    assert!(matches!(
+        command(&mut conn, &["DEBUG", "LISTPACK-ENTRIES", "string"]).await,
+        RESPValue::Error(e) if e.starts_with("WRONGTYPE")
+    ));
+}
+
+async fn expiretime(conn: &mut Connection, key: &str) -> i64 {
+    match command(conn, &["EXPIRETIME", key]).await {
+        RESPValue::Integer(at) => at,
+        reply => panic!("EXPIRETIME replied {:?}", reply),
+    }
+}
+
+#[tokio::test]
+async fn default_ttl_applies_to_sets_without_an_expiry() {
+    let mut config = Config::from_env();
+    config.default_ttl_seconds = 1000;
+    let mut conn = open(start(config).await).await;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    command(&mut conn, &["SET", "plain", "v"]).await;
+    assert!((now + 999..=now + 1001).contains(&expiretime(&mut conn, "plain").await));
+
+    // An explicit expiry or KEEPTTL wins
+    command(&mut conn, &["SET", "explicit", "v", "EX", "5000"]).await;
+    assert!(expiretime(&mut conn, "explicit").await >= now + 4999);
+    command(&mut conn, &["SET", "explicit", "w", "KEEPTTL"]).await;
+    assert!(expiretime(&mut conn, "explicit").await >= now + 4999);
+    assert_eq!(command(&mut conn, &["GET", "explicit"]).await, bulk("w"));
+    command(&mut conn, &["SET", "fresh", "v", "KEEPTTL"]).await;
+    assert_eq!(expiretime(&mut conn, "fresh").await, -1);
+
+    // PERSIST still removes it, and collections never get one
+    command(&mut conn, &["GETEX", "plain", "PERSIST"]).await;
+    assert_eq!(expiretime(&mut conn, "plain").await, -1);
+    command(&mut conn, &["RPUSH", "list", "a"]).await;
+    assert_eq!(expiretime(&mut conn, "list").await, -1);
+}
+
+#[tokio::test]
+async fn keepttl_without_a_default_keeps_the_old_expiry() {
+    let mut conn = connect().await;
+    command(&mut conn, &["SET", "plain", "v"]).await;
+    assert_eq!(expiretime(&mut conn, "plain").await, -1);
+    command(&mut conn, &["SET", "key", "v", "EXAT", "4000000000"]).await;
+    command(&mut conn, &["SET", "key", "w", "KEEPTTL"]).await;
+    assert_eq!(expiretime(&mut conn, "key").await, 4000000000);
+    command(&mut conn, &["SET", "key", "x"]).await;
+    assert_eq!(expiretime(&mut conn, "key").await, -1);
+}
+
+fn int(n: i64) -> RESPValue {
+    RESPValue::Integer(n)
+}
+
+#[tokio::test]
+async fn del_and_set_count_an_expired_key_as_expired_not_deleted() {
+    let mut conn = connect().await;
+    command(&mut conn, &["DEBUG", "SET-ACTIVE-EXPIRE", "0"]).await;
+    for key in ["gone", "live", "overwritten"] {
+        command(&mut conn, &["SET", key, "v"]).await;
+    }
+    command(&mut conn, &["PEXPIREAT", "gone", "1"]).await;
+    command(&mut conn, &["PEXPIREAT", "overwritten", "1"]).await;
+
+    // Only the live key was deleted; the expired one was already missing
+    assert_eq!(command(&mut conn, &["DEL", "gone", "live"]).await, int(1));
+    assert_eq!(info_field(&mut conn, "expired_keys").await, "1");
+
+    // SET over an expired key still expires it first
+    assert_eq!(
+        command(&mut conn, &["SET", "overwritten", "new"]).await,
+        ok()
+    );
+    assert_eq!(info_field(&mut conn, "expired_keys").await, "2");
+    assert_eq!(command(&mut conn, &["TTL", "overwritten"]).await, int(-1));
+}
+
+#[tokio::test]
+async fn an_expired_list_reads_as_missing_and_is_reaped() {
+    let mut conn = connect().await;
+    command(&mut conn, &["RPUSH", "list", "a", "b"]).await;
+    assert_eq!(command(&mut conn, &["TTL", "list"]).await, int(-1));
+    assert_eq!(
+        command(&mut conn, &["PEXPIREAT", "list", "1"]).await,
+        int(1)
+    );
+
+    // Lazily: hidden from reads, and a push starts a new list
+    command(&mut conn, &["DEBUG", "SET-ACTIVE-EXPIRE", "0"]).await;
+    command(&mut conn, &["RPUSH", "stale", "a"]).await;
+    command(&mut conn, &["PEXPIREAT", "stale", "1"]).await;
+    assert_eq!(command(&mut conn, &["LLEN", "stale"]).await, int(0));
+    assert_eq!(
+        command(&mut conn, &["LRANGE", "stale", "0", "-1"]).await,
+        array(vec![])
+    );
+    assert_eq!(
+        command(&mut conn, &["TYPE", "stale"]).await,
+        RESPValue::SimpleString("none".to_string())
+    );
+    assert_eq!(command(&mut conn, &["TTL", "stale"]).await, int(-2));
+    command(&mut conn, &["RPUSH", "stale", "b"]).await;
+    assert_eq!(
+        command(&mut conn, &["LRANGE", "stale", "0", "-1"]).await,
+        array(vec![bulk("b")])
+    );
+    assert_eq!(command(&mut conn, &["TTL", "stale"]).await, int(-1));
+
+    // Actively: the reaper removes the first list without it being read
+    command(&mut conn, &["DEBUG", "SET-ACTIVE-EXPIRE", "1"]).await;
+    for _ in 0..200 {
+        if command(&mut conn, &["DBSIZE"]).await == int(1) {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("the expired list was never reaped");
+}
+
+#[tokio::test]
+async fn expiry_commands_work_on_every_type() {
+    let mut conn = connect().await;
+    command(&mut conn, &["RPUSH", "list", "a"]).await;
+    command(&mut conn, &["SADD", "set", "a"]).await;
+    command(&mut conn, &["ZADD", "zset", "1", "a"]).await;
+    command(&mut conn, &["HSET", "hash", "f", "v"]).await;
+    command(&mut conn, &["SET", "string", "v"]).await;
+    for key in ["list", "set", "zset", "hash", "string"] {
+        assert_eq!(command(&mut conn, &["EXPIRE", key, "100"]).await, int(1));
+        assert_eq!(command(&mut conn, &["TTL", key]).await, int(100));
+        let RESPValue::Integer(pttl) = command(&mut conn, &["PTTL", key]).await else {
+            panic!("PTTL should reply with an integer");
+        };
+        assert!((99_000..=100_000).contains(&pttl));
+        assert!(expiretime(&mut conn, key).await > 0);
+        assert_eq!(command(&mut conn, &["PERSIST", key]).await, int(1));
+        assert_eq!(command(&mut conn, &["PERSIST", key]).await, int(0));
+        assert_eq!(command(&mut conn, &["TTL", key]).await, int(-1));
+    }
+
+    // A TTL already over deletes the key, whatever its type
+    assert_eq!(command(&mut conn, &["EXPIRE", "hash", "0"]).await, int(1));
+    assert_eq!(command(&mut conn, &["EXISTS", "hash"]).await, int(0));
+    assert_eq!(
+        command(&mut conn, &["EXPIRE", "missing", "100"]).await,
+        int(0)
+    );
+    assert_eq!(command(&mut conn, &["PTTL", "missing"]).await, int(-2));
+    assert_eq!(command(&mut conn, &["PERSIST", "missing"]).await, int(0));
+
+    // Overwriting a key drops its expiry, modifying it keeps it
+    command(&mut conn, &["EXPIRE", "set", "100"]).await;
+    command(&mut conn, &["SADD", "set", "b"]).await;
+    assert_eq!(command(&mut conn, &["TTL", "set"]).await, int(100));
+    command(&mut conn, &["SET", "set", "v"]).await;
+    assert_eq!(command(&mut conn, &["TTL", "set"]).await, int(-1));
+}
+
+#[tokio::test]
+async fn collection_expiries_survive_a_reload() {
+    let mut config = Config::from_env();
+    let path = std::env::temp_dir().join(format!("rudis-expiry-{}.rudis", std::process::id()));
+    config.dbfilename = path.to_string_lossy().to_string();
+    let mut conn = open(start(config).await).await;
+    command(&mut conn, &["RPUSH", "list", "a"]).await;
+    command(&mut conn, &["PEXPIREAT", "list", "4000000000000"]).await;
+    assert_eq!(command(&mut conn, &["DEBUG", "RELOAD"]).await, ok());
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(expiretime(&mut conn, "list").await, 4000000000);
+}
+
+#[tokio::test]
+async fn command_timeout_stops_a_long_keys() {
+    let mut config = Config::from_env();
+    config.command_timeout_ms = 1;
+    let mut conn = open(start(config).await).await;
+    command(&mut conn, &["DEBUG", "POPULATE", "500000"]).await;
+    assert_eq!(
+        command(&mut conn, &["KEYS", "*"]).await,
+        RESPValue::Error("ERR command exceeded time limit".to_string())
+    );
+
+    // Small results finish well within it
+    command(&mut conn, &["SADD", "set", "a"]).await;
+    assert_eq!(
+        command(&mut conn, &["SMEMBERS", "set"]).await,
+        array(vec![bulk("a")])
+    );
+}
+
+#[tokio::test]
+async fn resp2_subscribe_mode_only_takes_subscription_commands() {
+    let mut conn = connect().await;
+    command(&mut conn, &["SUBSCRIBE", "news"]).await;
+    assert_eq!(
+        command(&mut conn, &["GET", "k"]).await,
+        RESPValue::Error(
+            "ERR Can't execute 'get': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context".to_string()
+        )
+    );
+    assert_eq!(
+        command(&mut conn, &["PING"]).await,
+        array(vec![bulk("pong"), bulk("")])
+    );
+    assert_eq!(
+        command(&mut conn, &["PING", "hi"]).await,
+        array(vec![bulk("pong"), bulk("hi")])
+    );
+
+    // RESET leaves subscribe mode
+    assert_eq!(
+        command(&mut conn, &["RESET"]).await,
+        RESPValue::SimpleString("RESET".to_string())
+    );
+    assert_eq!(
+        command(&mut conn, &["GET", "k"]).await,
+        RESPValue::BulkString(None)
+    );
+    assert_eq!(
+        command(&mut conn, &["PING"]).await,
+        RESPValue::SimpleString("PONG".to_string())
+    );
+}
+
+#[tokio::test]
+async fn resp3_subscribe_mode_takes_any_command() {
+    let mut conn = connect().await;
+    send_command(&mut conn, &["HELLO", "3"]).await;
+    send_command(&mut conn, &["SUBSCRIBE", "news"]).await;
+    read_through(&mut conn, b"$4\r\nnews\r\n:1\r\n").await;
+    send_command(&mut conn, &["SET", "k", "v"]).await;
+    send_command(&mut conn, &["GET", "k"]).await;
+    assert_eq!(
+        read_through(&mut conn, b"$1\r\nv\r\n").await,
+        b"+OK\r\n$1\r\nv\r\n"
+    );
+    send_command(&mut conn, &["PING"]).await;
+    assert_eq!(read_through(&mut conn, b"\r\n").await, b"+PONG\r\n");
+}
+
+#[tokio::test]
+async fn pipelined_replies_are_flushed_in_batches() {
+    let mut conn = connect().await;
+    command(&mut conn, &["SET", "big", &"x".repeat(10_000)]).await;
+
+    // total_net_output_bytes and total_writes_processed, plus the length of
+    // the INFO reply they came in, which is counted after they were read
+    async fn output_stats(conn: &mut Connection) -> (usize, usize, usize) {
+        let reply = command(conn, &["INFO", "stats"]).await;
+        let RESPValue::BulkString(Some(info)) = &reply else {
+            panic!("INFO should reply with a bulk string");
+        };
+        let info = String::from_utf8_lossy(info);
+        let field = |name: &str| -> usize {
+            info.lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+                .unwrap()
+                .parse()
+                .unwrap()
+        };
+        (
+            field("total_net_output_bytes"),
+            field("total_writes_processed"),
+            reply.serialized_len(),
+        )
+    }
+    let (bytes_before, writes_before, info_len) = output_stats(&mut conn).await;
+
+    // ~1MB of replies to one small pipeline, which the server reads at once
+    let pipeline: Vec<u8> = (0..100)
+        .flat_map(|_| encode_command(&["GET", "big"]))
+        .collect();
+    conn.stream.write_all(&pipeline).await.unwrap();
+    let mut received = 0;
+    for _ in 0..100 {
+        let reply = read_reply(&mut conn).await;
+        assert_eq!(reply, RESPValue::BulkString(Some(vec![b'x'; 10_000])));
+        received += reply.serialized_len();
+    }
+    assert_eq!(received, 100 * 10_010);
+
+    let (bytes_after, writes_after, _) = output_stats(&mut conn).await;
+    assert_eq!(bytes_after - bytes_before, info_len + received);
+    // One write for the first INFO; at about 64KB a write the replies need
+    // 16, far fewer than one each but more than one for the whole pipeline
+    assert!(
+        (10..50).contains(&(writes_after - writes_before)),
+        "only {} writes",
+        writes_after - writes_before
+    );
+}
+
+#[tokio::test]
+async fn bgsave_writes_its_snapshot_while_writes_continue() {
+    let mut config = Config::from_env();
+    let path = std::env::temp_dir().join(format!("rudis-bgsave-{}.rudis", std::process::id()));
+    config.dbfilename = path.to_string_lossy().to_string();
+    let mut conn = open(start(config).await).await;
+    command(&mut conn, &["DEBUG", "POPULATE", "100000"]).await;
+    assert_eq!(
+        command(&mut conn, &["BGSAVE"]).await,
+        RESPValue::SimpleString("Background saving started".to_string())
+    );
+
+    // Served while the save is written, and not part of it
+    assert_eq!(command(&mut conn, &["SET", "after", "v"]).await, ok());
+    assert_eq!(command(&mut conn, &["DEL", "key:0"]).await, int(1));
+
+    for _ in 0..500 {
+        if command(&mut conn, &["LASTSAVE"]).await != int(0) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let saved = crate::snapshot::load_from_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(saved.len(), 100000);
+    assert!(saved.contains_key(&b"key:0"[..]));
+    assert!(!saved.contains_key(&b"after"[..]));
+}
+
+#[tokio::test]
+async fn string_encoding_follows_redis_thresholds() {
+    let mut conn = connect().await;
+    let encoding = |s: &str| RESPValue::BulkString(Some(s.as_bytes().to_vec()));
+    for (value, expected) in [
+        ("hello".to_string(), "embstr"),
+        ("x".repeat(44), "embstr"),
+        ("x".repeat(45), "raw"),
+        ("12345".to_string(), "int"),
+        ("-9223372036854775808".to_string(), "int"),
+        // Not canonical, or too big for an i64
+        ("007".to_string(), "embstr"),
+        ("9223372036854775808".to_string(), "embstr"),
+    ] {
+        command(&mut conn, &["SET", "s", &value]).await;
+        assert_eq!(
+            command(&mut conn, &["OBJECT", "ENCODING", "s"]).await,
+            encoding(expected),
+            "{}",
+            value
+        );
+    }
+}
+
+#[tokio::test]
+async fn byte_level_edits_demote_int_encoded_strings_to_raw() {
+    let mut conn = connect().await;
+    let encoding = |s: &str| RESPValue::BulkString(Some(s.as_bytes().to_vec()));
+
+    command(&mut conn, &["SET", "n", "123"]).await;
+    assert_eq!(command(&mut conn, &["APPEND", "n", "4"]).await, int(4));
+    assert_eq!(command(&mut conn, &["GET", "n"]).await, bulk("1234"));
+    assert_eq!(command(&mut conn, &["STRLEN", "n"]).await, int(4));
+    assert_eq!(
+        command(&mut conn, &["OBJECT", "ENCODING", "n"]).await,
+        encoding("raw")
+    );
+    // Still a valid counter; INCR stores the result as an int again
+    assert_eq!(command(&mut conn, &["INCR", "n"]).await, int(1235));
+    assert_eq!(
+        command(&mut conn, &["OBJECT", "ENCODING", "n"]).await,
+        encoding("int")
+    );
+
+    command(&mut conn, &["SET", "n", "5"]).await;
+    assert_eq!(
+        command(&mut conn, &["SETRANGE", "n", "1", "0"]).await,
+        int(2)
+    );
+    assert_eq!(command(&mut conn, &["GET", "n"]).await, bulk("50"));
+    assert_eq!(
+        command(&mut conn, &["OBJECT", "ENCODING", "n"]).await,
+        encoding("raw")
+    );
+
+    // Reads render the digits without changing the encoding, as in Redis
+    command(&mut conn, &["SET", "n", "100"]).await;
+    assert_eq!(
+        command(&mut conn, &["GETRANGE", "n", "0", "0"]).await,
+        bulk("1")
+    );
+    assert_eq!(
+        command(&mut conn, &["GETRANGE", "n", "-2", "-1"]).await,
+        bulk("00")
+    );
+    assert_eq!(command(&mut conn, &["STRLEN", "n"]).await, int(3));
+    assert_eq!(
+        command(&mut conn, &["OBJECT", "ENCODING", "n"]).await,
+        encoding("int")
+    );
+
+    // Short strings that are edited are raw too, until overwritten
+    command(&mut conn, &["SET", "s", "hello"]).await;
+    command(&mut conn, &["APPEND", "s", "!"]).await;
+    assert_eq!(
+        command(&mut conn, &["OBJECT", "ENCODING", "s"]).await,
+        encoding("raw")
+    );
+    command(&mut conn, &["SET", "s", "hello"]).await;
+    assert_eq!(
+        command(&mut conn, &["OBJECT", "ENCODING", "s"]).await,
+        encoding("embstr")
+    );
+}
+
+#[tokio::test]
+async fn set_encoding_converts_from_intset_as_members_are_added() {
+    let mut conn = connect().await;
+    let mut expected: Vec<Vec<u8>> = Vec::new();
+    let steps: [(Vec<String>, &str); 3] = [
+        ((1..=3).map(|i| i.to_string()).collect(), "intset"),
+        (vec!["x".to_string()], "listpack"),
+        ((0..200).map(|i| format!("m{}", i)).collect(), "hashtable"),
+    ];
+    for (members, encoding) in steps {
+        let mut args = vec!["SADD", "set"];
+        args.extend(members.iter().map(String::as_str));
+        command(&mut conn, &args).await;
+        expected.extend(members.into_iter().map(String::into_bytes));
+        assert_eq!(
+            command(&mut conn, &["OBJECT", "ENCODING", "set"]).await,
+            bulk(encoding)
+        );
+
+        let RESPValue::Array(Some(reply)) = command(&mut conn, &["SMEMBERS", "set"]).await else {
+            panic!("SMEMBERS didn't reply with an array");
+        };
+        let mut members: Vec<Vec<u8>> = reply
+            .into_iter()
+            .map(|member| match member {
+                RESPValue::BulkString(Some(member)) => member,
+                other => panic!("unexpected member {:?}", other),
+            })
+            .collect();
+        members.sort();
+        expected.sort();
+        assert_eq!(members, expected, "{}", encoding);
+    }
+}
+
+#[tokio::test]
+async fn key_expiry_follows_the_server_clock() {
+    let now = Arc::new(Mutex::new(SystemTime::now()));
+    let clock = {
+        let now = now.clone();
+        Clock::new(move || *now.lock().unwrap())
+    };
+    let mut conn = open(start_with_clock(Config::from_env(), clock).await).await;
+
+    command(&mut conn, &["SET", "k", "v", "EX", "10"]).await;
+    command(&mut conn, &["RPUSH", "list", "a"]).await;
+    command(&mut conn, &["EXPIRE", "list", "10"]).await;
+    *now.lock().unwrap() += Duration::from_secs(9);
+    assert_eq!(command(&mut conn, &["TTL", "k"]).await, int(1));
+    assert_eq!(command(&mut conn, &["GET", "k"]).await, bulk("v"));
+
+    *now.lock().unwrap() += Duration::from_secs(2);
+    assert_eq!(
+        command(&mut conn, &["GET", "k"]).await,
+        RESPValue::BulkString(None)
+    );
+    assert_eq!(command(&mut conn, &["TTL", "k"]).await, int(-2));
+    assert_eq!(command(&mut conn, &["EXISTS", "list"]).await, int(0));
+
+    // Hash fields expire on the same clock
+    command(&mut conn, &["HSET", "h", "f", "v", "g", "w"]).await;
+    assert_eq!(
+        command(&mut conn, &["HEXPIRE", "h", "10", "FIELDS", "1", "f"]).await,
+        array(vec![int(1)])
+    );
+    *now.lock().unwrap() += Duration::from_secs(9);
+    assert_eq!(
+        command(&mut conn, &["HTTL", "h", "FIELDS", "1", "f"]).await,
+        array(vec![int(1)])
+    );
+    assert_eq!(command(&mut conn, &["HGET", "h", "f"]).await, bulk("v"));
+    *now.lock().unwrap() += Duration::from_secs(2);
+    assert_eq!(
+        command(&mut conn, &["HGET", "h", "f"]).await,
+        RESPValue::BulkString(None)
+    );
+    assert_eq!(command(&mut conn, &["HLEN", "h"]).await, int(1));
+}
+
+#[tokio::test]
+async fn info_commandstats_counts_calls_per_command() {
+    let mut conn = connect().await;
+    for key in ["a", "b", "c"] {
+        command(&mut conn, &["SET", key, "v"]).await;
+    }
+    command(&mut conn, &["GET", "a"]).await;
+    command(&mut conn, &["GET", "missing"]).await;
+    command(&mut conn, &["GET"]).await;
+    command(&mut conn, &["INCR", "a"]).await;
+
+    let RESPValue::BulkString(Some(info)) = command(&mut conn, &["INFO", "commandstats"]).await
+    else {
+        panic!("INFO should reply with a bulk string");
+    };
+    let info = String::from_utf8(info).unwrap();
+    let stat = |name: &str| -> Vec<String> {
+        let prefix = format!("cmdstat_{}:", name);
+        let line = info
+            .lines()
+            .find_map(|line| line.strip_prefix(&prefix))
+            .unwrap_or_else(|| panic!("no {} line", prefix));
+        // Every field but the timings
+        line.split(',')
+            .filter(|field| !field.starts_with("usec"))
+            .map(str::to_string)
+            .collect()
+    };
+    assert_eq!(
+        stat("set"),
+        ["calls=3", "rejected_calls=0", "failed_calls=0"]
+    );
+    assert_eq!(
+        stat("get"),
+        ["calls=2", "rejected_calls=1", "failed_calls=0"]
+    );
+    assert_eq!(
+        stat("incr"),
+        ["calls=1", "rejected_calls=0", "failed_calls=1"]
+    );
+    assert!(info.contains("usec_per_call="));
+
+    // Not part of the default sections
+    let RESPValue::BulkString(Some(info)) = command(&mut conn, &["INFO"]).await else {
+        panic!("INFO should reply with a bulk string");
+    };
+    assert!(!String::from_utf8_lossy(&info).contains("cmdstat_"));
+}
+
+#[tokio::test]
+async fn latency_monitor_records_slow_commands() {
+    let mut config = Config::from_env();
+    config.latency_monitor_threshold = 50;
+    let mut conn = open(start(config).await).await;
+    command(&mut conn, &["GET", "k"]).await;
+    assert_eq!(
+        command(&mut conn, &["LATENCY", "LATEST"]).await,
+        array(vec![])
+    );
+
+    command(&mut conn, &["DEBUG", "SLEEP", "0.1"]).await;
+    let RESPValue::Array(Some(latest)) = command(&mut conn, &["LATENCY", "LATEST"]).await else {
+        panic!("LATENCY LATEST should reply with an array");
+    };
+    let [RESPValue::Array(Some(event))] = latest.as_slice() else {
+        panic!("expected one event, got {:?}", latest);
+    };
+    let [
+        name,
+        RESPValue::Integer(at),
+        RESPValue::Integer(last),
+        RESPValue::Integer(max),
+    ] = event.as_slice()
+    else {
+        panic!("unexpected event {:?}", event);
+    };
+    assert_eq!(name, &bulk("command"));
+    assert!(*last >= 100 && max == last);
+    assert_eq!(
+        command(&mut conn, &["LATENCY", "HISTORY", "command"]).await,
+        array(vec![array(vec![int(*at), int(*last)])])
+    );
+
+    assert_eq!(command(&mut conn, &["LATENCY", "RESET"]).await, int(1));
+    assert_eq!(
+        command(&mut conn, &["LATENCY", "LATEST"]).await,
+        array(vec![])
+    );
+    assert_eq!(
+        command(&mut conn, &["LATENCY", "HISTORY", "command"]).await,
+        array(vec![])
+    );
+}
+
+#[tokio::test]
+async fn bgrewriteaof_writes_commands_that_rebuild_the_dataset() {
+    let mut config = Config::from_env();
+    let path = std::env::temp_dir().join(format!("rudis-aof-{}.aof", std::process::id()));
+    config.appendfilename = path.to_string_lossy().to_string();
+    let mut conn = open(start(config).await).await;
+    command(&mut conn, &["SET", "s", "v", "PXAT", "4000000000000"]).await;
+    command(&mut conn, &["RPUSH", "l", "a", "b", "c"]).await;
+    command(&mut conn, &["PEXPIREAT", "l", "4000000000000"]).await;
+    command(&mut conn, &["SADD", "set", "x", "y"]).await;
+    command(&mut conn, &["ZADD", "z", "1.5", "m", "-inf", "n"]).await;
+    command(&mut conn, &["HSET", "h", "f1", "v1", "f2", "v2"]).await;
+    command(
+        &mut conn,
+        &["HPEXPIREAT", "h", "4000000000000", "FIELDS", "1", "f1"],
+    )
+    .await;
+    command(&mut conn, &["DEBUG", "POPULATE", "100000"]).await;
+    assert_eq!(
+        command(&mut conn, &["BGREWRITEAOF"]).await,
+        RESPValue::SimpleString("Background append only file rewriting started".to_string())
+    );
+
+    // Not in the snapshot; appended after it
+    assert_eq!(command(&mut conn, &["SET", "after", "v"]).await, ok());
+    assert_eq!(command(&mut conn, &["DEL", "key:0"]).await, int(1));
+    wait_for_info(&mut conn, "aof_rewrite_in_progress", "0").await;
+    assert_eq!(
+        info_field(&mut conn, "aof_last_bgrewrite_status").await,
+        "ok"
+    );
+
+    // Replaying the file into an empty server gives the same dataset
+    let aof = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let mut commands = 0;
+    let mut rest = &aof[..];
+    while let Some((_, used)) =
+        RESPValue::parse_frame(rest, DEFAULT_MAX_DEPTH, DEFAULT_MAX_BULK_LEN).unwrap()
+    {
+        rest = &rest[used..];
+        commands += 1;
+    }
+    assert!(rest.is_empty());
+    let mut replay = connect().await;
+    replay.stream.write_all(&aof).await.unwrap();
+    for _ in 0..commands {
+        let reply = read_reply(&mut replay).await;
+        assert!(!matches!(reply, RESPValue::Error(_)), "{:?}", reply);
+    }
+
+    for args in [
+        &["DBSIZE"][..],
+        &["GET", "after"],
+        &["EXISTS", "key:0"],
+        &["GET", "key:1"],
+        &["PEXPIRETIME", "s"],
+        &["LRANGE", "l", "0", "-1"],
+        &["PEXPIRETIME", "l"],
+        &["SMEMBERS", "set"],
+        &["ZRANGE", "z", "0", "-1", "WITHSCORES"],
+        &["HGETALL", "h"],
+        &["HPEXPIRETIME", "h", "FIELDS", "2", "f1", "f2"],
+    ] {
+        let expected = command(&mut conn, args).await;
+        let mut actual = command(&mut replay, args).await;
+        if let (RESPValue::Array(Some(expected)), RESPValue::Array(Some(actual))) =
+            (&expected, &mut actual)
+            && args[0] == "SMEMBERS"
+        {
+            actual.sort_by_key(|member| expected.iter().position(|m| m == member));
+        }
+        assert_eq!(actual, expected, "{:?}", args);
+    }
+    assert_eq!(command(&mut replay, &["DBSIZE"]).await, int(100005));
+}
+
+#[tokio::test]
+async fn expired_keys_counts_lazy_and_active_expiry() {
+    let now = Arc::new(Mutex::new(SystemTime::now()));
+    let clock = {
+        let now = now.clone();
+        Clock::new(move || *now.lock().unwrap())
+    };
+    let mut conn = open(start_with_clock(Config::from_env(), clock).await).await;
+    command(&mut conn, &["DEBUG", "SET-ACTIVE-EXPIRE", "0"]).await;
+    for key in ["a", "b", "c"] {
+        command(&mut conn, &["SET", key, "v", "EX", "10"]).await;
+    }
+    command(&mut conn, &["SET", "n", "1", "EX", "10"]).await;
+    assert_eq!(info_field(&mut conn, "expired_keys").await, "0");
+    *now.lock().unwrap() += Duration::from_secs(11);
+
+    // Found expired by the commands that read them
+    assert_eq!(
+        command(&mut conn, &["GET", "a"]).await,
+        RESPValue::BulkString(None)
+    );
+    assert_eq!(command(&mut conn, &["INCR", "n"]).await, int(1));
+    assert_eq!(info_field(&mut conn, "expired_keys").await, "2");
+
+    // And the rest by the reaper
+    command(&mut conn, &["DEBUG", "SET-ACTIVE-EXPIRE", "1"]).await;
+    wait_for_info(&mut conn, "expired_keys", "4").await;
+    assert_eq!(command(&mut conn, &["DBSIZE"]).await, int(1));
+}
+
+#[tokio::test]
+async fn keys_matches_glob_patterns_over_raw_bytes() {
+    let mut conn = connect().await;
+    for key in ["hello", "hallo", "hxllo", "heeello", "é"] {
+        command(&mut conn, &["SET", key, "v"]).await;
+    }
+    let keys = |reply: RESPValue| match reply {
+        RESPValue::Array(Some(items)) => {
+            let mut keys: Vec<Vec<u8>> = items
+                .into_iter()
+                .map(|item| match item {
+                    RESPValue::BulkString(Some(key)) => key,
+                    other => panic!("unexpected key {:?}", other),
+                })
+                .collect();
+            keys.sort();
+            keys.into_iter()
+                .map(|key| RESPValue::BulkString(Some(key)))
+                .collect::<Vec<_>>()
+        }
+        other => panic!("unexpected reply {:?}", other),
+    };
+    assert_eq!(
+        keys(command(&mut conn, &["KEYS", "h?llo"]).await),
+        vec![bulk("hallo"), bulk("hello"), bulk("hxllo")]
+    );
+    assert_eq!(
+        keys(command(&mut conn, &["KEYS", "h[ae]llo"]).await),
+        vec![bulk("hallo"), bulk("hello")]
+    );
+    assert_eq!(keys(command(&mut conn, &["KEYS", "h*llo"]).await).len(), 4);
+
+    // A pattern that is not valid UTF-8 still matches the key's bytes
+    let raw = RESPValue::Array(Some(vec![
+        RESPValue::BulkString(Some(b"KEYS".to_vec())),
+        RESPValue::BulkString(Some(b"\xc3*".to_vec())),
+    ]));
+    conn.stream.write_all(&raw.serialize()).await.unwrap();
+    assert_eq!(read_reply(&mut conn).await, array(vec![bulk("é")]));
+
+    // Key names are bytes too: two invalid UTF-8 keys stay distinct and
+    // come back from KEYS unchanged
+    assert_eq!(
+        command_bytes(&mut conn, &[b"SET", b"\xff", b"a"]).await,
+        ok()
+    );
+    assert_eq!(
+        command_bytes(&mut conn, &[b"GET", b"\xfe"]).await,
+        RESPValue::BulkString(None)
+    );
+    assert_eq!(
+        command_bytes(&mut conn, &[b"GET", b"\xff"]).await,
+        bulk("a")
+    );
+    assert_eq!(
+        command_bytes(&mut conn, &[b"KEYS", b"\xff"]).await,
+        RESPValue::Array(Some(vec![RESPValue::BulkString(Some(b"\xff".to_vec()))]))
+    );
+}
+
+// One SCAN call: the next cursor and the keys returned
+async fn scan(conn: &mut Connection, cursor: &str, count: &str) -> (String, Vec<Vec<u8>>) {
+    match command(conn, &["SCAN", cursor, "COUNT", count]).await {
+        RESPValue::Array(Some(reply)) => match reply.as_slice() {
+            [
+                RESPValue::BulkString(Some(next)),
+                RESPValue::Array(Some(keys)),
+            ] => (
+                String::from_utf8(next.clone()).unwrap(),
+                keys.iter()
+                    .map(|key| match key {
+                        RESPValue::BulkString(Some(key)) => key.clone(),
+                        other => panic!("unexpected key {:?}", other),
+                    })
+                    .collect(),
+            ),
+            _ => panic!("unexpected SCAN reply {:?}", reply),
+        },
+        other => panic!("unexpected SCAN reply {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn scan_with_a_huge_count_returns_every_key_at_once() {
+    let mut conn = connect().await;
+    for n in 0..20 {
+        command(&mut conn, &["SET", &format!("key:{}", n), "v"]).await;
+    }
+
+    // COUNT only bounds the walk; it is never allocated up front
+    let (next, mut keys) = scan(&mut conn, "0", "100000000000").await;
+    assert_eq!(next, "0");
+    keys.sort();
+    let mut expected: Vec<Vec<u8>> = (0..20).map(|n| format!("key:{}", n).into_bytes()).collect();
+    expected.sort();
+    assert_eq!(keys, expected);
+}
+
+#[tokio::test]
+async fn scan_resumes_from_a_cursor_mid_keyspace() {
+    let mut conn = connect().await;
+    for n in 0..50 {
+        command(&mut conn, &["SET", &format!("key:{}", n), "v"]).await;
+    }
+
+    // Whole buckets at a time, so a call may return a few more than COUNT
+    let (mut cursor, first) = scan(&mut conn, "0", "10").await;
+    assert!(first.len() >= 10);
+    assert_ne!(cursor, "0");
+
+    // Keys added after the first call don't disturb the ones still to come
+    for n in 50..60 {
+        command(&mut conn, &["SET", &format!("key:{}", n), "v"]).await;
+    }
+    let mut seen = first.clone();
+    while cursor != "0" {
+        let (next, keys) = scan(&mut conn, &cursor, "10").await;
+        // The resumed walk starts after the first batch rather than over again
+        assert!(keys.iter().all(|key| !first.contains(key)));
+        seen.extend(keys);
+        cursor = next;
+    }
+
+    for n in 0..50 {
+        assert!(seen.contains(&format!("key:{}", n).into_bytes()));
+    }
+    let len = seen.len();
+    seen.sort();
+    seen.dedup();
+    assert_eq!(seen.len(), len);
+}
+
+#[tokio::test]
+async fn dumpjson_pages_keys_by_name_and_base64_encodes_them() {
+    let mut conn = connect().await;
+    command_bytes(&mut conn, &[b"SET", b"\xfe", b"a"]).await;
+    command_bytes(&mut conn, &[b"SET", b"\xff", b"b"]).await;
+    command(&mut conn, &["SET", "k", "c"]).await;
+
+    // Keys that aren't UTF-8 stay distinct
+    assert_eq!(
+        command(
+            &mut conn,
+            &["DEBUG", "DUMPJSON", "OFFSET", "1", "COUNT", "1"]
+        )
+        .await,
+        bulk(concat!(
+            r#"{"keys":[{"key":"/g==","type":"string","expires_at_ms":null,"value":"YQ=="}],"#,
+            r#""total":3,"next":2}"#
+        ))
+    );
+    assert_eq!(
+        command(&mut conn, &["DEBUG", "DUMPJSON", "OFFSET", "2"]).await,
+        bulk(concat!(
+            r#"{"keys":[{"key":"/w==","type":"string","expires_at_ms":null,"value":"Yg=="}],"#,
+            r#""total":3,"next":null}"#
+        ))
+    );
+}
+
+#[tokio::test]
+async fn append_and_setrange_never_build_a_string_past_proto_max_bulk_len() {
+    let addr = start(Config::from_env()).await;
+    let mut conn = open(addr).await;
+    let mut other = open(addr).await;
+    command(
+        &mut conn,
+        &["CONFIG", "SET", "proto-max-bulk-len", "1048576"],
+    )
+    .await;
+    let too_big = RESPValue::Error(
+        "ERR string exceeds maximum allowed size (proto-max-bulk-len)".to_string(),
+    );
+
+    // Rejected before the key is created
+    assert_eq!(
+        command(&mut conn, &["SETRANGE", "s", "1048576", "x"]).await,
+        too_big
+    );
+    assert_eq!(command(&mut conn, &["EXISTS", "s"]).await, int(0));
+
+    // Two appends racing for the last of the room: the check and the write
+    // happen under one lock, so exactly one of them fits
+    let chunk = "x".repeat(600_000);
+    send_command(&mut conn, &["APPEND", "s", &chunk]).await;
+    send_command(&mut other, &["APPEND", "s", &chunk]).await;
+    let mut replies = vec![read_reply(&mut conn).await, read_reply(&mut other).await];
+    replies.sort_by_key(|reply| matches!(reply, RESPValue::Error(_)));
+    assert_eq!(replies, vec![int(600_000), too_big]);
+    assert_eq!(command(&mut conn, &["STRLEN", "s"]).await, int(600_000));
+}
+
+#[tokio::test]
+async fn zadd_flags_control_which_scores_change() {
+    let mut conn = connect().await;
+    command(&mut conn, &["ZADD", "board", "100", "alice", "50", "bob"]).await;
+
+    // GT only ever raises a score, but still adds new members
+    assert_eq!(
+        command(
+            &mut conn,
+            &[
+                "ZADD", "board", "GT", "90", "alice", "60", "bob", "5", "carol"
+            ]
+        )
+        .await,
+        int(1)
+    );
+    assert_eq!(
+        command(&mut conn, &["ZSCORE", "board", "alice"]).await,
+        bulk("100")
+    );
+    assert_eq!(
+        command(&mut conn, &["ZSCORE", "board", "bob"]).await,
+        bulk("60")
+    );
+
+    // CH counts updated scores as well as added members
+    assert_eq!(
+        command(
+            &mut conn,
+            &[
+                "ZADD", "board", "CH", "100", "alice", "70", "bob", "1", "dave"
+            ]
+        )
+        .await,
+        int(2)
+    );
+    assert_eq!(
+        command(
+            &mut conn,
+            &["ZADD", "board", "XX", "CH", "1", "erin", "71", "bob"]
+        )
+        .await,
+        int(1)
+    );
+    assert_eq!(
+        command(&mut conn, &["ZSCORE", "board", "erin"]).await,
+        RESPValue::BulkString(None)
+    );
+
+    // INCR replies with the new score, or nil when a flag refuses it
+    assert_eq!(
+        command(&mut conn, &["ZADD", "board", "INCR", "2.5", "alice"]).await,
+        bulk("102.5")
+    );
+    assert_eq!(
+        command(&mut conn, &["ZADD", "board", "INCR", "3", "frank"]).await,
+        bulk("3")
+    );
+    assert_eq!(
+        command(&mut conn, &["ZADD", "board", "LT", "INCR", "1", "alice"]).await,
+        RESPValue::BulkString(None)
+    );
+    assert_eq!(
+        command(&mut conn, &["ZADD", "board", "NX", "INCR", "1", "alice"]).await,
+        RESPValue::BulkString(None)
+    );
+
+    // XX on a missing key leaves no empty key behind
+    assert_eq!(
+        command(&mut conn, &["ZADD", "none", "XX", "1", "a"]).await,
+        int(0)
+    );
+    assert_eq!(command(&mut conn, &["EXISTS", "none"]).await, int(0));
+
+    assert_eq!(
+        command(&mut conn, &["ZADD", "board", "NX", "XX", "1", "a"]).await,
+        RESPValue::Error("ERR XX and NX options at the same time are not compatible".to_string())
+    );
+    assert_eq!(
+        command(&mut conn, &["ZADD", "board", "NX", "GT", "1", "a"]).await,
+        RESPValue::Error(
+            "ERR GT, LT, and/or NX options at the same time are not compatible".to_string()
+        )
+    );
+    assert_eq!(
+        command(&mut conn, &["ZADD", "board", "INCR", "1", "a", "2", "b"]).await,
+        RESPValue::Error("ERR INCR option supports a single increment-element pair".to_string())
+    );
+    assert_eq!(
+        command(&mut conn, &["ZADD", "board", "CH", "1"]).await,
+        RESPValue::Error("ERR syntax error".to_string())
+    );
+}
+
+#[tokio::test]
+async fn hscan_novalues_iterates_the_same_cursor_as_a_full_scan() {
+    let mut conn = connect().await;
+    for i in 0..25 {
+        let field = format!("f{}", i);
+        command(&mut conn, &["HSET", "h", &field, "v"]).await;
+    }
+
+    // Alternate NOVALUES between calls, overwriting a field halfway through
+    let mut seen = Vec::new();
+    let mut cursor = "0".to_string();
+    for call in 0.. {
+        let mut args = vec!["HSCAN", "h", &cursor, "COUNT", "4"];
+        let no_values = call % 2 == 1;
+        if no_values {
+            args.push("NOVALUES");
+        }
+        let RESPValue::Array(Some(reply)) = command(&mut conn, &args).await else {
+            panic!("HSCAN didn't reply with an array");
+        };
+        let [
+            RESPValue::BulkString(Some(next)),
+            RESPValue::Array(Some(items)),
+        ] = &reply[..]
+        else {
+            panic!("unexpected HSCAN reply {:?}", reply);
+        };
+        let step = if no_values { 1 } else { 2 };
+        for pair in items.chunks(step) {
+            if !no_values {
+                assert_eq!(pair[1], bulk("v"));
+            }
+            let RESPValue::BulkString(Some(field)) = &pair[0] else {
+                panic!("unexpected field {:?}", pair[0]);
+            };
+            seen.push(String::from_utf8(field.clone()).unwrap());
+        }
+        if call == 2 {
+            command(&mut conn, &["HSET", "h", "f0", "v", "f20", "v"]).await;
+        }
+        cursor = String::from_utf8(next.clone()).unwrap();
+        if cursor == "0" {
+            break;
+        }
+    }
+    seen.sort();
+    let mut expected: Vec<String> = (0..25).map(|i| format!("f{}", i)).collect();
+    expected.sort();
+    assert_eq!(seen, expected);
+
+    assert_eq!(
+        command(
+            &mut conn,
+            &[
+                "HSCAN", "h", "0", "MATCH", "f1?", "COUNT", "100", "NOVALUES"
+            ]
+        )
+        .await,
+        array(vec![
+            bulk("0"),
+            array((10..20).map(|i| bulk(&format!("f{}", i))).collect())
+        ])
+    );
+}
+
+#[test]
+fn split_inline_tokenizes_like_redis_cli() {
+    use crate::resp::split_inline;
+    // The arguments joined with |, to compare in one assertion
+    let args = |line: &[u8]| split_inline(line).map(|args| args.join(&b'|'));
+
+    // Unquoted tokens split on any run of whitespace
+    assert_eq!(
+        args(b"  SET\tkey   value "),
+        Some(b"SET|key|value".to_vec())
+    );
+    assert_eq!(split_inline(b"   "), Some(Vec::new()));
+
+    // Double quotes keep spaces and take escapes
+    assert_eq!(args(br#"ECHO "a b""#), Some(b"ECHO|a b".to_vec()));
+    assert_eq!(args(br#""l1\nl2\r\t""#), Some(b"l1\nl2\r\t".to_vec()));
+    assert_eq!(args(br#""\x41\x7a\xff""#), Some(b"Az\xff".to_vec()));
+    assert_eq!(args(br#""\\ \" \q \xZZ""#), Some(b"\\ \" q xZZ".to_vec()));
+    assert_eq!(args(br#""\b\a""#), Some(b"\x08\x07".to_vec()));
+    assert_eq!(args(br#""""#), Some(Vec::new()));
+
+    // Single quotes are literal apart from \'
+    assert_eq!(args(br"'c\td'"), Some(br"c\td".to_vec()));
+    assert_eq!(args(br"'it\'s' x"), Some(b"it's|x".to_vec()));
+    assert_eq!(args(br#"'say "hi"'"#), Some(br#"say "hi""#.to_vec()));
+
+    // A quote can start mid-token, but a closing quote must end it
+    assert_eq!(args(br#"key"a b""#), Some(b"keya b".to_vec()));
+    assert_eq!(split_inline(br#""a"b"#), None);
+    assert_eq!(split_inline(br"'a'b"), None);
+
+    // Unbalanced quotes
+    assert_eq!(split_inline(br#"SET k "value"#), None);
+    assert_eq!(split_inline(br"SET k 'value"), None);
+    assert_eq!(split_inline(br#"SET k "value\"#), None);
+}
+
+#[tokio::test]
+async fn inline_commands_are_parsed_like_redis_cli() {
+    let mut conn = connect().await;
+    conn.stream
+        .write_all(b"SET \"a b\" 'c\\td'\r\n\r\nGET \"a\\x20b\"\nPING\r\n")
+        .await
+        .unwrap();
+    assert_eq!(read_reply(&mut conn).await, ok());
+    assert_eq!(read_reply(&mut conn).await, bulk("c\\td"));
+    assert_eq!(
+        read_reply(&mut conn).await,
+        RESPValue::SimpleString("PONG".to_string())
+    );
+
+    // Multibulk and inline requests mix on one connection
+    send_command(&mut conn, &["STRLEN", "a b"]).await;
+    conn.stream.write_all(b"EXISTS 'a b'\r\n").await.unwrap();
+    assert_eq!(read_reply(&mut conn).await, int(4));
+    assert_eq!(read_reply(&mut conn).await, int(1));
+
+    // Unbalanced quotes are a protocol error, which closes the connection
+    conn.stream.write_all(b"GET \"a b\r\n").await.unwrap();
+    assert_eq!(
+        read_reply(&mut conn).await,
+        RESPValue::Error("ERR Protocol error: unbalanced quotes in request".to_string())
+    );
+    let mut rest = Vec::new();
+    assert_eq!(conn.stream.read_to_end(&mut rest).await.unwrap(), 0);
+}
+
+#[test]
+fn parser_accepts_bare_lf_line_endings() {
+    let parse = |input: &[u8]| {
+        RESPValue::parse_frame(input, DEFAULT_MAX_DEPTH, DEFAULT_MAX_BULK_LEN).unwrap()
+    };
+
+    let get = RESPValue::Array(Some(vec![bulk("GET"), bulk("k")]));
+    assert_eq!(parse(b"*2\n$3\nGET\n$1\nk\n"), Some((get.clone(), 15)));
+    // Mixed with \r\n in one frame
+    assert_eq!(parse(b"*2\r\n$3\nGET\r\n$1\r\nk\n"), Some((get, 18)));
+    assert_eq!(parse(b":42\n"), Some((int(42), 4)));
+    assert_eq!(
+        parse(b"+OK\n"),
+        Some((RESPValue::SimpleString("OK".to_string()), 4))
+    );
+    assert_eq!(parse(b"$-1\n"), Some((RESPValue::BulkString(None), 4)));
+    // Still incomplete until the terminator arrives
+    assert_eq!(parse(b"*1\n$3\nGET"), None);
+}
+
+#[test]
+fn parser_rejects_lines_empty_after_the_type_prefix() {
+    let error = |input: &[u8]| {
+        RESPValue::parse_frame(input, DEFAULT_MAX_DEPTH, DEFAULT_MAX_BULK_LEN)
+            .unwrap_err()
+            .to_string()
+    };
+
+    assert_eq!(error(b"$\r\n"), "Protocol error: invalid bulk length");
+    assert_eq!(error(b"$\n"), "Protocol error: invalid bulk length");
+    assert_eq!(error(b"*\r\n"), "Protocol error: invalid multibulk length");
+    assert_eq!(error(b":\n"), "Protocol error: invalid integer");
+    assert_eq!(error(b"*1\r\n$\r\n"), "Protocol error: invalid bulk length");
+    assert_eq!(error(b"\r\n"), "Protocol error: empty line");
+    // An empty simple string is still a valid frame
+    assert_eq!(
+        RESPValue::parse_frame(b"+\r\n", DEFAULT_MAX_DEPTH, DEFAULT_MAX_BULK_LEN).unwrap(),
+        Some((RESPValue::SimpleString(String::new()), 3))
+    );
+}
+
+#[test]
+fn a_request_arriving_a_byte_at_a_time_resumes_where_the_last_read_stopped() {
+    use crate::resp::FrameScan;
+    let parse = |input: &[u8], scan: &mut FrameScan| {
+        RESPValue::parse_request(input, scan, DEFAULT_MAX_DEPTH, DEFAULT_MAX_BULK_LEN).unwrap()
+    };
+
+    // Nested, with a bulk string and a blank inline line in front
+    let first: &[u8] = b"\r\n*3\r\n$3\r\nSET\r\n*1\r\n$1\r\nk\r\n$5\r\nvalue\r\n";
+    let mut input = first.to_vec();
+    input.extend_from_slice(b"*1\r\n$4\r\nPING\r\n");
+    let mut scan = FrameScan::default();
+    for end in 0..first.len() {
+        assert_eq!(parse(&input[..end], &mut scan), None);
+    }
+    let set = RESPValue::Array(Some(vec![
+        bulk("SET"),
+        array(vec![bulk("k")]),
+        bulk("value"),
+    ]));
+    assert_eq!(
+        parse(&input[..first.len()], &mut scan),
+        Some((set, first.len()))
+    );
+
+    // The scan starts over for the next request
+    input.drain(..first.len());
+    assert_eq!(
+        parse(&input, &mut scan),
+        Some((array(vec![bulk("PING")]), input.len()))
+    );
+}
+
+#[tokio::test]
+async fn a_multibulk_with_many_arguments_is_parsed_in_linear_time() {
+    let mut conn = connect().await;
+    let members: Vec<String> = (0..200_000).map(|n| n.to_string()).collect();
+    let mut args = vec!["SADD", "big"];
+    args.extend(members.iter().map(String::as_str));
+
+    // Arrives over many reads; walking it from the start on each one took
+    // over a minute
+    let reply = tokio::time::timeout(Duration::from_secs(20), command(&mut conn, &args)).await;
+    assert_eq!(reply.expect("SADD took too long"), int(200_000));
+}
+
+#[tokio::test]
+async fn deeply_nested_arrays_are_a_protocol_error_that_closes_the_connection() {
+    let addr = start(Config::from_env()).await;
+    let mut conn = open(addr).await;
+    let mut request = b"*1\r\n".repeat(200);
+    request.extend_from_slice(b"$4\r\nPING\r\n");
+    conn.stream.write_all(&request).await.unwrap();
+    assert_eq!(
+        read_reply(&mut conn).await,
+        RESPValue::Error("ERR Protocol error: nesting depth limit exceeded".to_string())
+    );
+    let mut rest = Vec::new();
+    assert_eq!(conn.stream.read_to_end(&mut rest).await.unwrap(), 0);
+
+    // The server itself is fine
+    let mut conn = open(addr).await;
+    assert_eq!(
+        command(&mut conn, &["PING"]).await,
+        RESPValue::SimpleString("PONG".to_string())
+    );
+}
+
+#[tokio::test]
+async fn a_panic_holding_the_keyspace_lock_does_not_break_later_commands() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::new(Config::from_env());
+    let store = server.store().clone();
+    tokio::spawn(async move { server.serve(vec![listener]).await });
+    let mut conn = open(addr).await;
+    command(&mut conn, &["SET", "k", "v"]).await;
+
+    // Poisons the lock, as a panicking command handler would
+    let panicked = std::thread::spawn(move || store.panic_holding_lock()).join();
+    assert!(panicked.is_err());
+
+    assert_eq!(command(&mut conn, &["GET", "k"]).await, bulk("v"));
+    assert_eq!(command(&mut conn, &["SET", "k2", "v2"]).await, ok());
+    assert_eq!(command(&mut conn, &["DBSIZE"]).await, int(2));
+    let mut other = open(addr).await;
+    assert_eq!(command(&mut other, &["GET", "k2"]).await, bulk("v2"));
+}
+
+#[tokio::test]
+async fn run_returns_the_bind_error_naming_the_address() {
+    let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = taken.local_addr().unwrap().to_string();
+    let mut config = Config::from_env();
+    config.addrs = vec!["127.0.0.1:0".to_string(), addr.clone()];
+
+    let err = Server::new(config).run().await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::AddrInUse);
+    assert!(err.to_string().contains(&addr));
+}
+
+#[test]
+fn poisoned_locks_are_taken_over() {
+    use crate::lock;
+    use std::sync::RwLock;
+
+    let mutex = Arc::new(Mutex::new(1));
+    let rwlock = Arc::new(RwLock::new(1));
+    let (m, r) = (mutex.clone(), rwlock.clone());
+    let panicked = std::thread::spawn(move || {
+        let _m = m.lock().unwrap();
+        let _r = r.write().unwrap();
+        panic!("poison both locks");
+    })
+    .join();
+    assert!(panicked.is_err());
+    assert!(mutex.is_poisoned() && rwlock.is_poisoned());
+
+    *lock::mutex(&mutex) += 1;
+    *lock::write(&rwlock) += 1;
+    assert_eq!(*lock::mutex(&mutex), 2);
+    assert_eq!(*lock::read(&rwlock), 2);
+}
+
+#[tokio::test]
+async fn multi_handles_nesting_watch_and_select() {
+    let mut conn = connect().await;
+    let queued = || RESPValue::SimpleString("QUEUED".to_string());
+
+    // Neither a nested MULTI nor a WATCH discards the transaction
+    assert_eq!(command(&mut conn, &["MULTI"]).await, ok());
+    assert_eq!(command(&mut conn, &["SET", "k", "1"]).await, queued());
+    assert_eq!(
+        command(&mut conn, &["MULTI"]).await,
+        RESPValue::Error("ERR MULTI calls can not be nested".to_string())
+    );
+    assert_eq!(
+        command(&mut conn, &["WATCH", "k"]).await,
+        RESPValue::Error("ERR WATCH inside MULTI is not allowed".to_string())
+    );
+    assert_eq!(command(&mut conn, &["INCR", "k"]).await, queued());
+    assert_eq!(
+        command(&mut conn, &["EXEC"]).await,
+        array(vec![ok(), int(2)])
+    );
+
+    // SELECT is queued like any command and runs in its place at EXEC
+    command(&mut conn, &["MULTI"]).await;
+    assert_eq!(command(&mut conn, &["SELECT", "0"]).await, queued());
+    assert_eq!(command(&mut conn, &["GET", "k"]).await, queued());
+    assert_eq!(command(&mut conn, &["SELECT", "1"]).await, queued());
+    assert_eq!(command(&mut conn, &["INCR", "k"]).await, queued());
+    assert_eq!(
+        command(&mut conn, &["EXEC"]).await,
+        array(vec![
+            ok(),
+            bulk("2"),
+            RESPValue::Error("ERR DB index is out of range".to_string()),
+            int(3)
+        ])
+    );
+}
+
+#[tokio::test]
+async fn watch_aborts_exec_when_a_watched_key_changes() {
+    let now = Arc::new(Mutex::new(SystemTime::now()));
+    let clock = {
+        let now = now.clone();
+        Clock::new(move || *now.lock().unwrap())
+    };
+    let addr = start_with_clock(Config::from_env(), clock).await;
+    let mut conn = open(addr).await;
+    let mut other = open(addr).await;
+
+    // Untouched: EXEC runs
+    command(&mut conn, &["SET", "k", "1"]).await;
+    assert_eq!(command(&mut conn, &["WATCH", "k", "missing"]).await, ok());
+    command(&mut other, &["SET", "unrelated", "x"]).await;
+    command(&mut conn, &["MULTI"]).await;
+    command(&mut conn, &["INCR", "k"]).await;
+    assert_eq!(command(&mut conn, &["EXEC"]).await, array(vec![int(2)]));
+
+    // Changed by another connection, or created: EXEC replies nil
+    for (key, change) in [
+        ("k", ["SET", "k", "9"]),
+        ("missing", ["SET", "missing", "v"]),
+    ] {
+        command(&mut conn, &["WATCH", key]).await;
+        command(&mut other, &change).await;
+        command(&mut conn, &["MULTI"]).await;
+        command(&mut conn, &["SET", "k", "from-exec"]).await;
+        assert_eq!(command(&mut conn, &["EXEC"]).await, RESPValue::Array(None));
+    }
+    assert_eq!(command(&mut conn, &["GET", "k"]).await, bulk("9"));
+
+    // EXEC cleared the watch, and UNWATCH drops one
+    command(&mut other, &["SET", "k", "10"]).await;
+    command(&mut conn, &["MULTI"]).await;
+    command(&mut conn, &["GET", "k"]).await;
+    assert_eq!(command(&mut conn, &["EXEC"]).await, array(vec![bulk("10")]));
+    command(&mut conn, &["WATCH", "k"]).await;
+    assert_eq!(command(&mut conn, &["UNWATCH"]).await, ok());
+    command(&mut other, &["SET", "k", "11"]).await;
+    command(&mut conn, &["MULTI"]).await;
+    command(&mut conn, &["GET", "k"]).await;
+    assert_eq!(command(&mut conn, &["EXEC"]).await, array(vec![bulk("11")]));
+
+    // Keys a store command only reads from aren't changed by it; its
+    // destination is
+    command(&mut conn, &["ZADD", "zsrc", "1", "a"]).await;
+    command(&mut conn, &["RPUSH", "lsrc", "2", "1"]).await;
+    command(&mut conn, &["WATCH", "zsrc", "lsrc"]).await;
+    command(&mut other, &["ZUNIONSTORE", "zdst", "1", "zsrc"]).await;
+    command(&mut other, &["SORT", "lsrc", "STORE", "ldst"]).await;
+    command(&mut conn, &["MULTI"]).await;
+    command(&mut conn, &["GET", "k"]).await;
+    assert_eq!(command(&mut conn, &["EXEC"]).await, array(vec![bulk("11")]));
+    command(&mut conn, &["WATCH", "zdst", "ldst"]).await;
+    command(&mut other, &["SORT", "lsrc", "STORE", "ldst"]).await;
+    command(&mut conn, &["MULTI"]).await;
+    command(&mut conn, &["GET", "k"]).await;
+    assert_eq!(command(&mut conn, &["EXEC"]).await, RESPValue::Array(None));
+
+    // Expiring counts as a change, even before the key is removed
+    command(&mut conn, &["SET", "k", "v", "EX", "10"]).await;
+    command(&mut conn, &["WATCH", "k"]).await;
+    *now.lock().unwrap() += Duration::from_secs(11);
+    command(&mut conn, &["MULTI"]).await;
+    command(&mut conn, &["SET", "k", "from-exec"]).await;
+    assert_eq!(command(&mut conn, &["EXEC"]).await, RESPValue::Array(None));
+    assert_eq!(command(&mut conn, &["EXISTS", "k"]).await, int(0));
+
+    assert_eq!(
+        command(&mut conn, &["WATCH"]).await,
+        RESPValue::Error("ERR wrong number of arguments for 'watch' command".to_string())
+    );
+}
+
+// Sends a command whose arguments may be arbitrary bytes, such as a DUMP payload
+async fn command_bytes(conn: &mut Connection, args: &[&[u8]]) -> RESPValue {
+    let request = RESPValue::Array(Some(
+        args.iter()
+            .map(|arg| RESPValue::BulkString(Some(arg.to_vec())))
+            .collect(),
+    ));
+    conn.stream.write_all(&request.serialize()).await.unwrap();
+    read_reply(conn).await
+}
+
+#[tokio::test]
+async fn restore_derives_the_encoding_from_the_restored_value() {
+    let mut conn = connect().await;
+    let encoding = |s: &str| RESPValue::BulkString(Some(s.as_bytes().to_vec()));
+    let dump = |reply: RESPValue| match reply {
+        RESPValue::BulkString(Some(payload)) => payload,
+        other => panic!("expected a DUMP payload, got {:?}", other),
+    };
+
+    for i in 0..200 {
+        let field = format!("f{}", i);
+        command(&mut conn, &["HSET", "big", &field, "v"]).await;
+    }
+    assert_eq!(
+        command(&mut conn, &["OBJECT", "ENCODING", "big"]).await,
+        encoding("hashtable")
+    );
+    let payload = dump(command(&mut conn, &["DUMP", "big"]).await);
+    assert_eq!(
+        command(&mut conn, &["DUMP", "missing"]).await,
+        RESPValue::BulkString(None)
+    );
+
+    assert_eq!(
+        command_bytes(&mut conn, &[b"RESTORE", b"copy", b"0", &payload]).await,
+        ok()
+    );
+    assert_eq!(command(&mut conn, &["HLEN", "copy"]).await, int(200));
+    assert_eq!(
+        command(&mut conn, &["HGET", "copy", "f150"]).await,
+        bulk("v")
+    );
+    assert_eq!(
+        command(&mut conn, &["OBJECT", "ENCODING", "copy"]).await,
+        encoding("hashtable")
+    );
+
+    // The payload carries no encoding: under a higher threshold the same
+    // hash comes back compact
+    command(
+        &mut conn,
+        &["CONFIG", "SET", "hash-max-listpack-entries", "512"],
+    )
+    .await;
+    assert_eq!(
+        command_bytes(&mut conn, &[b"RESTORE", b"compact", b"0", &payload]).await,
+        ok()
+    );
+    assert_eq!(
+        command(&mut conn, &["OBJECT", "ENCODING", "compact"]).await,
+        encoding("listpack")
+    );
+
+    command(&mut conn, &["SADD", "nums", "1", "2", "3"]).await;
+    let payload = dump(command(&mut conn, &["DUMP", "nums"]).await);
+    assert_eq!(
+        command_bytes(&mut conn, &[b"RESTORE", b"nums2", b"0", &payload]).await,
+        ok()
+    );
+    assert_eq!(
+        command(&mut conn, &["OBJECT", "ENCODING", "nums2"]).await,
+        encoding("intset")
+    );
+
+    // An existing key needs REPLACE
+    assert_eq!(
+        command_bytes(&mut conn, &[b"RESTORE", b"nums2", b"0", &payload]).await,
+        RESPValue::Error("BUSYKEY Target key name already exists.".to_string())
+    );
+    assert_eq!(
+        command_bytes(
+            &mut conn,
+            &[b"RESTORE", b"nums2", b"5000", &payload, b"REPLACE"]
+        )
+        .await,
+        ok()
+    );
+    match command(&mut conn, &["PTTL", "nums2"]).await {
+        RESPValue::Integer(ms) => assert!(ms > 0 && ms <= 5000, "PTTL {}", ms),
+        other => panic!("expected an integer, got {:?}", other),
+    }
+
+    let mut corrupt = payload.clone();
+    corrupt[1] ^= 0xff;
+    assert_eq!(
+        command_bytes(&mut conn, &[b"RESTORE", b"bad", b"0", &corrupt]).await,
+        RESPValue::Error("ERR DUMP payload version or checksum are wrong".to_string())
+    );
+    assert_eq!(
+        command_bytes(&mut conn, &[b"RESTORE", b"bad", b"-1", &payload]).await,
+        RESPValue::Error("ERR Invalid TTL value, must be >= 0".to_string())
+    );
+    assert_eq!(command(&mut conn, &["EXISTS", "bad"]).await, int(0));
+}
+
+#[tokio::test]
+async fn integer_increments_keep_the_key_expiry() {
+    let mut conn = connect().await;
+    let ttl = |reply: RESPValue| match reply {
+        RESPValue::Integer(secs) => secs,
+        other => panic!("expected an integer, got {:?}", other),
+    };
+
+    command(&mut conn, &["SET", "n", "1", "EX", "100"]).await;
+    assert_eq!(command(&mut conn, &["INCR", "n"]).await, int(2));
+    assert_eq!(command(&mut conn, &["DECR", "n"]).await, int(1));
+    assert_eq!(command(&mut conn, &["INCRBY", "n", "10"]).await, int(11));
+    assert_eq!(command(&mut conn, &["DECRBY", "n", "5"]).await, int(6));
+    let secs = ttl(command(&mut conn, &["TTL", "n"]).await);
+    assert!(secs > 0 && secs <= 100, "TTL {}", secs);
+
+    // A failed increment leaves both the value and the expiry alone
+    command(
+        &mut conn,
+        &["SET", "max", "9223372036854775807", "EX", "100"],
+    )
+    .await;
+    assert_eq!(
+        command(&mut conn, &["INCR", "max"]).await,
+        RESPValue::Error("ERR increment or decrement would overflow".to_string())
+    );
+    assert_eq!(
+        command(&mut conn, &["GET", "max"]).await,
+        bulk("9223372036854775807")
+    );
+    assert!(ttl(command(&mut conn, &["TTL", "max"]).await) > 0);
+
+    // A key INCR creates has no expiry
+    assert_eq!(command(&mut conn, &["INCR", "fresh"]).await, int(1));
+    assert_eq!(command(&mut conn, &["TTL", "fresh"]).await, int(-1));
+}
+
+#[tokio::test]
+async fn random_member_commands_reject_huge_negative_counts() {
+    let mut conn = connect().await;
+    let out_of_range = RESPValue::Error("ERR value is out of range".to_string());
+    let len = |reply: RESPValue| match reply {
+        RESPValue::Array(Some(items)) => items.len(),
+        other => panic!("expected an array, got {:?}", other),
+    };
+
+    command(&mut conn, &["SADD", "s", "a", "b"]).await;
+    command(&mut conn, &["HSET", "h", "f", "v"]).await;
+    command(&mut conn, &["ZADD", "z", "1", "m"]).await;
+
+    for args in [
+        &["SRANDMEMBER", "s", "-100000000000"][..],
+        &["HRANDFIELD", "h", "-100000000000"],
+        &["HRANDFIELD", "h", "-100000000000", "WITHVALUES"],
+        &["ZRANDMEMBER", "z", "-100000000000"],
+        &["ZRANDMEMBER", "z", "-100000000000", "WITHSCORES"],
+        &["SRANDMEMBER", "s", "-9223372036854775808"],
+    ] {
+        assert_eq!(command(&mut conn, args).await, out_of_range, "{:?}", args);
+    }
+
+    // Counts within the bound still repeat elements as asked, and a large
+    // positive count just returns every element
+    assert_eq!(
+        len(command(&mut conn, &["SRANDMEMBER", "s", "-1000"]).await),
+        1000
+    );
+    assert_eq!(
+        len(command(&mut conn, &["HRANDFIELD", "h", "-10", "WITHVALUES"]).await),
+        20
+    );
+    assert_eq!(
+        len(command(&mut conn, &["ZRANDMEMBER", "z", "-10", "WITHSCORES"]).await),
+        20
+    );
+    assert_eq!(
+        len(command(&mut conn, &["SRANDMEMBER", "s", "100000000000"]).await),
+        2
+    );
+}
+
+#[tokio::test]
+async fn restore_rejects_forged_element_counts_without_allocating_them() {
+    let mut conn = connect().await;
+    // A payload with a valid version and checksum around `body`, which is
+    // <type> <count:u32 BE> and nothing else
+    let forge = |body: &[u8]| {
+        let mut payload = body.to_vec();
+        payload.push(1);
+        let checksum = payload.iter().fold(0xcbf29ce484222325u64, |hash, &b| {
+            (hash ^ b as u64).wrapping_mul(0x100000001b3)
+        });
+        payload.extend_from_slice(&checksum.to_be_bytes());
+        payload
+    };
+
+    for kind in [1u8, 2, 3, 4] {
+        let payload = forge(&[kind, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(
+            command_bytes(&mut conn, &[b"RESTORE", b"forged", b"0", &payload]).await,
+            RESPValue::Error("ERR DUMP payload version or checksum are wrong".to_string()),
+            "type {}",
+            kind
+        );
+    }
+    assert_eq!(command(&mut conn, &["EXISTS", "forged"]).await, int(0));
+
+    // Well-formed contents behind a forged checksum still restore
+    let payload = forge(&[1, 0, 0, 0, 1, 0, 0, 0, 1, b'x']);
+    assert_eq!(
+        command_bytes(&mut conn, &[b"RESTORE", b"forged", b"0", &payload]).await,
+        ok()
+    );
+    assert_eq!(
+        command(&mut conn, &["LRANGE", "forged", "0", "-1"]).await,
+        array(vec![bulk("x")])
+    );
+}
+
+#[tokio::test]
+async fn sort_store_serves_a_client_blocked_on_the_destination() {
+    let addr = start(Config::from_env()).await;
+    let mut writer = open(addr).await;
+    command(&mut writer, &["RPUSH", "src", "3", "1", "2"]).await;
+
+    let mut popper = open(addr).await;
+    send_command(&mut popper, &["BLPOP", "dst", "5"]).await;
+    wait_for_blpop(&mut writer).await;
+    assert_eq!(
+        command(&mut writer, &["SORT", "src", "STORE", "dst"]).await,
+        int(3)
+    );
+    assert_eq!(
+        read_reply(&mut popper).await,
+        array(vec![bulk("dst"), bulk("1")])
+    );
+    assert_eq!(
+        command(&mut writer, &["LRANGE", "dst", "0", "-1"]).await,
+        array(vec![bulk("2"), bulk("3")])
+    );
+}
+
+#[tokio::test]
+async fn restore_serves_a_client_blocked_on_the_key() {
+    let addr = start(Config::from_env()).await;
+    let mut writer = open(addr).await;
+    command(&mut writer, &["RPUSH", "src", "a", "b"]).await;
+    let RESPValue::BulkString(Some(payload)) = command(&mut writer, &["DUMP", "src"]).await else {
+        panic!("DUMP of a list should reply with a payload");
+    };
+
+    let mut popper = open(addr).await;
+    send_command(&mut popper, &["BLPOP", "restored", "5"]).await;
+    wait_for_blpop(&mut writer).await;
+    assert_eq!(
+        command_bytes(&mut writer, &[b"RESTORE", b"restored", b"0", &payload]).await,
+        ok()
+    );
+    assert_eq!(
+        read_reply(&mut popper).await,
+        array(vec![bulk("restored"), bulk("a")])
+    );
+    assert_eq!(command(&mut writer, &["LLEN", "restored"]).await, int(1));
+}
+
+#[tokio::test]
+async fn maxmemory_sees_values_that_grow_and_shrink_in_place() {
+    let mut conn = start_with_maxmemory("noeviction", 25_000).await;
+    let big = "x".repeat(BIG);
+
+    // Each check comes before its write, so the third push still runs
+    for len in 1..=3 {
+        assert_eq!(command(&mut conn, &["RPUSH", "list", &big]).await, int(len));
+    }
+    assert_eq!(command(&mut conn, &["RPUSH", "list", &big]).await, oom());
+    assert_eq!(command(&mut conn, &["APPEND", "s", &big]).await, oom());
+
+    // Popping shrinks the list in place, which frees room again
+    command(&mut conn, &["LPOP", "list"]).await;
+    command(&mut conn, &["LPOP", "list"]).await;
+    assert_eq!(
+        command(&mut conn, &["APPEND", "s", &big]).await,
+        int(BIG as i64)
+    );
+    let used: usize = info_field(&mut conn, "used_memory").await.parse().unwrap();
+    assert!((20_000..25_000).contains(&used), "used_memory {}", used);
+}
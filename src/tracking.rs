@@ -0,0 +1,83 @@
+use crate::lock;
+use crate::pubsub::MessageSender;
+use crate::resp::RESPValue;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+#[derive(Default)]
+struct Table {
+    // Key -> ids of the tracking connections that read it since its last change
+    keys: HashMap<Vec<u8>, HashSet<u64>>,
+    // Connections with CLIENT TRACKING ON
+    clients: HashMap<u64, MessageSender>,
+}
+
+// Client-side caching in Redis' default mode: the server remembers which
+// keys each tracking connection has read and pushes an invalidation the
+// first time one of them changes. A key has to be read again to be tracked
+// again. Invalidations are only delivered to RESP3 connections.
+#[derive(Clone, Default)]
+pub struct Tracking {
+    table: Arc<Mutex<Table>>,
+}
+
+impl Tracking {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn enable(&self, client_id: u64, sender: MessageSender) {
+        lock::mutex(&self.table).clients.insert(client_id, sender);
+    }
+
+    // Entries in the key table are left to be dropped by the next invalidation
+    pub fn disable(&self, client_id: u64) {
+        lock::mutex(&self.table).clients.remove(&client_id);
+    }
+
+    pub fn remember(&self, client_id: u64, keys: &[&[u8]]) {
+        let mut table = lock::mutex(&self.table);
+        for key in keys {
+            table
+                .keys
+                .entry(key.to_vec())
+                .or_default()
+                .insert(client_id);
+        }
+    }
+
+    pub fn invalidate(&self, keys: &[&[u8]]) {
+        let mut table = lock::mutex(&self.table);
+        if table.keys.is_empty() {
+            return;
+        }
+        for key in keys {
+            let Some(readers) = table.keys.remove(*key) else {
+                continue;
+            };
+            for id in readers {
+                if let Some(sender) = table.clients.get(&id) {
+                    sender.push(invalidation(Some(key)));
+                }
+            }
+        }
+    }
+
+    // FLUSHDB: every tracking connection drops its whole cache
+    pub fn invalidate_all(&self) {
+        let mut table = lock::mutex(&self.table);
+        table.keys.clear();
+        for sender in table.clients.values() {
+            sender.push(invalidation(None));
+        }
+    }
+}
+
+// ["invalidate", [key]], or ["invalidate", nil] when everything changed
+fn invalidation(key: Option<&[u8]>) -> Vec<RESPValue> {
+    let keys = match key {
+        Some(key) => RESPValue::Array(Some(vec![RESPValue::BulkString(Some(key.to_vec()))])),
+        None => RESPValue::BulkString(None),
+    };
+    vec![RESPValue::BulkString(Some(b"invalidate".to_vec())), keys]
+}
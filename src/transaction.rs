@@ -0,0 +1,74 @@
+use crate::command::Command;
+use crate::command_table;
+use crate::resp::RESPValue;
+
+// Commands queued by MULTI on one connection. A command rejected while
+// queueing (unknown, wrong arity, not allowed in a transaction) marks the
+// whole transaction to be discarded by EXEC.
+#[derive(Default)]
+pub struct Transaction {
+    commands: Vec<Command>,
+    aborted: bool,
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns the reply for the queued command: QUEUED or the queue-time error
+    pub fn queue(&mut self, cmd: Command) -> RESPValue {
+        // Like Redis, a plain error that leaves the transaction intact
+        if cmd.name == "WATCH" {
+            return RESPValue::Error("ERR WATCH inside MULTI is not allowed".to_string());
+        }
+        let error = match command_table::lookup(&cmd.name) {
+            None => Some(format!("ERR unknown command '{}'", cmd.name)),
+            Some(spec) if !spec.accepts(cmd.args.len() + 1) => Some(format!(
+                "ERR wrong number of arguments for '{}' command",
+                cmd.name.to_lowercase()
+            )),
+            Some(_) if !allowed_in_multi(&cmd.name) => {
+                Some("ERR Command not allowed inside a transaction".to_string())
+            }
+            Some(_) => None,
+        };
+
+        match error {
+            Some(error) => {
+                self.aborted = true;
+                RESPValue::Error(error)
+            }
+            None => {
+                self.commands.push(cmd);
+                RESPValue::SimpleString("QUEUED".to_string())
+            }
+        }
+    }
+
+    // For commands the connection rejected before they reached `queue`
+    pub fn abort(&mut self) {
+        self.aborted = true;
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.aborted
+    }
+
+    // CLIENT PAUSE WRITE holds back an EXEC that would write
+    pub fn has_writes(&self) -> bool {
+        self.commands.iter().any(|cmd| cmd.is_write())
+    }
+
+    pub fn into_commands(self) -> Vec<Command> {
+        self.commands
+    }
+}
+
+// Commands that change the connection's mode can't run from EXEC
+fn allowed_in_multi(name: &str) -> bool {
+    !matches!(
+        name,
+        "SYNC" | "HELLO" | "SUBSCRIBE" | "UNSUBSCRIBE" | "PSUBSCRIBE" | "PUNSUBSCRIBE"
+    )
+}
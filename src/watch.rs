@@ -0,0 +1,94 @@
+use crate::lock;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+#[derive(Default)]
+struct Watched {
+    keys: HashSet<Vec<u8>>,
+    // Set once any of the keys changes; EXEC then aborts
+    dirty: bool,
+}
+
+#[derive(Default)]
+struct Table {
+    // Key -> ids of the connections watching it
+    keys: HashMap<Vec<u8>, HashSet<u64>>,
+    clients: HashMap<u64, Watched>,
+}
+
+// WATCH: optimistic locking for MULTI/EXEC. Every change to a key, including
+// its expiry, marks the connections watching it, and their next EXEC aborts.
+// Watches last until EXEC, DISCARD, UNWATCH, RESET or the connection closes.
+#[derive(Clone, Default)]
+pub struct Watches {
+    table: Arc<Mutex<Table>>,
+}
+
+impl Watches {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn watch(&self, client_id: u64, keys: &[Vec<u8>]) {
+        let mut table = lock::mutex(&self.table);
+        for key in keys {
+            table.keys.entry(key.clone()).or_default().insert(client_id);
+        }
+        table
+            .clients
+            .entry(client_id)
+            .or_default()
+            .keys
+            .extend(keys.iter().cloned());
+    }
+
+    pub fn touch(&self, keys: &[&[u8]]) {
+        let mut table = lock::mutex(&self.table);
+        if table.keys.is_empty() {
+            return;
+        }
+        let Table {
+            keys: watched,
+            clients,
+        } = &mut *table;
+        for key in keys {
+            for id in watched.get(*key).into_iter().flatten() {
+                if let Some(client) = clients.get_mut(id) {
+                    client.dirty = true;
+                }
+            }
+        }
+    }
+
+    // FLUSHDB and FLUSHALL
+    pub fn touch_all(&self) {
+        for client in lock::mutex(&self.table).clients.values_mut() {
+            client.dirty = true;
+        }
+    }
+
+    pub fn keys(&self, client_id: u64) -> Vec<Vec<u8>> {
+        lock::mutex(&self.table)
+            .clients
+            .get(&client_id)
+            .map_or_else(Vec::new, |client| client.keys.iter().cloned().collect())
+    }
+
+    // Drops every watch the connection has, returning whether one of the
+    // keys changed since it was watched
+    pub fn unwatch(&self, client_id: u64) -> bool {
+        let mut table = lock::mutex(&self.table);
+        let Some(client) = table.clients.remove(&client_id) else {
+            return false;
+        };
+        for key in &client.keys {
+            if let Some(watchers) = table.keys.get_mut(key) {
+                watchers.remove(&client_id);
+                if watchers.is_empty() {
+                    table.keys.remove(key);
+                }
+            }
+        }
+        client.dirty
+    }
+}
@@ -0,0 +1,179 @@
+use bytes::Bytes;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+
+// Scores are never NaN (parse_score rejects it), so ordering them is total
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Score(f64);
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+// Members indexed both by name (for score lookups) and by (score, member)
+// so ranges come out in Redis order: by score, ties broken lexicographically
+#[derive(Clone, Debug, Default)]
+pub struct SortedSet {
+    scores: HashMap<Bytes, f64>,
+    order: BTreeSet<(Score, Bytes)>,
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns true if the member was not present before
+    pub fn insert(&mut self, member: Bytes, score: f64) -> bool {
+        // -0 and 0 are the same score
+        let score = if score == 0.0 { 0.0 } else { score };
+        let added = match self.scores.insert(member.clone(), score) {
+            Some(old) => {
+                self.order.remove(&(Score(old), member.clone()));
+                false
+            }
+            None => true,
+        };
+        self.order.insert((Score(score), member));
+        added
+    }
+
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self.scores.remove_entry(member) {
+            Some((member, score)) => {
+                self.order.remove(&(Score(score), member));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    // Estimated bytes held, for INFO's used_memory. The member bytes are
+    // shared by both indexes, so they count once.
+    pub fn memory_usage(&self) -> usize {
+        self.scores
+            .keys()
+            .map(|member| {
+                member.len()
+                    + 2 * std::mem::size_of::<Bytes>()
+                    + std::mem::size_of::<f64>()
+                    + std::mem::size_of::<Score>()
+            })
+            .sum()
+    }
+
+    // Members in rank order
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, f64)> {
+        self.order.iter().map(|(score, member)| (member, score.0))
+    }
+
+    // Inclusive rank range with negative indices counting from the end, like LRANGE
+    pub fn range(&self, start: i64, stop: i64) -> Vec<(Bytes, f64)> {
+        let len = self.len() as i64;
+        let start = if start < 0 {
+            (len + start).max(0)
+        } else {
+            start
+        };
+        let stop = if stop < 0 {
+            len + stop
+        } else {
+            stop.min(len - 1)
+        };
+        if start > stop || start >= len {
+            return Vec::new();
+        }
+
+        self.iter()
+            .skip(start as usize)
+            .take((stop - start + 1) as usize)
+            .map(|(member, score)| (member.clone(), score))
+            .collect()
+    }
+}
+
+// Redis accepts inf/+inf/-inf but never NaN
+pub fn parse_score(value: &[u8]) -> Option<f64> {
+    let text = std::str::from_utf8(value).ok()?;
+    let score = match text.to_ascii_lowercase().as_str() {
+        "inf" | "+inf" => f64::INFINITY,
+        "-inf" => f64::NEG_INFINITY,
+        _ => text.parse::<f64>().ok()?,
+    };
+    if score.is_nan() { None } else { Some(score) }
+}
+
+// Shortest representation that parses back to the same score, switching to
+// exponent notation (`1e+20`, `1.5e-05`) where Redis' %.17g would
+pub fn format_score(score: f64) -> String {
+    if score == f64::INFINITY {
+        return "inf".to_string();
+    }
+    if score == f64::NEG_INFINITY {
+        return "-inf".to_string();
+    }
+
+    let magnitude = score.abs();
+    if magnitude != 0.0 && !(1e-4..1e17).contains(&magnitude) {
+        let formatted = format!("{:e}", score);
+        let (mantissa, exponent) = formatted.split_once('e').unwrap();
+        let (sign, digits) = match exponent.strip_prefix('-') {
+            Some(digits) => ('-', digits),
+            None => ('+', exponent),
+        };
+        format!("{}e{}{:0>2}", mantissa, sign, digits)
+    } else {
+        format!("{}", score)
+    }
+}
+
+// How ZUNIONSTORE/ZINTERSTORE combine the weighted scores of a member
+// present in several inputs
+#[derive(Clone, Copy, Debug)]
+pub enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    pub fn combine(self, a: f64, b: f64) -> f64 {
+        match self {
+            // inf + -inf is NaN; Redis treats that as 0
+            Aggregate::Sum => zero_if_nan(a + b),
+            Aggregate::Min => a.min(b),
+            Aggregate::Max => a.max(b),
+        }
+    }
+}
+
+// Weighted input score; 0 * inf is NaN, which Redis also turns into 0
+pub fn weighted(score: f64, weight: f64) -> f64 {
+    zero_if_nan(score * weight)
+}
+
+fn zero_if_nan(score: f64) -> f64 {
+    if score.is_nan() { 0.0 } else { score }
+}